@@ -0,0 +1,1032 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `accounts` adds, modifies and deletes users and groups, like the
+//! `useradd`, `usermod`, `userdel`, `groupadd`, `groupmod` and `groupdel`
+//! tools.
+//!
+//! Every operation takes the lock, reads `etc/passwd`, `etc/group`,
+//! `etc/shadow` and `etc/gshadow` below the root directory, checks the
+//! change and writes back the modified files with the [Writer]. The shadow
+//! files are optional, and only updated if they exist. The files are read in
+//! [strict mode](crate::error::ParseMode::Strict), so a corrupt file is
+//! never written back with lines missing. Fields that are not UTF-8, like a
+//! GECOS in latin-1, are written back with the bytes they were read with,
+//! and the `+` and `-` lines of NIS compat mode are kept as they are, with
+//! new entries added before them.
+//!
+//! New uids and gids are handed out by the [IdAllocator], in the ranges of
+//! `etc/login.defs` and outside the ranges in `etc/subuid` and `etc/subgid`,
+//! and the password aging of new users is taken from `etc/login.defs`.
+//!
+//! ```
+//! use user_lookup::accounts::{AccountManager, NewGroup, NewUser};
+//!
+//! let root = std::env::temp_dir().join(format!("user_lookup_accounts_{}", std::process::id()));
+//! std::fs::create_dir_all(root.join("etc")).unwrap();
+//! for file in ["passwd", "group", "shadow", "gshadow"] {
+//!     std::fs::copy(format!("test_files/{}", file), root.join("etc").join(file)).unwrap();
+//! }
+//!
+//! let manager = AccountManager::with_root(&root);
+//! manager.groupadd(&NewGroup::new("developers")).unwrap();
+//!
+//! let mut user = NewUser::new("user3");
+//! user.groups = vec!["developers".to_string()];
+//! let entry = manager.useradd(&user).unwrap();
+//! assert_eq!(1002, entry.uid);
+//! assert_eq!("/home/user3", entry.home_dir);
+//!
+//! let group = std::fs::read_to_string(root.join("etc/group")).unwrap();
+//! assert!(group.contains("developers:x:1000:user3\n"));
+//! assert!(group.contains("user3:x:1002:\n"));
+//!
+//! manager.userdel("user3").unwrap();
+//! let group = std::fs::read_to_string(root.join("etc/group")).unwrap();
+//! assert!(group.contains("developers:x:1000:\n"));
+//! assert!(!group.contains("user3"));
+//! # std::fs::remove_dir_all(&root).unwrap();
+//! ```
+use crate::allocator::IdAllocator;
+use crate::error::ParseError;
+use crate::error::ParseErrorKind;
+use crate::login_defs::LoginDefs;
+use crate::writer::check_fields;
+use crate::writer::Fields;
+use crate::writer::WriteLock;
+use crate::writer::Writer;
+use crate::Error;
+use crate::GroupEntry;
+use crate::GshadowEntry;
+use crate::PasswdEntry;
+use crate::ShadowEntry;
+use crate::SubIdEntry;
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+///Why an account operation was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AccountError {
+    ///The user or group name is not valid, see [is_valid_name]
+    InvalidName(String),
+    ///A field contains a `:` or a newline
+    InvalidField(&'static str),
+    ///The user already exists
+    UserExists(String),
+    ///The user does not exist
+    UserNotFound(String),
+    ///The uid is already used
+    UidExists(u32),
+    ///The group already exists
+    GroupExists(String),
+    ///The group does not exist
+    GroupNotFound(String),
+    ///The gid is already used
+    GidExists(u32),
+    ///The group is the primary group of a user, and can not be removed
+    PrimaryGroup {
+        ///The group
+        group: String,
+        ///The user
+        user: String,
+    },
+    ///There are no unused ids left in the range
+    NoFreeId,
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountError::InvalidName(name) => write!(f, "invalid name '{}'", name),
+            AccountError::InvalidField(field) => write!(f, "invalid {}", field),
+            AccountError::UserExists(name) => write!(f, "user '{}' already exists", name),
+            AccountError::UserNotFound(name) => write!(f, "user '{}' does not exist", name),
+            AccountError::UidExists(uid) => write!(f, "uid {} is not unique", uid),
+            AccountError::GroupExists(name) => write!(f, "group '{}' already exists", name),
+            AccountError::GroupNotFound(name) => write!(f, "group '{}' does not exist", name),
+            AccountError::GidExists(gid) => write!(f, "gid {} is not unique", gid),
+            AccountError::PrimaryGroup { group, user } => write!(
+                f,
+                "group '{}' is the primary group of user '{}'",
+                group, user
+            ),
+            AccountError::NoFreeId => write!(f, "no unused id available"),
+        }
+    }
+}
+
+impl std::error::Error for AccountError {}
+
+///Checks a user or group name the way shadow-utils does by default. The
+///name must start with a lower case letter or `_`, followed by lower case
+///letters, digits, `_`, `-` or `.`, may end with a `$` for Samba machine
+///accounts, and may be at most 32 characters.
+/// ```
+/// use user_lookup::accounts::is_valid_name;
+///
+/// assert!(is_valid_name("user1"));
+/// assert!(is_valid_name("host$"));
+/// assert!(!is_valid_name("1user"));
+/// assert!(!is_valid_name("user:1"));
+/// assert!(!is_valid_name(""));
+/// assert!(is_valid_name(&"a".repeat(32)));
+/// assert!(!is_valid_name(&"a".repeat(33)));
+/// assert!(!is_valid_name(&format!("{}$", "a".repeat(32))));
+/// ```
+pub fn is_valid_name(name: &str) -> bool {
+    let len = name.len();
+    let name = name.strip_suffix('$').unwrap_or(name);
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_lowercase() || c == '_' => (),
+        _ => return false,
+    }
+    len <= 32 && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "_-.".contains(c))
+}
+
+///A user to add with [AccountManager::useradd]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct NewUser {
+    ///Username
+    pub name: String,
+    ///User ID, or `None` to use the next free one
+    pub uid: Option<u32>,
+    ///Name of the primary group, or `None` to create a group
+    ///with the same name as the user
+    pub primary_group: Option<String>,
+    ///Names of the supplementary groups
+    pub groups: Vec<String>,
+    ///User full name or comment
+    pub gecos: String,
+    ///Home directory, or `None` for `/home/<name>`
+    pub home_dir: Option<String>,
+    ///Shell
+    pub shell: String,
+    ///Hashed password, or `None` to lock the password
+    pub passwd: Option<String>,
+    ///Whether the user is a system user, which gets a uid in the
+    ///system range
+    pub system: bool,
+}
+
+impl NewUser {
+    ///Creates a NewUser with a name, using defaults for everything else
+    pub fn new<T: Into<String>>(name: T) -> Self {
+        Self {
+            name: name.into(),
+            shell: "/bin/sh".to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+///Changes to make to a user with [AccountManager::usermod]. Fields
+///set to `None` are left unchanged.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct UserChanges {
+    ///New username
+    pub name: Option<String>,
+    ///New user ID
+    pub uid: Option<u32>,
+    ///Name of the new primary group
+    pub primary_group: Option<String>,
+    ///New list of supplementary groups
+    pub groups: Option<Vec<String>>,
+    ///Add the `groups` to the current supplementary groups,
+    ///instead of replacing them
+    pub append: bool,
+    ///New full name or comment
+    pub gecos: Option<String>,
+    ///New home directory
+    pub home_dir: Option<String>,
+    ///New shell
+    pub shell: Option<String>,
+    ///New hashed password
+    pub passwd: Option<String>,
+}
+
+///A group to add with [AccountManager::groupadd]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct NewGroup {
+    ///Group name
+    pub name: String,
+    ///Group ID, or `None` to use the next free one
+    pub gid: Option<u32>,
+    ///Names of the members
+    pub members: Vec<String>,
+    ///Whether the group is a system group, which gets a gid in the
+    ///system range
+    pub system: bool,
+}
+
+impl NewGroup {
+    ///Creates a NewGroup with a name, using defaults for everything else
+    pub fn new<T: Into<String>>(name: T) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+}
+
+///Changes to make to a group with [AccountManager::groupmod]. Fields
+///set to `None` are left unchanged.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct GroupChanges {
+    ///New group name
+    pub name: Option<String>,
+    ///New group ID. Users with the old gid as primary group are moved
+    ///to the new gid.
+    pub gid: Option<u32>,
+    ///New list of members
+    pub members: Option<Vec<String>>,
+}
+
+///Adds, modifies and deletes users and groups in the databases below
+///a root directory.
+#[derive(Debug, Clone)]
+pub struct AccountManager {
+    root: PathBuf,
+    writer: Writer,
+}
+
+impl Default for AccountManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AccountManager {
+    ///Creates a new AccountManager for the databases in `/etc`
+    pub fn new() -> Self {
+        Self::with_root("/")
+    }
+
+    ///Creates a new AccountManager for the databases in `etc`
+    ///below `root`, locking `etc/.pwd.lock`
+    pub fn with_root<T: Into<PathBuf>>(root: T) -> Self {
+        let root = root.into();
+        Self {
+            writer: Writer::in_dir(root.join("etc")),
+            root,
+        }
+    }
+
+    ///Sets the [Writer] used to lock and write the files
+    pub fn with_writer(mut self, writer: Writer) -> Self {
+        self.writer = writer;
+        self
+    }
+
+    fn path(&self, file: &str) -> PathBuf {
+        self.root.join("etc").join(file)
+    }
+
+    ///Loads the databases under the lock, applies `change` and writes
+    ///back the files that were modified
+    fn update<T>(
+        &self,
+        change: impl FnOnce(&mut Databases) -> Result<T, AccountError>,
+    ) -> Result<T, Error> {
+        let lock = self.writer.lock()?;
+        let mut kept = Kept::default();
+        let passwd = load(&self.path("passwd"), PasswdEntry::try_parse, &mut kept)?;
+        let group = load(&self.path("group"), GroupEntry::try_parse, &mut kept)?;
+        let shadow = load(&self.path("shadow"), ShadowEntry::try_parse, &mut kept)?;
+        let gshadow = load(&self.path("gshadow"), GshadowEntry::try_parse, &mut kept)?;
+        let subuid = load(&self.path("subuid"), SubIdEntry::try_parse, &mut kept)?;
+        let subgid = load(&self.path("subgid"), SubIdEntry::try_parse, &mut kept)?;
+        let mut db = Databases {
+            passwd: passwd.ok_or_else(|| not_found(&self.path("passwd")))?,
+            group: group.ok_or_else(|| not_found(&self.path("group")))?,
+            shadow,
+            gshadow,
+            subuid: subuid.unwrap_or_default(),
+            subgid: subgid.unwrap_or_default(),
+            defs: match LoginDefs::from_file(self.path("login.defs")) {
+                Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                    LoginDefs::default()
+                }
+                defs => defs?,
+            },
+            changed: Changed::default(),
+            kept,
+        };
+        let result = change(&mut db).map_err(Error::Account)?;
+        db.store(&lock, self)?;
+        Ok(result)
+    }
+
+    ///Adds a user, like `useradd`. Unless a primary group is given, a
+    ///group with the same name as the user is created, preferably with a
+    ///gid equal to the uid. Returns the new passwd entry.
+    pub fn useradd(&self, user: &NewUser) -> Result<PasswdEntry, Error> {
+        self.update(|db| db.useradd(user))
+    }
+
+    ///Modifies a user, like `usermod`. Renaming a user also renames it in
+    ///the member lists of all groups. Returns the modified passwd entry.
+    pub fn usermod(&self, name: &str, changes: &UserChanges) -> Result<PasswdEntry, Error> {
+        self.update(|db| db.usermod(name, changes))
+    }
+
+    ///Deletes a user, like `userdel`. The user is removed from all groups,
+    ///and its primary group is removed too if it has the same name as the
+    ///user and nobody else uses it.
+    pub fn userdel(&self, name: &str) -> Result<(), Error> {
+        self.update(|db| db.userdel(name))
+    }
+
+    ///Adds a group, like `groupadd`. Returns the new group entry.
+    pub fn groupadd(&self, group: &NewGroup) -> Result<GroupEntry, Error> {
+        self.update(|db| db.groupadd(group))
+    }
+
+    ///Modifies a group, like `groupmod`. Returns the modified group entry.
+    pub fn groupmod(&self, name: &str, changes: &GroupChanges) -> Result<GroupEntry, Error> {
+        self.update(|db| db.groupmod(name, changes))
+    }
+
+    ///Deletes a group, like `groupdel`. A group that is the primary group
+    ///of a user can not be deleted.
+    pub fn groupdel(&self, name: &str) -> Result<(), Error> {
+        self.update(|db| db.groupdel(name))
+    }
+}
+
+fn not_found(path: &Path) -> Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("{} does not exist", path.display()),
+    )
+    .into()
+}
+
+///Reads a file in strict mode, returning `None` if it does not exist. The
+///NIS compat lines and the fields that are not UTF-8 are added to `kept`.
+fn load<T>(
+    path: &Path,
+    parse: fn(&str) -> Result<T, ParseErrorKind>,
+    kept: &mut Kept,
+) -> Result<Option<Vec<T>>, Error> {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    kept.add_originals(&contents);
+    let mut entries = vec![];
+    let mut compat = vec![];
+    let mut pending = vec![];
+    for (i, line) in String::from_utf8_lossy(&contents).lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        match parse(line) {
+            Ok(entry) => {
+                let name = line.split(':').next().unwrap_or_default();
+                compat.extend(pending.drain(..).map(|l| (Some(name.to_string()), l)));
+                entries.push(entry);
+            }
+            Err(ParseErrorKind::CompatEntry) => pending.push(line.to_string()),
+            Err(kind) => {
+                return Err(ParseError {
+                    line: i + 1,
+                    content: line.to_string(),
+                    kind,
+                }
+                .into())
+            }
+        }
+    }
+    compat.extend(pending.into_iter().map(|l| (None, l)));
+    if !compat.is_empty() {
+        kept.compat.insert(path.to_path_buf(), compat);
+    }
+    Ok(Some(entries))
+}
+
+///What is written back the way it was read
+#[derive(Default)]
+struct Kept {
+    ///The fields, and items of comma separated fields, that are not UTF-8,
+    ///by how they read with U+FFFD in place of the odd bytes. `None` when
+    ///different bytes read the same, and can not be told apart.
+    originals: HashMap<String, Option<Vec<u8>>>,
+    ///The NIS compat lines of each file, with the name of the entry
+    ///following them, or `None` at the end of the file
+    compat: HashMap<PathBuf, Vec<(Option<String>, String)>>,
+}
+
+impl Kept {
+    fn add_originals(&mut self, contents: &[u8]) {
+        let lines = contents
+            .split(|b| *b == b'\n')
+            .filter(|line| std::str::from_utf8(line).is_err());
+        for item in lines.flat_map(|line| line.split(|b| *b == b':' || *b == b',')) {
+            if std::str::from_utf8(item).is_err() {
+                let bytes = Some(item.to_vec());
+                match self
+                    .originals
+                    .entry(String::from_utf8_lossy(item).into_owned())
+                {
+                    Entry::Occupied(mut e) if *e.get() != bytes => *e.get_mut() = None,
+                    Entry::Occupied(_) => (),
+                    Entry::Vacant(e) => {
+                        e.insert(bytes);
+                    }
+                }
+            }
+        }
+    }
+
+    ///The contents of the file at `path` with the entries. The compat lines
+    ///go before the entry they were before, and new entries go before the
+    ///compat lines at the end, like shadow-utils does. The lines of
+    ///removed or renamed entries are moved to the end, keeping their order.
+    fn contents<E: fmt::Display>(&self, path: &Path, entries: &[E]) -> Result<Vec<u8>, Error> {
+        let compat = self.compat.get(path).map(Vec::as_slice).unwrap_or_default();
+        let mut written = vec![false; compat.len()];
+        let mut lines = vec![];
+        for entry in entries {
+            let line = entry.to_string();
+            let name = line.split(':').next().unwrap_or_default();
+            for (i, (before, compat)) in compat.iter().enumerate() {
+                if !written[i] && before.as_deref() == Some(name) {
+                    written[i] = true;
+                    lines.push(compat.clone());
+                }
+            }
+            lines.push(line);
+        }
+        for (i, (_, compat)) in compat.iter().enumerate() {
+            if !written[i] {
+                lines.push(compat.clone());
+            }
+        }
+        self.restore(&lines)
+    }
+
+    ///The lines, with the fields that were read from bytes that are not
+    ///UTF-8 turned back into those bytes
+    fn restore(&self, lines: &[String]) -> Result<Vec<u8>, Error> {
+        let mut contents = vec![];
+        for line in lines {
+            if self.originals.is_empty() || !line.contains('\u{fffd}') {
+                contents.extend_from_slice(line.as_bytes());
+            } else {
+                let fields: Vec<Vec<u8>> = line
+                    .split(':')
+                    .map(|field| {
+                        let items = field.split(',').map(|item| self.original(item));
+                        Ok(items.collect::<Result<Vec<_>, Error>>()?.join(&b','))
+                    })
+                    .collect::<Result<_, Error>>()?;
+                contents.extend_from_slice(&fields.join(&b':'));
+            }
+            contents.push(b'\n');
+        }
+        Ok(contents)
+    }
+
+    fn original<'a>(&'a self, item: &'a str) -> Result<&'a [u8], Error> {
+        match self.originals.get(item) {
+            None => Ok(item.as_bytes()),
+            Some(Some(bytes)) => Ok(bytes),
+            Some(None) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("can not tell which bytes '{}' was read from", item),
+            )
+            .into()),
+        }
+    }
+}
+
+fn check_field(field: &'static str, value: &str) -> Result<(), AccountError> {
+    match value.contains(':') || value.contains('\n') {
+        true => Err(AccountError::InvalidField(field)),
+        false => Ok(()),
+    }
+}
+
+fn check_name(name: &str) -> Result<(), AccountError> {
+    match is_valid_name(name) {
+        true => Ok(()),
+        false => Err(AccountError::InvalidName(name.to_string())),
+    }
+}
+
+pub(crate) fn days_since_epoch() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| (d.as_secs() / (24 * 60 * 60)) as i64)
+        .unwrap_or(0)
+}
+
+#[derive(Default)]
+struct Changed {
+    passwd: bool,
+    group: bool,
+    shadow: bool,
+    gshadow: bool,
+}
+
+struct Databases {
+    passwd: Vec<PasswdEntry>,
+    group: Vec<GroupEntry>,
+    shadow: Option<Vec<ShadowEntry>>,
+    gshadow: Option<Vec<GshadowEntry>>,
+    subuid: Vec<SubIdEntry>,
+    subgid: Vec<SubIdEntry>,
+    defs: LoginDefs,
+    changed: Changed,
+    kept: Kept,
+}
+
+impl Databases {
+    fn store(&self, lock: &WriteLock, manager: &AccountManager) -> Result<(), Error> {
+        //Groups first, so new users never refer to a missing group
+        if self.changed.group {
+            self.write(lock, &manager.path("group"), &self.group, 0o644)?;
+        }
+        if let (true, Some(gshadow)) = (self.changed.gshadow, &self.gshadow) {
+            self.write(lock, &manager.path("gshadow"), gshadow, 0o600)?;
+        }
+        if self.changed.passwd {
+            self.write(lock, &manager.path("passwd"), &self.passwd, 0o644)?;
+        }
+        if let (true, Some(shadow)) = (self.changed.shadow, &self.shadow) {
+            self.write(lock, &manager.path("shadow"), shadow, 0o600)?;
+        }
+        Ok(())
+    }
+
+    ///Writes a file like the `write_` methods of [WriteLock], with the
+    ///NIS compat lines and the fields that were not UTF-8 as they were read
+    fn write<E: Fields + fmt::Display>(
+        &self,
+        lock: &WriteLock,
+        path: &Path,
+        entries: &[E],
+        mode: u32,
+    ) -> Result<(), Error> {
+        check_fields(entries)?;
+        lock.write_bytes(path, &self.kept.contents(path, entries)?, mode)
+    }
+
+    fn user(&self, name: &str) -> Result<usize, AccountError> {
+        self.passwd
+            .iter()
+            .position(|u| u.username == name)
+            .ok_or_else(|| AccountError::UserNotFound(name.to_string()))
+    }
+
+    fn group(&self, name: &str) -> Result<usize, AccountError> {
+        self.group
+            .iter()
+            .position(|g| g.name == name)
+            .ok_or_else(|| AccountError::GroupNotFound(name.to_string()))
+    }
+
+    fn used_uids(&self) -> HashSet<u32> {
+        self.passwd.iter().map(|u| u.uid).collect()
+    }
+
+    fn allocator(&self, system: bool) -> IdAllocator {
+        match system {
+            true => IdAllocator::system(&self.defs),
+            false => IdAllocator::new(&self.defs),
+        }
+    }
+
+    fn used_gids(&self) -> HashSet<u32> {
+        self.group.iter().map(|g| g.gid).collect()
+    }
+
+    fn check_groups_exist(&self, groups: &[String]) -> Result<(), AccountError> {
+        for group in groups {
+            self.group(group)?;
+        }
+        Ok(())
+    }
+
+    fn check_users_exist(&self, users: &[String]) -> Result<(), AccountError> {
+        for user in users {
+            self.user(user)?;
+        }
+        Ok(())
+    }
+
+    ///Sets the supplementary groups of a user, in both group and gshadow
+    fn set_memberships(&mut self, user: &str, groups: &[String]) {
+        for g in self.group.iter_mut() {
+            let member = g.users.iter().any(|u| u == user);
+            let wanted = groups.contains(&g.name);
+            if member != wanted {
+                match wanted {
+                    true => g.users.push(user.to_string()),
+                    false => g.users.retain(|u| u != user),
+                }
+                self.changed.group = true;
+            }
+        }
+        if let Some(gshadow) = self.gshadow.as_mut() {
+            for g in gshadow.iter_mut() {
+                let member = g.members.iter().any(|u| u == user);
+                let wanted = groups.contains(&g.name);
+                if member != wanted {
+                    match wanted {
+                        true => g.members.push(user.to_string()),
+                        false => g.members.retain(|u| u != user),
+                    }
+                    self.changed.gshadow = true;
+                }
+            }
+        }
+    }
+
+    fn memberships(&self, user: &str) -> Vec<String> {
+        self.group
+            .iter()
+            .filter(|g| g.users.iter().any(|u| u == user))
+            .map(|g| g.name.clone())
+            .collect()
+    }
+
+    fn add_group(&mut self, name: &str, gid: u32, members: &[String]) -> GroupEntry {
+        let passwd = match self.gshadow {
+            Some(_) => "x",
+            None => "!",
+        };
+        let entry = GroupEntry {
+            name: name.to_string(),
+            passwd: passwd.to_string(),
+            gid,
+            users: members.to_vec(),
+        };
+        self.group.push(entry.clone());
+        self.changed.group = true;
+        if let Some(gshadow) = self.gshadow.as_mut() {
+            gshadow.push(GshadowEntry {
+                name: name.to_string(),
+                passwd: "!".to_string(),
+                administrators: vec![],
+                members: members.to_vec(),
+            });
+            self.changed.gshadow = true;
+        }
+        entry
+    }
+
+    fn remove_group(&mut self, name: &str) {
+        self.group.retain(|g| g.name != name);
+        self.changed.group = true;
+        if let Some(gshadow) = self.gshadow.as_mut() {
+            gshadow.retain(|g| g.name != name);
+            self.changed.gshadow = true;
+        }
+    }
+
+    fn useradd(&mut self, user: &NewUser) -> Result<PasswdEntry, AccountError> {
+        check_name(&user.name)?;
+        check_field("gecos", &user.gecos)?;
+        check_field("shell", &user.shell)?;
+        if let Some(home_dir) = &user.home_dir {
+            check_field("home_dir", home_dir)?;
+        }
+        if let Some(passwd) = &user.passwd {
+            check_field("passwd", passwd)?;
+        }
+        if self.user(&user.name).is_ok() {
+            return Err(AccountError::UserExists(user.name.clone()));
+        }
+        self.check_groups_exist(&user.groups)?;
+
+        let used_uids = self.used_uids();
+        let uid = match user.uid {
+            Some(uid) if used_uids.contains(&uid) => return Err(AccountError::UidExists(uid)),
+            Some(uid) => uid,
+            None => {
+                let allocator = self.allocator(user.system).avoiding(&self.subuid);
+                //A private group gets the same id as the user, when possible
+                match user.primary_group {
+                    Some(_) => allocator.next_uid(&self.passwd),
+                    None => allocator
+                        .clone()
+                        .avoiding(&self.subgid)
+                        .next_pair(&self.passwd, &self.group)
+                        .or_else(|| allocator.next_uid(&self.passwd)),
+                }
+                .ok_or(AccountError::NoFreeId)?
+            }
+        };
+        let gid = match &user.primary_group {
+            Some(group) => self.group[self.group(group)?].gid,
+            None => {
+                if self.group(&user.name).is_ok() {
+                    return Err(AccountError::GroupExists(user.name.clone()));
+                }
+                let gid = match self.used_gids().contains(&uid) {
+                    true => self
+                        .allocator(user.system)
+                        .avoiding(&self.subgid)
+                        .next_gid(&self.group)
+                        .ok_or(AccountError::NoFreeId)?,
+                    false => uid,
+                };
+                self.add_group(&user.name, gid, &[]);
+                gid
+            }
+        };
+
+        let passwd = user.passwd.clone().unwrap_or_else(|| "!".to_string());
+        let entry = PasswdEntry {
+            username: user.name.clone(),
+            passwd: match self.shadow {
+                Some(_) => "x".to_string(),
+                None => passwd.clone(),
+            },
+            uid,
+            gid,
+            gecos: user.gecos.clone(),
+            home_dir: user
+                .home_dir
+                .clone()
+                .unwrap_or_else(|| format!("/home/{}", user.name)),
+            shell: user.shell.clone(),
+        };
+        self.passwd.push(entry.clone());
+        self.changed.passwd = true;
+        if let Some(shadow) = self.shadow.as_mut() {
+            shadow.push(ShadowEntry {
+                name: user.name.clone(),
+                passwd,
+                last_change: Some(days_since_epoch()),
+                min_days: self.defs.pass_min_days(),
+                max_days: self.defs.pass_max_days(),
+                warn_days: self.defs.pass_warn_age(),
+                inactive_days: None,
+                expire: None,
+                reserved: String::new(),
+            });
+            self.changed.shadow = true;
+        }
+        self.set_memberships(&user.name, &user.groups);
+        Ok(entry)
+    }
+
+    fn usermod(&mut self, name: &str, changes: &UserChanges) -> Result<PasswdEntry, AccountError> {
+        let index = self.user(name)?;
+        for (field, value) in [
+            ("gecos", &changes.gecos),
+            ("home_dir", &changes.home_dir),
+            ("shell", &changes.shell),
+            ("passwd", &changes.passwd),
+        ] {
+            if let Some(value) = value {
+                check_field(field, value)?;
+            }
+        }
+        if let Some(new_name) = &changes.name {
+            check_name(new_name)?;
+            if new_name != name && self.user(new_name).is_ok() {
+                return Err(AccountError::UserExists(new_name.clone()));
+            }
+        }
+        if let Some(uid) = changes.uid {
+            if self
+                .passwd
+                .iter()
+                .any(|u| u.uid == uid && u.username != name)
+            {
+                return Err(AccountError::UidExists(uid));
+            }
+        }
+        let gid = match &changes.primary_group {
+            Some(group) => Some(self.group[self.group(group)?].gid),
+            None => None,
+        };
+        if let Some(groups) = &changes.groups {
+            self.check_groups_exist(groups)?;
+        }
+
+        let groups = changes.groups.as_ref().map(|groups| {
+            let mut all = match changes.append {
+                true => self.memberships(name),
+                false => vec![],
+            };
+            all.extend(
+                groups
+                    .iter()
+                    .filter(|g| !all.contains(g))
+                    .cloned()
+                    .collect::<Vec<_>>(),
+            );
+            all
+        });
+        if let Some(groups) = groups {
+            self.set_memberships(name, &groups);
+        }
+
+        let shadowed = self.shadow.is_some();
+        let user = &mut self.passwd[index];
+        user.uid = changes.uid.unwrap_or(user.uid);
+        user.gid = gid.unwrap_or(user.gid);
+        if let Some(gecos) = &changes.gecos {
+            user.gecos = gecos.clone();
+        }
+        if let Some(home_dir) = &changes.home_dir {
+            user.home_dir = home_dir.clone();
+        }
+        if let Some(shell) = &changes.shell {
+            user.shell = shell.clone();
+        }
+        if let (Some(passwd), false) = (&changes.passwd, shadowed) {
+            user.passwd = passwd.clone();
+        }
+        self.changed.passwd = true;
+        if let (Some(shadow), Some(passwd)) = (self.shadow.as_mut(), &changes.passwd) {
+            match shadow.iter_mut().find(|s| s.name == name) {
+                Some(s) => {
+                    s.passwd = passwd.clone();
+                    s.last_change = Some(days_since_epoch());
+                }
+                //Like usermod, a user missing from the shadow file is added to it
+                None => {
+                    shadow.push(ShadowEntry {
+                        name: name.to_string(),
+                        passwd: passwd.clone(),
+                        last_change: Some(days_since_epoch()),
+                        min_days: self.defs.pass_min_days(),
+                        max_days: self.defs.pass_max_days(),
+                        warn_days: self.defs.pass_warn_age(),
+                        inactive_days: None,
+                        expire: None,
+                        reserved: String::new(),
+                    });
+                    self.passwd[index].passwd = "x".to_string();
+                }
+            }
+            self.changed.shadow = true;
+        }
+
+        if let Some(new_name) = changes.name.as_ref().filter(|n| *n != name) {
+            self.rename_user(name, new_name);
+        }
+        Ok(self.passwd[index].clone())
+    }
+
+    fn rename_user(&mut self, name: &str, new_name: &str) {
+        let rename = |list: &mut Vec<String>| -> bool {
+            let mut renamed = false;
+            for u in list.iter_mut().filter(|u| *u == name) {
+                *u = new_name.to_string();
+                renamed = true;
+            }
+            renamed
+        };
+        for u in self.passwd.iter_mut().filter(|u| u.username == name) {
+            u.username = new_name.to_string();
+            self.changed.passwd = true;
+        }
+        for g in self.group.iter_mut() {
+            self.changed.group |= rename(&mut g.users);
+        }
+        if let Some(shadow) = self.shadow.as_mut() {
+            for s in shadow.iter_mut().filter(|s| s.name == name) {
+                s.name = new_name.to_string();
+                self.changed.shadow = true;
+            }
+        }
+        if let Some(gshadow) = self.gshadow.as_mut() {
+            for g in gshadow.iter_mut() {
+                self.changed.gshadow |= rename(&mut g.members);
+                self.changed.gshadow |= rename(&mut g.administrators);
+            }
+        }
+    }
+
+    fn userdel(&mut self, name: &str) -> Result<(), AccountError> {
+        let user = self.passwd.remove(self.user(name)?);
+        self.changed.passwd = true;
+        if let Some(shadow) = self.shadow.as_mut() {
+            shadow.retain(|s| s.name != name);
+            self.changed.shadow = true;
+        }
+        self.set_memberships(name, &[]);
+        if let Some(gshadow) = self.gshadow.as_mut() {
+            for g in gshadow.iter_mut().filter(|g| g.is_administrator(name)) {
+                g.administrators.retain(|a| a != name);
+                self.changed.gshadow = true;
+            }
+        }
+
+        //Remove the user private group, unless someone else uses it
+        let private_group = self
+            .group
+            .iter()
+            .any(|g| g.name == name && g.gid == user.gid && g.users.is_empty());
+        if private_group && !self.passwd.iter().any(|u| u.gid == user.gid) {
+            self.remove_group(name);
+        }
+        Ok(())
+    }
+
+    fn groupadd(&mut self, group: &NewGroup) -> Result<GroupEntry, AccountError> {
+        check_name(&group.name)?;
+        if self.group(&group.name).is_ok() {
+            return Err(AccountError::GroupExists(group.name.clone()));
+        }
+        self.check_users_exist(&group.members)?;
+        let used_gids = self.used_gids();
+        let gid = match group.gid {
+            Some(gid) if used_gids.contains(&gid) => return Err(AccountError::GidExists(gid)),
+            Some(gid) => gid,
+            None => self
+                .allocator(group.system)
+                .avoiding(&self.subgid)
+                .next_gid(&self.group)
+                .ok_or(AccountError::NoFreeId)?,
+        };
+        Ok(self.add_group(&group.name, gid, &group.members))
+    }
+
+    fn groupmod(&mut self, name: &str, changes: &GroupChanges) -> Result<GroupEntry, AccountError> {
+        let index = self.group(name)?;
+        if let Some(new_name) = &changes.name {
+            check_name(new_name)?;
+            if new_name != name && self.group(new_name).is_ok() {
+                return Err(AccountError::GroupExists(new_name.clone()));
+            }
+        }
+        if let Some(gid) = changes.gid {
+            if self.group.iter().any(|g| g.gid == gid && g.name != name) {
+                return Err(AccountError::GidExists(gid));
+            }
+        }
+        if let Some(members) = &changes.members {
+            self.check_users_exist(members)?;
+        }
+
+        let old_gid = self.group[index].gid;
+        if let Some(gid) = changes.gid.filter(|gid| *gid != old_gid) {
+            self.group[index].gid = gid;
+            for u in self.passwd.iter_mut().filter(|u| u.gid == old_gid) {
+                u.gid = gid;
+                self.changed.passwd = true;
+            }
+        }
+        if let Some(members) = &changes.members {
+            self.group[index].users = members.clone();
+            if let Some(g) = self
+                .gshadow
+                .as_mut()
+                .and_then(|gshadow| gshadow.iter_mut().find(|g| g.name == name))
+            {
+                g.members = members.clone();
+                self.changed.gshadow = true;
+            }
+        }
+        if let Some(new_name) = &changes.name {
+            self.group[index].name = new_name.clone();
+            if let Some(g) = self
+                .gshadow
+                .as_mut()
+                .and_then(|gshadow| gshadow.iter_mut().find(|g| g.name == name))
+            {
+                g.name = new_name.clone();
+                self.changed.gshadow = true;
+            }
+        }
+        self.changed.group = true;
+        Ok(self.group[index].clone())
+    }
+
+    fn groupdel(&mut self, name: &str) -> Result<(), AccountError> {
+        let gid = self.group[self.group(name)?].gid;
+        if let Some(user) = self.passwd.iter().find(|u| u.gid == gid) {
+            //Another group with the same gid keeps the user's primary group valid
+            if self.group.iter().filter(|g| g.gid == gid).count() == 1 {
+                return Err(AccountError::PrimaryGroup {
+                    group: name.to_string(),
+                    user: user.username.clone(),
+                });
+            }
+        }
+        self.remove_group(name);
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `allocator` finds unused uids and gids, in the ranges configured in
+//! /etc/login.defs.
+//!
+//! ```
+//! # #[cfg(feature = "sync")] {
+//! use user_lookup::allocator::IdAllocator;
+//! use user_lookup::login_defs::LoginDefs;
+//! use user_lookup::sync_reader::{GroupReader, PasswdReader, SubIdReader};
+//! use std::time::Duration;
+//!
+//! let defs = LoginDefs::from_file("test_files/login.defs").unwrap();
+//! let mut passwd = PasswdReader::from_file("test_files/passwd", Duration::new(0, 0));
+//! let mut group = GroupReader::from_file("test_files/group", Duration::new(0, 0));
+//! let users = passwd.get_entries().unwrap();
+//! let groups = group.get_groups().unwrap();
+//!
+//! let allocator = IdAllocator::new(&defs);
+//! assert_eq!(Some(1002), allocator.next_uid(users));
+//! assert_eq!(Some(1000), allocator.next_gid(groups));
+//! assert_eq!(Some(1002), allocator.next_pair(users, groups));
+//!
+//! //Like useradd -r, system ids are handed out from the top
+//! let allocator = IdAllocator::system(&defs);
+//! assert_eq!(Some(999), allocator.next_uid(users));
+//!
+//! let mut subuid = SubIdReader::from_file("test_files/subuid", Duration::new(0, 0));
+//! let allocator = IdAllocator::new(&defs)
+//!     .with_range(1000, 200000)
+//!     .descending(true)
+//!     .avoiding(subuid.get_entries().unwrap());
+//! assert_eq!(Some(99999), allocator.next_uid(users));
+//! # }
+//! ```
+use crate::login_defs::LoginDefs;
+use crate::GroupEntry;
+use crate::PasswdEntry;
+use crate::SubIdEntry;
+
+use std::collections::HashSet;
+
+///Finds unused ids in a range. The range of uids and gids is taken
+///from [LoginDefs], either the regular or the system range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdAllocator {
+    uids: (u32, u32),
+    gids: (u32, u32),
+    descending: bool,
+    avoid: Vec<(u64, u64)>,
+}
+
+impl IdAllocator {
+    ///Creates an allocator for regular users and groups, handing out
+    ///the lowest free id between `UID_MIN`/`GID_MIN` and `UID_MAX`/`GID_MAX`
+    pub fn new(defs: &LoginDefs) -> Self {
+        Self {
+            uids: (defs.uid_min(), defs.uid_max()),
+            gids: (defs.gid_min(), defs.gid_max()),
+            descending: false,
+            avoid: vec![],
+        }
+    }
+
+    ///Creates an allocator for system users and groups, handing out
+    ///the highest free id between `SYS_UID_MIN`/`SYS_GID_MIN` and
+    ///`SYS_UID_MAX`/`SYS_GID_MAX`, like `useradd -r` does
+    pub fn system(defs: &LoginDefs) -> Self {
+        Self {
+            uids: (defs.sys_uid_min(), defs.sys_uid_max()),
+            gids: (defs.sys_gid_min(), defs.sys_gid_max()),
+            descending: true,
+            avoid: vec![],
+        }
+    }
+
+    ///Sets whether the highest free id is handed out instead of the lowest
+    pub fn descending(mut self, descending: bool) -> Self {
+        self.descending = descending;
+        self
+    }
+
+    ///Uses the range `min` to `max`, inclusive, for both uids and gids
+    pub fn with_range(mut self, min: u32, max: u32) -> Self {
+        self.uids = (min, max);
+        self.gids = (min, max);
+        self
+    }
+
+    ///Never hands out ids inside the subordinate id ranges, from
+    ///`/etc/subuid` or `/etc/subgid`
+    pub fn avoiding(mut self, ranges: &[SubIdEntry]) -> Self {
+        self.avoid
+            .extend(ranges.iter().map(|r| (r.start as u64, r.end())));
+        self
+    }
+
+    fn is_avoided(&self, id: u32) -> bool {
+        self.avoid
+            .iter()
+            .any(|(start, end)| (*start..*end).contains(&(id as u64)))
+    }
+
+    fn find(&self, (min, max): (u32, u32), free: impl Fn(u32) -> bool) -> Option<u32> {
+        let free = |id: &u32| free(*id) && !self.is_avoided(*id);
+        match self.descending {
+            true => (min..=max).rev().find(free),
+            false => (min..=max).find(free),
+        }
+    }
+
+    ///Finds an uid not used by any of the users
+    pub fn next_uid(&self, users: &[PasswdEntry]) -> Option<u32> {
+        let used: HashSet<u32> = users.iter().map(|u| u.uid).collect();
+        self.find(self.uids, |id| !used.contains(&id))
+    }
+
+    ///Finds a gid not used by any of the groups
+    pub fn next_gid(&self, groups: &[GroupEntry]) -> Option<u32> {
+        let used: HashSet<u32> = groups.iter().map(|g| g.gid).collect();
+        self.find(self.gids, |id| !used.contains(&id))
+    }
+
+    ///Finds an id that is free both as uid and as gid, to create a user
+    ///and its group with matching ids. The id has to be in both ranges.
+    pub fn next_pair(&self, users: &[PasswdEntry], groups: &[GroupEntry]) -> Option<u32> {
+        let uids: HashSet<u32> = users.iter().map(|u| u.uid).collect();
+        let gids: HashSet<u32> = groups.iter().map(|g| g.gid).collect();
+        let range = (self.uids.0.max(self.gids.0), self.uids.1.min(self.gids.1));
+        self.find(range, |id| !uids.contains(&id) && !gids.contains(&id))
+    }
+}
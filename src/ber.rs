@@ -0,0 +1,153 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The subset of the Basic Encoding Rules used by LDAP messages. Only
+//! definite lengths and single byte tags are supported.
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+
+pub(crate) const BOOLEAN: u8 = 0x01;
+pub(crate) const INTEGER: u8 = 0x02;
+pub(crate) const OCTET_STRING: u8 = 0x04;
+pub(crate) const ENUMERATED: u8 = 0x0a;
+pub(crate) const SEQUENCE: u8 = 0x30;
+pub(crate) const SET: u8 = 0x31;
+
+///The largest message that is read, to not trust any length
+const MAX_LENGTH: usize = 64 * 1024 * 1024;
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+///Encodes a tag, length and content
+pub(crate) fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+///Encodes an integer in the fewest bytes of two's complement
+pub(crate) fn integer(tag: u8, value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut start = 0;
+    while start < 7 {
+        let redundant = (bytes[start] == 0 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0);
+        if !redundant {
+            break;
+        }
+        start += 1;
+    }
+    tlv(tag, &bytes[start..])
+}
+
+///Encodes the concatenation of already encoded parts
+pub(crate) fn constructed(tag: u8, parts: &[Vec<u8>]) -> Vec<u8> {
+    tlv(tag, &parts.concat())
+}
+
+///Reads the elements of encoded content, one at a time
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub(crate) fn peek_tag(&self) -> Option<u8> {
+        self.data.first().copied()
+    }
+
+    ///Reads the next element, returning its tag and content
+    pub(crate) fn read(&mut self) -> Result<(u8, &'a [u8]), Error> {
+        let (tag, header, len) = header(self.data)?;
+        let end = header
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| invalid("truncated element"))?;
+        let content = &self.data[header..end];
+        self.data = &self.data[end..];
+        Ok((tag, content))
+    }
+
+    ///Reads the next element, which must have the tag
+    pub(crate) fn expect(&mut self, tag: u8) -> Result<&'a [u8], Error> {
+        match self.read()? {
+            (t, content) if t == tag => Ok(content),
+            _ => Err(invalid("unexpected element")),
+        }
+    }
+
+    ///Reads an integer, or an enumerated with the tag
+    pub(crate) fn integer(&mut self, tag: u8) -> Result<i64, Error> {
+        let content = self.expect(tag)?;
+        if content.is_empty() || content.len() > 8 {
+            return Err(invalid("invalid integer"));
+        }
+        let negative = content[0] & 0x80 != 0;
+        Ok(content
+            .iter()
+            .fold(if negative { -1 } else { 0 }, |n, b| (n << 8) | *b as i64))
+    }
+}
+
+///Returns the tag, the length of the header and the length of the content
+fn header(data: &[u8]) -> Result<(u8, usize, usize), Error> {
+    let truncated = || invalid("truncated element");
+    let tag = *data.first().ok_or_else(truncated)?;
+    let first = *data.get(1).ok_or_else(truncated)?;
+    if first < 0x80 {
+        return Ok((tag, 2, first as usize));
+    }
+    let count = (first & 0x7f) as usize;
+    if count == 0 || count > 4 {
+        return Err(invalid("unsupported length"));
+    }
+    let bytes = data.get(2..2 + count).ok_or_else(truncated)?;
+    let len = bytes.iter().fold(0, |n, b| (n << 8) | *b as usize);
+    Ok((tag, 2 + count, len))
+}
+
+///Reads one complete element from a stream, returning its tag and content
+pub(crate) fn read_element<R: Read>(stream: &mut R) -> Result<(u8, Vec<u8>), Error> {
+    let mut head = vec![0; 2];
+    stream.read_exact(&mut head)?;
+    if head[1] >= 0x80 {
+        let count = (head[1] & 0x7f) as usize;
+        if count == 0 || count > 4 {
+            return Err(invalid("unsupported length"));
+        }
+        let mut bytes = vec![0; count];
+        stream.read_exact(&mut bytes)?;
+        head.extend_from_slice(&bytes);
+    }
+    let (tag, _, len) = header(&head)?;
+    if len > MAX_LENGTH {
+        return Err(invalid("message too large"));
+    }
+    let mut content = vec![0; len];
+    stream.read_exact(&mut content)?;
+    Ok((tag, content))
+}
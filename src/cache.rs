@@ -0,0 +1,217 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `cache` holds the [CachePolicy] used by the readers to decide when
+//! the backing file has to be read again.
+#![cfg_attr(not(any(feature = "sync", feature = "async")), allow(dead_code))]
+use crate::error::ParseError;
+use crate::error::ParseErrorKind;
+use crate::error::ParseMode;
+
+use std::fs::Metadata;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+///How long sources without a file keep their data if the policy has no
+///maximum age
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(60);
+
+///Decides when a reader re-reads its file.
+///
+///By default the file is only read again when its modification
+///time, size or inode changed. The inode check catches the atomic
+///rename done by tools like `vipw` and `useradd`. A maximum age can be
+///added as an upper bound, after which the file is read again even if
+///it looks unchanged.
+///
+///A `Duration` converts into a policy that checks the file and uses the
+///duration as maximum age, so a Duration of 0 still disables caching.
+///
+///Sources without a file, like LDAP and systemd-userdbd, can not see
+///changes and only use the maximum age. Without one they keep their
+///data for [DEFAULT_MAX_AGE].
+/// ```
+/// use user_lookup::cache::CachePolicy;
+/// use std::time::Duration;
+///
+/// let policy = CachePolicy::on_change().with_max_age(Duration::from_secs(60));
+/// assert_eq!(Some(Duration::from_secs(60)), policy.max_age());
+/// assert!(policy.checks_file());
+///
+/// assert_eq!(policy, CachePolicy::from(Duration::from_secs(60)));
+/// assert!(!CachePolicy::ttl(Duration::from_secs(60)).checks_file());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CachePolicy {
+    max_age: Option<Duration>,
+    check_file: bool,
+}
+
+impl CachePolicy {
+    ///Re-read the file only when its mtime, size or inode changed.
+    pub fn on_change() -> Self {
+        Self {
+            max_age: None,
+            check_file: true,
+        }
+    }
+
+    ///Re-read the file only when `max_age` has passed since the last
+    ///load, without looking at the file in between.
+    pub fn ttl(max_age: Duration) -> Self {
+        Self {
+            max_age: Some(max_age),
+            check_file: false,
+        }
+    }
+
+    ///Sets the maximum age of the cached data.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    ///The maximum age of the cached data, if any
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    ///The maximum age for sources without a file, [DEFAULT_MAX_AGE] if
+    ///none is set
+    pub fn max_age_or_default(&self) -> Duration {
+        self.max_age.unwrap_or(DEFAULT_MAX_AGE)
+    }
+
+    ///Whether the file is checked for changes before using the cache
+    pub fn checks_file(&self) -> bool {
+        self.check_file
+    }
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        Self::on_change()
+    }
+}
+
+impl From<Duration> for CachePolicy {
+    fn from(max_age: Duration) -> Self {
+        Self::on_change().with_max_age(max_age)
+    }
+}
+
+///The identity of a file at a point in time. Two stamps differ if the
+///file was modified, truncated/extended or replaced by another file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FileStamp {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+impl From<&Metadata> for FileStamp {
+    fn from(meta: &Metadata) -> Self {
+        Self {
+            dev: meta.dev(),
+            ino: meta.ino(),
+            size: meta.size(),
+            mtime: meta.mtime(),
+            mtime_nsec: meta.mtime_nsec(),
+        }
+    }
+}
+
+struct Loaded {
+    at: Instant,
+    wall_clock: SystemTime,
+    stamp: Option<FileStamp>,
+}
+
+///Book keeping shared by the sync and async readers. The readers do
+///the actual IO and ask the cache if it has to be done.
+pub(crate) struct FileCache {
+    path: PathBuf,
+    policy: CachePolicy,
+    loaded: Option<Loaded>,
+    mode: ParseMode,
+    warnings: Vec<ParseError>,
+}
+
+impl FileCache {
+    pub(crate) fn new(path: PathBuf, policy: CachePolicy) -> Self {
+        Self {
+            path,
+            policy,
+            loaded: None,
+            mode: ParseMode::default(),
+            warnings: vec![],
+        }
+    }
+
+    pub(crate) fn set_mode(&mut self, mode: ParseMode) {
+        self.mode = mode;
+    }
+
+    ///Parses the file contents with the configured mode, keeping the
+    ///warnings until the next parse. What is not UTF-8 is replaced with
+    ///U+FFFD, so one odd field does not make the whole file unreadable.
+    pub(crate) fn parse<T>(
+        &mut self,
+        contents: &[u8],
+        parse: fn(&str) -> Result<T, ParseErrorKind>,
+    ) -> Result<Vec<T>, ParseError> {
+        let contents = String::from_utf8_lossy(contents);
+        let (entries, warnings) = crate::parse_lines(&contents, self.mode, parse)?;
+        self.warnings = warnings;
+        Ok(entries)
+    }
+
+    pub(crate) fn warnings(&self) -> &[ParseError] {
+        &self.warnings
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    ///Whether the file has to be stat'ed before calling `is_fresh`
+    pub(crate) fn checks_file(&self) -> bool {
+        self.policy.check_file
+    }
+
+    pub(crate) fn is_fresh(&self, now: Instant, stamp: Option<FileStamp>) -> bool {
+        let loaded = match &self.loaded {
+            None => return false,
+            Some(loaded) => loaded,
+        };
+        if let Some(max_age) = self.policy.max_age {
+            if now.saturating_duration_since(loaded.at) >= max_age {
+                return false;
+            }
+        }
+        !self.policy.check_file || stamp == loaded.stamp
+    }
+
+    pub(crate) fn mark_loaded(&mut self, now: Instant, stamp: Option<FileStamp>) {
+        self.loaded = Some(Loaded {
+            at: now,
+            wall_clock: SystemTime::now(),
+            stamp,
+        });
+    }
+
+    pub(crate) fn last_loaded(&self) -> Option<SystemTime> {
+        self.loaded.as_ref().map(|l| l.wall_clock)
+    }
+}
@@ -0,0 +1,620 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `check` audits the user and group databases, like the `pwck` and
+//! `grpck` tools, and reports what is wrong as [Finding]s.
+//!
+//! `etc/passwd` and `etc/group` below the root directory are checked,
+//! together with `etc/shadow`, `etc/gshadow`, `etc/shells` and
+//! `etc/login.defs` if they exist. The [Checker] can also fix the findings
+//! that are safe to fix, see [Finding::is_fixable].
+//!
+//! ```
+//! use user_lookup::check::{Checker, Finding};
+//!
+//! let root = std::env::temp_dir().join(format!("user_lookup_check_{}", std::process::id()));
+//! std::fs::create_dir_all(root.join("etc")).unwrap();
+//! std::fs::write(root.join("etc/passwd"), "root:x:0:0:root:/:/bin/sh\nuser1:x:1000:100::/:/bin/sh\n").unwrap();
+//! std::fs::write(root.join("etc/group"), "root:x:0:\nwheel:x:10:user1,ghost\n").unwrap();
+//!
+//! let checker = Checker::with_root(&root);
+//! let findings = checker.check().unwrap();
+//! assert_eq!(
+//!     vec![
+//!         Finding::MissingPrimaryGroup { username: "user1".to_string(), gid: 100 },
+//!         Finding::UnknownMember { group: "wheel".to_string(), member: "ghost".to_string() },
+//!     ],
+//!     findings
+//! );
+//! assert_eq!("group 'wheel': no user 'ghost'", findings[1].to_string());
+//!
+//! assert_eq!(vec![findings[1].clone()], checker.fix().unwrap());
+//! assert_eq!(1, checker.check().unwrap().len());
+//! # std::fs::remove_dir_all(&root).unwrap();
+//! ```
+use crate::accounts::days_since_epoch;
+use crate::error::ParseError;
+use crate::error::ParseErrorKind;
+use crate::error::ParseMode;
+use crate::login_defs::LoginDefs;
+use crate::writer::Writer;
+use crate::Error;
+use crate::GroupEntry;
+use crate::GshadowEntry;
+use crate::PasswdEntry;
+use crate::ShadowEntry;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::path::PathBuf;
+
+///A file checked by the [Checker]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckedFile {
+    ///`etc/passwd`
+    Passwd,
+    ///`etc/group`
+    Group,
+    ///`etc/shadow`
+    Shadow,
+    ///`etc/gshadow`
+    Gshadow,
+}
+
+impl fmt::Display for CheckedFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CheckedFile::Passwd => "passwd",
+            CheckedFile::Group => "group",
+            CheckedFile::Shadow => "shadow",
+            CheckedFile::Gshadow => "gshadow",
+        })
+    }
+}
+
+///A problem found by the [Checker]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Finding {
+    ///A line that can not be parsed, which the readers skip
+    MalformedLine {
+        ///The file of the line
+        file: CheckedFile,
+        ///Why the line can not be parsed, with its line number
+        error: ParseError,
+    },
+    ///More than one user with the username
+    DuplicateUsername {
+        ///The username used more than once
+        username: String,
+    },
+    ///More than one user with the uid
+    DuplicateUid {
+        ///The uid used more than once
+        uid: u32,
+        ///The users with the uid, in the order of the file
+        usernames: Vec<String>,
+    },
+    ///More than one group with the name
+    DuplicateGroupName {
+        ///The group name used more than once
+        name: String,
+    },
+    ///More than one group with the gid
+    DuplicateGid {
+        ///The gid used more than once
+        gid: u32,
+        ///The groups with the gid, in the order of the file
+        names: Vec<String>,
+    },
+    ///There is no group with the gid of the user
+    MissingPrimaryGroup {
+        ///The user
+        username: String,
+        ///The primary gid of the user
+        gid: u32,
+    },
+    ///A member of a group is not a user
+    UnknownMember {
+        ///The group
+        group: String,
+        ///The member that is not a user
+        member: String,
+    },
+    ///The home directory of the user does not exist
+    MissingHomeDir {
+        ///The user
+        username: String,
+        ///The home directory, as written in the passwd file
+        home_dir: String,
+    },
+    ///The home directory of the user is owned by someone else
+    WrongHomeOwner {
+        ///The user
+        username: String,
+        ///The home directory, as written in the passwd file
+        home_dir: String,
+        ///The uid owning the home directory
+        owner: u32,
+    },
+    ///The shell of the user is not listed in `/etc/shells`
+    InvalidShell {
+        ///The user
+        username: String,
+        ///The shell of the user
+        shell: String,
+    },
+    ///The user has no entry in the shadow file
+    MissingShadow {
+        ///The user
+        username: String,
+    },
+}
+
+impl Finding {
+    ///Whether [Checker::fix] fixes this finding. Unknown members are
+    ///removed from their groups, in the gshadow file as well, and a shadow
+    ///entry is added for a user without one, holding the password of the
+    ///passwd entry or a locked password. The other findings need someone
+    ///to decide what is right.
+    pub fn is_fixable(&self) -> bool {
+        matches!(
+            self,
+            Finding::UnknownMember { .. } | Finding::MissingShadow { .. }
+        )
+    }
+}
+
+fn quoted(names: &[String]) -> String {
+    let names: Vec<String> = names.iter().map(|n| format!("'{}'", n)).collect();
+    names.join(", ")
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::MalformedLine { file, error } => write!(f, "{}: {}", file, error),
+            Finding::DuplicateUsername { username } => write!(f, "duplicate user '{}'", username),
+            Finding::DuplicateUid { uid, usernames } => {
+                write!(f, "uid {} is used by {}", uid, quoted(usernames))
+            }
+            Finding::DuplicateGroupName { name } => write!(f, "duplicate group '{}'", name),
+            Finding::DuplicateGid { gid, names } => {
+                write!(f, "gid {} is used by {}", gid, quoted(names))
+            }
+            Finding::MissingPrimaryGroup { username, gid } => {
+                write!(f, "user '{}': no group {}", username, gid)
+            }
+            Finding::UnknownMember { group, member } => {
+                write!(f, "group '{}': no user '{}'", group, member)
+            }
+            Finding::MissingHomeDir { username, home_dir } => write!(
+                f,
+                "user '{}': directory '{}' does not exist",
+                username, home_dir
+            ),
+            Finding::WrongHomeOwner {
+                username,
+                home_dir,
+                owner,
+            } => write!(
+                f,
+                "user '{}': directory '{}' is owned by uid {}",
+                username, home_dir, owner
+            ),
+            Finding::InvalidShell { username, shell } => write!(
+                f,
+                "user '{}': shell '{}' is not in /etc/shells",
+                username, shell
+            ),
+            Finding::MissingShadow { username } => {
+                write!(f, "user '{}': no entry in the shadow file", username)
+            }
+        }
+    }
+}
+
+///The files as read by the Checker
+struct Files {
+    passwd: Vec<PasswdEntry>,
+    group: Vec<GroupEntry>,
+    shadow: Option<Vec<ShadowEntry>>,
+    gshadow: Option<Vec<GshadowEntry>>,
+    malformed: Vec<Finding>,
+}
+
+///Checks the user and group databases below a root directory
+#[derive(Debug, Clone)]
+pub struct Checker {
+    root: PathBuf,
+    writer: Writer,
+    check_home_dirs: bool,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Checker {
+    ///Creates a new Checker for the databases in `/etc`
+    pub fn new() -> Self {
+        Self::with_root("/")
+    }
+
+    ///Creates a new Checker for the databases in `etc` below `root`.
+    ///The home directories are looked for below `root` as well.
+    pub fn with_root<T: Into<PathBuf>>(root: T) -> Self {
+        let root = root.into();
+        Self {
+            writer: Writer::in_dir(root.join("etc")),
+            root,
+            check_home_dirs: true,
+        }
+    }
+
+    ///Sets the [Writer] used to lock and write the files when fixing
+    pub fn with_writer(mut self, writer: Writer) -> Self {
+        self.writer = writer;
+        self
+    }
+
+    ///Sets whether the home directories are checked. The default is to
+    ///check them.
+    pub fn with_home_dirs(mut self, check: bool) -> Self {
+        self.check_home_dirs = check;
+        self
+    }
+
+    fn path(&self, file: &str) -> PathBuf {
+        self.root.join("etc").join(file)
+    }
+
+    fn read(&self, mode: ParseMode) -> Result<Files, Error> {
+        let mut malformed = vec![];
+        let passwd = load(
+            &self.path("passwd"),
+            mode,
+            PasswdEntry::try_parse,
+            CheckedFile::Passwd,
+            &mut malformed,
+        )?
+        .ok_or_else(|| not_found(&self.path("passwd")))?;
+        let group = load(
+            &self.path("group"),
+            mode,
+            GroupEntry::try_parse,
+            CheckedFile::Group,
+            &mut malformed,
+        )?
+        .ok_or_else(|| not_found(&self.path("group")))?;
+        let shadow = load(
+            &self.path("shadow"),
+            mode,
+            ShadowEntry::try_parse,
+            CheckedFile::Shadow,
+            &mut malformed,
+        )?;
+        let gshadow = load(
+            &self.path("gshadow"),
+            mode,
+            GshadowEntry::try_parse,
+            CheckedFile::Gshadow,
+            &mut malformed,
+        )?;
+        Ok(Files {
+            passwd,
+            group,
+            shadow,
+            gshadow,
+            malformed,
+        })
+    }
+
+    ///Checks the databases, returning what was found. The lines that can
+    ///not be parsed come first, in all files, then the findings of the
+    ///passwd file, then those of the group file.
+    pub fn check(&self) -> Result<Vec<Finding>, Error> {
+        self.findings(&self.read(ParseMode::Lenient)?)
+    }
+
+    fn findings(&self, files: &Files) -> Result<Vec<Finding>, Error> {
+        let mut findings = files.malformed.clone();
+        let shells = match std::fs::read_to_string(self.path("shells")) {
+            Ok(contents) => Some(
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                    .map(str::to_string)
+                    .collect::<HashSet<String>>(),
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let defs = match LoginDefs::from_file(self.path("login.defs")) {
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => LoginDefs::default(),
+            defs => defs?,
+        };
+
+        let usernames = duplicates(&files.passwd, |u| u.username.clone());
+        findings.extend(usernames.iter().filter(|(_, users)| users.len() > 1).map(
+            |(username, _)| Finding::DuplicateUsername {
+                username: username.clone(),
+            },
+        ));
+        findings.extend(
+            duplicates(&files.passwd, |u| u.uid)
+                .into_iter()
+                .filter(|(_, users)| users.len() > 1)
+                .map(|(uid, users)| Finding::DuplicateUid {
+                    uid,
+                    usernames: users.iter().map(|u| u.username.clone()).collect(),
+                }),
+        );
+
+        let gids: HashSet<u32> = files.group.iter().map(|g| g.gid).collect();
+        let shadow: Option<HashSet<&str>> = files
+            .shadow
+            .as_ref()
+            .map(|shadow| shadow.iter().map(|s| s.name.as_str()).collect());
+        let mut homes: HashMap<&str, usize> = HashMap::new();
+        for user in &files.passwd {
+            *homes.entry(user.home_dir.as_str()).or_default() += 1;
+        }
+        for user in &files.passwd {
+            if !gids.contains(&user.gid) {
+                findings.push(Finding::MissingPrimaryGroup {
+                    username: user.username.clone(),
+                    gid: user.gid,
+                });
+            }
+            if self.check_home_dirs {
+                let shared = homes.get(user.home_dir.as_str()).copied().unwrap_or(0) > 1;
+                findings.extend(self.check_home_dir(user, shared, &defs));
+            }
+            if let Some(shells) = &shells {
+                if !is_valid_shell(shells, &user.shell) {
+                    findings.push(Finding::InvalidShell {
+                        username: user.username.clone(),
+                        shell: user.shell.clone(),
+                    });
+                }
+            }
+            if let Some(shadow) = &shadow {
+                if !shadow.contains(user.username.as_str()) {
+                    findings.push(Finding::MissingShadow {
+                        username: user.username.clone(),
+                    });
+                }
+            }
+        }
+
+        findings.extend(
+            duplicates(&files.group, |g| g.name.clone())
+                .into_iter()
+                .filter(|(_, groups)| groups.len() > 1)
+                .map(|(name, _)| Finding::DuplicateGroupName { name }),
+        );
+        findings.extend(
+            duplicates(&files.group, |g| g.gid)
+                .into_iter()
+                .filter(|(_, groups)| groups.len() > 1)
+                .map(|(gid, groups)| Finding::DuplicateGid {
+                    gid,
+                    names: groups.iter().map(|g| g.name.clone()).collect(),
+                }),
+        );
+        let users: HashSet<&str> = files.passwd.iter().map(|u| u.username.as_str()).collect();
+        for group in &files.group {
+            let mut seen = HashSet::new();
+            for member in group.users.iter().filter(|m| !m.is_empty()) {
+                if !users.contains(member.as_str()) && seen.insert(member) {
+                    findings.push(Finding::UnknownMember {
+                        group: group.name.clone(),
+                        member: member.clone(),
+                    });
+                }
+            }
+        }
+        Ok(findings)
+    }
+
+    ///The home directory should exist. For regular users, whose home
+    ///directory is not shared with another user, it should also be owned
+    ///by the user. `/nonexistent` is the home of users meant to have none.
+    fn check_home_dir(
+        &self,
+        user: &PasswdEntry,
+        shared: bool,
+        defs: &LoginDefs,
+    ) -> Option<Finding> {
+        if user.home_dir == "/nonexistent" {
+            return None;
+        }
+        let path = self.root.join(user.home_dir.trim_start_matches('/'));
+        let meta = match std::fs::metadata(&path) {
+            Ok(meta) if !user.home_dir.is_empty() => meta,
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound && !user.home_dir.is_empty() => {
+                return None
+            }
+            _ => {
+                return Some(Finding::MissingHomeDir {
+                    username: user.username.clone(),
+                    home_dir: user.home_dir.clone(),
+                })
+            }
+        };
+        match !shared && defs.regular_uids().contains(&user.uid) && meta.uid() != user.uid {
+            true => Some(Finding::WrongHomeOwner {
+                username: user.username.clone(),
+                home_dir: user.home_dir.clone(),
+                owner: meta.uid(),
+            }),
+            false => None,
+        }
+    }
+
+    ///Fixes the findings that are safe to fix, and returns them. The
+    ///files are read again under the lock, and a file with lines that can
+    ///not be parsed, or that is not UTF-8, fails the fix, since those
+    ///lines or bytes would be lost when writing it back.
+    pub fn fix(&self) -> Result<Vec<Finding>, Error> {
+        let lock = self.writer.lock()?;
+        let mut files = self.read(ParseMode::Strict)?;
+        let fixable: Vec<Finding> = self
+            .findings(&files)?
+            .into_iter()
+            .filter(Finding::is_fixable)
+            .collect();
+
+        let (mut passwd, mut group, mut shadow, mut gshadow) = (false, false, false, false);
+        for finding in &fixable {
+            match finding {
+                Finding::UnknownMember {
+                    group: name,
+                    member,
+                } => {
+                    for g in files.group.iter_mut().filter(|g| &g.name == name) {
+                        g.users.retain(|u| u != member);
+                    }
+                    group = true;
+                    //Like grpck, keep the members of gshadow the same
+                    for g in files.gshadow.iter_mut().flatten() {
+                        if &g.name == name && g.members.contains(member) {
+                            g.members.retain(|u| u != member);
+                            gshadow = true;
+                        }
+                    }
+                }
+                Finding::MissingShadow { username } => {
+                    let entries = files.shadow.get_or_insert_with(Vec::new);
+                    let user = files.passwd.iter_mut().find(|u| &u.username == username);
+                    //A duplicated username is reported once per entry
+                    if let (Some(user), false) = (user, entries.iter().any(|s| &s.name == username))
+                    {
+                        //Like pwck, the password is moved to the shadow file
+                        let password = match user.passwd.as_str() {
+                            "x" => "!".to_string(),
+                            password => password.to_string(),
+                        };
+                        user.passwd = "x".to_string();
+                        entries.push(ShadowEntry {
+                            name: username.clone(),
+                            passwd: password,
+                            last_change: Some(days_since_epoch()),
+                            min_days: None,
+                            max_days: None,
+                            warn_days: None,
+                            inactive_days: None,
+                            expire: None,
+                            reserved: String::new(),
+                        });
+                        passwd = true;
+                        shadow = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+        if passwd {
+            lock.write_passwd(self.path("passwd"), &files.passwd)?;
+        }
+        if group {
+            lock.write_group(self.path("group"), &files.group)?;
+        }
+        if let (true, Some(entries)) = (shadow, &files.shadow) {
+            lock.write_shadow(self.path("shadow"), entries)?;
+        }
+        if let (true, Some(entries)) = (gshadow, &files.gshadow) {
+            lock.write_gshadow(self.path("gshadow"), entries)?;
+        }
+        Ok(fixable)
+    }
+}
+
+///Shells that only refuse logins are fine without being in /etc/shells.
+///An empty shell means `/bin/sh`.
+fn is_valid_shell(shells: &HashSet<String>, shell: &str) -> bool {
+    match shell {
+        "" => shells.contains("/bin/sh"),
+        shell if shells.contains(shell) => true,
+        shell => matches!(
+            Path::new(shell).file_name().and_then(|n| n.to_str()),
+            Some("nologin") | Some("false")
+        ),
+    }
+}
+
+///The entries by key, in the order the keys first appear
+fn duplicates<T, K: Eq + std::hash::Hash + Clone>(
+    entries: &[T],
+    key: impl Fn(&T) -> K,
+) -> Vec<(K, Vec<&T>)> {
+    let mut index: HashMap<K, usize> = HashMap::new();
+    let mut keys: Vec<(K, Vec<&T>)> = vec![];
+    for entry in entries {
+        let k = key(entry);
+        match index.get(&k) {
+            Some(i) => keys[*i].1.push(entry),
+            None => {
+                index.insert(k.clone(), keys.len());
+                keys.push((k, vec![entry]));
+            }
+        }
+    }
+    keys
+}
+
+fn not_found(path: &Path) -> Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("{} does not exist", path.display()),
+    )
+    .into()
+}
+
+///Reads a file, or `None` if it does not exist. In [ParseMode::Lenient]
+///the lines that can not be parsed are added to `malformed`, except the
+///NIS compat lines, which are fine in a file read in compat mode. In
+///[ParseMode::Strict] the file has to be UTF-8, as it is read to be
+///written back.
+fn load<T>(
+    path: &Path,
+    mode: ParseMode,
+    parse: fn(&str) -> Result<T, ParseErrorKind>,
+    file: CheckedFile,
+    malformed: &mut Vec<Finding>,
+) -> Result<Option<Vec<T>>, Error> {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let contents = match mode {
+        ParseMode::Lenient => String::from_utf8_lossy(&contents).into_owned(),
+        ParseMode::Strict => String::from_utf8(contents).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} is not UTF-8", path.display()),
+            )
+        })?,
+    };
+    let (entries, warnings) = crate::parse_lines(&contents, mode, parse)?;
+    malformed.extend(
+        warnings
+            .into_iter()
+            .filter(|e| e.kind != ParseErrorKind::CompatEntry)
+            .map(|error| Finding::MalformedLine { file, error }),
+    );
+    Ok(Some(entries))
+}
@@ -0,0 +1,592 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `compat` reads /etc/passwd and /etc/group in NIS compat mode, where a
+//! line starting with `+` includes users or groups of an upstream source,
+//! like NIS, and a line starting with `-` excludes them:
+//!
+//! - `+` includes all users, and `+user` or `+@netgroup` some of them. The
+//!   non-empty password, gecos, home directory and shell fields override the
+//!   fields of the included users. The uid and gid are never overridden.
+//! - `-user` and `-@netgroup` exclude users from the following `+` lines.
+//!
+//! The group file has `+`, `+group` and `-group` lines, where the password
+//! can be overridden.
+//!
+//! The lines are used in order, and the first line deciding a name wins, like
+//! for a lookup with the `compat` service of glibc. The normal parsers reject
+//! these lines with [crate::error::ParseErrorKind::CompatEntry].
+//!
+//! ```
+//! use user_lookup::compat::{CompatPasswd, Netgroups};
+//! use user_lookup::source::MemorySource;
+//! use user_lookup::PasswdEntry;
+//!
+//! let compat = CompatPasswd::parse(
+//!     "root:x:0:0:root:/root:/bin/bash\n-mallory\n+@staff::::::/bin/zsh\n",
+//! );
+//! let netgroups = Netgroups::parse("staff (,alice,) (,mallory,)\n");
+//! let mut nis = MemorySource::new(
+//!     vec![
+//!         PasswdEntry::parse("alice:x:1000:100::/home/alice:/bin/sh").unwrap(),
+//!         PasswdEntry::parse("mallory:x:1001:100::/home/mallory:/bin/sh").unwrap(),
+//!     ],
+//!     vec![],
+//! );
+//!
+//! let users = compat.merge(&mut nis, &netgroups).unwrap();
+//! assert_eq!(2, users.len());
+//! assert_eq!("alice:x:1000:100::/home/alice:/bin/zsh", users[1].to_string());
+//! ```
+use crate::error::ParseErrorKind;
+use crate::error::ParseMode;
+use crate::source::GroupSource;
+use crate::source::UserSource;
+use crate::Error;
+use crate::GroupEntry;
+use crate::PasswdEntry;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+///The fields of a `+` passwd line overriding the included users
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PasswdOverrides {
+    ///Replaces the password
+    pub passwd: Option<String>,
+    ///Replaces the gecos field
+    pub gecos: Option<String>,
+    ///Replaces the home directory
+    pub home_dir: Option<String>,
+    ///Replaces the shell
+    pub shell: Option<String>,
+}
+
+impl PasswdOverrides {
+    ///Replaces the fields of the entry
+    pub fn apply(&self, entry: &mut PasswdEntry) {
+        let replace = |field: &mut String, value: &Option<String>| {
+            if let Some(value) = value {
+                *field = value.clone();
+            }
+        };
+        replace(&mut entry.passwd, &self.passwd);
+        replace(&mut entry.gecos, &self.gecos);
+        replace(&mut entry.home_dir, &self.home_dir);
+        replace(&mut entry.shell, &self.shell);
+    }
+
+    ///Whether no field is overridden
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+///A line of a passwd file in compat mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompatPasswdEntry {
+    ///A normal user
+    Entry(PasswdEntry),
+    ///`+`, all users of the upstream source
+    IncludeAll(PasswdOverrides),
+    ///`+user`, a user of the upstream source
+    IncludeUser(String, PasswdOverrides),
+    ///`+@netgroup`, the users of a netgroup from the upstream source
+    IncludeNetgroup(String, PasswdOverrides),
+    ///`-user`
+    ExcludeUser(String),
+    ///`-@netgroup`
+    ExcludeNetgroup(String),
+}
+
+impl CompatPasswdEntry {
+    ///Create a CompatPasswdEntry from &str.
+    pub fn parse(s: &str) -> Option<CompatPasswdEntry> {
+        Self::try_parse(s).ok()
+    }
+
+    ///Create a CompatPasswdEntry from &str, telling what is wrong if it fails.
+    /// ```
+    /// use user_lookup::compat::{CompatPasswdEntry, PasswdOverrides};
+    ///
+    /// let entry = CompatPasswdEntry::parse("+::::::/bin/false").unwrap();
+    /// let shell = Some("/bin/false".to_string());
+    /// assert_eq!(CompatPasswdEntry::IncludeAll(PasswdOverrides { shell, ..Default::default() }), entry);
+    /// assert_eq!("+::::::/bin/false", entry.to_string());
+    /// assert_eq!(Some(CompatPasswdEntry::ExcludeUser("baduser".to_string())),
+    ///     CompatPasswdEntry::parse("-baduser"));
+    /// ```
+    pub fn try_parse(s: &str) -> Result<CompatPasswdEntry, ParseErrorKind> {
+        let (sign, fields) = match compat_fields(s, 7)? {
+            Some(compat) => compat,
+            None => return PasswdEntry::try_parse(s).map(CompatPasswdEntry::Entry),
+        };
+        let field = |i: usize| {
+            fields
+                .get(i)
+                .filter(|f| !f.is_empty())
+                .map(|f| f.to_string())
+        };
+        let overrides = PasswdOverrides {
+            passwd: field(1),
+            gecos: field(4),
+            home_dir: field(5),
+            shell: field(6),
+        };
+        Ok(match (sign, name(fields[0])?) {
+            ('+', Name::All) => CompatPasswdEntry::IncludeAll(overrides),
+            ('+', Name::One(user)) => CompatPasswdEntry::IncludeUser(user, overrides),
+            ('+', Name::Netgroup(group)) => CompatPasswdEntry::IncludeNetgroup(group, overrides),
+            (_, Name::All) => return Err(ParseErrorKind::EmptyField("username")),
+            (_, Name::One(user)) => CompatPasswdEntry::ExcludeUser(user),
+            (_, Name::Netgroup(group)) => CompatPasswdEntry::ExcludeNetgroup(group),
+        })
+    }
+}
+
+impl fmt::Display for CompatPasswdEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let include = |f: &mut fmt::Formatter<'_>, name: &str, o: &PasswdOverrides| {
+            write!(f, "+{}", name)?;
+            if o.is_empty() {
+                return Ok(());
+            }
+            let field = |value: &Option<String>| value.clone().unwrap_or_default();
+            write!(
+                f,
+                ":{}:::{}:{}:{}",
+                field(&o.passwd),
+                field(&o.gecos),
+                field(&o.home_dir),
+                field(&o.shell)
+            )
+        };
+        match self {
+            CompatPasswdEntry::Entry(entry) => entry.fmt(f),
+            CompatPasswdEntry::IncludeAll(o) => include(f, "", o),
+            CompatPasswdEntry::IncludeUser(user, o) => include(f, user, o),
+            CompatPasswdEntry::IncludeNetgroup(group, o) => include(f, &format!("@{}", group), o),
+            CompatPasswdEntry::ExcludeUser(user) => write!(f, "-{}", user),
+            CompatPasswdEntry::ExcludeNetgroup(group) => write!(f, "-@{}", group),
+        }
+    }
+}
+
+///A line of a group file in compat mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompatGroupEntry {
+    ///A normal group
+    Entry(GroupEntry),
+    ///`+`, all groups of the upstream source, optionally with another
+    ///password
+    IncludeAll(Option<String>),
+    ///`+group`, a group of the upstream source, optionally with another
+    ///password
+    IncludeGroup(String, Option<String>),
+    ///`-group`
+    ExcludeGroup(String),
+}
+
+impl CompatGroupEntry {
+    ///Create a CompatGroupEntry from &str.
+    pub fn parse(s: &str) -> Option<CompatGroupEntry> {
+        Self::try_parse(s).ok()
+    }
+
+    ///Create a CompatGroupEntry from &str, telling what is wrong if it fails.
+    ///Netgroups are not supported in group files.
+    pub fn try_parse(s: &str) -> Result<CompatGroupEntry, ParseErrorKind> {
+        let (sign, fields) = match compat_fields(s, 4)? {
+            Some(compat) => compat,
+            None => return GroupEntry::try_parse(s).map(CompatGroupEntry::Entry),
+        };
+        let passwd = fields
+            .get(1)
+            .filter(|f| !f.is_empty())
+            .map(|f| f.to_string());
+        Ok(match (sign, name(fields[0])?) {
+            (_, Name::Netgroup(_)) => return Err(ParseErrorKind::CompatEntry),
+            ('+', Name::All) => CompatGroupEntry::IncludeAll(passwd),
+            ('+', Name::One(group)) => CompatGroupEntry::IncludeGroup(group, passwd),
+            (_, Name::All) => return Err(ParseErrorKind::EmptyField("name")),
+            (_, Name::One(group)) => CompatGroupEntry::ExcludeGroup(group),
+        })
+    }
+}
+
+impl fmt::Display for CompatGroupEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let include = |f: &mut fmt::Formatter<'_>, name: &str, passwd: &Option<String>| match passwd
+        {
+            Some(passwd) => write!(f, "+{}:{}::", name, passwd),
+            None => write!(f, "+{}", name),
+        };
+        match self {
+            CompatGroupEntry::Entry(entry) => entry.fmt(f),
+            CompatGroupEntry::IncludeAll(passwd) => include(f, "", passwd),
+            CompatGroupEntry::IncludeGroup(group, passwd) => include(f, group, passwd),
+            CompatGroupEntry::ExcludeGroup(group) => write!(f, "-{}", group),
+        }
+    }
+}
+
+///The sign and fields of a `+` or `-` line, or `None` for a normal line
+fn compat_fields(s: &str, max: usize) -> Result<Option<(char, Vec<&str>)>, ParseErrorKind> {
+    let sign = match s.chars().next() {
+        Some(sign @ ('+' | '-')) => sign,
+        _ => return Ok(None),
+    };
+    let fields: Vec<&str> = s[1..].split(':').collect();
+    if fields.len() > max {
+        return Err(ParseErrorKind::ExtraFields);
+    }
+    Ok(Some((sign, fields)))
+}
+
+enum Name {
+    All,
+    One(String),
+    Netgroup(String),
+}
+
+fn name(field: &str) -> Result<Name, ParseErrorKind> {
+    match field.strip_prefix('@') {
+        Some("") => Err(ParseErrorKind::EmptyField("netgroup")),
+        Some(group) => Ok(Name::Netgroup(group.to_string())),
+        None if field.is_empty() => Ok(Name::All),
+        None => Ok(Name::One(field.to_string())),
+    }
+}
+
+///Parses all lines in compat mode, skipping empty lines and, depending on
+///the mode, lines that can not be parsed
+fn parse_all<T>(
+    contents: &str,
+    mode: ParseMode,
+    parse: fn(&str) -> Result<T, ParseErrorKind>,
+) -> Result<Vec<T>, Error> {
+    Ok(crate::parse_lines(contents, mode, parse)?.0)
+}
+
+///The lines of a passwd file in compat mode
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CompatPasswd {
+    entries: Vec<CompatPasswdEntry>,
+}
+
+impl CompatPasswd {
+    ///Creates a CompatPasswd of the lines
+    pub fn new(entries: Vec<CompatPasswdEntry>) -> Self {
+        Self { entries }
+    }
+
+    ///Parses the contents of a passwd file, skipping lines that can not
+    ///be parsed
+    pub fn parse(contents: &str) -> Self {
+        Self::new(
+            parse_all(contents, ParseMode::Lenient, CompatPasswdEntry::try_parse)
+                .unwrap_or_default(),
+        )
+    }
+
+    ///Reads a passwd file. In [ParseMode::Strict], a line that can not be
+    ///parsed fails the read.
+    pub fn from_file<P: AsRef<Path>>(path: P, mode: ParseMode) -> Result<Self, Error> {
+        let contents = std::fs::read(path)?;
+        let contents = String::from_utf8_lossy(&contents);
+        Ok(Self::new(parse_all(
+            &contents,
+            mode,
+            CompatPasswdEntry::try_parse,
+        )?))
+    }
+
+    ///The lines, in order
+    pub fn entries(&self) -> &[CompatPasswdEntry] {
+        &self.entries
+    }
+
+    ///Resolves the `+` and `-` lines against the upstream source, returning
+    ///the users in the order of the lines deciding them. Users of `+user`
+    ///and `+@netgroup` lines that the upstream source does not have are
+    ///left out.
+    pub fn merge(
+        &self,
+        upstream: &mut dyn UserSource,
+        netgroups: &Netgroups,
+    ) -> Result<Vec<PasswdEntry>, Error> {
+        let mut users = vec![];
+        let mut decided = HashSet::new();
+        let mut excluded_netgroups: Vec<&str> = vec![];
+        let mut all = None;
+        for line in &self.entries {
+            let excluded = |name: &str, decided: &HashSet<String>| {
+                decided.contains(name)
+                    || excluded_netgroups
+                        .iter()
+                        .any(|group| netgroups.contains(group, name))
+            };
+            match line {
+                CompatPasswdEntry::Entry(entry) => {
+                    if !excluded(&entry.username, &decided) {
+                        decided.insert(entry.username.clone());
+                        users.push(entry.clone());
+                    }
+                }
+                CompatPasswdEntry::ExcludeUser(user) => {
+                    decided.insert(user.clone());
+                }
+                CompatPasswdEntry::ExcludeNetgroup(group) => excluded_netgroups.push(group),
+                CompatPasswdEntry::IncludeUser(user, overrides) => {
+                    if excluded(user, &decided) {
+                        continue;
+                    }
+                    if let Some(mut entry) = upstream.get_by_username(user)? {
+                        overrides.apply(&mut entry);
+                        decided.insert(entry.username.clone());
+                        users.push(entry);
+                    }
+                }
+                CompatPasswdEntry::IncludeNetgroup(group, overrides) => {
+                    for user in netgroups.users(group) {
+                        if excluded(&user, &decided) {
+                            continue;
+                        }
+                        if let Some(mut entry) = upstream.get_by_username(&user)? {
+                            overrides.apply(&mut entry);
+                            decided.insert(entry.username.clone());
+                            users.push(entry);
+                        }
+                    }
+                }
+                CompatPasswdEntry::IncludeAll(overrides) => {
+                    let entries: &Vec<PasswdEntry> = match &mut all {
+                        Some(entries) => entries,
+                        None => all.insert(upstream.get_entries()?),
+                    };
+                    for entry in entries {
+                        if !excluded(&entry.username, &decided) {
+                            let mut entry = entry.clone();
+                            overrides.apply(&mut entry);
+                            decided.insert(entry.username.clone());
+                            users.push(entry);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(users)
+    }
+}
+
+///The lines of a group file in compat mode
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CompatGroup {
+    entries: Vec<CompatGroupEntry>,
+}
+
+impl CompatGroup {
+    ///Creates a CompatGroup of the lines
+    pub fn new(entries: Vec<CompatGroupEntry>) -> Self {
+        Self { entries }
+    }
+
+    ///Parses the contents of a group file, skipping lines that can not
+    ///be parsed
+    pub fn parse(contents: &str) -> Self {
+        Self::new(
+            parse_all(contents, ParseMode::Lenient, CompatGroupEntry::try_parse)
+                .unwrap_or_default(),
+        )
+    }
+
+    ///Reads a group file. In [ParseMode::Strict], a line that can not be
+    ///parsed fails the read.
+    pub fn from_file<P: AsRef<Path>>(path: P, mode: ParseMode) -> Result<Self, Error> {
+        let contents = std::fs::read(path)?;
+        let contents = String::from_utf8_lossy(&contents);
+        Ok(Self::new(parse_all(
+            &contents,
+            mode,
+            CompatGroupEntry::try_parse,
+        )?))
+    }
+
+    ///The lines, in order
+    pub fn entries(&self) -> &[CompatGroupEntry] {
+        &self.entries
+    }
+
+    ///Resolves the `+` and `-` lines against the upstream source, returning
+    ///the groups in the order of the lines deciding them.
+    pub fn merge(&self, upstream: &mut dyn GroupSource) -> Result<Vec<GroupEntry>, Error> {
+        let mut groups = vec![];
+        let mut decided = HashSet::new();
+        let mut all = None;
+        let with_passwd = |mut group: GroupEntry, passwd: &Option<String>| {
+            if let Some(passwd) = passwd {
+                group.passwd = passwd.clone();
+            }
+            group
+        };
+        for line in &self.entries {
+            match line {
+                CompatGroupEntry::Entry(group) => {
+                    if decided.insert(group.name.clone()) {
+                        groups.push(group.clone());
+                    }
+                }
+                CompatGroupEntry::ExcludeGroup(group) => {
+                    decided.insert(group.clone());
+                }
+                CompatGroupEntry::IncludeGroup(group, passwd) => {
+                    if decided.contains(group) {
+                        continue;
+                    }
+                    if let Some(group) = upstream.get_by_name(group)? {
+                        decided.insert(group.name.clone());
+                        groups.push(with_passwd(group, passwd));
+                    }
+                }
+                CompatGroupEntry::IncludeAll(passwd) => {
+                    let entries: &Vec<GroupEntry> = match &mut all {
+                        Some(entries) => entries,
+                        None => all.insert(upstream.get_groups()?),
+                    };
+                    for group in entries {
+                        if decided.insert(group.name.clone()) {
+                            groups.push(with_passwd(group.clone(), passwd));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(groups)
+    }
+}
+
+///A member of a netgroup
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Member {
+    ///A `(host,user,domain)` triple, where `None` is any user
+    User(Option<String>),
+    ///Another netgroup
+    Netgroup(String),
+}
+
+///Netgroups in the format of /etc/netgroup, used to resolve `+@netgroup`
+///and `-@netgroup` lines. Only the user of each `(host,user,domain)` triple
+///is used.
+/// ```
+/// use user_lookup::compat::Netgroups;
+///
+/// let netgroups = Netgroups::parse("admins (,root,) staff\nstaff (,alice,) \\\n (,bob,)\n");
+/// assert_eq!(vec!["root", "alice", "bob"], netgroups.users("admins"));
+/// assert!(netgroups.contains("staff", "bob"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Netgroups {
+    groups: HashMap<String, Vec<Member>>,
+}
+
+impl Netgroups {
+    ///Creates an empty set of netgroups
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Parses the contents of a netgroup file
+    pub fn parse(contents: &str) -> Self {
+        let mut netgroups = Self::new();
+        let joined = contents.replace("\\\n", " ");
+        for line in joined.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut rest = line.trim();
+            let name = match rest.split_whitespace().next() {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            rest = rest[name.len()..].trim_start();
+            let members = netgroups.groups.entry(name).or_default();
+            while !rest.is_empty() {
+                if let Some(triple) = rest.strip_prefix('(') {
+                    let (triple, after) = triple.split_once(')').unwrap_or((triple, ""));
+                    let user = triple.split(',').nth(1).map(str::trim).unwrap_or_default();
+                    match user {
+                        "" => members.push(Member::User(None)),
+                        //A user of `-` matches no user
+                        "-" => {}
+                        user => members.push(Member::User(Some(user.to_string()))),
+                    }
+                    rest = after.trim_start();
+                } else {
+                    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                    members.push(Member::Netgroup(rest[..end].to_string()));
+                    rest = rest[end..].trim_start();
+                }
+            }
+        }
+        netgroups
+    }
+
+    ///Reads a netgroup file, like /etc/netgroup
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    ///Adds a user to a netgroup
+    pub fn add_user(&mut self, netgroup: &str, user: &str) {
+        self.groups
+            .entry(netgroup.to_string())
+            .or_default()
+            .push(Member::User(Some(user.to_string())));
+    }
+
+    ///The users of a netgroup and the netgroups it includes, in order
+    ///and without duplicates. Triples matching any user are left out.
+    pub fn users(&self, netgroup: &str) -> Vec<String> {
+        let mut users = vec![];
+        self.walk(netgroup, &mut HashSet::new(), &mut |user| {
+            if let Some(user) = user {
+                if !users.iter().any(|u| u == user) {
+                    users.push(user.to_string());
+                }
+            }
+        });
+        users
+    }
+
+    ///Whether the user is in the netgroup or a netgroup it includes,
+    ///including by a triple matching any user
+    pub fn contains(&self, netgroup: &str, user: &str) -> bool {
+        let mut found = false;
+        self.walk(netgroup, &mut HashSet::new(), &mut |member| {
+            found |= member.is_none_or(|m| m == user);
+        });
+        found
+    }
+
+    fn walk<'a>(
+        &'a self,
+        netgroup: &'a str,
+        seen: &mut HashSet<&'a str>,
+        f: &mut dyn FnMut(Option<&'a str>),
+    ) {
+        if !seen.insert(netgroup) {
+            return;
+        }
+        for member in self.groups.get(netgroup).into_iter().flatten() {
+            match member {
+                Member::User(user) => f(user.as_deref()),
+                Member::Netgroup(inner) => self.walk(inner, seen, f),
+            }
+        }
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `database` holds the [UserDatabase], one consistent view of the users
+//! and groups at a point in time.
+use crate::error::ParseMode;
+use crate::index::GroupIndex;
+use crate::index::PasswdIndex;
+use crate::login_defs::AccountClass;
+use crate::login_defs::LoginDefs;
+use crate::Error;
+use crate::GroupEntry;
+use crate::PasswdEntry;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+
+///An immutable snapshot of the passwd and group entries, with the
+///lookups of the readers and the references between users and groups.
+///It never reads the files again, and cloning it only clones an `Arc`.
+/// ```
+/// use user_lookup::database::UserDatabase;
+///
+/// let db = UserDatabase::parse(
+///     "root:x:0:0:root:/root:/bin/bash\nuser1:x:1000:100::/home/user1:/bin/sh\n",
+///     "root:x:0:\nwheel:x:10:user1\nusers:x:100:\n",
+/// );
+/// let user1 = db.get_by_uid(1000).unwrap();
+/// assert_eq!("users", db.get_primary_group(user1).unwrap().name);
+/// assert_eq!(vec![100, 10], db.get_gids_for_user(user1));
+///
+/// let wheel = db.get_group_by_name("wheel").unwrap();
+/// let members: Vec<&str> = db.get_members(wheel).iter().map(|u| u.username.as_str()).collect();
+/// assert_eq!(vec!["user1"], members);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct UserDatabase {
+    inner: Arc<Tables>,
+}
+
+#[derive(Debug, Default)]
+struct Tables {
+    passwd: PasswdIndex,
+    groups: GroupIndex,
+    by_primary_gid: HashMap<u32, Vec<usize>>,
+}
+
+impl UserDatabase {
+    ///Creates a UserDatabase of the users and groups
+    pub fn new(users: Vec<PasswdEntry>, groups: Vec<GroupEntry>) -> Self {
+        let mut by_primary_gid: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (i, user) in users.iter().enumerate() {
+            by_primary_gid.entry(user.gid).or_default().push(i);
+        }
+        Self {
+            inner: Arc::new(Tables {
+                passwd: PasswdIndex::new(users),
+                groups: GroupIndex::new(groups),
+                by_primary_gid,
+            }),
+        }
+    }
+
+    ///Parses the contents of a passwd and a group file, skipping lines
+    ///that can not be parsed
+    pub fn parse(passwd: &str, group: &str) -> Self {
+        let users = crate::parse_lines(passwd, ParseMode::Lenient, PasswdEntry::try_parse);
+        let groups = crate::parse_lines(group, ParseMode::Lenient, GroupEntry::try_parse);
+        Self::new(
+            users.map(|(e, _)| e).unwrap_or_default(),
+            groups.map(|(e, _)| e).unwrap_or_default(),
+        )
+    }
+
+    ///Reads a passwd and a group file. In [ParseMode::Strict], a line
+    ///that can not be parsed fails the read.
+    /// ```
+    /// use user_lookup::database::UserDatabase;
+    /// use user_lookup::error::ParseMode;
+    ///
+    /// let db = UserDatabase::from_files("test_files/passwd", "test_files/group", ParseMode::Strict).unwrap();
+    /// assert_eq!(Some(vec![100, 10]), db.get_gids_by_username("user1"));
+    /// assert_eq!(None, db.get_gids_by_username("nobody"));
+    /// ```
+    pub fn from_files<T: AsRef<Path>, U: AsRef<Path>>(
+        passwd: T,
+        group: U,
+        mode: ParseMode,
+    ) -> Result<Self, Error> {
+        let passwd = std::fs::read(passwd)?;
+        let group = std::fs::read(group)?;
+        let (users, _) = crate::parse_lines(
+            &String::from_utf8_lossy(&passwd),
+            mode,
+            PasswdEntry::try_parse,
+        )?;
+        let (groups, _) = crate::parse_lines(
+            &String::from_utf8_lossy(&group),
+            mode,
+            GroupEntry::try_parse,
+        )?;
+        Ok(Self::new(users, groups))
+    }
+
+    ///The index over the passwd entries
+    pub fn passwd(&self) -> &PasswdIndex {
+        &self.inner.passwd
+    }
+
+    ///The index over the group entries
+    pub fn groups(&self) -> &GroupIndex {
+        &self.inner.groups
+    }
+
+    ///Get all the entire list of passwd entries
+    pub fn get_entries(&self) -> &Vec<PasswdEntry> {
+        self.inner.passwd.entries()
+    }
+
+    ///Get the entire list of group entries
+    pub fn get_groups(&self) -> &Vec<GroupEntry> {
+        self.inner.groups.groups()
+    }
+
+    ///Will return an iterator over the regular users, with
+    ///a uid between `UID_MIN` and `UID_MAX` of `defs`
+    pub fn regular_users<'a>(
+        &'a self,
+        defs: &LoginDefs,
+    ) -> impl Iterator<Item = &'a PasswdEntry> + 'a {
+        let is_regular = defs.uid_filter(AccountClass::Regular);
+        self.get_entries().iter().filter(move |e| is_regular(e.uid))
+    }
+
+    ///Will return an iterator over the system users, with
+    ///a uid of at most `SYS_UID_MAX` of `defs` that is not regular
+    pub fn system_users<'a>(
+        &'a self,
+        defs: &LoginDefs,
+    ) -> impl Iterator<Item = &'a PasswdEntry> + 'a {
+        let is_system = defs.uid_filter(AccountClass::System);
+        self.get_entries().iter().filter(move |e| is_system(e.uid))
+    }
+
+    ///Will return an iterator over the regular groups, with
+    ///a gid between `GID_MIN` and `GID_MAX` of `defs`
+    pub fn regular_groups<'a>(
+        &'a self,
+        defs: &LoginDefs,
+    ) -> impl Iterator<Item = &'a GroupEntry> + 'a {
+        let is_regular = defs.gid_filter(AccountClass::Regular);
+        self.get_groups().iter().filter(move |e| is_regular(e.gid))
+    }
+
+    ///Will return an iterator over the system groups, with
+    ///a gid of at most `SYS_GID_MAX` of `defs` that is not regular
+    pub fn system_groups<'a>(
+        &'a self,
+        defs: &LoginDefs,
+    ) -> impl Iterator<Item = &'a GroupEntry> + 'a {
+        let is_system = defs.gid_filter(AccountClass::System);
+        self.get_groups().iter().filter(move |e| is_system(e.gid))
+    }
+
+    ///Look up a PasswdEntry by username
+    pub fn get_by_username(&self, username: &str) -> Option<&PasswdEntry> {
+        self.inner.passwd.get_by_username(username)
+    }
+
+    ///Look up a PasswdEntry by uid
+    pub fn get_by_uid(&self, uid: u32) -> Option<&PasswdEntry> {
+        self.inner.passwd.get_by_uid(uid)
+    }
+
+    ///Look up a username by uid
+    pub fn get_username_by_uid(&self, uid: u32) -> Option<&str> {
+        self.get_by_uid(uid).map(|e| e.username.as_str())
+    }
+
+    ///Look up a user ID by username
+    pub fn get_uid_by_username(&self, username: &str) -> Option<u32> {
+        self.get_by_username(username).map(|e| e.uid)
+    }
+
+    ///Look up a GroupEntry by the group name
+    pub fn get_group_by_name(&self, name: &str) -> Option<&GroupEntry> {
+        self.inner.groups.get_by_name(name)
+    }
+
+    ///Look up a GroupEntry by gid
+    pub fn get_group_by_gid(&self, gid: u32) -> Option<&GroupEntry> {
+        self.inner.groups.get_by_gid(gid)
+    }
+
+    ///Look up a group name by gid
+    pub fn get_name_by_gid(&self, gid: u32) -> Option<&str> {
+        self.get_group_by_gid(gid).map(|e| e.name.as_str())
+    }
+
+    ///Look up a group ID by the group name
+    pub fn get_gid_by_name(&self, name: &str) -> Option<u32> {
+        self.get_group_by_name(name).map(|e| e.gid)
+    }
+
+    ///Get all groups of a user, like `getgrouplist`. This is the
+    ///primary group followed by every group listing the user as a
+    ///member, without duplicates.
+    pub fn get_groups_for_user(&self, user: &PasswdEntry) -> Vec<&GroupEntry> {
+        self.inner.groups.get_group_list(&user.username, user.gid)
+    }
+
+    ///Get all group IDs of a user, like `getgrouplist`. The primary
+    ///gid is always included, even if there is no such group.
+    pub fn get_gids_for_user(&self, user: &PasswdEntry) -> Vec<u32> {
+        self.inner.groups.get_gid_list(&user.username, user.gid)
+    }
+
+    ///Get all groups of a user by username. Returns `None` if the user
+    ///does not exist.
+    pub fn get_groups_by_username(&self, username: &str) -> Option<Vec<&GroupEntry>> {
+        self.get_by_username(username)
+            .map(|user| self.get_groups_for_user(user))
+    }
+
+    ///Get all group IDs of a user by username. Returns `None` if the user
+    ///does not exist.
+    pub fn get_gids_by_username(&self, username: &str) -> Option<Vec<u32>> {
+        self.get_by_username(username)
+            .map(|user| self.get_gids_for_user(user))
+    }
+
+    ///The group with the gid of the user, if there is one
+    pub fn get_primary_group(&self, user: &PasswdEntry) -> Option<&GroupEntry> {
+        self.get_group_by_gid(user.gid)
+    }
+
+    ///The users of a group. These are the users listed as members that
+    ///exist, followed by the users having it as primary group, without
+    ///duplicates.
+    /// ```
+    /// use user_lookup::database::UserDatabase;
+    /// use user_lookup::{GroupEntry, PasswdEntry};
+    ///
+    /// let db = UserDatabase::new(
+    ///     vec![
+    ///         PasswdEntry::parse("alice:x:1000:100:::").unwrap(),
+    ///         PasswdEntry::parse("bob:x:1001:100:::").unwrap(),
+    ///     ],
+    ///     vec![GroupEntry::parse("users:x:100:bob,ghost").unwrap()],
+    /// );
+    /// let users = db.get_group_by_gid(100).unwrap();
+    /// let members: Vec<u32> = db.get_members(users).iter().map(|u| u.uid).collect();
+    /// assert_eq!(vec![1001, 1000], members);
+    /// ```
+    pub fn get_members(&self, group: &GroupEntry) -> Vec<&PasswdEntry> {
+        let mut seen = HashSet::new();
+        let entries = self.get_entries();
+        group
+            .users
+            .iter()
+            .filter_map(|name| self.get_by_username(name))
+            .chain(
+                self.inner
+                    .by_primary_gid
+                    .get(&group.gid)
+                    .into_iter()
+                    .flatten()
+                    .map(|i| &entries[*i]),
+            )
+            .filter(|u| seen.insert(u.username.as_str()))
+            .collect()
+    }
+
+    ///The users of the group with the name. Returns `None` if the group
+    ///does not exist.
+    pub fn get_members_by_name(&self, name: &str) -> Option<Vec<&PasswdEntry>> {
+        self.get_group_by_name(name)
+            .map(|group| self.get_members(group))
+    }
+}
+
+impl From<(Vec<PasswdEntry>, Vec<GroupEntry>)> for UserDatabase {
+    fn from((users, groups): (Vec<PasswdEntry>, Vec<GroupEntry>)) -> Self {
+        Self::new(users, groups)
+    }
+}
@@ -0,0 +1,458 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `diff` compares two sets of users and groups, like the accounts of
+//! today and yesterday. Entries are matched by name. An entry removed and
+//! one added with the same uid or gid are taken as a rename.
+//!
+//! The [Diff] is written as a report by `Display`, or as JSON by
+//! [Diff::to_json].
+//! ```
+//! use user_lookup::database::UserDatabase;
+//! use user_lookup::diff::Diff;
+//!
+//! let yesterday = UserDatabase::parse(
+//!     "root:x:0:0:root:/root:/bin/bash\nalice:x:1000:100::/home/alice:/bin/sh\n",
+//!     "users:x:100:alice\n",
+//! );
+//! let today = UserDatabase::parse(
+//!     "root:x:0:0:root:/root:/bin/zsh\nalicia:x:1000:100::/home/alicia:/bin/sh\nbob:x:1001:100:::\n",
+//!     "users:x:100:alicia,bob\n",
+//! );
+//! let diff = Diff::new(&yesterday, &today);
+//! assert_eq!(
+//!     "~ user root: shell \"/bin/bash\" -> \"/bin/zsh\"\n\
+//!      ~ user alice -> alicia (uid 1000): home_dir \"/home/alice\" -> \"/home/alicia\"\n\
+//!      + user bob (uid 1001)\n\
+//!      ~ group users (gid 100): members +alicia +bob -alice\n",
+//!     diff.to_string()
+//! );
+//! ```
+use crate::database::UserDatabase;
+use crate::json::Value;
+use crate::GroupEntry;
+use crate::PasswdEntry;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+
+///A field with different values before and after
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    ///The name of the field, like `shell` or `gid`
+    pub field: &'static str,
+    ///The value before
+    pub before: String,
+    ///The value after
+    pub after: String,
+}
+
+impl FieldChange {
+    fn to_json(&self) -> Value {
+        Value::Object(vec![
+            ("field".to_string(), self.field.into()),
+            ("before".to_string(), self.before.as_str().into()),
+            ("after".to_string(), self.after.as_str().into()),
+        ])
+    }
+}
+
+///How a user differs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UserChange {
+    ///The user only exists after
+    Added(PasswdEntry),
+    ///The user only exists before
+    Removed(PasswdEntry),
+    ///Fields of the user changed. If the username is one of them, the
+    ///user was renamed, keeping the uid.
+    Modified {
+        ///The user before
+        before: PasswdEntry,
+        ///The user after
+        after: PasswdEntry,
+        ///The fields that differ
+        fields: Vec<FieldChange>,
+    },
+}
+
+impl UserChange {
+    ///Whether this is a user that got another username
+    pub fn is_rename(&self) -> bool {
+        matches!(self, UserChange::Modified { before, after, .. } if before.username != after.username)
+    }
+}
+
+///How a group differs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupChange {
+    ///The group only exists after
+    Added(GroupEntry),
+    ///The group only exists before
+    Removed(GroupEntry),
+    ///Fields or members of the group changed. If the name is one of the
+    ///fields, the group was renamed, keeping the gid.
+    Modified {
+        ///The group before
+        before: GroupEntry,
+        ///The group after
+        after: GroupEntry,
+        ///The fields that differ, not counting the members
+        fields: Vec<FieldChange>,
+        ///The members only listed after
+        added_members: Vec<String>,
+        ///The members only listed before
+        removed_members: Vec<String>,
+    },
+}
+
+impl GroupChange {
+    ///Whether this is a group that got another name
+    pub fn is_rename(&self) -> bool {
+        matches!(self, GroupChange::Modified { before, after, .. } if before.name != after.name)
+    }
+}
+
+///The differences between two sets of users and groups
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Diff {
+    ///The changed users, see [diff_users]
+    pub users: Vec<UserChange>,
+    ///The changed groups, see [diff_groups]
+    pub groups: Vec<GroupChange>,
+}
+
+impl Diff {
+    ///The differences between the users and groups of two snapshots
+    pub fn new(before: &UserDatabase, after: &UserDatabase) -> Self {
+        Self {
+            users: diff_users(before.get_entries(), after.get_entries()),
+            groups: diff_groups(before.get_groups(), after.get_groups()),
+        }
+    }
+
+    ///Whether the users and groups are the same
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.groups.is_empty()
+    }
+
+    ///The differences as JSON text, with one object per change
+    /// ```
+    /// use user_lookup::diff::{diff_users, Diff};
+    /// use user_lookup::PasswdEntry;
+    ///
+    /// let before = vec![PasswdEntry::parse("user1:x:1000:100:::/bin/sh").unwrap()];
+    /// let after = vec![PasswdEntry::parse("user1:x:1000:100:::/bin/zsh").unwrap()];
+    /// let diff = Diff { users: diff_users(&before, &after), groups: vec![] };
+    /// assert_eq!(
+    ///     r#"{"users":[{"change":"modified","name":"user1","uid":1000,"fields":[{"field":"shell","before":"/bin/sh","after":"/bin/zsh"}]}],"groups":[]}"#,
+    ///     diff.to_json()
+    /// );
+    /// ```
+    pub fn to_json(&self) -> String {
+        Value::Object(vec![
+            (
+                "users".to_string(),
+                Value::Array(self.users.iter().map(user_json).collect()),
+            ),
+            (
+                "groups".to_string(),
+                Value::Array(self.groups.iter().map(group_json).collect()),
+            ),
+        ])
+        .to_string()
+    }
+}
+
+///The changes turning the users `before` into `after`. They come in the
+///order of `after`, followed by the removed users in the order of
+///`before`.
+pub fn diff_users(before: &[PasswdEntry], after: &[PasswdEntry]) -> Vec<UserChange> {
+    let pairs = pair(before, after, |u| u.username.as_str(), |u| u.uid);
+    let mut changes = vec![];
+    for (i, user) in after.iter().enumerate() {
+        match pairs.after[i] {
+            None => changes.push(UserChange::Added(user.clone())),
+            Some(b) if before[b] != *user => changes.push(UserChange::Modified {
+                before: before[b].clone(),
+                after: user.clone(),
+                fields: user_fields(&before[b], user),
+            }),
+            Some(_) => {}
+        }
+    }
+    for (i, user) in before.iter().enumerate() {
+        if !pairs.before[i] {
+            changes.push(UserChange::Removed(user.clone()));
+        }
+    }
+    changes
+}
+
+///The changes turning the groups `before` into `after`. They come in the
+///order of `after`, followed by the removed groups in the order of
+///`before`.
+pub fn diff_groups(before: &[GroupEntry], after: &[GroupEntry]) -> Vec<GroupChange> {
+    let pairs = pair(before, after, |g| g.name.as_str(), |g| g.gid);
+    let mut changes = vec![];
+    for (i, group) in after.iter().enumerate() {
+        match pairs.after[i] {
+            None => changes.push(GroupChange::Added(group.clone())),
+            Some(b) => {
+                let fields = group_fields(&before[b], group);
+                let added_members = missing(&group.users, &before[b].users);
+                let removed_members = missing(&before[b].users, &group.users);
+                //Members only listed in another order are not a change
+                if !fields.is_empty() || !added_members.is_empty() || !removed_members.is_empty() {
+                    changes.push(GroupChange::Modified {
+                        before: before[b].clone(),
+                        after: group.clone(),
+                        fields,
+                        added_members,
+                        removed_members,
+                    });
+                }
+            }
+        }
+    }
+    for (i, group) in before.iter().enumerate() {
+        if !pairs.before[i] {
+            changes.push(GroupChange::Removed(group.clone()));
+        }
+    }
+    changes
+}
+
+///Which entry before each entry after was paired with, and which entries
+///before were paired at all
+struct Pairs {
+    after: Vec<Option<usize>>,
+    before: Vec<bool>,
+}
+
+///Pairs the entries by name, and then the ones left by id
+fn pair<T>(before: &[T], after: &[T], name: fn(&T) -> &str, id: fn(&T) -> u32) -> Pairs {
+    let mut by_name: HashMap<&str, usize> = HashMap::new();
+    for (i, e) in before.iter().enumerate() {
+        by_name.entry(name(e)).or_insert(i);
+    }
+    let mut pairs = Pairs {
+        after: vec![None; after.len()],
+        before: vec![false; before.len()],
+    };
+    for (i, e) in after.iter().enumerate() {
+        if let Some(b) = by_name.get(name(e)).copied().filter(|b| !pairs.before[*b]) {
+            pairs.after[i] = Some(b);
+            pairs.before[b] = true;
+        }
+    }
+    let mut by_id: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, e) in before.iter().enumerate().rev() {
+        if !pairs.before[i] {
+            by_id.entry(id(e)).or_default().push(i);
+        }
+    }
+    for (i, e) in after.iter().enumerate() {
+        if pairs.after[i].is_some() {
+            continue;
+        }
+        if let Some(b) = by_id.get_mut(&id(e)).and_then(|left| left.pop()) {
+            pairs.after[i] = Some(b);
+            pairs.before[b] = true;
+        }
+    }
+    pairs
+}
+
+fn field(changes: &mut Vec<FieldChange>, field: &'static str, before: String, after: String) {
+    if before != after {
+        changes.push(FieldChange {
+            field,
+            before,
+            after,
+        });
+    }
+}
+
+fn user_fields(before: &PasswdEntry, after: &PasswdEntry) -> Vec<FieldChange> {
+    let mut changes = vec![];
+    let (b, a) = (before.clone(), after.clone());
+    field(&mut changes, "username", b.username, a.username);
+    field(&mut changes, "passwd", b.passwd, a.passwd);
+    field(&mut changes, "uid", b.uid.to_string(), a.uid.to_string());
+    field(&mut changes, "gid", b.gid.to_string(), a.gid.to_string());
+    field(&mut changes, "gecos", b.gecos, a.gecos);
+    field(&mut changes, "home_dir", b.home_dir, a.home_dir);
+    field(&mut changes, "shell", b.shell, a.shell);
+    changes
+}
+
+fn group_fields(before: &GroupEntry, after: &GroupEntry) -> Vec<FieldChange> {
+    let mut changes = vec![];
+    let (b, a) = (before.clone(), after.clone());
+    field(&mut changes, "name", b.name, a.name);
+    field(&mut changes, "passwd", b.passwd, a.passwd);
+    field(&mut changes, "gid", b.gid.to_string(), a.gid.to_string());
+    changes
+}
+
+///The members of `users` not in `other`
+fn missing(users: &[String], other: &[String]) -> Vec<String> {
+    let other: HashSet<&String> = other.iter().collect();
+    users
+        .iter()
+        .filter(|u| !u.is_empty() && !other.contains(u))
+        .cloned()
+        .collect()
+}
+
+fn user_json(change: &UserChange) -> Value {
+    let (kind, user, fields) = match change {
+        UserChange::Added(user) => ("added", user, None),
+        UserChange::Removed(user) => ("removed", user, None),
+        UserChange::Modified { after, fields, .. } => match change.is_rename() {
+            true => ("renamed", after, Some(fields)),
+            false => ("modified", after, Some(fields)),
+        },
+    };
+    let mut json = Value::Object(vec![
+        ("change".to_string(), kind.into()),
+        ("name".to_string(), user.username.as_str().into()),
+        ("uid".to_string(), user.uid.into()),
+    ]);
+    match fields {
+        Some(fields) => json.set(
+            "fields",
+            Value::Array(fields.iter().map(FieldChange::to_json).collect()),
+        ),
+        None => json.set("entry", user.to_string().into()),
+    }
+    json
+}
+
+fn group_json(change: &GroupChange) -> Value {
+    let (kind, group) = match change {
+        GroupChange::Added(group) => ("added", group),
+        GroupChange::Removed(group) => ("removed", group),
+        GroupChange::Modified { after, .. } => match change.is_rename() {
+            true => ("renamed", after),
+            false => ("modified", after),
+        },
+    };
+    let mut json = Value::Object(vec![
+        ("change".to_string(), kind.into()),
+        ("name".to_string(), group.name.as_str().into()),
+        ("gid".to_string(), group.gid.into()),
+    ]);
+    match change {
+        GroupChange::Modified {
+            fields,
+            added_members,
+            removed_members,
+            ..
+        } => {
+            json.set(
+                "fields",
+                Value::Array(fields.iter().map(FieldChange::to_json).collect()),
+            );
+            json.set("added_members", added_members.clone().into());
+            json.set("removed_members", removed_members.clone().into());
+        }
+        _ => json.set("entry", group.to_string().into()),
+    }
+    json
+}
+
+fn write_fields(f: &mut fmt::Formatter<'_>, fields: &[FieldChange], skip: &str) -> fmt::Result {
+    let fields: Vec<String> = fields
+        .iter()
+        .filter(|c| c.field != skip)
+        .map(|c| format!("{} {:?} -> {:?}", c.field, c.before, c.after))
+        .collect();
+    f.write_str(&fields.join(", "))
+}
+
+///Writes one line per change, starting with `+` for added, `-` for
+///removed and `~` for modified entries
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.users {
+            match change {
+                UserChange::Added(user) => {
+                    writeln!(f, "+ user {} (uid {})", user.username, user.uid)?
+                }
+                UserChange::Removed(user) => {
+                    writeln!(f, "- user {} (uid {})", user.username, user.uid)?
+                }
+                UserChange::Modified {
+                    before,
+                    after,
+                    fields,
+                } => {
+                    match change.is_rename() {
+                        true => write!(
+                            f,
+                            "~ user {} -> {} (uid {})",
+                            before.username, after.username, after.uid
+                        )?,
+                        false => write!(f, "~ user {}", after.username)?,
+                    }
+                    if fields.iter().any(|c| c.field != "username") {
+                        f.write_str(": ")?;
+                        write_fields(f, fields, "username")?;
+                    }
+                    writeln!(f)?;
+                }
+            }
+        }
+        for change in &self.groups {
+            match change {
+                GroupChange::Added(group) => {
+                    writeln!(f, "+ group {} (gid {})", group.name, group.gid)?
+                }
+                GroupChange::Removed(group) => {
+                    writeln!(f, "- group {} (gid {})", group.name, group.gid)?
+                }
+                GroupChange::Modified {
+                    before,
+                    after,
+                    fields,
+                    added_members,
+                    removed_members,
+                } => {
+                    match change.is_rename() {
+                        true => write!(f, "~ group {} -> {}", before.name, after.name)?,
+                        false => write!(f, "~ group {}", after.name)?,
+                    }
+                    write!(f, " (gid {})", after.gid)?;
+                    let changed_fields = fields.iter().any(|c| c.field != "name");
+                    let changed_members = !added_members.is_empty() || !removed_members.is_empty();
+                    if changed_fields || changed_members {
+                        f.write_str(": ")?;
+                    }
+                    write_fields(f, fields, "name")?;
+                    if changed_members {
+                        if changed_fields {
+                            f.write_str(", ")?;
+                        }
+                        f.write_str("members")?;
+                        for user in added_members {
+                            write!(f, " +{}", user)?;
+                        }
+                        for user in removed_members {
+                            write!(f, " -{}", user)?;
+                        }
+                    }
+                    writeln!(f)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `error` holds the [Error] type returned by the readers and writers, and the
+//! [ParseError] describing a line that could not be parsed.
+use crate::accounts::AccountError;
+#[cfg(feature = "ldap")]
+use crate::ldap::LdapError;
+use crate::userdb::RecordError;
+#[cfg(feature = "varlink")]
+use crate::varlink::VarlinkError;
+
+use std::fmt;
+
+///The ways a line can fail to parse
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseErrorKind {
+    ///A field is missing, the line has too few colons
+    MissingField(&'static str),
+    ///A field that must have a value is empty
+    EmptyField(&'static str),
+    ///The line has more fields than expected
+    ExtraFields,
+    ///The uid is not a valid number
+    InvalidUid(String),
+    ///The gid is not a valid number
+    InvalidGid(String),
+    ///Another numeric field is not a valid number
+    InvalidNumber {
+        ///The name of the field
+        field: &'static str,
+        ///The content of the field
+        value: String,
+    },
+    ///The line is a `+` or `-` entry of NIS compat mode, see [crate::compat]
+    CompatEntry,
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::MissingField(field) => write!(f, "missing field {}", field),
+            ParseErrorKind::EmptyField(field) => write!(f, "empty field {}", field),
+            ParseErrorKind::ExtraFields => write!(f, "too many fields"),
+            ParseErrorKind::InvalidUid(value) => write!(f, "invalid uid '{}'", value),
+            ParseErrorKind::InvalidGid(value) => write!(f, "invalid gid '{}'", value),
+            ParseErrorKind::InvalidNumber { field, value } => {
+                write!(f, "invalid {} '{}'", field, value)
+            }
+            ParseErrorKind::CompatEntry => write!(f, "NIS compat entry"),
+        }
+    }
+}
+
+///A line that could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    ///Line number, starting at 1
+    pub line: usize,
+    ///The content of the line
+    pub content: String,
+    ///What is wrong with the line
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}: '{}'", self.line, self.kind, self.content)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+///How the readers handle lines that can not be parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    ///Skip the line, and keep it as a warning
+    #[default]
+    Lenient,
+    ///Fail the whole read with [Error::Parse]
+    Strict,
+}
+
+///The error type of the crate
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    ///Reading the file failed
+    Io(std::io::Error),
+    ///A line could not be parsed, in [ParseMode::Strict]
+    Parse(ParseError),
+    ///An account operation was refused
+    Account(AccountError),
+    ///A JSON user or group record could not be read, in [ParseMode::Strict]
+    Record(RecordError),
+    ///A varlink call returned an error
+    #[cfg(feature = "varlink")]
+    Varlink(VarlinkError),
+    ///An LDAP operation failed
+    #[cfg(feature = "ldap")]
+    Ldap(LdapError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => e.fmt(f),
+            Error::Parse(e) => e.fmt(f),
+            Error::Account(e) => e.fmt(f),
+            Error::Record(e) => e.fmt(f),
+            #[cfg(feature = "varlink")]
+            Error::Varlink(e) => e.fmt(f),
+            #[cfg(feature = "ldap")]
+            Error::Ldap(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Parse(e) => Some(e),
+            Error::Account(e) => Some(e),
+            Error::Record(e) => Some(e),
+            #[cfg(feature = "varlink")]
+            Error::Varlink(e) => Some(e),
+            #[cfg(feature = "ldap")]
+            Error::Ldap(e) => Some(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<AccountError> for Error {
+    fn from(e: AccountError) -> Self {
+        Error::Account(e)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Self {
+        Error::Parse(e)
+    }
+}
+
+impl From<RecordError> for Error {
+    fn from(e: RecordError) -> Self {
+        Error::Record(e)
+    }
+}
+
+#[cfg(feature = "varlink")]
+impl From<VarlinkError> for Error {
+    fn from(e: VarlinkError) -> Self {
+        Error::Varlink(e)
+    }
+}
+
+#[cfg(feature = "ldap")]
+impl From<LdapError> for Error {
+    fn from(e: LdapError) -> Self {
+        Error::Ldap(e)
+    }
+}
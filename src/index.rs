@@ -0,0 +1,373 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `index` provides [PasswdIndex], [GroupIndex], [ShadowIndex], [GshadowIndex]
+//! and [SubIdIndex], the lookup tables the readers build each time they read
+//! their file. They can also be used directly to do many lookups against the
+//! same data.
+use crate::GroupEntry;
+use crate::GshadowEntry;
+use crate::PasswdEntry;
+use crate::ShadowEntry;
+use crate::SubIdEntry;
+use crate::SubIdOwner;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+///A list of passwd entries indexed by uid and username.
+///
+///If several entries share a uid or username, the lookup
+///returns the first one, just like the libc functions do.
+/// ```
+/// use user_lookup::index::PasswdIndex;
+/// use user_lookup::PasswdEntry;
+///
+/// let index = PasswdIndex::new(vec![
+///     PasswdEntry::parse("root:x:0:0:root:/root:/bin/bash").unwrap(),
+///     PasswdEntry::parse("user1:x:1000:100:User One:/home/user1:/bin/bash").unwrap(),
+/// ]);
+///
+/// assert_eq!(2, index.entries().len());
+/// assert_eq!(Some("user1"), index.get_by_uid(1000).map(|e| e.username.as_str()));
+/// assert_eq!(Some(0), index.get_by_username("root").map(|e| e.uid));
+/// assert_eq!(None, index.get_by_uid(1001));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PasswdIndex {
+    entries: Vec<PasswdEntry>,
+    by_uid: HashMap<u32, usize>,
+    by_username: HashMap<String, usize>,
+}
+
+impl PasswdIndex {
+    ///Builds the index for a list of entries
+    pub fn new(entries: Vec<PasswdEntry>) -> Self {
+        let mut by_uid = HashMap::with_capacity(entries.len());
+        let mut by_username = HashMap::with_capacity(entries.len());
+        for (i, e) in entries.iter().enumerate() {
+            by_uid.entry(e.uid).or_insert(i);
+            by_username.entry(e.username.clone()).or_insert(i);
+        }
+        Self {
+            entries,
+            by_uid,
+            by_username,
+        }
+    }
+
+    ///All entries, in file order
+    pub fn entries(&self) -> &Vec<PasswdEntry> {
+        &self.entries
+    }
+
+    ///Look up a PasswdEntry by uid
+    pub fn get_by_uid(&self, uid: u32) -> Option<&PasswdEntry> {
+        self.by_uid.get(&uid).map(|i| &self.entries[*i])
+    }
+
+    ///Look up a PasswdEntry by username
+    pub fn get_by_username(&self, username: &str) -> Option<&PasswdEntry> {
+        self.by_username.get(username).map(|i| &self.entries[*i])
+    }
+}
+
+impl From<Vec<PasswdEntry>> for PasswdIndex {
+    fn from(entries: Vec<PasswdEntry>) -> Self {
+        Self::new(entries)
+    }
+}
+
+///A list of group entries indexed by gid and group name.
+///
+///If several entries share a gid or name, the lookup
+///returns the first one, just like the libc functions do.
+/// ```
+/// use user_lookup::index::GroupIndex;
+/// use user_lookup::GroupEntry;
+///
+/// let index = GroupIndex::new(vec![
+///     GroupEntry::parse("wheel:x:10:user1").unwrap(),
+///     GroupEntry::parse("users:x:100:user1,user2").unwrap(),
+/// ]);
+///
+/// assert_eq!(Some("users"), index.get_by_gid(100).map(|e| e.name.as_str()));
+/// assert_eq!(Some(10), index.get_by_name("wheel").map(|e| e.gid));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct GroupIndex {
+    groups: Vec<GroupEntry>,
+    by_gid: HashMap<u32, usize>,
+    by_name: HashMap<String, usize>,
+    by_member: HashMap<String, Vec<usize>>,
+}
+
+impl GroupIndex {
+    ///Builds the index for a list of groups
+    pub fn new(groups: Vec<GroupEntry>) -> Self {
+        let mut by_gid = HashMap::with_capacity(groups.len());
+        let mut by_name = HashMap::with_capacity(groups.len());
+        let mut by_member: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, e) in groups.iter().enumerate() {
+            by_gid.entry(e.gid).or_insert(i);
+            by_name.entry(e.name.clone()).or_insert(i);
+            for user in e.users.iter().filter(|u| !u.is_empty()) {
+                by_member.entry(user.clone()).or_default().push(i);
+            }
+        }
+        Self {
+            groups,
+            by_gid,
+            by_name,
+            by_member,
+        }
+    }
+
+    ///All groups, in file order
+    pub fn groups(&self) -> &Vec<GroupEntry> {
+        &self.groups
+    }
+
+    ///Look up a GroupEntry by gid
+    pub fn get_by_gid(&self, gid: u32) -> Option<&GroupEntry> {
+        self.by_gid.get(&gid).map(|i| &self.groups[*i])
+    }
+
+    ///Look up a GroupEntry by the group name
+    pub fn get_by_name(&self, name: &str) -> Option<&GroupEntry> {
+        self.by_name.get(name).map(|i| &self.groups[*i])
+    }
+
+    ///All groups listing the user as a member
+    pub fn get_by_member<'a>(
+        &'a self,
+        username: &str,
+    ) -> impl Iterator<Item = &'a GroupEntry> + 'a {
+        self.by_member
+            .get(username)
+            .into_iter()
+            .flatten()
+            .map(|i| &self.groups[*i])
+    }
+
+    ///All groups of a user, like `getgrouplist`. The primary group `gid`
+    ///comes first, followed by every group listing the user as a member.
+    ///Groups are de-duplicated by gid.
+    /// ```
+    /// use user_lookup::index::GroupIndex;
+    /// use user_lookup::GroupEntry;
+    ///
+    /// let index = GroupIndex::new(vec![
+    ///     GroupEntry::parse("wheel:x:10:user1").unwrap(),
+    ///     GroupEntry::parse("users:x:100:user1,user2").unwrap(),
+    /// ]);
+    ///
+    /// let groups: Vec<&str> = index.get_group_list("user1", 100).iter().map(|g| g.name.as_str()).collect();
+    /// assert_eq!(vec!["users", "wheel"], groups);
+    /// assert_eq!(vec![4711, 100], index.get_gid_list("user2", 4711));
+    /// ```
+    pub fn get_group_list(&self, username: &str, gid: u32) -> Vec<&GroupEntry> {
+        let mut seen = HashSet::new();
+        self.get_by_gid(gid)
+            .into_iter()
+            .chain(self.get_by_member(username))
+            .filter(|g| seen.insert(g.gid))
+            .collect()
+    }
+
+    ///All gids of a user, like `getgrouplist`. The primary `gid` comes
+    ///first, even if there is no group with that gid, followed by the gid
+    ///of every group listing the user as a member.
+    pub fn get_gid_list(&self, username: &str, gid: u32) -> Vec<u32> {
+        let mut seen = HashSet::new();
+        std::iter::once(gid)
+            .chain(self.get_by_member(username).map(|g| g.gid))
+            .filter(|gid| seen.insert(*gid))
+            .collect()
+    }
+}
+
+impl From<Vec<GroupEntry>> for GroupIndex {
+    fn from(groups: Vec<GroupEntry>) -> Self {
+        Self::new(groups)
+    }
+}
+
+///A list of shadow entries indexed by username.
+#[derive(Debug, Clone, Default)]
+pub struct ShadowIndex {
+    entries: Vec<ShadowEntry>,
+    by_name: HashMap<String, usize>,
+}
+
+impl ShadowIndex {
+    ///Builds the index for a list of entries
+    pub fn new(entries: Vec<ShadowEntry>) -> Self {
+        let mut by_name = HashMap::with_capacity(entries.len());
+        for (i, e) in entries.iter().enumerate() {
+            by_name.entry(e.name.clone()).or_insert(i);
+        }
+        Self { entries, by_name }
+    }
+
+    ///All entries, in file order
+    pub fn entries(&self) -> &Vec<ShadowEntry> {
+        &self.entries
+    }
+
+    ///Look up a ShadowEntry by username
+    pub fn get_by_name(&self, name: &str) -> Option<&ShadowEntry> {
+        self.by_name.get(name).map(|i| &self.entries[*i])
+    }
+}
+
+impl From<Vec<ShadowEntry>> for ShadowIndex {
+    fn from(entries: Vec<ShadowEntry>) -> Self {
+        Self::new(entries)
+    }
+}
+
+///A list of gshadow entries indexed by group name.
+#[derive(Debug, Clone, Default)]
+pub struct GshadowIndex {
+    groups: Vec<GshadowEntry>,
+    by_name: HashMap<String, usize>,
+}
+
+impl GshadowIndex {
+    ///Builds the index for a list of groups
+    pub fn new(groups: Vec<GshadowEntry>) -> Self {
+        let mut by_name = HashMap::with_capacity(groups.len());
+        for (i, e) in groups.iter().enumerate() {
+            by_name.entry(e.name.clone()).or_insert(i);
+        }
+        Self { groups, by_name }
+    }
+
+    ///All groups, in file order
+    pub fn groups(&self) -> &Vec<GshadowEntry> {
+        &self.groups
+    }
+
+    ///Look up a GshadowEntry by the group name
+    pub fn get_by_name(&self, name: &str) -> Option<&GshadowEntry> {
+        self.by_name.get(name).map(|i| &self.groups[*i])
+    }
+}
+
+impl From<Vec<GshadowEntry>> for GshadowIndex {
+    fn from(groups: Vec<GshadowEntry>) -> Self {
+        Self::new(groups)
+    }
+}
+
+///The lowest id handed out by [SubIdIndex::find_free_range], the
+///default for `SUB_UID_MIN` and `SUB_GID_MIN` in `/etc/login.defs`.
+pub const SUB_ID_MIN: u32 = 100000;
+///The highest id handed out by [SubIdIndex::find_free_range], the
+///default for `SUB_UID_MAX` and `SUB_GID_MAX` in `/etc/login.defs`.
+pub const SUB_ID_MAX: u32 = 600100000;
+
+///A list of subordinate id ranges, from `/etc/subuid` or `/etc/subgid`.
+/// ```
+/// use user_lookup::index::SubIdIndex;
+/// use user_lookup::SubIdEntry;
+///
+/// let index = SubIdIndex::new(vec![
+///     SubIdEntry::parse("user1:100000:65536").unwrap(),
+///     SubIdEntry::parse("user2:231072:65536").unwrap(),
+/// ]);
+///
+/// assert_eq!(Some(100000), index.get_owner_of(100000).map(|e| e.start));
+/// assert_eq!(None, index.get_owner_of(200000));
+/// assert_eq!(Some(165536), index.find_free_range(65536));
+/// assert_eq!(Some(296608), index.find_free_range(65537));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SubIdIndex {
+    entries: Vec<SubIdEntry>,
+    by_start: Vec<usize>,
+}
+
+impl SubIdIndex {
+    ///Builds the index for a list of ranges
+    pub fn new(entries: Vec<SubIdEntry>) -> Self {
+        let mut by_start: Vec<usize> = (0..entries.len()).collect();
+        by_start.sort_by_key(|i| entries[*i].start);
+        Self { entries, by_start }
+    }
+
+    ///All ranges, in file order
+    pub fn entries(&self) -> &Vec<SubIdEntry> {
+        &self.entries
+    }
+
+    ///All ranges owned by a user, matching both the
+    ///username and the uid
+    pub fn get_by_user<'a>(
+        &'a self,
+        user: &'a PasswdEntry,
+    ) -> impl Iterator<Item = &'a SubIdEntry> + 'a {
+        self.entries.iter().filter(move |e| e.is_owned_by(user))
+    }
+
+    ///All ranges owned by an owner, as written in the file
+    pub fn get_by_owner<'a>(
+        &'a self,
+        owner: &'a SubIdOwner,
+    ) -> impl Iterator<Item = &'a SubIdEntry> + 'a {
+        self.entries.iter().filter(move |e| e.owner == *owner)
+    }
+
+    ///Look up the range containing an id
+    pub fn get_owner_of(&self, id: u32) -> Option<&SubIdEntry> {
+        let i = self
+            .by_start
+            .partition_point(|i| self.entries[*i].start <= id);
+        //Ranges may overlap, so every range starting before id is a candidate
+        self.by_start[..i]
+            .iter()
+            .rev()
+            .map(|i| &self.entries[*i])
+            .find(|e| e.contains(id))
+    }
+
+    ///Finds the lowest start of `count` consecutive ids between [SUB_ID_MIN]
+    ///and [SUB_ID_MAX] that do not overlap any range.
+    pub fn find_free_range(&self, count: u32) -> Option<u32> {
+        self.find_free_range_in(count, SUB_ID_MIN, SUB_ID_MAX)
+    }
+
+    ///Finds the lowest start of `count` consecutive ids between `min` and `max`,
+    ///inclusive, that do not overlap any range.
+    pub fn find_free_range_in(&self, count: u32, min: u32, max: u32) -> Option<u32> {
+        if count == 0 {
+            return None;
+        }
+        let mut candidate = min as u64;
+        for e in self.by_start.iter().map(|i| &self.entries[*i]) {
+            if e.count == 0 || e.end() <= candidate {
+                continue;
+            }
+            if e.start as u64 >= candidate + count as u64 {
+                break;
+            }
+            candidate = e.end();
+        }
+        match candidate + count as u64 - 1 <= max as u64 {
+            true => u32::try_from(candidate).ok(),
+            false => None,
+        }
+    }
+}
+
+impl From<Vec<SubIdEntry>> for SubIdIndex {
+    fn from(entries: Vec<SubIdEntry>) -> Self {
+        Self::new(entries)
+    }
+}
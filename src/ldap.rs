@@ -0,0 +1,1330 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `ldap` looks up users and groups in an LDAP directory using the RFC 2307
+//! schema, like `nslcd` does. A `posixAccount` becomes a [PasswdEntry], and a
+//! `posixGroup` becomes a [GroupEntry]. The members of a group are its
+//! `memberUid` values, followed by the users of its `member` DNs as used by
+//! rfc2307bis, where the members of nested groups are included as well.
+//!
+//! The [PasswdReader] and [GroupReader] search the whole subtree of the base
+//! DNs of the [LdapConfig], and keep the entries for the cache time. Entries
+//! that can not be mapped, like accounts without a `uidNumber`, are skipped.
+//!
+//! The readers search a [Directory]. The [Connection] talks LDAPv3 over TCP,
+//! with a simple bind and paged results, but without TLS. The
+//! [MemoryDirectory] holds the entries in memory instead, for tests.
+//!
+//! **Warning:** as there is no TLS, the password of
+//! [LdapConfig::with_bind] is sent in clear text. Only bind to a server on
+//! the same host, or through a tunnel like `stunnel` or an SSH forward.
+//!
+//! This module requires the `ldap` feature.
+//!
+//! ```
+//! use user_lookup::ldap::{GroupReader, LdapConfig, LdapEntry, MemoryDirectory};
+//! use std::time::Duration;
+//!
+//! let directory = MemoryDirectory::new(vec![
+//!     LdapEntry::new("uid=jdoe,ou=people,dc=example,dc=org")
+//!         .with("objectClass", "posixAccount")
+//!         .with("uid", "jdoe")
+//!         .with("uidNumber", "1000")
+//!         .with("gidNumber", "100"),
+//!     LdapEntry::new("cn=staff,ou=groups,dc=example,dc=org")
+//!         .with("objectClass", "posixGroup")
+//!         .with("cn", "staff")
+//!         .with("gidNumber", "100")
+//!         .with("member", "uid=jdoe,ou=people,dc=example,dc=org"),
+//! ]);
+//! let config = LdapConfig::new("ldap://localhost", "dc=example,dc=org");
+//! let mut reader = GroupReader::with_directory(directory, config, Duration::new(0, 0));
+//! let staff = reader.get_by_gid(100).unwrap().unwrap();
+//! assert_eq!(vec!["jdoe"], staff.users);
+//! ```
+use crate::ber;
+use crate::cache::CachePolicy;
+use crate::index::GroupIndex;
+use crate::index::PasswdIndex;
+use crate::source::GroupSource;
+use crate::source::UserSource;
+use crate::Error;
+use crate::GroupEntry;
+use crate::PasswdEntry;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::Write;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+///How long to wait for the server by default
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+///The number of entries per page by default
+const PAGE_SIZE: u32 = 500;
+
+///The OID of the simple paged results control, RFC 2696
+const PAGED_RESULTS: &str = "1.2.840.113556.1.4.319";
+
+///The result code when the base DN does not exist
+const NO_SUCH_OBJECT: u32 = 32;
+
+const USER_ATTRIBUTES: &[&str] = &[
+    "uid",
+    "uidNumber",
+    "gidNumber",
+    "gecos",
+    "cn",
+    "homeDirectory",
+    "loginShell",
+];
+
+const GROUP_ATTRIBUTES: &[&str] = &["cn", "gidNumber", "memberUid", "member"];
+
+///An error of the LDAP backend
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LdapError {
+    ///The server returned a result code other than success
+    Result {
+        ///The LDAP result code, like 49 for invalid credentials
+        code: u32,
+        ///The diagnostic message of the server
+        message: String,
+    },
+    ///A search filter could not be parsed
+    InvalidFilter(String),
+}
+
+impl fmt::Display for LdapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LdapError::Result { code, message } if message.is_empty() => {
+                write!(f, "LDAP result code {}", code)
+            }
+            LdapError::Result { code, message } => {
+                write!(f, "LDAP result code {}: {}", code, message)
+            }
+            LdapError::InvalidFilter(filter) => write!(f, "invalid LDAP filter {}", filter),
+        }
+    }
+}
+
+impl std::error::Error for LdapError {}
+
+fn invalid_data(message: &str) -> Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string()).into()
+}
+
+///An entry of the directory, with the values of its attributes. Attribute
+///names are compared without regard to case.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LdapEntry {
+    ///The distinguished name of the entry
+    pub dn: String,
+    ///The attributes and their values
+    pub attributes: Vec<(String, Vec<String>)>,
+}
+
+impl LdapEntry {
+    ///Creates an entry without attributes
+    pub fn new(dn: &str) -> Self {
+        Self {
+            dn: dn.to_string(),
+            attributes: vec![],
+        }
+    }
+
+    ///Adds a value to an attribute, returning the entry
+    pub fn with(mut self, attribute: &str, value: &str) -> Self {
+        self.add(attribute, value);
+        self
+    }
+
+    ///Adds a value to an attribute
+    pub fn add(&mut self, attribute: &str, value: &str) {
+        match self
+            .attributes
+            .iter_mut()
+            .find(|(name, _)| name.eq_ignore_ascii_case(attribute))
+        {
+            Some((_, values)) => values.push(value.to_string()),
+            None => self
+                .attributes
+                .push((attribute.to_string(), vec![value.to_string()])),
+        }
+    }
+
+    ///All values of an attribute
+    pub fn get(&self, attribute: &str) -> &[String] {
+        self.attributes
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(attribute))
+            .map(|(_, values)| values.as_slice())
+            .unwrap_or_default()
+    }
+
+    ///The first value of an attribute
+    pub fn first(&self, attribute: &str) -> Option<&str> {
+        self.get(attribute).first().map(String::as_str)
+    }
+}
+
+///A search filter, as described in RFC 4515
+/// ```
+/// use user_lookup::ldap::{Filter, LdapEntry};
+///
+/// let filter = Filter::parse("(&(objectClass=posixAccount)(uid=j*))").unwrap();
+/// let entry = LdapEntry::new("uid=jdoe,dc=example,dc=org")
+///     .with("objectClass", "posixAccount")
+///     .with("uid", "jdoe");
+/// assert!(filter.matches(&entry));
+/// assert_eq!("(&(objectClass=posixAccount)(uid=j*))", filter.to_string());
+/// assert!(Filter::parse("(uid=jdoe").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    ///All of the filters match
+    And(Vec<Filter>),
+    ///Any of the filters match
+    Or(Vec<Filter>),
+    ///The filter does not match
+    Not(Box<Filter>),
+    ///A value of the attribute is equal to the value
+    Equal(String, String),
+    ///A value of the attribute matches the substrings
+    Substrings {
+        ///The attribute
+        attribute: String,
+        ///What the value starts with
+        initial: Option<String>,
+        ///What the value contains, in order
+        any: Vec<String>,
+        ///What the value ends with
+        last: Option<String>,
+    },
+    ///A value of the attribute is greater than or equal to the value
+    GreaterOrEqual(String, String),
+    ///A value of the attribute is less than or equal to the value
+    LessOrEqual(String, String),
+    ///The attribute has a value
+    Present(String),
+    ///A value of the attribute is approximately equal to the value
+    Approx(String, String),
+}
+
+impl Filter {
+    ///Parses a filter. The outer parentheses may be left out.
+    pub fn parse(s: &str) -> Result<Filter, LdapError> {
+        let trimmed = s.trim();
+        let wrapped;
+        let text = if trimmed.starts_with('(') {
+            trimmed
+        } else {
+            wrapped = format!("({})", trimmed);
+            &wrapped
+        };
+        let mut parser = FilterParser { s: text, pos: 0 };
+        match parser.filter(0) {
+            Some(filter) if parser.pos == text.len() => Ok(filter),
+            _ => Err(LdapError::InvalidFilter(s.to_string())),
+        }
+    }
+
+    ///Whether the entry matches the filter. Values are compared without
+    ///regard to case, and numerically when both are numbers.
+    pub fn matches(&self, entry: &LdapEntry) -> bool {
+        let any = |attribute: &str, test: &dyn Fn(&str) -> bool| {
+            entry.get(attribute).iter().any(|v| test(v))
+        };
+        match self {
+            Filter::And(filters) => filters.iter().all(|f| f.matches(entry)),
+            Filter::Or(filters) => filters.iter().any(|f| f.matches(entry)),
+            Filter::Not(filter) => !filter.matches(entry),
+            Filter::Equal(attribute, value) | Filter::Approx(attribute, value) => {
+                any(attribute, &|v| v.eq_ignore_ascii_case(value))
+            }
+            Filter::Substrings {
+                attribute,
+                initial,
+                any: middle,
+                last,
+            } => any(attribute, &|v| {
+                substrings_match(&v.to_lowercase(), initial, middle, last)
+            }),
+            Filter::GreaterOrEqual(attribute, value) => any(attribute, &|v| {
+                compare(v, value) != std::cmp::Ordering::Less
+            }),
+            Filter::LessOrEqual(attribute, value) => any(attribute, &|v| {
+                compare(v, value) != std::cmp::Ordering::Greater
+            }),
+            Filter::Present(attribute) => !entry.get(attribute).is_empty(),
+        }
+    }
+
+    ///Encodes the filter for a search request
+    fn encode(&self) -> Vec<u8> {
+        let pair = |tag, attribute: &str, value: &str| {
+            ber::constructed(
+                tag,
+                &[
+                    ber::tlv(ber::OCTET_STRING, attribute.as_bytes()),
+                    ber::tlv(ber::OCTET_STRING, value.as_bytes()),
+                ],
+            )
+        };
+        match self {
+            Filter::And(filters) => ber::constructed(
+                0xa0,
+                &filters.iter().map(Filter::encode).collect::<Vec<_>>(),
+            ),
+            Filter::Or(filters) => ber::constructed(
+                0xa1,
+                &filters.iter().map(Filter::encode).collect::<Vec<_>>(),
+            ),
+            Filter::Not(filter) => ber::tlv(0xa2, &filter.encode()),
+            Filter::Equal(attribute, value) => pair(0xa3, attribute, value),
+            Filter::Substrings {
+                attribute,
+                initial,
+                any,
+                last,
+            } => {
+                let mut parts = vec![];
+                parts.extend(initial.iter().map(|s| ber::tlv(0x80, s.as_bytes())));
+                parts.extend(any.iter().map(|s| ber::tlv(0x81, s.as_bytes())));
+                parts.extend(last.iter().map(|s| ber::tlv(0x82, s.as_bytes())));
+                ber::constructed(
+                    0xa4,
+                    &[
+                        ber::tlv(ber::OCTET_STRING, attribute.as_bytes()),
+                        ber::constructed(ber::SEQUENCE, &parts),
+                    ],
+                )
+            }
+            Filter::GreaterOrEqual(attribute, value) => pair(0xa5, attribute, value),
+            Filter::LessOrEqual(attribute, value) => pair(0xa6, attribute, value),
+            Filter::Present(attribute) => ber::tlv(0x87, attribute.as_bytes()),
+            Filter::Approx(attribute, value) => pair(0xa8, attribute, value),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |f: &mut fmt::Formatter<'_>, op, filters: &[Filter]| {
+            write!(f, "({}", op)?;
+            for filter in filters {
+                filter.fmt(f)?;
+            }
+            write!(f, ")")
+        };
+        match self {
+            Filter::And(filters) => list(f, '&', filters),
+            Filter::Or(filters) => list(f, '|', filters),
+            Filter::Not(filter) => write!(f, "(!{})", filter),
+            Filter::Equal(a, v) => write!(f, "({}={})", a, escape(v)),
+            Filter::Substrings {
+                attribute,
+                initial,
+                any,
+                last,
+            } => {
+                write!(f, "({}=", attribute)?;
+                if let Some(initial) = initial {
+                    write!(f, "{}", escape(initial))?;
+                }
+                for value in any {
+                    write!(f, "*{}", escape(value))?;
+                }
+                write!(f, "*")?;
+                if let Some(last) = last {
+                    write!(f, "{}", escape(last))?;
+                }
+                write!(f, ")")
+            }
+            Filter::GreaterOrEqual(a, v) => write!(f, "({}>={})", a, escape(v)),
+            Filter::LessOrEqual(a, v) => write!(f, "({}<={})", a, escape(v)),
+            Filter::Present(a) => write!(f, "({}=*)", a),
+            Filter::Approx(a, v) => write!(f, "({}~={})", a, escape(v)),
+        }
+    }
+}
+
+///Escapes the characters of a value that are special in a filter
+fn escape(value: &str) -> String {
+    let mut out = String::new();
+    for c in value.chars() {
+        match c {
+            '*' | '(' | ')' | '\\' | '\0' => out.push_str(&format!("\\{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+///Reverses [escape], returning `None` for an invalid escape
+fn unescape(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut out = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' {
+            let hex = value.get(i + 1..i + 3)?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn compare(a: &str, b: &str) -> std::cmp::Ordering {
+    match (a.parse::<i64>(), b.parse::<i64>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.to_lowercase().cmp(&b.to_lowercase()),
+    }
+}
+
+fn substrings_match(
+    value: &str,
+    initial: &Option<String>,
+    any: &[String],
+    last: &Option<String>,
+) -> bool {
+    let mut rest = value;
+    if let Some(initial) = initial {
+        match rest.strip_prefix(initial.to_lowercase().as_str()) {
+            Some(after) => rest = after,
+            None => return false,
+        }
+    }
+    for part in any {
+        let part = part.to_lowercase();
+        match rest.find(part.as_str()) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    match last {
+        Some(last) => rest.ends_with(last.to_lowercase().as_str()),
+        None => true,
+    }
+}
+
+///A recursive descent parser of filters
+///Nesting deeper than this is refused, to not overflow the stack
+const MAX_DEPTH: usize = 128;
+
+struct FilterParser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl FilterParser<'_> {
+    fn eat(&mut self, c: char) -> Option<()> {
+        if self.s[self.pos..].starts_with(c) {
+            self.pos += c.len_utf8();
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn filter(&mut self, depth: usize) -> Option<Filter> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        self.eat('(')?;
+        let filter = if self.eat('&').is_some() {
+            Filter::And(self.list(depth + 1)?)
+        } else if self.eat('|').is_some() {
+            Filter::Or(self.list(depth + 1)?)
+        } else if self.eat('!').is_some() {
+            Filter::Not(Box::new(self.filter(depth + 1)?))
+        } else {
+            self.item()?
+        };
+        self.eat(')')?;
+        Some(filter)
+    }
+
+    fn list(&mut self, depth: usize) -> Option<Vec<Filter>> {
+        let mut filters = vec![];
+        while self.s[self.pos..].starts_with('(') {
+            filters.push(self.filter(depth)?);
+        }
+        Some(filters)
+    }
+
+    fn item(&mut self) -> Option<Filter> {
+        let rest = &self.s[self.pos..];
+        let item = &rest[..rest.find(')')?];
+        self.pos += item.len();
+        let (attribute, value) = item.split_once('=')?;
+        let (attribute, op) = match attribute.char_indices().last() {
+            Some((i, op @ ('>' | '<' | '~'))) => (&attribute[..i], op),
+            _ => (attribute, '='),
+        };
+        let valid = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | ';' | '.');
+        if attribute.is_empty() || !attribute.chars().all(valid) {
+            return None;
+        }
+        let attribute = attribute.to_string();
+        match op {
+            '>' => return Some(Filter::GreaterOrEqual(attribute, unescape(value)?)),
+            '<' => return Some(Filter::LessOrEqual(attribute, unescape(value)?)),
+            '~' => return Some(Filter::Approx(attribute, unescape(value)?)),
+            _ if !value.contains('*') => return Some(Filter::Equal(attribute, unescape(value)?)),
+            _ if value == "*" => return Some(Filter::Present(attribute)),
+            _ => {}
+        }
+        let parts: Vec<&str> = value.split('*').collect();
+        let part = |s: &str| -> Option<Option<String>> {
+            if s.is_empty() {
+                Some(None)
+            } else {
+                unescape(s).map(Some)
+            }
+        };
+        let mut any = vec![];
+        for middle in &parts[1..parts.len() - 1] {
+            if let Some(middle) = part(middle)? {
+                any.push(middle);
+            }
+        }
+        Some(Filter::Substrings {
+            attribute,
+            initial: part(parts[0])?,
+            any,
+            last: part(parts[parts.len() - 1])?,
+        })
+    }
+}
+
+///Where the readers search for entries
+pub trait Directory {
+    ///Searches the subtree of `base` for the entries matching the filter,
+    ///returning the attributes. All attributes are returned when
+    ///`attributes` is empty.
+    fn search(
+        &mut self,
+        base: &str,
+        filter: &Filter,
+        attributes: &[&str],
+    ) -> Result<Vec<LdapEntry>, Error>;
+}
+
+///Where and how to search for users and groups
+#[derive(Clone, PartialEq, Eq)]
+pub struct LdapConfig {
+    url: String,
+    bind: Option<(String, String)>,
+    user_base: String,
+    group_base: String,
+    user_filter: Filter,
+    group_filter: Filter,
+    timeout: Duration,
+    page_size: u32,
+}
+
+impl LdapConfig {
+    ///Creates a config for the server at an `ldap://host:port` URL, searching
+    ///for users and groups below `base_dn` without binding.
+    pub fn new(url: &str, base_dn: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            bind: None,
+            user_base: base_dn.to_string(),
+            group_base: base_dn.to_string(),
+            user_filter: Filter::Equal("objectClass".into(), "posixAccount".into()),
+            group_filter: Filter::Equal("objectClass".into(), "posixGroup".into()),
+            timeout: TIMEOUT,
+            page_size: PAGE_SIZE,
+        }
+    }
+
+    ///Binds with a DN and password before searching
+    ///
+    ///**Warning:** the simple bind sends the password in clear text, as the
+    ///connection does not use TLS or StartTLS. Anyone on the network path can
+    ///read it, so only use this with a server on a loopback address or
+    ///behind a local tunnel.
+    pub fn with_bind(mut self, dn: &str, password: &str) -> Self {
+        self.bind = Some((dn.to_string(), password.to_string()));
+        self
+    }
+
+    ///Sets the base DN of the users, like `ou=people,dc=example,dc=org`
+    pub fn with_user_base(mut self, base_dn: &str) -> Self {
+        self.user_base = base_dn.to_string();
+        self
+    }
+
+    ///Sets the base DN of the groups, like `ou=groups,dc=example,dc=org`
+    pub fn with_group_base(mut self, base_dn: &str) -> Self {
+        self.group_base = base_dn.to_string();
+        self
+    }
+
+    ///Sets the filter of the users, `(objectClass=posixAccount)` by default
+    pub fn with_user_filter(mut self, filter: Filter) -> Self {
+        self.user_filter = filter;
+        self
+    }
+
+    ///Sets the filter of the groups, `(objectClass=posixGroup)` by default
+    pub fn with_group_filter(mut self, filter: Filter) -> Self {
+        self.group_filter = filter;
+        self
+    }
+
+    ///Sets how long to wait for the server
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    ///Sets the number of entries to ask for per page, or 0 to not use
+    ///paged results
+    pub fn with_page_size(mut self, page_size: u32) -> Self {
+        self.page_size = page_size;
+        self
+    }
+}
+
+impl fmt::Debug for LdapConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LdapConfig")
+            .field("url", &self.url)
+            .field("bind_dn", &self.bind.as_ref().map(|(dn, _)| dn))
+            .field("user_base", &self.user_base)
+            .field("group_base", &self.group_base)
+            .field("user_filter", &self.user_filter.to_string())
+            .field("group_filter", &self.group_filter.to_string())
+            .field("timeout", &self.timeout)
+            .field("page_size", &self.page_size)
+            .finish()
+    }
+}
+
+///Searches an LDAP server over TCP. Every search uses a new connection.
+#[derive(Debug, Clone)]
+pub struct Connection {
+    config: LdapConfig,
+}
+
+impl Connection {
+    ///Creates a connection to the server of the config
+    pub fn new(config: LdapConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl Directory for Connection {
+    fn search(
+        &mut self,
+        base: &str,
+        filter: &Filter,
+        attributes: &[&str],
+    ) -> Result<Vec<LdapEntry>, Error> {
+        let mut session = Session::open(&self.config)?;
+        if let Some((dn, password)) = &self.config.bind {
+            session.bind(dn, password)?;
+        }
+        let entries = session.search(base, filter, attributes, self.config.page_size)?;
+        session.unbind();
+        Ok(entries)
+    }
+}
+
+///The `host:port` of an `ldap://` URL
+fn address(url: &str) -> Result<String, Error> {
+    let rest = url.strip_prefix("ldap://").ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("unsupported LDAP URL {}", url),
+        )
+    })?;
+    let host = rest.split('/').next().unwrap_or_default();
+    Ok(match host {
+        "" => "localhost:389".to_string(),
+        host if host.ends_with(']') || !host.contains(':') => format!("{}:389", host),
+        host => host.to_string(),
+    })
+}
+
+///One connection to the server
+struct Session {
+    stream: TcpStream,
+    next_id: i64,
+}
+
+impl Session {
+    fn open(config: &LdapConfig) -> Result<Self, Error> {
+        let mut last = None;
+        for addr in address(&config.url)?.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, config.timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(config.timeout))?;
+                    stream.set_write_timeout(Some(config.timeout))?;
+                    return Ok(Self { stream, next_id: 1 });
+                }
+                Err(e) => last = Some(e),
+            }
+        }
+        Err(last
+            .unwrap_or_else(|| std::io::ErrorKind::AddrNotAvailable.into())
+            .into())
+    }
+
+    ///Sends a message, returning its ID
+    fn send(&mut self, op: Vec<u8>, controls: Option<Vec<u8>>) -> Result<i64, Error> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut parts = vec![ber::integer(ber::INTEGER, id), op];
+        parts.extend(controls);
+        self.stream
+            .write_all(&ber::constructed(ber::SEQUENCE, &parts))?;
+        Ok(id)
+    }
+
+    ///Receives the next reply to a message, returning the tag and content
+    ///of the operation and the content of the controls
+    fn receive(&mut self, id: i64) -> Result<(u8, Vec<u8>, Vec<u8>), Error> {
+        loop {
+            let (tag, content) = ber::read_element(&mut self.stream)?;
+            if tag != ber::SEQUENCE {
+                return Err(invalid_data("reply is not an LDAP message"));
+            }
+            let mut reader = ber::Reader::new(&content);
+            let reply_id = reader.integer(ber::INTEGER)?;
+            let (op_tag, op) = reader.read()?;
+            let controls = match reader.peek_tag() {
+                Some(0xa0) => reader.read()?.1.to_vec(),
+                _ => vec![],
+            };
+            if reply_id == 0 {
+                //A notice of disconnection
+                let (code, message) = result(op).unwrap_or((0, String::new()));
+                return Err(Error::Ldap(LdapError::Result { code, message }));
+            }
+            if reply_id == id {
+                return Ok((op_tag, op.to_vec(), controls));
+            }
+        }
+    }
+
+    fn bind(&mut self, dn: &str, password: &str) -> Result<(), Error> {
+        let op = ber::constructed(
+            0x60,
+            &[
+                ber::integer(ber::INTEGER, 3),
+                ber::tlv(ber::OCTET_STRING, dn.as_bytes()),
+                ber::tlv(0x80, password.as_bytes()),
+            ],
+        );
+        let id = self.send(op, None)?;
+        match self.receive(id)? {
+            (0x61, op, _) => match result(&op)? {
+                (0, _) => Ok(()),
+                (code, message) => Err(Error::Ldap(LdapError::Result { code, message })),
+            },
+            _ => Err(invalid_data("unexpected reply to bind")),
+        }
+    }
+
+    fn search(
+        &mut self,
+        base: &str,
+        filter: &Filter,
+        attributes: &[&str],
+        page_size: u32,
+    ) -> Result<Vec<LdapEntry>, Error> {
+        let attributes: Vec<Vec<u8>> = attributes
+            .iter()
+            .map(|a| ber::tlv(ber::OCTET_STRING, a.as_bytes()))
+            .collect();
+        let mut entries = vec![];
+        let mut cookie = vec![];
+        loop {
+            let op = ber::constructed(
+                0x63,
+                &[
+                    ber::tlv(ber::OCTET_STRING, base.as_bytes()),
+                    //The whole subtree, never dereferencing aliases
+                    ber::integer(ber::ENUMERATED, 2),
+                    ber::integer(ber::ENUMERATED, 0),
+                    ber::integer(ber::INTEGER, 0),
+                    ber::integer(ber::INTEGER, 0),
+                    ber::tlv(ber::BOOLEAN, &[0]),
+                    filter.encode(),
+                    ber::constructed(ber::SEQUENCE, &attributes),
+                ],
+            );
+            let controls = (page_size > 0).then(|| paged_results(page_size, &cookie));
+            let id = self.send(op, controls)?;
+            loop {
+                match self.receive(id)? {
+                    (0x64, op, _) => entries.push(entry(&op)?),
+                    //References to other servers are not followed
+                    (0x73, _, _) => {}
+                    (0x65, op, controls) => {
+                        match result(&op)? {
+                            (0, _) => {}
+                            (NO_SUCH_OBJECT, _) => return Ok(entries),
+                            (code, message) => {
+                                return Err(Error::Ldap(LdapError::Result { code, message }))
+                            }
+                        }
+                        cookie = paged_cookie(&controls)?;
+                        break;
+                    }
+                    _ => return Err(invalid_data("unexpected reply to search")),
+                }
+            }
+            if page_size == 0 || cookie.is_empty() {
+                return Ok(entries);
+            }
+        }
+    }
+
+    fn unbind(mut self) {
+        //The server closes the connection without replying
+        let _ = self.send(ber::tlv(0x42, &[]), None);
+    }
+}
+
+///The result code and diagnostic message of an LDAPResult
+fn result(op: &[u8]) -> Result<(u32, String), Error> {
+    let mut reader = ber::Reader::new(op);
+    let code = reader.integer(ber::ENUMERATED)?;
+    reader.expect(ber::OCTET_STRING)?;
+    let message = reader.expect(ber::OCTET_STRING)?;
+    let code = u32::try_from(code).map_err(|_| invalid_data("invalid result code"))?;
+    Ok((code, String::from_utf8_lossy(message).into_owned()))
+}
+
+///Decodes a SearchResultEntry
+fn entry(op: &[u8]) -> Result<LdapEntry, Error> {
+    let mut reader = ber::Reader::new(op);
+    let dn = reader.expect(ber::OCTET_STRING)?;
+    let mut entry = LdapEntry::new(&String::from_utf8_lossy(dn));
+    let mut attributes = ber::Reader::new(reader.expect(ber::SEQUENCE)?);
+    while !attributes.is_empty() {
+        let mut attribute = ber::Reader::new(attributes.expect(ber::SEQUENCE)?);
+        let name = String::from_utf8_lossy(attribute.expect(ber::OCTET_STRING)?).into_owned();
+        let mut values = ber::Reader::new(attribute.expect(ber::SET)?);
+        let mut list = vec![];
+        while !values.is_empty() {
+            list.push(String::from_utf8_lossy(values.expect(ber::OCTET_STRING)?).into_owned());
+        }
+        entry.attributes.push((name, list));
+    }
+    Ok(entry)
+}
+
+///The controls asking for a page of results
+fn paged_results(page_size: u32, cookie: &[u8]) -> Vec<u8> {
+    let value = ber::constructed(
+        ber::SEQUENCE,
+        &[
+            ber::integer(ber::INTEGER, page_size.into()),
+            ber::tlv(ber::OCTET_STRING, cookie),
+        ],
+    );
+    ber::constructed(
+        0xa0,
+        &[ber::constructed(
+            ber::SEQUENCE,
+            &[
+                ber::tlv(ber::OCTET_STRING, PAGED_RESULTS.as_bytes()),
+                ber::tlv(ber::OCTET_STRING, &value),
+            ],
+        )],
+    )
+}
+
+///The cookie of the paged results control of a reply, empty on the last
+///page or if the server does not page
+fn paged_cookie(controls: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut controls = ber::Reader::new(controls);
+    while !controls.is_empty() {
+        let mut control = ber::Reader::new(controls.expect(ber::SEQUENCE)?);
+        let oid = control.expect(ber::OCTET_STRING)?;
+        if control.peek_tag() == Some(ber::BOOLEAN) {
+            control.read()?;
+        }
+        if oid != PAGED_RESULTS.as_bytes() || control.is_empty() {
+            continue;
+        }
+        let mut value = ber::Reader::new(control.expect(ber::OCTET_STRING)?);
+        let mut value = ber::Reader::new(value.expect(ber::SEQUENCE)?);
+        value.integer(ber::INTEGER)?;
+        return Ok(value.expect(ber::OCTET_STRING)?.to_vec());
+    }
+    Ok(vec![])
+}
+
+///A directory of entries in memory. Filters are evaluated with
+///[Filter::matches].
+#[derive(Debug, Clone, Default)]
+pub struct MemoryDirectory {
+    entries: Vec<LdapEntry>,
+}
+
+impl MemoryDirectory {
+    ///Creates a directory of the entries
+    pub fn new(entries: Vec<LdapEntry>) -> Self {
+        Self { entries }
+    }
+
+    ///Adds an entry
+    pub fn add(&mut self, entry: LdapEntry) {
+        self.entries.push(entry);
+    }
+}
+
+impl Directory for MemoryDirectory {
+    fn search(
+        &mut self,
+        base: &str,
+        filter: &Filter,
+        attributes: &[&str],
+    ) -> Result<Vec<LdapEntry>, Error> {
+        let base = normalize_dn(base);
+        Ok(self
+            .entries
+            .iter()
+            .filter(|e| {
+                let dn = normalize_dn(&e.dn);
+                base.is_empty() || dn == base || dn.ends_with(&format!(",{}", base))
+            })
+            .filter(|e| filter.matches(e))
+            .map(|e| LdapEntry {
+                dn: e.dn.clone(),
+                attributes: e
+                    .attributes
+                    .iter()
+                    .filter(|(name, _)| {
+                        attributes.is_empty()
+                            || attributes.iter().any(|a| a.eq_ignore_ascii_case(name))
+                    })
+                    .cloned()
+                    .collect(),
+            })
+            .collect())
+    }
+}
+
+///A DN in lower case without spaces around the separators, to compare DNs
+fn normalize_dn(dn: &str) -> String {
+    dn.split(',')
+        .map(|rdn| rdn.split('=').map(str::trim).collect::<Vec<_>>().join("="))
+        .collect::<Vec<_>>()
+        .join(",")
+        .to_lowercase()
+}
+
+///The value of the first RDN of a DN, if its attribute is `attribute`
+fn rdn_value<'a>(dn: &'a str, attribute: &str) -> Option<&'a str> {
+    let (name, value) = dn.split(',').next()?.split_once('=')?;
+    name.trim()
+        .eq_ignore_ascii_case(attribute)
+        .then(|| value.trim())
+}
+
+fn number(entry: &LdapEntry, attribute: &str) -> Option<u32> {
+    entry.first(attribute)?.trim().parse().ok()
+}
+
+///Whether a value can be a field of a passwd or group line
+fn valid_field(value: &str) -> bool {
+    !value.contains([':', '\n'])
+}
+
+///Maps a posixAccount to a PasswdEntry. The name in the DN is used if the
+///account has several `uid` values.
+fn posix_account(entry: &LdapEntry) -> Option<PasswdEntry> {
+    let names = entry.get("uid");
+    let username = rdn_value(&entry.dn, "uid")
+        .and_then(|rdn| names.iter().find(|n| n.as_str() == rdn))
+        .or_else(|| names.first())?
+        .clone();
+    let user = PasswdEntry {
+        username,
+        passwd: "x".to_string(),
+        uid: number(entry, "uidNumber")?,
+        gid: number(entry, "gidNumber")?,
+        gecos: entry
+            .first("gecos")
+            .or_else(|| entry.first("cn"))
+            .unwrap_or_default()
+            .to_string(),
+        home_dir: entry.first("homeDirectory").unwrap_or_default().to_string(),
+        shell: entry.first("loginShell").unwrap_or_default().to_string(),
+    };
+    let fields = [&user.username, &user.gecos, &user.home_dir, &user.shell];
+    (!user.username.is_empty() && fields.iter().all(|f| valid_field(f))).then_some(user)
+}
+
+///Maps posixGroups to GroupEntries, resolving member DNs to user names
+///with the users, and to the members of nested groups.
+fn posix_groups(groups: &[LdapEntry], users: &[LdapEntry]) -> Vec<GroupEntry> {
+    let users: HashMap<String, PasswdEntry> = users
+        .iter()
+        .filter_map(|u| Some((normalize_dn(&u.dn), posix_account(u)?)))
+        .collect();
+    let by_dn: HashMap<String, &LdapEntry> =
+        groups.iter().map(|g| (normalize_dn(&g.dn), g)).collect();
+    groups
+        .iter()
+        .filter_map(|group| {
+            let name = group.first("cn")?;
+            let mut seen = HashSet::new();
+            let mut members = vec![];
+            add_members(group, &users, &by_dn, &mut seen, &mut members);
+            let fields = std::iter::once(name).chain(members.iter().map(String::as_str));
+            if !fields.clone().all(valid_field) || members.iter().any(|m| m.contains(',')) {
+                return None;
+            }
+            Some(GroupEntry {
+                name: name.to_string(),
+                passwd: "x".to_string(),
+                gid: number(group, "gidNumber")?,
+                users: members,
+            })
+        })
+        .collect()
+}
+
+fn add_members(
+    group: &LdapEntry,
+    users: &HashMap<String, PasswdEntry>,
+    groups: &HashMap<String, &LdapEntry>,
+    seen: &mut HashSet<String>,
+    members: &mut Vec<String>,
+) {
+    seen.insert(normalize_dn(&group.dn));
+    let mut add = |name: &str| {
+        if !members.iter().any(|m| m == name) {
+            members.push(name.to_string());
+        }
+    };
+    for name in group.get("memberUid") {
+        add(name);
+    }
+    let mut nested = vec![];
+    for dn in group.get("member") {
+        let key = normalize_dn(dn);
+        if let Some(user) = users.get(&key) {
+            add(&user.username);
+        } else if let Some(inner) = groups.get(&key) {
+            nested.push(*inner);
+        } else if let Some(name) = rdn_value(dn, "uid") {
+            add(name);
+        }
+    }
+    for inner in nested {
+        if !seen.contains(&normalize_dn(&inner.dn)) {
+            add_members(inner, users, groups, seen, members);
+        }
+    }
+}
+
+///The main entity to lookup user information in LDAP.
+pub struct PasswdReader {
+    directory: Box<dyn Directory + Send>,
+    config: LdapConfig,
+    max_age: Duration,
+    loaded: Option<(Instant, SystemTime)>,
+    passwd: PasswdIndex,
+}
+
+impl PasswdReader {
+    ///Creates a new PasswdReader searching the server of the config,
+    ///keeping the users for `cache_time`.
+    ///
+    ///Use cache_time with a Duration of 0 to search again for every call.
+    ///A [CachePolicy] can be passed instead of a Duration, of which only the
+    ///maximum age is used.
+    pub fn new<P: Into<CachePolicy>>(config: LdapConfig, cache_time: P) -> Self {
+        Self::with_directory(Connection::new(config.clone()), config, cache_time)
+    }
+
+    ///Creates a new PasswdReader searching another directory
+    pub fn with_directory<D, P>(directory: D, config: LdapConfig, cache_time: P) -> Self
+    where
+        D: Directory + Send + 'static,
+        P: Into<CachePolicy>,
+    {
+        Self {
+            directory: Box::new(directory),
+            config,
+            max_age: cache_time.into().max_age_or_default(),
+            loaded: None,
+            passwd: PasswdIndex::default(),
+        }
+    }
+
+    fn refresh_if_needed(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        if let Some((at, _)) = self.loaded {
+            if now.saturating_duration_since(at) < self.max_age {
+                return Ok(());
+            }
+        }
+        let users = self.directory.search(
+            &self.config.user_base,
+            &self.config.user_filter,
+            USER_ATTRIBUTES,
+        )?;
+        self.passwd = PasswdIndex::new(users.iter().filter_map(posix_account).collect());
+        self.loaded = Some((now, SystemTime::now()));
+        Ok(())
+    }
+
+    ///Returns when the users were last searched, or `None` if they have
+    ///not been searched yet.
+    pub fn last_loaded(&self) -> Option<SystemTime> {
+        self.loaded.map(|(_, wall_clock)| wall_clock)
+    }
+
+    ///Get the entire list of passwd entries
+    pub fn get_entries(&mut self) -> Result<&Vec<PasswdEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.passwd.entries())
+    }
+
+    ///Get the index over the passwd entries
+    pub fn get_index(&mut self) -> Result<&PasswdIndex, Error> {
+        self.refresh_if_needed()?;
+        Ok(&self.passwd)
+    }
+
+    ///Will return an iterator over &PasswdEntry
+    pub fn try_iter(&mut self) -> Result<std::slice::Iter<'_, PasswdEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.passwd.entries().iter())
+    }
+
+    ///Look up a PasswdEntry by username
+    pub fn get_by_username(&mut self, username: &str) -> Result<Option<PasswdEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.passwd.get_by_username(username).cloned())
+    }
+
+    ///Look up a PasswdEntry by uid
+    pub fn get_by_uid(&mut self, uid: u32) -> Result<Option<PasswdEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.passwd.get_by_uid(uid).cloned())
+    }
+
+    ///Look up a username by uid
+    pub fn get_username_by_uid(&mut self, uid: u32) -> Result<Option<String>, Error> {
+        Ok(self.get_by_uid(uid)?.map(|e| e.username))
+    }
+
+    ///Look up a user ID by username
+    pub fn get_uid_by_username(&mut self, username: &str) -> Result<Option<u32>, Error> {
+        Ok(self.get_by_username(username)?.map(|e| e.uid))
+    }
+}
+
+///The main entity to lookup group information in LDAP.
+pub struct GroupReader {
+    directory: Box<dyn Directory + Send>,
+    config: LdapConfig,
+    max_age: Duration,
+    loaded: Option<(Instant, SystemTime)>,
+    groups: GroupIndex,
+}
+
+impl GroupReader {
+    ///Creates a new GroupReader searching the server of the config,
+    ///keeping the groups for `cache_time`.
+    ///
+    ///Use cache_time with a Duration of 0 to search again for every call.
+    ///A [CachePolicy] can be passed instead of a Duration, of which only the
+    ///maximum age is used.
+    pub fn new<P: Into<CachePolicy>>(config: LdapConfig, cache_time: P) -> Self {
+        Self::with_directory(Connection::new(config.clone()), config, cache_time)
+    }
+
+    ///Creates a new GroupReader searching another directory
+    pub fn with_directory<D, P>(directory: D, config: LdapConfig, cache_time: P) -> Self
+    where
+        D: Directory + Send + 'static,
+        P: Into<CachePolicy>,
+    {
+        Self {
+            directory: Box::new(directory),
+            config,
+            max_age: cache_time.into().max_age_or_default(),
+            loaded: None,
+            groups: GroupIndex::default(),
+        }
+    }
+
+    fn refresh_if_needed(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        if let Some((at, _)) = self.loaded {
+            if now.saturating_duration_since(at) < self.max_age {
+                return Ok(());
+            }
+        }
+        let groups = self.directory.search(
+            &self.config.group_base,
+            &self.config.group_filter,
+            GROUP_ATTRIBUTES,
+        )?;
+        //The users are only needed to resolve member DNs
+        let users = if groups.iter().any(|g| !g.get("member").is_empty()) {
+            self.directory.search(
+                &self.config.user_base,
+                &self.config.user_filter,
+                USER_ATTRIBUTES,
+            )?
+        } else {
+            vec![]
+        };
+        self.groups = GroupIndex::new(posix_groups(&groups, &users));
+        self.loaded = Some((now, SystemTime::now()));
+        Ok(())
+    }
+
+    ///Returns when the groups were last searched, or `None` if they have
+    ///not been searched yet.
+    pub fn last_loaded(&self) -> Option<SystemTime> {
+        self.loaded.map(|(_, wall_clock)| wall_clock)
+    }
+
+    ///Get the entire list of group entries
+    pub fn get_groups(&mut self) -> Result<&Vec<GroupEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.groups.groups())
+    }
+
+    ///Get the index over the group entries
+    pub fn get_index(&mut self) -> Result<&GroupIndex, Error> {
+        self.refresh_if_needed()?;
+        Ok(&self.groups)
+    }
+
+    ///Will return an iterator over &GroupEntry
+    pub fn try_iter(&mut self) -> Result<std::slice::Iter<'_, GroupEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.groups.groups().iter())
+    }
+
+    ///Look up a GroupEntry by the group name
+    pub fn get_by_name(&mut self, name: &str) -> Result<Option<GroupEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.groups.get_by_name(name).cloned())
+    }
+
+    ///Look up a GroupEntry by gid
+    pub fn get_by_gid(&mut self, gid: u32) -> Result<Option<GroupEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.groups.get_by_gid(gid).cloned())
+    }
+
+    ///Look up a group name by gid
+    pub fn get_name_by_gid(&mut self, gid: u32) -> Result<Option<String>, Error> {
+        Ok(self.get_by_gid(gid)?.map(|g| g.name))
+    }
+
+    ///Look up a group ID by the group name
+    pub fn get_gid_by_name(&mut self, name: &str) -> Result<Option<u32>, Error> {
+        Ok(self.get_by_name(name)?.map(|g| g.gid))
+    }
+
+    ///Get all groups of a user, the primary group followed by the groups
+    ///listing the user as a member
+    pub fn get_groups_for_user(&mut self, user: &PasswdEntry) -> Result<Vec<GroupEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self
+            .groups
+            .get_group_list(&user.username, user.gid)
+            .into_iter()
+            .cloned()
+            .collect())
+    }
+
+    ///Get all group IDs of a user. The primary gid is always included,
+    ///even if there is no such group.
+    pub fn get_gids_for_user(&mut self, user: &PasswdEntry) -> Result<Vec<u32>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.groups.get_gid_list(&user.username, user.gid))
+    }
+
+    ///Get all groups of a user by username, using `passwd` to find the
+    ///primary group. Returns `None` if the user does not exist.
+    pub fn get_groups_by_username(
+        &mut self,
+        username: &str,
+        passwd: &mut PasswdReader,
+    ) -> Result<Option<Vec<GroupEntry>>, Error> {
+        match passwd.get_by_username(username)? {
+            Some(user) => self.get_groups_for_user(&user).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    ///Get all group IDs of a user by username, using `passwd` to find the
+    ///primary group. Returns `None` if the user does not exist.
+    pub fn get_gids_by_username(
+        &mut self,
+        username: &str,
+        passwd: &mut PasswdReader,
+    ) -> Result<Option<Vec<u32>>, Error> {
+        match passwd.get_by_username(username)? {
+            Some(user) => self.get_gids_for_user(&user).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl UserSource for PasswdReader {
+    fn get_by_username(&mut self, username: &str) -> Result<Option<PasswdEntry>, Error> {
+        self.get_by_username(username)
+    }
+
+    fn get_by_uid(&mut self, uid: u32) -> Result<Option<PasswdEntry>, Error> {
+        self.get_by_uid(uid)
+    }
+
+    fn get_entries(&mut self) -> Result<Vec<PasswdEntry>, Error> {
+        self.get_entries().cloned()
+    }
+}
+
+impl GroupSource for GroupReader {
+    fn get_by_name(&mut self, name: &str) -> Result<Option<GroupEntry>, Error> {
+        self.get_by_name(name)
+    }
+
+    fn get_by_gid(&mut self, gid: u32) -> Result<Option<GroupEntry>, Error> {
+        self.get_by_gid(gid)
+    }
+
+    fn get_groups(&mut self) -> Result<Vec<GroupEntry>, Error> {
+        self.get_groups().cloned()
+    }
+
+    fn get_gids_for_user(&mut self, user: &PasswdEntry) -> Result<Vec<u32>, Error> {
+        self.get_gids_for_user(user)
+    }
+}
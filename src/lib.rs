@@ -7,8 +7,28 @@
 // except according to those terms.
 
 //! `user_lookup` provides an easy way to lookup Linux/Unix user and group information
-//! from /etc/passwd and /etc/group. It will cache the information for a
-//! duration specified by the user. If no caching is desired, a Duration of 0.0 can be used.
+//! from /etc/passwd and /etc/group, as well as /etc/shadow. It will cache the information
+//! until the files change, or for a duration specified by the user. If no caching is
+//! desired, a Duration of 0.0 can be used. See [cache::CachePolicy].
+//!
+//! Readers, by feature:
+//! - `async` (default): `async_reader`, for tokio.
+//! - `sync` (default): `sync_reader`.
+//! - `sync` or `async`: `shared`, the snapshots of the shared readers.
+//! - `nss`: `nss`, lookups through the C library and /etc/nsswitch.conf.
+//! - `varlink`: `varlink`, lookups in `systemd-userdbd`.
+//! - `ldap`: `ldap`, searches in an LDAP directory.
+//!
+//! Always built:
+//! - [index]: lookups by id and name in parsed entries.
+//! - [accounts], [check], [writer]: editing the files.
+//! - [database], [diff], `watch` (Linux only): following changes.
+//! - [userdb], [compat], [login_defs], [allocator], [nsswitch], [source]:
+//!   other account sources and settings.
+//!
+//! Lines that can not be parsed are skipped, see [error::ParseMode].
+//! Fields that are not UTF-8 get U+FFFD, and [OsPasswdEntry] and
+//! [OsGroupEntry] keep the bytes.
 //!
 //!```rust,ignore
 //!use user_lookup::async_reader::PasswdReader;
@@ -23,10 +43,184 @@
 //!}
 //!
 //!```
+pub mod accounts;
+pub mod allocator;
 #[cfg(feature = "async")]
 pub mod async_reader;
+#[cfg(feature = "ldap")]
+mod ber;
+pub mod cache;
+pub mod check;
+pub mod compat;
+pub mod database;
+pub mod diff;
+pub mod error;
+pub mod index;
+pub(crate) mod json;
+#[cfg(feature = "ldap")]
+pub mod ldap;
+pub mod login_defs;
+#[cfg(all(
+    feature = "nss",
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub mod nss;
+pub mod nsswitch;
+#[cfg(any(feature = "sync", feature = "async"))]
+pub mod shared;
+pub mod source;
 #[cfg(feature = "sync")]
 pub mod sync_reader;
+mod sys;
+pub mod userdb;
+#[cfg(feature = "varlink")]
+pub mod varlink;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub mod watch;
+pub mod writer;
+
+pub use error::Error;
+
+use error::ParseError;
+use error::ParseErrorKind;
+use error::ParseMode;
+
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::ffi::OsStringExt;
+use std::path::Path;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+///The colon separated fields of a line
+struct Fields<'a> {
+    fields: std::str::Split<'a, char>,
+}
+
+impl<'a> Fields<'a> {
+    fn new(s: &'a str) -> Self {
+        Self {
+            fields: s.split(':'),
+        }
+    }
+
+    fn next(&mut self, field: &'static str) -> Result<&'a str, ParseErrorKind> {
+        self.fields
+            .next()
+            .ok_or(ParseErrorKind::MissingField(field))
+    }
+
+    fn end(mut self) -> Result<(), ParseErrorKind> {
+        match self.fields.next() {
+            None => Ok(()),
+            Some(_) => Err(ParseErrorKind::ExtraFields),
+        }
+    }
+}
+
+fn parse_list(s: &str) -> Vec<String> {
+    match s {
+        "" => vec![],
+        s => s.split(',').map(|p| p.to_string()).collect(),
+    }
+}
+
+///The `+` and `-` entries of NIS compat mode are not users or groups, they
+///are handled by [compat]
+fn reject_compat(s: &str) -> Result<(), ParseErrorKind> {
+    if s.starts_with(['+', '-']) {
+        Err(ParseErrorKind::CompatEntry)
+    } else {
+        Ok(())
+    }
+}
+
+fn parse_uid(s: &str) -> Result<u32, ParseErrorKind> {
+    s.parse()
+        .map_err(|_| ParseErrorKind::InvalidUid(s.to_string()))
+}
+
+fn parse_gid(s: &str) -> Result<u32, ParseErrorKind> {
+    s.parse()
+        .map_err(|_| ParseErrorKind::InvalidGid(s.to_string()))
+}
+
+fn parse_number(field: &'static str, s: &str) -> Result<u32, ParseErrorKind> {
+    s.parse().map_err(|_| ParseErrorKind::InvalidNumber {
+        field,
+        value: s.to_string(),
+    })
+}
+
+///Parses all lines of a file, skipping empty lines. Depending on the mode,
+///lines that fail to parse are either returned as warnings or fail the parse.
+#[cfg_attr(not(any(feature = "sync", feature = "async")), allow(dead_code))]
+pub(crate) fn parse_lines<T>(
+    contents: &str,
+    mode: ParseMode,
+    parse: fn(&str) -> Result<T, ParseErrorKind>,
+) -> Result<(Vec<T>, Vec<ParseError>), ParseError> {
+    let mut entries = vec![];
+    let mut warnings = vec![];
+    for (i, line) in contents.lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        match parse(line) {
+            Ok(entry) => entries.push(entry),
+            Err(kind) => {
+                let error = ParseError {
+                    line: i + 1,
+                    content: line.to_string(),
+                    kind,
+                };
+                match mode {
+                    ParseMode::Lenient => warnings.push(error),
+                    ParseMode::Strict => return Err(error),
+                }
+            }
+        }
+    }
+    Ok((entries, warnings))
+}
+
+///Parses all lines of a file that may not be UTF-8, like [parse_lines]
+fn parse_byte_lines<T>(
+    contents: &[u8],
+    mode: ParseMode,
+    parse: fn(&[u8]) -> Result<T, ParseErrorKind>,
+) -> Result<(Vec<T>, Vec<ParseError>), ParseError> {
+    let mut entries = vec![];
+    let mut warnings = vec![];
+    for (i, line) in contents.split(|b| *b == b'\n').enumerate() {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            continue;
+        }
+        match parse(line) {
+            Ok(entry) => entries.push(entry),
+            Err(kind) => {
+                let error = ParseError {
+                    line: i + 1,
+                    content: String::from_utf8_lossy(line).into_owned(),
+                    kind,
+                };
+                match mode {
+                    ParseMode::Lenient => warnings.push(error),
+                    ParseMode::Strict => return Err(error),
+                }
+            }
+        }
+    }
+    Ok((entries, warnings))
+}
 
 /// A passwd entry, representing one row in
 /// `/etc/passwd`
@@ -51,37 +245,59 @@ pub struct PasswdEntry {
 impl PasswdEntry {
     ///Create a PasswdEntry from &str.
     pub fn parse(s: &str) -> Option<PasswdEntry> {
-        let mut entries = s.splitn(7, ':');
-        Some(PasswdEntry {
-            username: match entries.next() {
-                None => return None,
-                Some(s) => s.to_string(),
-            },
-            passwd: match entries.next() {
-                None => return None,
-                Some(s) => s.to_string(),
-            },
-            uid: match entries.next().and_then(|s| s.parse().ok()) {
-                None => return None,
-                Some(s) => s,
-            },
-            gid: match entries.next().and_then(|s| s.parse().ok()) {
-                None => return None,
-                Some(s) => s,
-            },
-            gecos: match entries.next() {
-                None => return None,
-                Some(s) => s.to_string(),
-            },
-            home_dir: match entries.next() {
-                None => return None,
-                Some(s) => s.to_string(),
-            },
-            shell: match entries.next() {
-                None => return None,
-                Some(s) => s.to_string(),
-            },
-        })
+        Self::try_parse(s).ok()
+    }
+
+    ///Create a PasswdEntry from &str, telling what is wrong if it fails.
+    /// ```
+    /// use user_lookup::PasswdEntry;
+    /// use user_lookup::error::ParseErrorKind;
+    ///
+    /// assert_eq!(Err(ParseErrorKind::InvalidUid("x".to_string())),
+    ///     PasswdEntry::try_parse("user1:x:x:100:User One:/home/user1:/bin/bash"));
+    /// assert_eq!(Err(ParseErrorKind::MissingField("shell")),
+    ///     PasswdEntry::try_parse("user1:x:1000:100:User One:/home/user1"));
+    /// assert_eq!(Err(ParseErrorKind::CompatEntry),
+    ///     PasswdEntry::try_parse("+@admins::0:0:::"));
+    /// ```
+    pub fn try_parse(s: &str) -> Result<PasswdEntry, ParseErrorKind> {
+        reject_compat(s)?;
+        let mut fields = Fields::new(s);
+        let entry = PasswdEntry {
+            username: fields.next("username")?.to_string(),
+            passwd: fields.next("passwd")?.to_string(),
+            uid: parse_uid(fields.next("uid")?)?,
+            gid: parse_gid(fields.next("gid")?)?,
+            gecos: fields.next("gecos")?.to_string(),
+            home_dir: fields.next("home_dir")?.to_string(),
+            shell: fields.next("shell")?.to_string(),
+        };
+        fields.end()?;
+        Ok(entry)
+    }
+}
+
+impl PasswdEntry {
+    ///Formats the entry as a line of its file, without the trailing newline.
+    ///This is the inverse of [PasswdEntry::parse].
+    /// ```
+    /// use user_lookup::PasswdEntry;
+    ///
+    /// let line = "user1:x:1000:100:User One:/home/user1:/bin/bash";
+    /// assert_eq!(line, PasswdEntry::parse(line).unwrap().to_line());
+    /// ```
+    pub fn to_line(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for PasswdEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}:{}:{}:{}",
+            self.username, self.passwd, self.uid, self.gid, self.gecos, self.home_dir, self.shell
+        )
     }
 }
 
@@ -102,24 +318,680 @@ pub struct GroupEntry {
 impl GroupEntry {
     ///Create a GroupEntry from &str.
     pub fn parse(s: &str) -> Option<GroupEntry> {
-        let mut entries = s.splitn(4, ':');
-        Some(GroupEntry {
-            name: match entries.next() {
-                None => return None,
-                Some(s) => s.to_string(),
-            },
-            passwd: match entries.next() {
-                None => return None,
-                Some(s) => s.to_string(),
-            },
-            gid: match entries.next().and_then(|s| s.parse().ok()) {
-                None => return None,
-                Some(s) => s,
-            },
-            users: match entries.next() {
-                None => return None,
-                Some(s) => s.split(',').map(|p| p.to_string()).collect(),
+        Self::try_parse(s).ok()
+    }
+
+    ///Create a GroupEntry from &str, telling what is wrong if it fails.
+    pub fn try_parse(s: &str) -> Result<GroupEntry, ParseErrorKind> {
+        reject_compat(s)?;
+        let mut fields = Fields::new(s);
+        let entry = GroupEntry {
+            name: fields.next("name")?.to_string(),
+            passwd: fields.next("passwd")?.to_string(),
+            gid: parse_gid(fields.next("gid")?)?,
+            users: parse_list(fields.next("users")?),
+        };
+        fields.end()?;
+        Ok(entry)
+    }
+}
+
+impl GroupEntry {
+    ///Formats the entry as a line of its file, without the trailing newline.
+    ///This is the inverse of [GroupEntry::parse].
+    /// ```
+    /// use user_lookup::GroupEntry;
+    ///
+    /// let group = GroupEntry::parse("root:x:0:").unwrap();
+    /// assert!(group.users.is_empty());
+    /// assert_eq!("root:x:0:", group.to_line());
+    /// ```
+    pub fn to_line(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for GroupEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}",
+            self.name,
+            self.passwd,
+            self.gid,
+            self.users.join(",")
+        )
+    }
+}
+
+///The colon separated fields of a line that may not be UTF-8
+struct ByteFields<'a> {
+    fields: std::slice::Split<'a, u8, fn(&u8) -> bool>,
+}
+
+impl<'a> ByteFields<'a> {
+    fn new(s: &'a [u8]) -> Self {
+        Self {
+            fields: s.split(|b| *b == b':'),
+        }
+    }
+
+    fn next(&mut self, field: &'static str) -> Result<&'a [u8], ParseErrorKind> {
+        self.fields
+            .next()
+            .ok_or(ParseErrorKind::MissingField(field))
+    }
+
+    fn end(mut self) -> Result<(), ParseErrorKind> {
+        match self.fields.next() {
+            None => Ok(()),
+            Some(_) => Err(ParseErrorKind::ExtraFields),
+        }
+    }
+}
+
+fn os_string(s: &[u8]) -> OsString {
+    OsString::from_vec(s.to_vec())
+}
+
+fn lossy(s: &OsStr) -> String {
+    s.to_string_lossy().into_owned()
+}
+
+///Ids are ASCII digits, so an id that is not UTF-8 is invalid anyway
+fn parse_id(s: &[u8], invalid: fn(String) -> ParseErrorKind) -> Result<u32, ParseErrorKind> {
+    std::str::from_utf8(s)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid(String::from_utf8_lossy(s).into_owned()))
+}
+
+/// A passwd entry keeping the bytes of its fields, for files that are not
+/// UTF-8, like a GECOS field in latin-1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OsPasswdEntry {
+    /// Username
+    pub username: OsString,
+    /// User password
+    pub passwd: OsString,
+    /// User ID
+    pub uid: u32,
+    /// Group ID
+    pub gid: u32,
+    /// User full name or comment
+    pub gecos: OsString,
+    /// Home directory
+    pub home_dir: OsString,
+    /// Shell
+    pub shell: OsString,
+}
+
+impl OsPasswdEntry {
+    ///Create an OsPasswdEntry from bytes.
+    pub fn parse(s: &[u8]) -> Option<OsPasswdEntry> {
+        Self::try_parse(s).ok()
+    }
+
+    ///Create an OsPasswdEntry from bytes, telling what is wrong if it fails.
+    /// ```
+    /// use user_lookup::OsPasswdEntry;
+    ///
+    /// let line = b"user1:x:1000:100:Ren\xe9:/home/user1:/bin/bash";
+    /// let entry = OsPasswdEntry::try_parse(line).unwrap();
+    /// assert_eq!(line.to_vec(), entry.to_line());
+    /// assert_eq!("Ren\u{fffd}", entry.to_entry_lossy().gecos);
+    /// assert_eq!(None, entry.to_entry());
+    /// ```
+    pub fn try_parse(s: &[u8]) -> Result<OsPasswdEntry, ParseErrorKind> {
+        if s.starts_with(b"+") || s.starts_with(b"-") {
+            return Err(ParseErrorKind::CompatEntry);
+        }
+        let mut fields = ByteFields::new(s);
+        let entry = OsPasswdEntry {
+            username: os_string(fields.next("username")?),
+            passwd: os_string(fields.next("passwd")?),
+            uid: parse_id(fields.next("uid")?, ParseErrorKind::InvalidUid)?,
+            gid: parse_id(fields.next("gid")?, ParseErrorKind::InvalidGid)?,
+            gecos: os_string(fields.next("gecos")?),
+            home_dir: os_string(fields.next("home_dir")?),
+            shell: os_string(fields.next("shell")?),
+        };
+        fields.end()?;
+        Ok(entry)
+    }
+
+    ///Reads all entries of a passwd file, keeping the bytes of every field.
+    ///Lines that can not be parsed are skipped, unless `mode` is
+    ///[ParseMode::Strict].
+    /// ```
+    /// use user_lookup::OsPasswdEntry;
+    /// use user_lookup::error::ParseMode;
+    ///
+    /// let users = OsPasswdEntry::from_file("test_files/passwd.latin1", ParseMode::Strict).unwrap();
+    /// assert_eq!(b"b\xf6rje", users[2].username.as_encoded_bytes());
+    /// ```
+    pub fn from_file<T: AsRef<Path>>(path: T, mode: ParseMode) -> Result<Vec<Self>, Error> {
+        let contents = std::fs::read(path)?;
+        Ok(parse_byte_lines(&contents, mode, Self::try_parse)?.0)
+    }
+
+    ///Formats the entry as a line of its file, without the trailing newline.
+    ///This is the inverse of [OsPasswdEntry::parse].
+    pub fn to_line(&self) -> Vec<u8> {
+        let (uid, gid) = (self.uid.to_string(), self.gid.to_string());
+        [
+            self.username.as_bytes(),
+            self.passwd.as_bytes(),
+            uid.as_bytes(),
+            gid.as_bytes(),
+            self.gecos.as_bytes(),
+            self.home_dir.as_bytes(),
+            self.shell.as_bytes(),
+        ]
+        .join(&b':')
+    }
+
+    ///Converts the entry to a [PasswdEntry], or `None` if a field is not
+    ///UTF-8
+    pub fn to_entry(&self) -> Option<PasswdEntry> {
+        Some(PasswdEntry {
+            username: self.username.to_str()?.to_string(),
+            passwd: self.passwd.to_str()?.to_string(),
+            uid: self.uid,
+            gid: self.gid,
+            gecos: self.gecos.to_str()?.to_string(),
+            home_dir: self.home_dir.to_str()?.to_string(),
+            shell: self.shell.to_str()?.to_string(),
+        })
+    }
+
+    ///Converts the entry to a [PasswdEntry], replacing what is not UTF-8
+    ///with U+FFFD
+    pub fn to_entry_lossy(&self) -> PasswdEntry {
+        PasswdEntry {
+            username: lossy(&self.username),
+            passwd: lossy(&self.passwd),
+            uid: self.uid,
+            gid: self.gid,
+            gecos: lossy(&self.gecos),
+            home_dir: lossy(&self.home_dir),
+            shell: lossy(&self.shell),
+        }
+    }
+}
+
+impl From<PasswdEntry> for OsPasswdEntry {
+    fn from(entry: PasswdEntry) -> Self {
+        Self {
+            username: entry.username.into(),
+            passwd: entry.passwd.into(),
+            uid: entry.uid,
+            gid: entry.gid,
+            gecos: entry.gecos.into(),
+            home_dir: entry.home_dir.into(),
+            shell: entry.shell.into(),
+        }
+    }
+}
+
+/// A group entry keeping the bytes of its fields, for files that are not
+/// UTF-8
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OsGroupEntry {
+    /// Group name
+    pub name: OsString,
+    /// Password
+    pub passwd: OsString,
+    /// Group ID
+    pub gid: u32,
+    /// List of users
+    pub users: Vec<OsString>,
+}
+
+impl OsGroupEntry {
+    ///Create an OsGroupEntry from bytes.
+    pub fn parse(s: &[u8]) -> Option<OsGroupEntry> {
+        Self::try_parse(s).ok()
+    }
+
+    ///Create an OsGroupEntry from bytes, telling what is wrong if it fails.
+    /// ```
+    /// use user_lookup::OsGroupEntry;
+    ///
+    /// let line = b"users:x:100:user1,b\xf6rje";
+    /// let group = OsGroupEntry::try_parse(line).unwrap();
+    /// assert_eq!(2, group.users.len());
+    /// assert_eq!(line.to_vec(), group.to_line());
+    /// assert_eq!("users:x:100:user1,b\u{fffd}rje", group.to_entry_lossy().to_line());
+    /// ```
+    pub fn try_parse(s: &[u8]) -> Result<OsGroupEntry, ParseErrorKind> {
+        if s.starts_with(b"+") || s.starts_with(b"-") {
+            return Err(ParseErrorKind::CompatEntry);
+        }
+        let mut fields = ByteFields::new(s);
+        let entry = OsGroupEntry {
+            name: os_string(fields.next("name")?),
+            passwd: os_string(fields.next("passwd")?),
+            gid: parse_id(fields.next("gid")?, ParseErrorKind::InvalidGid)?,
+            users: match fields.next("users")? {
+                b"" => vec![],
+                users => users.split(|b| *b == b',').map(os_string).collect(),
             },
+        };
+        fields.end()?;
+        Ok(entry)
+    }
+
+    ///Reads all entries of a group file, keeping the bytes of every field.
+    ///Lines that can not be parsed are skipped, unless `mode` is
+    ///[ParseMode::Strict].
+    pub fn from_file<T: AsRef<Path>>(path: T, mode: ParseMode) -> Result<Vec<Self>, Error> {
+        let contents = std::fs::read(path)?;
+        Ok(parse_byte_lines(&contents, mode, Self::try_parse)?.0)
+    }
+
+    ///Formats the entry as a line of its file, without the trailing newline.
+    ///This is the inverse of [OsGroupEntry::parse].
+    pub fn to_line(&self) -> Vec<u8> {
+        let gid = self.gid.to_string();
+        let users: Vec<&[u8]> = self.users.iter().map(|u| u.as_bytes()).collect();
+        [
+            self.name.as_bytes(),
+            self.passwd.as_bytes(),
+            gid.as_bytes(),
+            &users.join(&b','),
+        ]
+        .join(&b':')
+    }
+
+    ///Converts the entry to a [GroupEntry], or `None` if a field is not
+    ///UTF-8
+    pub fn to_entry(&self) -> Option<GroupEntry> {
+        Some(GroupEntry {
+            name: self.name.to_str()?.to_string(),
+            passwd: self.passwd.to_str()?.to_string(),
+            gid: self.gid,
+            users: self
+                .users
+                .iter()
+                .map(|u| u.to_str().map(str::to_string))
+                .collect::<Option<_>>()?,
         })
     }
+
+    ///Converts the entry to a [GroupEntry], replacing what is not UTF-8
+    ///with U+FFFD
+    pub fn to_entry_lossy(&self) -> GroupEntry {
+        GroupEntry {
+            name: lossy(&self.name),
+            passwd: lossy(&self.passwd),
+            gid: self.gid,
+            users: self.users.iter().map(|u| lossy(u)).collect(),
+        }
+    }
+}
+
+impl From<GroupEntry> for OsGroupEntry {
+    fn from(entry: GroupEntry) -> Self {
+        Self {
+            name: entry.name.into(),
+            passwd: entry.passwd.into(),
+            gid: entry.gid,
+            users: entry.users.into_iter().map(OsString::from).collect(),
+        }
+    }
+}
+
+/// A shadow entry, representing one row in
+/// `/etc/shadow`
+///
+/// All dates are stored as days since Jan 1, 1970, like in the
+/// file. Empty fields are represented by `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShadowEntry {
+    /// Username
+    pub name: String,
+    /// Hashed password
+    pub passwd: String,
+    /// Date of last password change, 0 means that the password
+    /// must be changed on next login
+    pub last_change: Option<i64>,
+    /// Minimum number of days between password changes
+    pub min_days: Option<i64>,
+    /// Maximum number of days a password is valid
+    pub max_days: Option<i64>,
+    /// Number of days before the password expires the user is warned
+    pub warn_days: Option<i64>,
+    /// Number of days after the password expired it is still accepted
+    pub inactive_days: Option<i64>,
+    /// Date when the account expires
+    pub expire: Option<i64>,
+    /// Reserved field
+    pub reserved: String,
+}
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+fn parse_days(field: &'static str, s: &str) -> Result<Option<i64>, ParseErrorKind> {
+    match s {
+        "" => Ok(None),
+        s => s
+            .parse()
+            .map(Some)
+            .map_err(|_| ParseErrorKind::InvalidNumber {
+                field,
+                value: s.to_string(),
+            }),
+    }
+}
+
+///Formats an optional number of days, with `None` as an empty field
+struct DaysField(Option<i64>);
+
+impl fmt::Display for DaysField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(days) => write!(f, "{}", days),
+            None => Ok(()),
+        }
+    }
+}
+
+fn days_to_time(days: i64) -> Option<SystemTime> {
+    let days = u64::try_from(days).ok()?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(days.checked_mul(SECONDS_PER_DAY)?))
+}
+
+impl ShadowEntry {
+    ///Create a ShadowEntry from &str.
+    pub fn parse(s: &str) -> Option<ShadowEntry> {
+        Self::try_parse(s).ok()
+    }
+
+    ///Create a ShadowEntry from &str, telling what is wrong if it fails.
+    pub fn try_parse(s: &str) -> Result<ShadowEntry, ParseErrorKind> {
+        let mut fields = Fields::new(s);
+        let entry = ShadowEntry {
+            name: fields.next("name")?.to_string(),
+            passwd: fields.next("passwd")?.to_string(),
+            last_change: parse_days("last_change", fields.next("last_change")?)?,
+            min_days: parse_days("min_days", fields.next("min_days")?)?,
+            max_days: parse_days("max_days", fields.next("max_days")?)?,
+            warn_days: parse_days("warn_days", fields.next("warn_days")?)?,
+            inactive_days: parse_days("inactive_days", fields.next("inactive_days")?)?,
+            expire: parse_days("expire", fields.next("expire")?)?,
+            reserved: fields.next("reserved")?.to_string(),
+        };
+        fields.end()?;
+        Ok(entry)
+    }
+
+    ///The date of the last password change. Returns `None` if
+    ///it is not set, or if the password must be changed on next login.
+    pub fn last_change_date(&self) -> Option<SystemTime> {
+        match self.last_change {
+            Some(days) if days > 0 => days_to_time(days),
+            _ => None,
+        }
+    }
+
+    ///Whether the user must change the password on next login
+    pub fn must_change_password(&self) -> bool {
+        self.last_change == Some(0)
+    }
+
+    ///The date when the password expires. Returns `None` if the
+    ///password never expires. Like `chage`, a maximum age of 10000
+    ///days or more is treated as no expiry.
+    /// ```
+    /// use user_lookup::ShadowEntry;
+    /// use std::time::{Duration, UNIX_EPOCH};
+    ///
+    /// let entry = ShadowEntry::parse("user1:$6$xyz:19000:0:90:7:14::").unwrap();
+    /// let day = Duration::from_secs(24 * 60 * 60);
+    ///
+    /// assert_eq!(Some(UNIX_EPOCH + day * 19090), entry.password_expiry_date());
+    /// assert_eq!(Some(UNIX_EPOCH + day * 19104), entry.password_inactive_date());
+    /// assert_eq!(None, entry.account_expiry_date());
+    /// ```
+    pub fn password_expiry_date(&self) -> Option<SystemTime> {
+        match (self.last_change, self.max_days) {
+            (Some(last), Some(max)) if last > 0 && (0..10000).contains(&max) => {
+                days_to_time(last.checked_add(max)?)
+            }
+            _ => None,
+        }
+    }
+
+    ///The date after which an expired password is no longer accepted,
+    ///and the account is locked. Returns `None` if that never happens.
+    ///Dates too far away to be represented are treated as never.
+    /// ```
+    /// use user_lookup::ShadowEntry;
+    ///
+    /// let max = i64::MAX;
+    /// let entry = ShadowEntry::parse(&format!("user1:$6$xyz:{max}:0:9999:7:{max}::{max}")).unwrap();
+    /// assert_eq!(None, entry.password_expiry_date());
+    /// assert_eq!(None, entry.password_inactive_date());
+    /// assert_eq!(None, entry.account_expiry_date());
+    ///
+    /// let entry = ShadowEntry::parse(&format!("user1:$6$xyz:19000:0:90:7:{max}::")).unwrap();
+    /// assert!(entry.password_expiry_date().is_some());
+    /// assert_eq!(None, entry.password_inactive_date());
+    /// ```
+    pub fn password_inactive_date(&self) -> Option<SystemTime> {
+        match self.inactive_days {
+            Some(inactive) if inactive >= 0 => {
+                let seconds = (inactive as u64).checked_mul(SECONDS_PER_DAY)?;
+                self.password_expiry_date()?
+                    .checked_add(Duration::from_secs(seconds))
+            }
+            _ => None,
+        }
+    }
+
+    ///The date when the account expires, or `None` if it never does
+    pub fn account_expiry_date(&self) -> Option<SystemTime> {
+        self.expire.and_then(days_to_time)
+    }
+
+    ///Whether the password has expired at the given time
+    pub fn is_password_expired(&self, now: SystemTime) -> bool {
+        self.must_change_password() || self.password_expiry_date().is_some_and(|d| now >= d)
+    }
+
+    ///Whether the account has expired at the given time
+    pub fn is_account_expired(&self, now: SystemTime) -> bool {
+        self.account_expiry_date().is_some_and(|d| now >= d)
+    }
+}
+
+impl ShadowEntry {
+    ///Formats the entry as a line of its file, without the trailing newline.
+    ///This is the inverse of [ShadowEntry::parse].
+    pub fn to_line(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for ShadowEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}:{}:{}:{}:{}:{}",
+            self.name,
+            self.passwd,
+            DaysField(self.last_change),
+            DaysField(self.min_days),
+            DaysField(self.max_days),
+            DaysField(self.warn_days),
+            DaysField(self.inactive_days),
+            DaysField(self.expire),
+            self.reserved
+        )
+    }
+}
+
+/// A gshadow entry, representing one row in
+/// `/etc/gshadow`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GshadowEntry {
+    /// Group name
+    pub name: String,
+    /// Encrypted password
+    pub passwd: String,
+    /// Users allowed to administer the group
+    pub administrators: Vec<String>,
+    /// List of users
+    pub members: Vec<String>,
+}
+
+impl GshadowEntry {
+    ///Create a GshadowEntry from &str.
+    /// ```
+    /// use user_lookup::GshadowEntry;
+    ///
+    /// let entry = GshadowEntry::parse("users:!:admin:user1,user2").unwrap();
+    /// assert_eq!(vec!["admin"], entry.administrators);
+    /// assert_eq!(vec!["user1", "user2"], entry.members);
+    ///
+    /// let entry = GshadowEntry::parse("root:*::").unwrap();
+    /// assert!(entry.administrators.is_empty());
+    /// assert!(entry.members.is_empty());
+    /// ```
+    pub fn parse(s: &str) -> Option<GshadowEntry> {
+        Self::try_parse(s).ok()
+    }
+
+    ///Create a GshadowEntry from &str, telling what is wrong if it fails.
+    pub fn try_parse(s: &str) -> Result<GshadowEntry, ParseErrorKind> {
+        let mut fields = Fields::new(s);
+        let entry = GshadowEntry {
+            name: fields.next("name")?.to_string(),
+            passwd: fields.next("passwd")?.to_string(),
+            administrators: parse_list(fields.next("administrators")?),
+            members: parse_list(fields.next("members")?),
+        };
+        fields.end()?;
+        Ok(entry)
+    }
+
+    ///Whether the user is an administrator of the group
+    pub fn is_administrator(&self, username: &str) -> bool {
+        self.administrators.iter().any(|a| a == username)
+    }
+}
+
+impl GshadowEntry {
+    ///Formats the entry as a line of its file, without the trailing newline.
+    ///This is the inverse of [GshadowEntry::parse].
+    pub fn to_line(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for GshadowEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}",
+            self.name,
+            self.passwd,
+            self.administrators.join(","),
+            self.members.join(",")
+        )
+    }
+}
+
+/// The owner of a subordinate id range, given either as a
+/// login name or as a numeric uid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubIdOwner {
+    /// Login name
+    Name(String),
+    /// User ID
+    Uid(u32),
+}
+
+/// A subordinate id entry, representing one row in
+/// `/etc/subuid` or `/etc/subgid`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubIdEntry {
+    /// The user owning the range
+    pub owner: SubIdOwner,
+    /// First id of the range
+    pub start: u32,
+    /// Number of ids in the range
+    pub count: u32,
+}
+
+impl SubIdEntry {
+    ///Create a SubIdEntry from &str.
+    /// ```
+    /// use user_lookup::{SubIdEntry, SubIdOwner};
+    ///
+    /// let entry = SubIdEntry::parse("user1:100000:65536").unwrap();
+    /// assert_eq!(SubIdOwner::Name("user1".to_string()), entry.owner);
+    /// assert!(entry.contains(165535));
+    /// assert!(!entry.contains(165536));
+    ///
+    /// let entry = SubIdEntry::parse("1001:165536:65536").unwrap();
+    /// assert_eq!(SubIdOwner::Uid(1001), entry.owner);
+    /// ```
+    pub fn parse(s: &str) -> Option<SubIdEntry> {
+        Self::try_parse(s).ok()
+    }
+
+    ///Create a SubIdEntry from &str, telling what is wrong if it fails.
+    pub fn try_parse(s: &str) -> Result<SubIdEntry, ParseErrorKind> {
+        let mut fields = Fields::new(s);
+        let entry = SubIdEntry {
+            owner: match fields.next("owner")? {
+                "" => return Err(ParseErrorKind::EmptyField("owner")),
+                owner => match owner.parse() {
+                    Ok(uid) => SubIdOwner::Uid(uid),
+                    Err(_) => SubIdOwner::Name(owner.to_string()),
+                },
+            },
+            start: parse_number("start", fields.next("start")?)?,
+            count: parse_number("count", fields.next("count")?)?,
+        };
+        fields.end()?;
+        Ok(entry)
+    }
+
+    ///One past the last id of the range
+    pub fn end(&self) -> u64 {
+        self.start as u64 + self.count as u64
+    }
+
+    ///Whether the id is part of the range
+    pub fn contains(&self, id: u32) -> bool {
+        id >= self.start && (id as u64) < self.end()
+    }
+
+    ///Whether the range is owned by the user
+    pub fn is_owned_by(&self, user: &PasswdEntry) -> bool {
+        match &self.owner {
+            SubIdOwner::Name(name) => *name == user.username,
+            SubIdOwner::Uid(uid) => *uid == user.uid,
+        }
+    }
+}
+
+impl SubIdEntry {
+    ///Formats the entry as a line of its file, without the trailing newline.
+    ///This is the inverse of [SubIdEntry::parse].
+    pub fn to_line(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for SubIdEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.owner {
+            SubIdOwner::Name(name) => write!(f, "{}:{}:{}", name, self.start, self.count),
+            SubIdOwner::Uid(uid) => write!(f, "{}:{}:{}", uid, self.start, self.count),
+        }
+    }
 }
@@ -0,0 +1,295 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `login_defs` reads the shadow-utils configuration in /etc/login.defs,
+//! and uses its id ranges to tell system accounts from regular ones.
+//!
+//! ```
+//! use user_lookup::login_defs::{AccountClass, LoginDefs};
+//! use user_lookup::PasswdEntry;
+//!
+//! let defs = LoginDefs::from_file("test_files/login.defs").unwrap();
+//! assert_eq!(201, defs.sys_uid_min());
+//! assert_eq!(Some("SHA512"), defs.encrypt_method());
+//! assert_eq!(Some(0o022), defs.get_u32("UMASK"));
+//!
+//! let root = PasswdEntry::parse("root:x:0:0:root:/root:/bin/bash").unwrap();
+//! let user = PasswdEntry::parse("user1:x:1000:100:User One:/home/user1:/bin/bash").unwrap();
+//! let nobody = PasswdEntry::parse("nobody:x:65534:65534::/:/sbin/nologin").unwrap();
+//! assert_eq!(AccountClass::System, root.class(&defs));
+//! assert_eq!(AccountClass::Regular, user.class(&defs));
+//! assert_eq!(AccountClass::Other, nobody.class(&defs));
+//! ```
+use crate::Error;
+use crate::GroupEntry;
+use crate::PasswdEntry;
+
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+///Whether an account is a system account, a regular account, or outside
+///both ranges, like `nobody` usually is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccountClass {
+    ///The id is at most `SYS_UID_MAX` or `SYS_GID_MAX`
+    System,
+    ///The id is between `UID_MIN` and `UID_MAX`, or `GID_MIN` and `GID_MAX`
+    Regular,
+    ///The id is in none of the ranges
+    Other,
+}
+
+///The settings of /etc/login.defs. Settings missing from the file
+///get the same defaults as shadow-utils uses.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoginDefs {
+    values: HashMap<String, String>,
+}
+
+///Parses a number like `strtol` with base 0 does, as shadow-utils does
+fn parse_number(s: &str) -> Option<i64> {
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let value = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if s.len() > 1 && s.starts_with('0') {
+        i64::from_str_radix(&s[1..], 8).ok()?
+    } else {
+        s.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+impl LoginDefs {
+    ///Creates LoginDefs from the contents of a login.defs file
+    pub fn parse(s: &str) -> Self {
+        let values = s
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .filter_map(|l| {
+                let (key, value) = l.split_once(|c: char| c.is_whitespace())?;
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                Some((key.to_string(), value.to_string()))
+            })
+            .collect();
+        Self { values }
+    }
+
+    ///Reads /etc/login.defs
+    pub fn load() -> Result<Self, Error> {
+        Self::from_file("/etc/login.defs")
+    }
+
+    ///Reads a login.defs file at a specific path
+    pub fn from_file<T: AsRef<Path>>(path: T) -> Result<Self, Error> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    ///Reads /etc/login.defs, or uses the defaults if it does not exist
+    pub fn load_or_default() -> Result<Self, Error> {
+        match Self::load() {
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            result => result,
+        }
+    }
+
+    ///The value of a setting, as written in the file
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|v| v.as_str())
+    }
+
+    ///The value of a numeric setting. Like shadow-utils, numbers
+    ///starting with `0x` are hexadecimal and numbers starting with `0`
+    ///are octal.
+    pub fn get_i64(&self, key: &str) -> Option<i64> {
+        self.get(key).and_then(parse_number)
+    }
+
+    ///The value of a numeric setting, if it fits an u32
+    pub fn get_u32(&self, key: &str) -> Option<u32> {
+        self.get_i64(key).and_then(|v| u32::try_from(v).ok())
+    }
+
+    ///The value of a yes/no setting
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.get(key).map(|v| v.eq_ignore_ascii_case("yes"))
+    }
+
+    ///`UID_MIN`, the first uid of regular users
+    pub fn uid_min(&self) -> u32 {
+        self.get_u32("UID_MIN").unwrap_or(1000)
+    }
+
+    ///`UID_MAX`, the last uid of regular users
+    pub fn uid_max(&self) -> u32 {
+        self.get_u32("UID_MAX").unwrap_or(60000)
+    }
+
+    ///`SYS_UID_MIN`, the first uid handed out to system users
+    pub fn sys_uid_min(&self) -> u32 {
+        self.get_u32("SYS_UID_MIN").unwrap_or(101)
+    }
+
+    ///`SYS_UID_MAX`, the last uid of system users
+    pub fn sys_uid_max(&self) -> u32 {
+        self.get_u32("SYS_UID_MAX")
+            .unwrap_or_else(|| self.uid_min().saturating_sub(1))
+    }
+
+    ///`GID_MIN`, the first gid of regular groups
+    pub fn gid_min(&self) -> u32 {
+        self.get_u32("GID_MIN").unwrap_or(1000)
+    }
+
+    ///`GID_MAX`, the last gid of regular groups
+    pub fn gid_max(&self) -> u32 {
+        self.get_u32("GID_MAX").unwrap_or(60000)
+    }
+
+    ///`SYS_GID_MIN`, the first gid handed out to system groups
+    pub fn sys_gid_min(&self) -> u32 {
+        self.get_u32("SYS_GID_MIN").unwrap_or(101)
+    }
+
+    ///`SYS_GID_MAX`, the last gid of system groups
+    pub fn sys_gid_max(&self) -> u32 {
+        self.get_u32("SYS_GID_MAX")
+            .unwrap_or_else(|| self.gid_min().saturating_sub(1))
+    }
+
+    ///`SUB_UID_MIN`, the first subordinate uid handed out
+    pub fn sub_uid_min(&self) -> u32 {
+        self.get_u32("SUB_UID_MIN").unwrap_or(100000)
+    }
+
+    ///`SUB_UID_MAX`, the last subordinate uid handed out
+    pub fn sub_uid_max(&self) -> u32 {
+        self.get_u32("SUB_UID_MAX").unwrap_or(600100000)
+    }
+
+    ///`SUB_UID_COUNT`, the number of subordinate uids given to each user
+    pub fn sub_uid_count(&self) -> u32 {
+        self.get_u32("SUB_UID_COUNT").unwrap_or(65536)
+    }
+
+    ///`SUB_GID_MIN`, the first subordinate gid handed out
+    pub fn sub_gid_min(&self) -> u32 {
+        self.get_u32("SUB_GID_MIN").unwrap_or(100000)
+    }
+
+    ///`SUB_GID_MAX`, the last subordinate gid handed out
+    pub fn sub_gid_max(&self) -> u32 {
+        self.get_u32("SUB_GID_MAX").unwrap_or(600100000)
+    }
+
+    ///`SUB_GID_COUNT`, the number of subordinate gids given to each user
+    pub fn sub_gid_count(&self) -> u32 {
+        self.get_u32("SUB_GID_COUNT").unwrap_or(65536)
+    }
+
+    ///`ENCRYPT_METHOD`, the algorithm used to hash passwords
+    pub fn encrypt_method(&self) -> Option<&str> {
+        self.get("ENCRYPT_METHOD")
+    }
+
+    ///`PASS_MAX_DAYS`, the default maximum password age
+    pub fn pass_max_days(&self) -> Option<i64> {
+        self.get_i64("PASS_MAX_DAYS")
+    }
+
+    ///`PASS_MIN_DAYS`, the default minimum days between password changes
+    pub fn pass_min_days(&self) -> Option<i64> {
+        self.get_i64("PASS_MIN_DAYS")
+    }
+
+    ///`PASS_WARN_AGE`, the default days of warning before a password expires
+    pub fn pass_warn_age(&self) -> Option<i64> {
+        self.get_i64("PASS_WARN_AGE")
+    }
+
+    ///`USERGROUPS_ENAB`, whether users get a group with the same name
+    pub fn usergroups_enab(&self) -> bool {
+        self.get_bool("USERGROUPS_ENAB").unwrap_or(false)
+    }
+
+    ///The uids of system users, from 0 to `SYS_UID_MAX`
+    pub fn system_uids(&self) -> RangeInclusive<u32> {
+        0..=self.sys_uid_max()
+    }
+
+    ///The uids of regular users, from `UID_MIN` to `UID_MAX`
+    pub fn regular_uids(&self) -> RangeInclusive<u32> {
+        self.uid_min()..=self.uid_max()
+    }
+
+    ///The gids of system groups, from 0 to `SYS_GID_MAX`
+    pub fn system_gids(&self) -> RangeInclusive<u32> {
+        0..=self.sys_gid_max()
+    }
+
+    ///The gids of regular groups, from `GID_MIN` to `GID_MAX`
+    pub fn regular_gids(&self) -> RangeInclusive<u32> {
+        self.gid_min()..=self.gid_max()
+    }
+
+    ///Classifies a uid. If the ranges overlap, the uid is regular.
+    pub fn classify_uid(&self, uid: u32) -> AccountClass {
+        classify(uid, &self.regular_uids(), &self.system_uids())
+    }
+
+    ///Classifies a gid. If the ranges overlap, the gid is regular.
+    pub fn classify_gid(&self, gid: u32) -> AccountClass {
+        classify(gid, &self.regular_gids(), &self.system_gids())
+    }
+
+    ///Tells if uids are of `class` like [Self::classify_uid], without
+    ///borrowing the defs
+    pub(crate) fn uid_filter(&self, class: AccountClass) -> impl Fn(u32) -> bool {
+        let (regular, system) = (self.regular_uids(), self.system_uids());
+        move |uid| classify(uid, &regular, &system) == class
+    }
+
+    ///Tells if gids are of `class` like [Self::classify_gid], without
+    ///borrowing the defs
+    pub(crate) fn gid_filter(&self, class: AccountClass) -> impl Fn(u32) -> bool {
+        let (regular, system) = (self.regular_gids(), self.system_gids());
+        move |gid| classify(gid, &regular, &system) == class
+    }
+}
+
+fn classify(id: u32, regular: &RangeInclusive<u32>, system: &RangeInclusive<u32>) -> AccountClass {
+    if regular.contains(&id) {
+        AccountClass::Regular
+    } else if system.contains(&id) {
+        AccountClass::System
+    } else {
+        AccountClass::Other
+    }
+}
+
+impl PasswdEntry {
+    ///Whether the user is a system or regular user, according to `defs`
+    pub fn class(&self, defs: &LoginDefs) -> AccountClass {
+        defs.classify_uid(self.uid)
+    }
+}
+
+impl GroupEntry {
+    ///Whether the group is a system or regular group, according to `defs`
+    pub fn class(&self, defs: &LoginDefs) -> AccountClass {
+        defs.classify_gid(self.gid)
+    }
+}
@@ -0,0 +1,488 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `nss` provides a PasswdReader and a GroupReader that look up users and
+//! groups through the C library, like `getent` does, instead of reading
+//! /etc/passwd and /etc/group. This finds the users of every source
+//! configured in /etc/nsswitch.conf, like LDAP, SSSD or systemd-homed.
+//!
+//! The readers have the same methods as the ones in `sync_reader`. Lookups
+//! by name or id always ask the C library, which has its own caching in
+//! `nscd` or `sssd`. Listing all entries enumerates them with `getpwent` and
+//! `getgrent`, which can be slow for network sources, so the list is kept for
+//! `cache_time`.
+//!
+//! This module requires the `nss` feature, and is built on Linux on x86_64
+//! and aarch64.
+//!
+//!```rust,ignore
+//! use user_lookup::nss::PasswdReader;
+//! use std::time::Duration;
+//!
+//! fn main() {
+//!    let mut reader = PasswdReader::new(Duration::new(0,0));
+//!
+//!    println!("User with uid 1000 is: {}",
+//!    reader.get_username_by_uid(1000).unwrap().unwrap());
+//! }
+//!
+//!```
+use crate::Error;
+use crate::GroupEntry;
+use crate::PasswdEntry;
+
+use crate::index::GroupIndex;
+use crate::index::PasswdIndex;
+use crate::login_defs::AccountClass;
+use crate::login_defs::LoginDefs;
+use crate::sys;
+
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::mem::MaybeUninit;
+use std::os::raw::c_char;
+use std::os::raw::c_int;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+///The enumeration functions keep their position in global state, so only
+///one thread at a time may enumerate
+static ENUMERATION: Mutex<()> = Mutex::new(());
+
+///The first buffer size tried for the strings of an entry
+const INITIAL_BUFFER: usize = 1024;
+///The largest buffer tried before giving up with ERANGE
+const MAX_BUFFER: usize = 1024 * 1024;
+
+///The main entity to lookup user information through the C library.
+///```
+/// use user_lookup::nss::PasswdReader;
+/// use std::time::Duration;
+///
+/// let mut reader = PasswdReader::new(Duration::new(0, 0));
+/// assert_eq!(Some("root".to_string()), reader.get_username_by_uid(0).unwrap());
+/// assert_eq!(Some(0), reader.get_uid_by_username("root").unwrap());
+/// assert_eq!(None, reader.get_by_username("no such user").unwrap());
+/// assert!(reader.get_entries().unwrap().iter().any(|e| e.uid == 0));
+/// ```
+pub struct PasswdReader {
+    cache_time: Duration,
+    loaded: Option<(Instant, SystemTime)>,
+    passwd: PasswdIndex,
+}
+
+impl PasswdReader {
+    ///Creates a new PasswdReader, keeping the list of all users for
+    ///`cache_time`.
+    ///
+    ///Use cache_time with a Duration of 0 to enumerate the users again
+    ///for every call.
+    pub fn new(cache_time: Duration) -> Self {
+        Self {
+            cache_time,
+            loaded: None,
+            passwd: PasswdIndex::default(),
+        }
+    }
+
+    fn refresh_if_needed(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        if let Some((at, _)) = self.loaded {
+            if now.saturating_duration_since(at) < self.cache_time {
+                return Ok(());
+            }
+        }
+        self.passwd = PasswdIndex::new(enumerate(
+            sys::setpwent,
+            sys::getpwent,
+            sys::endpwent,
+            passwd_entry,
+        )?);
+        self.loaded = Some((now, SystemTime::now()));
+        Ok(())
+    }
+
+    ///Returns when the users were last enumerated, or `None` if they
+    ///have not been enumerated yet.
+    pub fn last_loaded(&self) -> Option<SystemTime> {
+        self.loaded.map(|(_, wall_clock)| wall_clock)
+    }
+
+    ///Get the entire list of passwd entries, enumerated with `getpwent`
+    pub fn get_entries(&mut self) -> Result<&Vec<PasswdEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.passwd.entries())
+    }
+
+    ///Get the index over the enumerated passwd entries
+    pub fn get_index(&mut self) -> Result<&PasswdIndex, Error> {
+        self.refresh_if_needed()?;
+        Ok(&self.passwd)
+    }
+
+    ///Will return an iterator over &PasswdEntry
+    pub fn try_iter(&mut self) -> Result<std::slice::Iter<'_, PasswdEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.passwd.entries().iter())
+    }
+
+    ///Will return an iterator over the regular users, with
+    ///a uid between `UID_MIN` and `UID_MAX` of `defs`
+    pub fn regular_users(
+        &mut self,
+        defs: &LoginDefs,
+    ) -> Result<impl Iterator<Item = &PasswdEntry>, Error> {
+        self.refresh_if_needed()?;
+        let is_regular = defs.uid_filter(AccountClass::Regular);
+        Ok(self
+            .passwd
+            .entries()
+            .iter()
+            .filter(move |e| is_regular(e.uid)))
+    }
+
+    ///Will return an iterator over the system users, with
+    ///a uid of at most `SYS_UID_MAX` of `defs` that is not regular
+    pub fn system_users(
+        &mut self,
+        defs: &LoginDefs,
+    ) -> Result<impl Iterator<Item = &PasswdEntry>, Error> {
+        self.refresh_if_needed()?;
+        let is_system = defs.uid_filter(AccountClass::System);
+        Ok(self
+            .passwd
+            .entries()
+            .iter()
+            .filter(move |e| is_system(e.uid)))
+    }
+
+    ///Look up a PasswdEntry by username, with `getpwnam_r`
+    pub fn get_by_username(&mut self, username: &str) -> Result<Option<PasswdEntry>, Error> {
+        let name = match CString::new(username) {
+            Ok(name) => name,
+            Err(_) => return Ok(None),
+        };
+        lookup(
+            |pwd, buf, len, result| unsafe {
+                sys::getpwnam_r(name.as_ptr(), pwd, buf, len, result)
+            },
+            passwd_entry,
+        )
+    }
+
+    ///Look up a PasswdEntry by uid, with `getpwuid_r`
+    pub fn get_by_uid(&mut self, uid: u32) -> Result<Option<PasswdEntry>, Error> {
+        lookup(
+            |pwd, buf, len, result| unsafe { sys::getpwuid_r(uid, pwd, buf, len, result) },
+            passwd_entry,
+        )
+    }
+
+    ///Look up a username by uid
+    pub fn get_username_by_uid(&mut self, uid: u32) -> Result<Option<String>, Error> {
+        Ok(self.get_by_uid(uid)?.map(|e| e.username))
+    }
+
+    ///Look up a user ID by username
+    pub fn get_uid_by_username(&mut self, username: &str) -> Result<Option<u32>, Error> {
+        Ok(self.get_by_username(username)?.map(|e| e.uid))
+    }
+}
+
+///The main entity to lookup group information through the C library.
+///```
+/// use user_lookup::nss::{GroupReader, PasswdReader};
+/// use std::time::Duration;
+///
+/// let mut reader = GroupReader::new(Duration::new(0, 0));
+/// assert_eq!(Some("root".to_string()), reader.get_name_by_gid(0).unwrap());
+/// assert_eq!(Some(0), reader.get_gid_by_name("root").unwrap());
+/// assert_eq!(None, reader.get_by_name("no such group").unwrap());
+///
+/// let mut passwd = PasswdReader::new(Duration::new(0, 0));
+/// let gids = reader.get_gids_by_username("root", &mut passwd).unwrap().unwrap();
+/// assert_eq!(0, gids[0]);
+/// ```
+pub struct GroupReader {
+    cache_time: Duration,
+    loaded: Option<(Instant, SystemTime)>,
+    groups: GroupIndex,
+}
+
+impl GroupReader {
+    ///Creates a new GroupReader, keeping the list of all groups for
+    ///`cache_time`.
+    ///
+    ///Use cache_time with a Duration of 0 to enumerate the groups again
+    ///for every call.
+    pub fn new(cache_time: Duration) -> Self {
+        Self {
+            cache_time,
+            loaded: None,
+            groups: GroupIndex::default(),
+        }
+    }
+
+    fn refresh_if_needed(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        if let Some((at, _)) = self.loaded {
+            if now.saturating_duration_since(at) < self.cache_time {
+                return Ok(());
+            }
+        }
+        self.groups = GroupIndex::new(enumerate(
+            sys::setgrent,
+            sys::getgrent,
+            sys::endgrent,
+            group_entry,
+        )?);
+        self.loaded = Some((now, SystemTime::now()));
+        Ok(())
+    }
+
+    ///Returns when the groups were last enumerated, or `None` if they
+    ///have not been enumerated yet.
+    pub fn last_loaded(&self) -> Option<SystemTime> {
+        self.loaded.map(|(_, wall_clock)| wall_clock)
+    }
+
+    ///Get the entire list of group entries, enumerated with `getgrent`
+    pub fn get_groups(&mut self) -> Result<&Vec<GroupEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.groups.groups())
+    }
+
+    ///Get the index over the enumerated group entries
+    pub fn get_index(&mut self) -> Result<&GroupIndex, Error> {
+        self.refresh_if_needed()?;
+        Ok(&self.groups)
+    }
+
+    ///Will return an iterator over &GroupEntry
+    pub fn try_iter(&mut self) -> Result<std::slice::Iter<'_, GroupEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.groups.groups().iter())
+    }
+
+    ///Will return an iterator over the regular groups, with
+    ///a gid between `GID_MIN` and `GID_MAX` of `defs`
+    pub fn regular_groups(
+        &mut self,
+        defs: &LoginDefs,
+    ) -> Result<impl Iterator<Item = &GroupEntry>, Error> {
+        self.refresh_if_needed()?;
+        let is_regular = defs.gid_filter(AccountClass::Regular);
+        Ok(self
+            .groups
+            .groups()
+            .iter()
+            .filter(move |e| is_regular(e.gid)))
+    }
+
+    ///Will return an iterator over the system groups, with
+    ///a gid of at most `SYS_GID_MAX` of `defs` that is not regular
+    pub fn system_groups(
+        &mut self,
+        defs: &LoginDefs,
+    ) -> Result<impl Iterator<Item = &GroupEntry>, Error> {
+        self.refresh_if_needed()?;
+        let is_system = defs.gid_filter(AccountClass::System);
+        Ok(self
+            .groups
+            .groups()
+            .iter()
+            .filter(move |e| is_system(e.gid)))
+    }
+
+    ///Look up a GroupEntry by the group name, with `getgrnam_r`
+    pub fn get_by_name(&mut self, name: &str) -> Result<Option<GroupEntry>, Error> {
+        let name = match CString::new(name) {
+            Ok(name) => name,
+            Err(_) => return Ok(None),
+        };
+        lookup(
+            |grp, buf, len, result| unsafe {
+                sys::getgrnam_r(name.as_ptr(), grp, buf, len, result)
+            },
+            group_entry,
+        )
+    }
+
+    ///Look up a GroupEntry by gid, with `getgrgid_r`
+    pub fn get_by_gid(&mut self, gid: u32) -> Result<Option<GroupEntry>, Error> {
+        lookup(
+            |grp, buf, len, result| unsafe { sys::getgrgid_r(gid, grp, buf, len, result) },
+            group_entry,
+        )
+    }
+
+    ///Look up a group name by gid
+    pub fn get_name_by_gid(&mut self, gid: u32) -> Result<Option<String>, Error> {
+        Ok(self.get_by_gid(gid)?.map(|e| e.name))
+    }
+
+    ///Look up a group ID by the group name
+    pub fn get_gid_by_name(&mut self, name: &str) -> Result<Option<u32>, Error> {
+        Ok(self.get_by_name(name)?.map(|e| e.gid))
+    }
+
+    ///Get all groups of a user, with `getgrouplist`. Groups that can not
+    ///be looked up by gid are left out.
+    pub fn get_groups_for_user(&mut self, user: &PasswdEntry) -> Result<Vec<GroupEntry>, Error> {
+        let mut groups = vec![];
+        for gid in self.get_gids_for_user(user)? {
+            if let Some(group) = self.get_by_gid(gid)? {
+                groups.push(group);
+            }
+        }
+        Ok(groups)
+    }
+
+    ///Get all group IDs of a user, with `getgrouplist`. The primary
+    ///gid is always included, even if there is no such group.
+    pub fn get_gids_for_user(&mut self, user: &PasswdEntry) -> Result<Vec<u32>, Error> {
+        let name = match CString::new(user.username.as_str()) {
+            Ok(name) => name,
+            Err(_) => return Ok(vec![user.gid]),
+        };
+        Ok(group_list(&name, user.gid))
+    }
+
+    ///Get all groups of a user by username, using `passwd` to find the
+    ///primary group. Returns `None` if the user does not exist.
+    pub fn get_groups_by_username(
+        &mut self,
+        username: &str,
+        passwd: &mut PasswdReader,
+    ) -> Result<Option<Vec<GroupEntry>>, Error> {
+        match passwd.get_by_username(username)? {
+            Some(user) => self.get_groups_for_user(&user).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    ///Get all group IDs of a user by username, using `passwd` to find the
+    ///primary group. Returns `None` if the user does not exist.
+    pub fn get_gids_by_username(
+        &mut self,
+        username: &str,
+        passwd: &mut PasswdReader,
+    ) -> Result<Option<Vec<u32>>, Error> {
+        match passwd.get_by_username(username)? {
+            Some(user) => self.get_gids_for_user(&user).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+///Calls one of the reentrant `get*_r` functions, growing the buffer for
+///the strings as long as it returns ERANGE
+fn lookup<T, E>(
+    mut call: impl FnMut(*mut T, *mut c_char, usize, *mut *mut T) -> c_int,
+    convert: unsafe fn(&T) -> E,
+) -> Result<Option<E>, Error> {
+    let mut buf: Vec<c_char> = vec![0; INITIAL_BUFFER];
+    loop {
+        let mut entry = MaybeUninit::<T>::uninit();
+        let mut result = std::ptr::null_mut();
+        let code = call(entry.as_mut_ptr(), buf.as_mut_ptr(), buf.len(), &mut result);
+        if !result.is_null() {
+            //The entry points into `buf`, which is still alive
+            return Ok(Some(unsafe { convert(&*result) }));
+        }
+        match code {
+            sys::ERANGE if buf.len() < MAX_BUFFER => buf.resize(buf.len() * 2, 0),
+            //Not finding the entry is reported in several ways
+            0 | sys::ENOENT | sys::ESRCH | sys::EBADF | sys::EPERM => return Ok(None),
+            code => return Err(std::io::Error::from_raw_os_error(code).into()),
+        }
+    }
+}
+
+///Enumerates all entries with `setpwent`/`getpwent`/`endpwent` or the
+///group equivalents, holding the enumeration lock
+fn enumerate<T, E>(
+    set: unsafe extern "C" fn(),
+    get: unsafe extern "C" fn() -> *mut T,
+    end: unsafe extern "C" fn(),
+    convert: unsafe fn(&T) -> E,
+) -> Result<Vec<E>, Error> {
+    let _guard = ENUMERATION.lock().unwrap_or_else(|e| e.into_inner());
+    let mut entries = vec![];
+    unsafe {
+        set();
+        loop {
+            //The end of the enumeration and an error both return null
+            *sys::__errno_location() = 0;
+            let entry = get();
+            if entry.is_null() {
+                let errno = *sys::__errno_location();
+                end();
+                return match errno {
+                    0 | sys::ENOENT | sys::ESRCH => Ok(entries),
+                    errno => Err(std::io::Error::from_raw_os_error(errno).into()),
+                };
+            }
+            entries.push(convert(&*entry));
+        }
+    }
+}
+
+///Calls `getgrouplist`, growing the list as long as it is too short
+fn group_list(name: &CStr, gid: u32) -> Vec<u32> {
+    let mut gids: Vec<u32> = vec![0; 64];
+    loop {
+        let mut count = gids.len() as c_int;
+        let found = unsafe { sys::getgrouplist(name.as_ptr(), gid, gids.as_mut_ptr(), &mut count) };
+        if found >= 0 {
+            gids.truncate(count as usize);
+            return gids;
+        }
+        //`count` is set to the number of groups found
+        let len = (count as usize).max(gids.len() * 2);
+        gids.resize(len, 0);
+    }
+}
+
+unsafe fn string(s: *const c_char) -> String {
+    match s.is_null() {
+        true => String::new(),
+        false => CStr::from_ptr(s).to_string_lossy().into_owned(),
+    }
+}
+
+unsafe fn passwd_entry(pwd: &sys::passwd) -> PasswdEntry {
+    PasswdEntry {
+        username: string(pwd.pw_name),
+        passwd: string(pwd.pw_passwd),
+        uid: pwd.pw_uid,
+        gid: pwd.pw_gid,
+        gecos: string(pwd.pw_gecos),
+        home_dir: string(pwd.pw_dir),
+        shell: string(pwd.pw_shell),
+    }
+}
+
+unsafe fn group_entry(grp: &sys::group) -> GroupEntry {
+    let mut users = vec![];
+    if !grp.gr_mem.is_null() {
+        let mut member = grp.gr_mem;
+        while !(*member).is_null() {
+            users.push(string(*member));
+            member = member.add(1);
+        }
+    }
+    GroupEntry {
+        name: string(grp.gr_name),
+        passwd: string(grp.gr_passwd),
+        gid: grp.gr_gid,
+        users,
+    }
+}
@@ -0,0 +1,318 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `nsswitch` reads /etc/nsswitch.conf and builds the [Chain] of sources it
+//! describes for the passwd, group and shadow databases, so lookups are done
+//! in the same order as `getent` does on the machine.
+//!
+//! Only some services can be used by the crate, like `files`, and `systemd`
+//! with the `varlink` feature. The other services are left out of the
+//! chains, and reported by [NsswitchConf::unsupported]. If none of the
+//! services of a database can be used, the chain falls back to `files`.
+//!
+//! ```
+//! use user_lookup::nsswitch::NsswitchConf;
+//! use user_lookup::source::{Action, Status};
+//!
+//! let conf = NsswitchConf::from_file("test_files/nsswitch.conf")
+//!     .unwrap()
+//!     .with_files_dir("test_files");
+//! let services: Vec<&str> = conf.services("passwd").iter().map(|s| s.name()).collect();
+//! assert_eq!(vec!["files", "sss", "systemd"], services);
+//! assert_eq!(Action::Return, conf.services("passwd")[1].actions().get(Status::NotFound));
+//! let unsupported = match cfg!(feature = "varlink") {
+//!     true => vec!["sss"],
+//!     false => vec!["sss", "systemd"],
+//! };
+//! assert_eq!(unsupported, conf.unsupported("passwd"));
+//!
+//!
+//! # #[cfg(feature = "sync")] {
+//! use user_lookup::source::UserSource;
+//! use std::time::Duration;
+//!
+//! let mut chain = conf.passwd_chain(Duration::new(0, 0));
+//! assert_eq!(match cfg!(feature = "varlink") { true => 2, false => 1 }, chain.len());
+//! assert_eq!(Some(1000), chain.get_by_username("user1").unwrap().map(|e| e.uid));
+//! # }
+//! ```
+use crate::source::Action;
+use crate::source::Actions;
+use crate::source::Status;
+use crate::Error;
+
+#[cfg(feature = "sync")]
+use crate::cache::CachePolicy;
+#[cfg(feature = "sync")]
+use crate::source::Chain;
+#[cfg(feature = "sync")]
+use crate::source::GroupChain;
+#[cfg(feature = "sync")]
+use crate::source::GroupSource;
+#[cfg(feature = "sync")]
+use crate::source::ShadowChain;
+#[cfg(feature = "sync")]
+use crate::source::ShadowSource;
+#[cfg(feature = "sync")]
+use crate::source::UserChain;
+#[cfg(feature = "sync")]
+use crate::source::UserSource;
+#[cfg(feature = "sync")]
+use crate::sync_reader::GroupReader;
+#[cfg(feature = "sync")]
+use crate::sync_reader::PasswdReader;
+#[cfg(feature = "sync")]
+use crate::sync_reader::ShadowReader;
+#[cfg(all(feature = "sync", feature = "varlink"))]
+use crate::varlink;
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+///Every status, to expand negated action items like `[!SUCCESS=return]`
+const STATUSES: [Status; 4] = [
+    Status::Success,
+    Status::NotFound,
+    Status::Unavail,
+    Status::TryAgain,
+];
+
+///A service of a database line, with the actions following it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Service {
+    name: String,
+    actions: Actions,
+}
+
+impl Service {
+    ///Creates a service with the default actions
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            actions: Actions::default(),
+        }
+    }
+
+    ///The name of the service, like `files` or `sss`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    ///What to do after asking the service
+    pub fn actions(&self) -> Actions {
+        self.actions
+    }
+}
+
+///The database lines of /etc/nsswitch.conf.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NsswitchConf {
+    databases: HashMap<String, Vec<Service>>,
+    files_dir: PathBuf,
+    default: Vec<Service>,
+}
+
+impl Default for NsswitchConf {
+    fn default() -> Self {
+        Self {
+            databases: HashMap::new(),
+            files_dir: PathBuf::from("/etc"),
+            default: vec![Service::new("files")],
+        }
+    }
+}
+
+impl NsswitchConf {
+    ///Creates a NsswitchConf from the contents of a nsswitch.conf file.
+    ///Action items with an unknown status or action, like `merge`, are
+    ///ignored.
+    pub fn parse(s: &str) -> Self {
+        let databases = s
+            .lines()
+            .map(|l| l.split('#').next().unwrap_or_default().trim())
+            .filter_map(|l| l.split_once(':'))
+            .map(|(database, services)| (database.trim().to_string(), parse_services(services)))
+            .collect();
+        Self {
+            databases,
+            ..Self::default()
+        }
+    }
+
+    ///Reads /etc/nsswitch.conf
+    pub fn load() -> Result<Self, Error> {
+        Self::from_file("/etc/nsswitch.conf")
+    }
+
+    ///Reads a nsswitch.conf file at a specific path
+    pub fn from_file<T: AsRef<Path>>(path: T) -> Result<Self, Error> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    ///Reads /etc/nsswitch.conf, or uses `files` for every database if it
+    ///does not exist
+    pub fn load_or_default() -> Result<Self, Error> {
+        match Self::load() {
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            result => result,
+        }
+    }
+
+    ///Sets the directory of the files used by the `files` service,
+    ///instead of /etc
+    pub fn with_files_dir<T: Into<PathBuf>>(mut self, dir: T) -> Self {
+        self.files_dir = dir.into();
+        self
+    }
+
+    ///The services of a database, in order. A database missing from the
+    ///file uses `files`, like the C library does.
+    pub fn services(&self, database: &str) -> &[Service] {
+        self.databases.get(database).unwrap_or(&self.default)
+    }
+
+    ///The services of a database that can not be used by the crate, and
+    ///are left out of the chain
+    pub fn unsupported(&self, database: &str) -> Vec<&str> {
+        self.services(database)
+            .iter()
+            .map(|s| s.name())
+            .filter(|name| !is_supported(database, name))
+            .collect()
+    }
+
+    ///Builds the chain of user sources of the passwd database
+    ///
+    ///The `systemd` service can not check a file, and only uses the
+    ///maximum age of the policy, see [CachePolicy::max_age_or_default].
+    #[cfg(feature = "sync")]
+    pub fn passwd_chain<P: Into<CachePolicy>>(&self, cache_time: P) -> UserChain {
+        let policy = cache_time.into();
+        self.chain("passwd", |service| match service {
+            "files" => Some(Box::new(PasswdReader::from_file(
+                self.files_dir.join("passwd"),
+                policy,
+            )) as Box<dyn UserSource>),
+            #[cfg(feature = "varlink")]
+            "systemd" => Some(Box::new(varlink::PasswdReader::new(policy))),
+            _ => None,
+        })
+    }
+
+    ///Builds the chain of group sources of the group database
+    ///
+    ///The `systemd` service can not check a file, and only uses the
+    ///maximum age of the policy, see [CachePolicy::max_age_or_default].
+    #[cfg(feature = "sync")]
+    pub fn group_chain<P: Into<CachePolicy>>(&self, cache_time: P) -> GroupChain {
+        let policy = cache_time.into();
+        self.chain("group", |service| match service {
+            "files" => Some(
+                Box::new(GroupReader::from_file(self.files_dir.join("group"), policy))
+                    as Box<dyn GroupSource>,
+            ),
+            #[cfg(feature = "varlink")]
+            "systemd" => Some(Box::new(varlink::GroupReader::new(policy))),
+            _ => None,
+        })
+    }
+
+    ///Builds the chain of shadow sources of the shadow database
+    #[cfg(feature = "sync")]
+    pub fn shadow_chain<P: Into<CachePolicy>>(&self, cache_time: P) -> ShadowChain {
+        let policy = cache_time.into();
+        self.chain("shadow", |service| match service {
+            "files" => Some(Box::new(ShadowReader::from_file(
+                self.files_dir.join("shadow"),
+                policy,
+            )) as Box<dyn ShadowSource>),
+            _ => None,
+        })
+    }
+
+    #[cfg(feature = "sync")]
+    fn chain<S: ?Sized>(
+        &self,
+        database: &str,
+        source: impl Fn(&str) -> Option<Box<S>>,
+    ) -> Chain<S> {
+        let mut chain = Chain::new();
+        for service in self.services(database) {
+            if let Some(source) = source(service.name()) {
+                chain.push(source, service.actions());
+            }
+        }
+        if chain.is_empty() {
+            if let Some(files) = source("files") {
+                chain.push(files, Actions::default());
+            }
+        }
+        chain
+    }
+}
+
+///Whether the crate has a source for the service of the database
+fn is_supported(database: &str, service: &str) -> bool {
+    let varlink = cfg!(feature = "varlink") && matches!(database, "passwd" | "group");
+    matches!(database, "passwd" | "group" | "shadow") && service == "files"
+        || varlink && service == "systemd"
+}
+
+///Parses the services and action items after the database name
+fn parse_services(s: &str) -> Vec<Service> {
+    let mut services: Vec<Service> = vec![];
+    let mut rest = s.trim();
+    while !rest.is_empty() {
+        if let Some(items) = rest.strip_prefix('[') {
+            let (items, after) = items.split_once(']').unwrap_or((items, ""));
+            //Action items before the first service are ignored
+            if let Some(service) = services.last_mut() {
+                for item in items.split_whitespace() {
+                    service.actions = parse_action(service.actions, item);
+                }
+            }
+            rest = after.trim_start();
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || c == '[')
+                .unwrap_or(rest.len());
+            services.push(Service::new(&rest[..end]));
+            rest = rest[end..].trim_start();
+        }
+    }
+    services
+}
+
+///Applies an action item like `NOTFOUND=return` or `!SUCCESS=return`
+fn parse_action(actions: Actions, item: &str) -> Actions {
+    let (status, action) = match item.split_once('=') {
+        Some(item) => item,
+        None => return actions,
+    };
+    let action = match action.to_ascii_lowercase().as_str() {
+        "return" => Action::Return,
+        "continue" => Action::Continue,
+        _ => return actions,
+    };
+    let (negated, status) = match status.strip_prefix('!') {
+        Some(status) => (true, status),
+        None => (false, status),
+    };
+    let status = match status.to_ascii_uppercase().as_str() {
+        "SUCCESS" => Status::Success,
+        "NOTFOUND" => Status::NotFound,
+        "UNAVAIL" => Status::Unavail,
+        "TRYAGAIN" => Status::TryAgain,
+        _ => return actions,
+    };
+    STATUSES
+        .iter()
+        .filter(|s| (**s == status) != negated)
+        .fold(actions, |actions, s| actions.on(*s, action))
+}
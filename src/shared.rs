@@ -0,0 +1,187 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `shared` holds the [Snapshot] handed out by the `SharedReader` of
+//! `sync_reader` and `async_reader`. A snapshot is never changed, a
+//! refresh builds a new one and swaps it in, so lookups against an old
+//! snapshot are never blocked by a refresh.
+use crate::cache::CachePolicy;
+use crate::cache::FileStamp;
+use crate::database::UserDatabase;
+use crate::error::ParseError;
+use crate::error::ParseMode;
+use crate::index::GroupIndex;
+use crate::index::PasswdIndex;
+use crate::Error;
+use crate::GroupEntry;
+use crate::PasswdEntry;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::PoisonError;
+use std::sync::RwLock;
+use std::time::Instant;
+use std::time::SystemTime;
+
+///The passwd and group entries as read at one point in time
+#[derive(Debug)]
+pub struct Snapshot {
+    database: UserDatabase,
+    passwd_warnings: Vec<ParseError>,
+    group_warnings: Vec<ParseError>,
+    at: Instant,
+    wall_clock: SystemTime,
+    stamps: [Option<FileStamp>; 2],
+}
+
+impl Snapshot {
+    ///The users and groups of this snapshot
+    pub fn database(&self) -> &UserDatabase {
+        &self.database
+    }
+
+    ///The index over the passwd entries
+    pub fn passwd(&self) -> &PasswdIndex {
+        self.database.passwd()
+    }
+
+    ///The index over the group entries
+    pub fn groups(&self) -> &GroupIndex {
+        self.database.groups()
+    }
+
+    ///The lines of the passwd file skipped in [ParseMode::Lenient]
+    pub fn passwd_warnings(&self) -> &[ParseError] {
+        &self.passwd_warnings
+    }
+
+    ///The lines of the group file skipped in [ParseMode::Lenient]
+    pub fn group_warnings(&self) -> &[ParseError] {
+        &self.group_warnings
+    }
+
+    ///When the files of this snapshot were read
+    pub fn last_loaded(&self) -> SystemTime {
+        self.wall_clock
+    }
+}
+
+///The state shared by all clones of a `SharedReader`. The readers do
+///the IO and the locking of refreshes, since that differs between sync
+///and async.
+#[derive(Debug)]
+pub(crate) struct SharedState {
+    passwd: PathBuf,
+    group: PathBuf,
+    policy: CachePolicy,
+    mode: ParseMode,
+    current: RwLock<Option<Arc<Snapshot>>>,
+}
+
+impl SharedState {
+    pub(crate) fn new(passwd: PathBuf, group: PathBuf, policy: CachePolicy) -> Self {
+        Self {
+            passwd,
+            group,
+            policy,
+            mode: ParseMode::default(),
+            current: RwLock::new(None),
+        }
+    }
+
+    ///A state with the same files and policy, but nothing loaded
+    pub(crate) fn with_parse_mode(&self, mode: ParseMode) -> Self {
+        Self {
+            mode,
+            ..Self::new(self.passwd.clone(), self.group.clone(), self.policy)
+        }
+    }
+
+    pub(crate) fn passwd_path(&self) -> &PathBuf {
+        &self.passwd
+    }
+
+    pub(crate) fn group_path(&self) -> &PathBuf {
+        &self.group
+    }
+
+    ///Whether the files have to be stat'ed before calling `fresh`
+    pub(crate) fn checks_file(&self) -> bool {
+        self.policy.checks_file()
+    }
+
+    pub(crate) fn current(&self) -> Option<Arc<Snapshot>> {
+        self.current
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    ///The current snapshot, if it can be used for a lookup requested at
+    ///`now` with the files looking like `stamps`
+    pub(crate) fn fresh(
+        &self,
+        now: Instant,
+        stamps: [Option<FileStamp>; 2],
+    ) -> Option<Arc<Snapshot>> {
+        let current = self.current()?;
+        if let Some(max_age) = self.policy.max_age() {
+            if now.saturating_duration_since(current.at) >= max_age {
+                return None;
+            }
+        }
+        match !self.policy.checks_file() || stamps == current.stamps {
+            true => Some(current),
+            false => None,
+        }
+    }
+
+    ///The current snapshot, if it is fresh or its files were read after
+    ///`requested`. Called while holding the refresh lock, so that
+    ///everyone waiting for a refresh shares the one that was done.
+    pub(crate) fn refreshed(
+        &self,
+        requested: Instant,
+        stamps: [Option<FileStamp>; 2],
+    ) -> Option<Arc<Snapshot>> {
+        match self.current() {
+            Some(current) if current.at >= requested => Some(current),
+            _ => self.fresh(requested, stamps),
+        }
+    }
+
+    ///Parses the file contents into a new snapshot and swaps it in
+    pub(crate) fn store(
+        &self,
+        started: Instant,
+        stamps: [Option<FileStamp>; 2],
+        passwd: &[u8],
+        group: &[u8],
+    ) -> Result<Arc<Snapshot>, Error> {
+        let (users, passwd_warnings) = crate::parse_lines(
+            &String::from_utf8_lossy(passwd),
+            self.mode,
+            PasswdEntry::try_parse,
+        )?;
+        let (groups, group_warnings) = crate::parse_lines(
+            &String::from_utf8_lossy(group),
+            self.mode,
+            GroupEntry::try_parse,
+        )?;
+        let snapshot = Arc::new(Snapshot {
+            database: UserDatabase::new(users, groups),
+            passwd_warnings,
+            group_warnings,
+            at: started,
+            wall_clock: SystemTime::now(),
+            stamps,
+        });
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = Some(snapshot.clone());
+        Ok(snapshot)
+    }
+}
@@ -0,0 +1,564 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `source` holds the [UserSource], [GroupSource] and [ShadowSource]
+//! traits, implemented by the readers, so code can look up users and groups
+//! without knowing where they come from.
+//!
+//! A [Chain] asks several sources in order, like the services of a line in
+//! /etc/nsswitch.conf. Each source returns a [Status], and the [Actions] of
+//! the source decide if the chain returns or continues with the next source.
+//! By default the chain returns on the first success, like `nsswitch`.
+//!
+//! ```
+//! # #[cfg(feature = "sync")] {
+//! use user_lookup::source::{Action, Actions, MemorySource, Status, UserChain, UserSource};
+//! use user_lookup::sync_reader::PasswdReader;
+//! use user_lookup::PasswdEntry;
+//! use std::time::Duration;
+//!
+//! let overlay = MemorySource::new(
+//!     vec![PasswdEntry::parse("user3:x:1002:100::/home/user3:/bin/sh").unwrap()],
+//!     vec![],
+//! );
+//! let mut chain = UserChain::new()
+//!     .with(Box::new(PasswdReader::from_file("test_files/passwd", Duration::new(0, 0))))
+//!     .with(Box::new(overlay));
+//!
+//! assert_eq!(Some(1000), chain.get_by_username("user1").unwrap().map(|e| e.uid));
+//! assert_eq!(Some(1002), chain.get_by_username("user3").unwrap().map(|e| e.uid));
+//! assert_eq!(4, chain.get_entries().unwrap().len());
+//!
+//! //Like `files [NOTFOUND=return]`, users missing from the file are not
+//! //looked up in the overlay
+//! let overlay = MemorySource::new(
+//!     vec![PasswdEntry::parse("user3:x:1002:100::/home/user3:/bin/sh").unwrap()],
+//!     vec![],
+//! );
+//! let mut chain = UserChain::new()
+//!     .with_actions(
+//!         Box::new(PasswdReader::from_file("test_files/passwd", Duration::new(0, 0))),
+//!         Actions::default().on(Status::NotFound, Action::Return),
+//!     )
+//!     .with(Box::new(overlay));
+//! assert_eq!(None, chain.get_by_username("user3").unwrap());
+//! # }
+//! ```
+use crate::Error;
+use crate::GroupEntry;
+use crate::PasswdEntry;
+use crate::ShadowEntry;
+
+use crate::index::GroupIndex;
+use crate::index::PasswdIndex;
+
+use std::collections::HashSet;
+use std::io::ErrorKind;
+
+///A source of users, like a passwd file or the C library
+pub trait UserSource {
+    ///Look up a PasswdEntry by username
+    fn get_by_username(&mut self, username: &str) -> Result<Option<PasswdEntry>, Error>;
+
+    ///Look up a PasswdEntry by uid
+    fn get_by_uid(&mut self, uid: u32) -> Result<Option<PasswdEntry>, Error>;
+
+    ///Get all the passwd entries of the source
+    fn get_entries(&mut self) -> Result<Vec<PasswdEntry>, Error>;
+}
+
+///A source of groups, like a group file or the C library
+pub trait GroupSource {
+    ///Look up a GroupEntry by the group name
+    fn get_by_name(&mut self, name: &str) -> Result<Option<GroupEntry>, Error>;
+
+    ///Look up a GroupEntry by gid
+    fn get_by_gid(&mut self, gid: u32) -> Result<Option<GroupEntry>, Error>;
+
+    ///Get all the group entries of the source
+    fn get_groups(&mut self) -> Result<Vec<GroupEntry>, Error>;
+
+    ///Get all group IDs of a user, like `getgrouplist`. The primary
+    ///gid comes first.
+    fn get_gids_for_user(&mut self, user: &PasswdEntry) -> Result<Vec<u32>, Error> {
+        let groups = self.get_groups()?;
+        Ok(GroupIndex::new(groups).get_gid_list(&user.username, user.gid))
+    }
+}
+
+///A source of shadow entries, like a shadow file
+pub trait ShadowSource {
+    ///Look up a ShadowEntry by username
+    fn get_by_name(&mut self, name: &str) -> Result<Option<ShadowEntry>, Error>;
+
+    ///Get all the shadow entries of the source
+    fn get_entries(&mut self) -> Result<Vec<ShadowEntry>, Error>;
+}
+
+///The outcome of asking a source, like the status of an nsswitch service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Status {
+    ///The entry was found
+    Success,
+    ///The source was asked, but has no such entry
+    NotFound,
+    ///The source can not be used, like a missing file
+    Unavail,
+    ///The source is busy and may work if asked again
+    TryAgain,
+}
+
+impl Status {
+    ///The status of a lookup result
+    pub fn of<T>(result: &Result<Option<T>, Error>) -> Status {
+        match result {
+            Ok(Some(_)) => Status::Success,
+            Ok(None) => Status::NotFound,
+            Err(e) => Status::of_error(e),
+        }
+    }
+
+    ///The status of a failed lookup. Timeouts and interrupted calls are
+    ///[Status::TryAgain], every other error is [Status::Unavail].
+    pub fn of_error(e: &Error) -> Status {
+        match e {
+            Error::Io(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::Interrupted | ErrorKind::TimedOut
+                ) =>
+            {
+                Status::TryAgain
+            }
+            _ => Status::Unavail,
+        }
+    }
+}
+
+///What a [Chain] does after a source returned a [Status]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    ///Return the result of this source
+    Return,
+    ///Ask the next source
+    Continue,
+}
+
+///The [Action] for every [Status], like the `[NOTFOUND=return]` items
+///of /etc/nsswitch.conf. The default returns on [Status::Success] and
+///continues otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Actions {
+    success: Action,
+    not_found: Action,
+    unavail: Action,
+    try_again: Action,
+}
+
+impl Default for Actions {
+    fn default() -> Self {
+        Self {
+            success: Action::Return,
+            not_found: Action::Continue,
+            unavail: Action::Continue,
+            try_again: Action::Continue,
+        }
+    }
+}
+
+impl Actions {
+    ///Sets the action for a status
+    pub fn on(mut self, status: Status, action: Action) -> Self {
+        *self.action_mut(status) = action;
+        self
+    }
+
+    ///The action for a status
+    pub fn get(&self, status: Status) -> Action {
+        match status {
+            Status::Success => self.success,
+            Status::NotFound => self.not_found,
+            Status::Unavail => self.unavail,
+            Status::TryAgain => self.try_again,
+        }
+    }
+
+    fn action_mut(&mut self, status: Status) -> &mut Action {
+        match status {
+            Status::Success => &mut self.success,
+            Status::NotFound => &mut self.not_found,
+            Status::Unavail => &mut self.unavail,
+            Status::TryAgain => &mut self.try_again,
+        }
+    }
+}
+
+struct Link<S: ?Sized> {
+    source: Box<S>,
+    actions: Actions,
+}
+
+///Asks several sources in order, like a line of /etc/nsswitch.conf.
+///`S` is the kind of source, `dyn UserSource`, `dyn GroupSource` or
+///`dyn ShadowSource`.
+///
+///A lookup stops at the first source whose [Actions] say
+///[Action::Return] for its [Status], and returns the result of that
+///source. If every source says [Action::Continue], the result of the last
+///source is returned, so an error of the last source is returned as is.
+///
+///Listing all entries concatenates the entries of every source, like
+///`getent passwd`. Sources that fail are skipped, unless their action for
+///the failure is [Action::Return].
+pub struct Chain<S: ?Sized> {
+    links: Vec<Link<S>>,
+}
+
+///A [Chain] of user sources
+pub type UserChain = Chain<dyn UserSource>;
+
+///A [Chain] of group sources
+pub type GroupChain = Chain<dyn GroupSource>;
+
+///A [Chain] of shadow sources
+pub type ShadowChain = Chain<dyn ShadowSource>;
+
+impl<S: ?Sized> Default for Chain<S> {
+    fn default() -> Self {
+        Self { links: vec![] }
+    }
+}
+
+impl<S: ?Sized> Chain<S> {
+    ///Creates an empty chain, which finds nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Adds a source at the end of the chain, with the default [Actions]
+    pub fn with(self, source: Box<S>) -> Self {
+        self.with_actions(source, Actions::default())
+    }
+
+    ///Adds a source at the end of the chain
+    pub fn with_actions(mut self, source: Box<S>, actions: Actions) -> Self {
+        self.push(source, actions);
+        self
+    }
+
+    ///Adds a source at the end of the chain
+    pub fn push(&mut self, source: Box<S>, actions: Actions) {
+        self.links.push(Link { source, actions });
+    }
+
+    ///The number of sources in the chain
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+
+    ///Whether the chain has no sources
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    fn lookup<T>(
+        &mut self,
+        mut query: impl FnMut(&mut S) -> Result<Option<T>, Error>,
+    ) -> Result<Option<T>, Error> {
+        let mut result = Ok(None);
+        for link in &mut self.links {
+            result = query(&mut link.source);
+            if link.actions.get(Status::of(&result)) == Action::Return {
+                break;
+            }
+        }
+        result
+    }
+
+    fn collect<T>(
+        &mut self,
+        mut query: impl FnMut(&mut S) -> Result<Vec<T>, Error>,
+    ) -> Result<Vec<T>, Error> {
+        let mut all = vec![];
+        for link in &mut self.links {
+            match query(&mut link.source) {
+                Ok(entries) => all.extend(entries),
+                Err(e) if link.actions.get(Status::of_error(&e)) == Action::Return => {
+                    return Err(e)
+                }
+                Err(_) => {}
+            }
+        }
+        Ok(all)
+    }
+}
+
+impl<S: UserSource + ?Sized> UserSource for Chain<S> {
+    fn get_by_username(&mut self, username: &str) -> Result<Option<PasswdEntry>, Error> {
+        self.lookup(|s| s.get_by_username(username))
+    }
+
+    fn get_by_uid(&mut self, uid: u32) -> Result<Option<PasswdEntry>, Error> {
+        self.lookup(|s| s.get_by_uid(uid))
+    }
+
+    fn get_entries(&mut self) -> Result<Vec<PasswdEntry>, Error> {
+        self.collect(|s| s.get_entries())
+    }
+}
+
+impl<S: GroupSource + ?Sized> GroupSource for Chain<S> {
+    fn get_by_name(&mut self, name: &str) -> Result<Option<GroupEntry>, Error> {
+        self.lookup(|s| s.get_by_name(name))
+    }
+
+    fn get_by_gid(&mut self, gid: u32) -> Result<Option<GroupEntry>, Error> {
+        self.lookup(|s| s.get_by_gid(gid))
+    }
+
+    fn get_groups(&mut self) -> Result<Vec<GroupEntry>, Error> {
+        self.collect(|s| s.get_groups())
+    }
+
+    ///The gids of the user in every source, like `initgroups`
+    fn get_gids_for_user(&mut self, user: &PasswdEntry) -> Result<Vec<u32>, Error> {
+        let mut seen = HashSet::new();
+        let gids = self.collect(|s| s.get_gids_for_user(user))?;
+        Ok(std::iter::once(user.gid)
+            .chain(gids)
+            .filter(|gid| seen.insert(*gid))
+            .collect())
+    }
+}
+
+impl<S: ShadowSource + ?Sized> ShadowSource for Chain<S> {
+    fn get_by_name(&mut self, name: &str) -> Result<Option<ShadowEntry>, Error> {
+        self.lookup(|s| s.get_by_name(name))
+    }
+
+    fn get_entries(&mut self) -> Result<Vec<ShadowEntry>, Error> {
+        self.collect(|s| s.get_entries())
+    }
+}
+
+///Users and groups kept in memory, to add entries on top of other sources
+///in a [Chain]
+#[derive(Debug, Clone, Default)]
+pub struct MemorySource {
+    passwd: PasswdIndex,
+    groups: GroupIndex,
+}
+
+impl MemorySource {
+    ///Creates a source with the users and groups
+    pub fn new(users: Vec<PasswdEntry>, groups: Vec<GroupEntry>) -> Self {
+        Self {
+            passwd: PasswdIndex::new(users),
+            groups: GroupIndex::new(groups),
+        }
+    }
+
+    ///Adds a user. An earlier user with the same name or uid is found first.
+    pub fn add_user(&mut self, user: PasswdEntry) {
+        let mut users = std::mem::take(&mut self.passwd).entries().clone();
+        users.push(user);
+        self.passwd = PasswdIndex::new(users);
+    }
+
+    ///Adds a group. An earlier group with the same name or gid is found first.
+    pub fn add_group(&mut self, group: GroupEntry) {
+        let mut groups = std::mem::take(&mut self.groups).groups().clone();
+        groups.push(group);
+        self.groups = GroupIndex::new(groups);
+    }
+}
+
+impl UserSource for MemorySource {
+    fn get_by_username(&mut self, username: &str) -> Result<Option<PasswdEntry>, Error> {
+        Ok(self.passwd.get_by_username(username).cloned())
+    }
+
+    fn get_by_uid(&mut self, uid: u32) -> Result<Option<PasswdEntry>, Error> {
+        Ok(self.passwd.get_by_uid(uid).cloned())
+    }
+
+    fn get_entries(&mut self) -> Result<Vec<PasswdEntry>, Error> {
+        Ok(self.passwd.entries().clone())
+    }
+}
+
+impl GroupSource for MemorySource {
+    fn get_by_name(&mut self, name: &str) -> Result<Option<GroupEntry>, Error> {
+        Ok(self.groups.get_by_name(name).cloned())
+    }
+
+    fn get_by_gid(&mut self, gid: u32) -> Result<Option<GroupEntry>, Error> {
+        Ok(self.groups.get_by_gid(gid).cloned())
+    }
+
+    fn get_groups(&mut self) -> Result<Vec<GroupEntry>, Error> {
+        Ok(self.groups.groups().clone())
+    }
+
+    fn get_gids_for_user(&mut self, user: &PasswdEntry) -> Result<Vec<u32>, Error> {
+        Ok(self.groups.get_gid_list(&user.username, user.gid))
+    }
+}
+
+impl UserSource for crate::database::UserDatabase {
+    fn get_by_username(&mut self, username: &str) -> Result<Option<PasswdEntry>, Error> {
+        Ok(crate::database::UserDatabase::get_by_username(self, username).cloned())
+    }
+
+    fn get_by_uid(&mut self, uid: u32) -> Result<Option<PasswdEntry>, Error> {
+        Ok(crate::database::UserDatabase::get_by_uid(self, uid).cloned())
+    }
+
+    fn get_entries(&mut self) -> Result<Vec<PasswdEntry>, Error> {
+        Ok(crate::database::UserDatabase::get_entries(self).clone())
+    }
+}
+
+impl GroupSource for crate::database::UserDatabase {
+    fn get_by_name(&mut self, name: &str) -> Result<Option<GroupEntry>, Error> {
+        Ok(self.get_group_by_name(name).cloned())
+    }
+
+    fn get_by_gid(&mut self, gid: u32) -> Result<Option<GroupEntry>, Error> {
+        Ok(self.get_group_by_gid(gid).cloned())
+    }
+
+    fn get_groups(&mut self) -> Result<Vec<GroupEntry>, Error> {
+        Ok(crate::database::UserDatabase::get_groups(self).clone())
+    }
+
+    fn get_gids_for_user(&mut self, user: &PasswdEntry) -> Result<Vec<u32>, Error> {
+        Ok(crate::database::UserDatabase::get_gids_for_user(self, user))
+    }
+}
+
+#[cfg(feature = "sync")]
+impl UserSource for crate::sync_reader::PasswdReader {
+    fn get_by_username(&mut self, username: &str) -> Result<Option<PasswdEntry>, Error> {
+        self.get_by_username(username)
+    }
+
+    fn get_by_uid(&mut self, uid: u32) -> Result<Option<PasswdEntry>, Error> {
+        self.get_by_uid(uid)
+    }
+
+    fn get_entries(&mut self) -> Result<Vec<PasswdEntry>, Error> {
+        self.get_entries().cloned()
+    }
+}
+
+#[cfg(feature = "sync")]
+impl GroupSource for crate::sync_reader::GroupReader {
+    fn get_by_name(&mut self, name: &str) -> Result<Option<GroupEntry>, Error> {
+        self.get_by_name(name)
+    }
+
+    fn get_by_gid(&mut self, gid: u32) -> Result<Option<GroupEntry>, Error> {
+        self.get_by_gid(gid)
+    }
+
+    fn get_groups(&mut self) -> Result<Vec<GroupEntry>, Error> {
+        self.get_groups().cloned()
+    }
+
+    fn get_gids_for_user(&mut self, user: &PasswdEntry) -> Result<Vec<u32>, Error> {
+        self.get_gids_for_user(user)
+    }
+}
+
+#[cfg(feature = "sync")]
+impl UserSource for crate::sync_reader::SharedReader {
+    fn get_by_username(&mut self, username: &str) -> Result<Option<PasswdEntry>, Error> {
+        crate::sync_reader::SharedReader::get_by_username(self, username)
+    }
+
+    fn get_by_uid(&mut self, uid: u32) -> Result<Option<PasswdEntry>, Error> {
+        crate::sync_reader::SharedReader::get_by_uid(self, uid)
+    }
+
+    fn get_entries(&mut self) -> Result<Vec<PasswdEntry>, Error> {
+        Ok(self.snapshot()?.passwd().entries().clone())
+    }
+}
+
+#[cfg(feature = "sync")]
+impl GroupSource for crate::sync_reader::SharedReader {
+    fn get_by_name(&mut self, name: &str) -> Result<Option<GroupEntry>, Error> {
+        self.get_group_by_name(name)
+    }
+
+    fn get_by_gid(&mut self, gid: u32) -> Result<Option<GroupEntry>, Error> {
+        self.get_group_by_gid(gid)
+    }
+
+    fn get_groups(&mut self) -> Result<Vec<GroupEntry>, Error> {
+        Ok(self.snapshot()?.groups().groups().clone())
+    }
+
+    fn get_gids_for_user(&mut self, user: &PasswdEntry) -> Result<Vec<u32>, Error> {
+        crate::sync_reader::SharedReader::get_gids_for_user(self, user)
+    }
+}
+
+#[cfg(feature = "sync")]
+impl ShadowSource for crate::sync_reader::ShadowReader {
+    fn get_by_name(&mut self, name: &str) -> Result<Option<ShadowEntry>, Error> {
+        self.get_by_name(name)
+    }
+
+    fn get_entries(&mut self) -> Result<Vec<ShadowEntry>, Error> {
+        self.get_entries().cloned()
+    }
+}
+
+#[cfg(all(
+    feature = "nss",
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+impl UserSource for crate::nss::PasswdReader {
+    fn get_by_username(&mut self, username: &str) -> Result<Option<PasswdEntry>, Error> {
+        self.get_by_username(username)
+    }
+
+    fn get_by_uid(&mut self, uid: u32) -> Result<Option<PasswdEntry>, Error> {
+        self.get_by_uid(uid)
+    }
+
+    fn get_entries(&mut self) -> Result<Vec<PasswdEntry>, Error> {
+        self.get_entries().cloned()
+    }
+}
+
+#[cfg(all(
+    feature = "nss",
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+impl GroupSource for crate::nss::GroupReader {
+    fn get_by_name(&mut self, name: &str) -> Result<Option<GroupEntry>, Error> {
+        self.get_by_name(name)
+    }
+
+    fn get_by_gid(&mut self, gid: u32) -> Result<Option<GroupEntry>, Error> {
+        self.get_by_gid(gid)
+    }
+
+    fn get_groups(&mut self) -> Result<Vec<GroupEntry>, Error> {
+        self.get_groups().cloned()
+    }
+
+    fn get_gids_for_user(&mut self, user: &PasswdEntry) -> Result<Vec<u32>, Error> {
+        self.get_gids_for_user(user)
+    }
+}
@@ -6,8 +6,9 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-//! `sync_reader` provides readers for PasswdReader and GroupReader,
-//! to read and process /etc/passwd and /etc/group
+//! `sync_reader` provides readers for PasswdReader, GroupReader, ShadowReader,
+//! GshadowReader and SubIdReader, to read and process /etc/passwd, /etc/group,
+//! /etc/shadow, /etc/gshadow and /etc/subuid or /etc/subgid
 //!
 //!```rust,ignore
 //! use user_lookup::sync_reader::PasswdReader;
@@ -21,12 +22,52 @@
 //! }
 //!
 //!```
+use crate::Error;
 use crate::GroupEntry;
+use crate::GshadowEntry;
 use crate::PasswdEntry;
+use crate::ShadowEntry;
+use crate::SubIdEntry;
+use crate::SubIdOwner;
 
+use crate::cache::CachePolicy;
+use crate::cache::FileCache;
+use crate::cache::FileStamp;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+use crate::database::UserDatabase;
+use crate::error::ParseError;
+use crate::error::ParseMode;
+use crate::index::GroupIndex;
+use crate::index::GshadowIndex;
+use crate::index::PasswdIndex;
+use crate::index::ShadowIndex;
+use crate::index::SubIdIndex;
+use crate::login_defs::AccountClass;
+use crate::login_defs::LoginDefs;
+use crate::shared::SharedState;
+use crate::shared::Snapshot;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+use crate::watch::Event;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+use crate::watch::FileWatcher;
+
+use std::collections::VecDeque;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::PoisonError;
 use std::time::Duration;
 use std::time::Instant;
+use std::time::SystemTime;
 
 ///The main entity to reaad and lookup user information. It
 ///supports caching the information to avoid having to read
@@ -43,10 +84,8 @@ use std::time::Instant;
 /// assert_eq!(Some("user2".to_string()), reader.get_username_by_uid(1001).unwrap());
 /// ```
 pub struct PasswdReader {
-    file: Option<PathBuf>,
-    cache_time: Duration,
-    last_check: Instant,
-    passwd: Vec<PasswdEntry>,
+    cache: FileCache,
+    passwd: PasswdIndex,
 }
 
 impl PasswdReader {
@@ -54,13 +93,12 @@ impl PasswdReader {
     ///specified cache_time in seconds.
     ///
     ///Use cache_time with a Duration of 0 to disable caching.
-    pub fn new(cache_time: Duration) -> Self {
-        let last_check = Instant::now() - (cache_time);
+    ///A [CachePolicy] can be passed instead of a Duration for
+    ///more control over when the file is read again.
+    pub fn new<P: Into<CachePolicy>>(cache_time: P) -> Self {
         Self {
-            file: None,
-            cache_time,
-            last_check,
-            passwd: vec![],
+            cache: FileCache::new("/etc/passwd".into(), cache_time.into()),
+            passwd: PasswdIndex::default(),
         }
     }
 
@@ -69,79 +107,176 @@ impl PasswdReader {
     /// location. Uses the specified cache_time in seconds.
     ///
     ///Use cache_time with a Duration of 0 to disable caching.
-    pub fn from_file<T: Into<PathBuf>>(file: T, cache_time: Duration) -> Self {
-        let last_check = Instant::now() - (cache_time);
+    ///A [CachePolicy] can be passed instead of a Duration for
+    ///more control over when the file is read again.
+    pub fn from_file<T: Into<PathBuf>, P: Into<CachePolicy>>(file: T, cache_time: P) -> Self {
         Self {
-            file: Some(file.into()),
-            cache_time,
-            last_check,
-            passwd: vec![],
+            cache: FileCache::new(file.into(), cache_time.into()),
+            passwd: PasswdIndex::default(),
         }
     }
 
-    fn refresh_if_needed(&mut self) -> Result<(), std::io::Error> {
-        if Instant::now() < (self.last_check + self.cache_time) {
+    fn refresh_if_needed(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        let stamp = match self.cache.checks_file() {
+            true => Some(FileStamp::from(&std::fs::metadata(self.cache.path())?)),
+            false => None,
+        };
+        if self.cache.is_fresh(now, stamp) {
             return Ok(());
         }
-        let contents =
-            std::fs::read_to_string(self.file.as_ref().unwrap_or(&"/etc/passwd".into()))?;
-        self.passwd = contents.lines().filter_map(PasswdEntry::parse).collect();
+        let contents = std::fs::read(self.cache.path())?;
+        self.passwd = PasswdIndex::new(self.cache.parse(&contents, PasswdEntry::try_parse)?);
+        self.cache.mark_loaded(now, stamp);
         Ok(())
     }
 
+    ///Sets how lines that can not be parsed are handled. The default
+    ///is [ParseMode::Lenient], which skips them.
+    pub fn with_parse_mode(mut self, mode: ParseMode) -> Self {
+        self.cache.set_mode(mode);
+        self
+    }
+
+    ///The lines skipped the last time the file was read, in
+    ///[ParseMode::Lenient]
+    /// ```
+    /// use user_lookup::sync_reader::PasswdReader;
+    /// use user_lookup::error::{ParseErrorKind, ParseMode};
+    /// use user_lookup::Error;
+    /// use std::time::Duration;
+    ///
+    /// let mut reader = PasswdReader::from_file("test_files/passwd.broken", Duration::new(0, 0));
+    /// assert_eq!(2, reader.get_entries().unwrap().len());
+    /// assert_eq!(2, reader.warnings()[0].line);
+    /// assert_eq!(ParseErrorKind::InvalidUid("abc".to_string()), reader.warnings()[0].kind);
+    ///
+    /// let mut reader = PasswdReader::from_file("test_files/passwd.broken", Duration::new(0, 0))
+    ///     .with_parse_mode(ParseMode::Strict);
+    /// match reader.get_entries() {
+    ///     Err(Error::Parse(e)) => assert_eq!(2, e.line),
+    ///     _ => panic!("expected a parse error"),
+    /// }
+    /// ```
+    pub fn warnings(&self) -> &[ParseError] {
+        self.cache.warnings()
+    }
+
+    ///Returns when the file was last read, or `None` if it has
+    ///not been read yet.
+    /// ```
+    /// use user_lookup::sync_reader::PasswdReader;
+    /// use user_lookup::cache::CachePolicy;
+    ///
+    /// let mut reader = PasswdReader::from_file("test_files/passwd", CachePolicy::on_change());
+    /// assert_eq!(None, reader.last_loaded());
+    ///
+    /// reader.get_entries().unwrap();
+    /// let loaded = reader.last_loaded().unwrap();
+    ///
+    /// //The file is unchanged, so it is not read again
+    /// reader.get_entries().unwrap();
+    /// assert_eq!(Some(loaded), reader.last_loaded());
+    /// ```
+    pub fn last_loaded(&self) -> Option<SystemTime> {
+        self.cache.last_loaded()
+    }
+
     ///Get all the entire list of passwd entries
-    pub fn get_entries(&mut self) -> Result<&Vec<PasswdEntry>, std::io::Error> {
+    pub fn get_entries(&mut self) -> Result<&Vec<PasswdEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.passwd.entries())
+    }
+
+    ///Get the index over the passwd entries, to do several
+    ///lookups against the same data without re-checking the file.
+    /// ```
+    /// use user_lookup::sync_reader::PasswdReader;
+    /// use std::time::Duration;
+    ///
+    /// let mut reader = PasswdReader::from_file("test_files/passwd", Duration::new(0, 0));
+    /// let index = reader.get_index().unwrap();
+    /// let owners: Vec<&str> = [0, 1001, 4711]
+    ///     .iter()
+    ///     .filter_map(|uid| index.get_by_uid(*uid))
+    ///     .map(|e| e.username.as_str())
+    ///     .collect();
+    ///
+    /// assert_eq!(vec!["root", "user2"], owners);
+    /// ```
+    pub fn get_index(&mut self) -> Result<&PasswdIndex, Error> {
         self.refresh_if_needed()?;
         Ok(&self.passwd)
     }
 
     ///Will return an iterator over &PasswdEntry
-    pub fn try_iter(&mut self) -> Result<std::slice::Iter<PasswdEntry>, std::io::Error> {
+    pub fn try_iter(&mut self) -> Result<std::slice::Iter<'_, PasswdEntry>, Error> {
         self.refresh_if_needed()?;
-        Ok(self.passwd.iter())
+        Ok(self.passwd.entries().iter())
     }
 
-    ///Look up a PasswdEntry by username
-    pub fn get_by_username(
+    ///Will return an iterator over the regular users, with
+    ///a uid between `UID_MIN` and `UID_MAX` of `defs`
+    /// ```
+    /// use user_lookup::sync_reader::PasswdReader;
+    /// use user_lookup::login_defs::LoginDefs;
+    /// use std::time::Duration;
+    ///
+    /// let defs = LoginDefs::from_file("test_files/login.defs").unwrap();
+    /// let mut reader = PasswdReader::from_file("test_files/passwd", Duration::new(0, 0));
+    /// let users: Vec<&str> = reader.regular_users(&defs).unwrap().map(|e| e.username.as_str()).collect();
+    /// assert_eq!(vec!["user1", "user2"], users);
+    /// ```
+    pub fn regular_users(
         &mut self,
-        username: &str,
-    ) -> Result<Option<PasswdEntry>, std::io::Error> {
+        defs: &LoginDefs,
+    ) -> Result<impl Iterator<Item = &PasswdEntry>, Error> {
         self.refresh_if_needed()?;
+        let is_regular = defs.uid_filter(AccountClass::Regular);
         Ok(self
             .passwd
+            .entries()
             .iter()
-            .find(|e| e.username == username)
-            .map(|e| e.to_owned()))
+            .filter(move |e| is_regular(e.uid)))
     }
 
-    ///Look up a PasswdEntry by uid
-    pub fn get_by_uid(&mut self, uid: u32) -> Result<Option<PasswdEntry>, std::io::Error> {
+    ///Will return an iterator over the system users, with
+    ///a uid of at most `SYS_UID_MAX` of `defs` that is not regular
+    pub fn system_users(
+        &mut self,
+        defs: &LoginDefs,
+    ) -> Result<impl Iterator<Item = &PasswdEntry>, Error> {
         self.refresh_if_needed()?;
+        let is_system = defs.uid_filter(AccountClass::System);
         Ok(self
             .passwd
+            .entries()
             .iter()
-            .find(|e| e.uid == uid)
-            .map(|e| e.to_owned()))
+            .filter(move |e| is_system(e.uid)))
+    }
+
+    ///Look up a PasswdEntry by username
+    pub fn get_by_username(&mut self, username: &str) -> Result<Option<PasswdEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.passwd.get_by_username(username).cloned())
+    }
+
+    ///Look up a PasswdEntry by uid
+    pub fn get_by_uid(&mut self, uid: u32) -> Result<Option<PasswdEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.passwd.get_by_uid(uid).cloned())
     }
 
     ///Look up a username by uid
-    pub fn get_username_by_uid(&mut self, uid: u32) -> Result<Option<String>, std::io::Error> {
+    pub fn get_username_by_uid(&mut self, uid: u32) -> Result<Option<String>, Error> {
         self.refresh_if_needed()?;
-        Ok(self
-            .passwd
-            .iter()
-            .find(|e| e.uid == uid)
-            .map(|e| e.username.to_owned()))
+        Ok(self.passwd.get_by_uid(uid).map(|e| e.username.to_owned()))
     }
 
     ///Look up a user ID by username
-    pub fn get_uid_by_username(&mut self, username: &str) -> Result<Option<u32>, std::io::Error> {
+    pub fn get_uid_by_username(&mut self, username: &str) -> Result<Option<u32>, Error> {
         self.refresh_if_needed()?;
-        Ok(self
-            .passwd
-            .iter()
-            .find(|e| e.username == username)
-            .map(|e| e.uid))
+        Ok(self.passwd.get_by_username(username).map(|e| e.uid))
     }
 }
 
@@ -149,7 +284,7 @@ impl PasswdReader {
 ///supports caching the information to avoid having to read
 ///the information from disk more than needed.
 ///```
-/// use user_lookup::sync_reader::GroupReader;
+/// use user_lookup::sync_reader::{GroupReader, PasswdReader};
 /// use std::time::Duration;
 ///
 /// let mut reader = GroupReader::from_file("test_files/group",Duration::new(0,0));
@@ -157,12 +292,13 @@ impl PasswdReader {
 ///
 /// assert_eq!(3, groups.len());
 /// assert_eq!(Some("users".to_string()), reader.get_name_by_gid(100).unwrap());
+///
+/// let mut passwd = PasswdReader::from_file("test_files/passwd",Duration::new(0,0));
+/// assert_eq!(Some(vec![100, 10]), reader.get_gids_by_username("user1", &mut passwd).unwrap());
 /// ```
 pub struct GroupReader {
-    file: Option<PathBuf>,
-    cache_time: Duration,
-    last_check: Instant,
-    groups: Vec<GroupEntry>,
+    cache: FileCache,
+    groups: GroupIndex,
 }
 
 impl GroupReader {
@@ -170,13 +306,12 @@ impl GroupReader {
     ///specified cache_time in seconds.
     ///
     ///Use cache_time with a duration of 0 to disable caching.
-    pub fn new(cache_time: Duration) -> Self {
-        let last_check = Instant::now() - (cache_time);
+    ///A [CachePolicy] can be passed instead of a Duration for
+    ///more control over when the file is read again.
+    pub fn new<P: Into<CachePolicy>>(cache_time: P) -> Self {
         Self {
-            file: None,
-            cache_time,
-            last_check,
-            groups: vec![],
+            cache: FileCache::new("/etc/group".into(), cache_time.into()),
+            groups: GroupIndex::default(),
         }
     }
 
@@ -185,70 +320,897 @@ impl GroupReader {
     ///uses the specified cache_time in seconds.
     ///
     ///Use cache_time with a duration of 0 to disable caching.
-    pub fn from_file<T: Into<PathBuf>>(file: T, cache_time: Duration) -> Self {
-        let last_check = Instant::now() - (cache_time);
+    ///A [CachePolicy] can be passed instead of a Duration for
+    ///more control over when the file is read again.
+    pub fn from_file<T: Into<PathBuf>, P: Into<CachePolicy>>(file: T, cache_time: P) -> Self {
         Self {
-            file: Some(file.into()),
-            cache_time,
-            last_check,
-            groups: vec![],
+            cache: FileCache::new(file.into(), cache_time.into()),
+            groups: GroupIndex::default(),
         }
     }
 
-    fn refresh_if_needed(&mut self) -> Result<(), std::io::Error> {
-        if Instant::now() < (self.last_check + self.cache_time) {
+    fn refresh_if_needed(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        let stamp = match self.cache.checks_file() {
+            true => Some(FileStamp::from(&std::fs::metadata(self.cache.path())?)),
+            false => None,
+        };
+        if self.cache.is_fresh(now, stamp) {
             return Ok(());
         }
-        let contents = std::fs::read_to_string(self.file.as_ref().unwrap_or(&"/etc/group".into()))?;
-        self.groups = contents.lines().filter_map(GroupEntry::parse).collect();
+        let contents = std::fs::read(self.cache.path())?;
+        self.groups = GroupIndex::new(self.cache.parse(&contents, GroupEntry::try_parse)?);
+        self.cache.mark_loaded(now, stamp);
         Ok(())
     }
 
+    ///Sets how lines that can not be parsed are handled. The default
+    ///is [ParseMode::Lenient], which skips them.
+    pub fn with_parse_mode(mut self, mode: ParseMode) -> Self {
+        self.cache.set_mode(mode);
+        self
+    }
+
+    ///The lines skipped the last time the file was read, in
+    ///[ParseMode::Lenient]
+    pub fn warnings(&self) -> &[ParseError] {
+        self.cache.warnings()
+    }
+
+    ///Returns when the file was last read, or `None` if it has
+    ///not been read yet.
+    pub fn last_loaded(&self) -> Option<SystemTime> {
+        self.cache.last_loaded()
+    }
+
     ///Get the entire list of group entries
-    pub fn get_groups(&mut self) -> Result<&Vec<GroupEntry>, std::io::Error> {
+    pub fn get_groups(&mut self) -> Result<&Vec<GroupEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.groups.groups())
+    }
+
+    ///Get the index over the group entries, to do several
+    ///lookups against the same data without re-checking the file.
+    pub fn get_index(&mut self) -> Result<&GroupIndex, Error> {
         self.refresh_if_needed()?;
         Ok(&self.groups)
     }
 
     ///Will return an iterator over &GroupEntry
-    pub fn try_iter(&mut self) -> Result<std::slice::Iter<GroupEntry>, std::io::Error> {
+    pub fn try_iter(&mut self) -> Result<std::slice::Iter<'_, GroupEntry>, Error> {
         self.refresh_if_needed()?;
-        Ok(self.groups.iter())
+        Ok(self.groups.groups().iter())
     }
 
-    ///Look up a GroupEntry by the group name
-    pub fn get_by_name(&mut self, name: &str) -> Result<Option<GroupEntry>, std::io::Error> {
+    ///Will return an iterator over the regular groups, with
+    ///a gid between `GID_MIN` and `GID_MAX` of `defs`
+    pub fn regular_groups(
+        &mut self,
+        defs: &LoginDefs,
+    ) -> Result<impl Iterator<Item = &GroupEntry>, Error> {
         self.refresh_if_needed()?;
+        let is_regular = defs.gid_filter(AccountClass::Regular);
         Ok(self
             .groups
+            .groups()
             .iter()
-            .find(|e| e.name == name)
-            .map(|e| e.to_owned()))
+            .filter(move |e| is_regular(e.gid)))
     }
 
-    ///Look up a GroupEntry by gid
-    pub fn get_by_gid(&mut self, gid: u32) -> Result<Option<GroupEntry>, std::io::Error> {
+    ///Will return an iterator over the system groups, with
+    ///a gid of at most `SYS_GID_MAX` of `defs` that is not regular
+    pub fn system_groups(
+        &mut self,
+        defs: &LoginDefs,
+    ) -> Result<impl Iterator<Item = &GroupEntry>, Error> {
         self.refresh_if_needed()?;
+        let is_system = defs.gid_filter(AccountClass::System);
         Ok(self
             .groups
+            .groups()
             .iter()
-            .find(|e| e.gid == gid)
-            .map(|e| e.to_owned()))
+            .filter(move |e| is_system(e.gid)))
+    }
+
+    ///Look up a GroupEntry by the group name
+    pub fn get_by_name(&mut self, name: &str) -> Result<Option<GroupEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.groups.get_by_name(name).cloned())
+    }
+
+    ///Look up a GroupEntry by gid
+    pub fn get_by_gid(&mut self, gid: u32) -> Result<Option<GroupEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.groups.get_by_gid(gid).cloned())
     }
 
     ///Look up a group name by gid
-    pub fn get_name_by_gid(&mut self, gid: u32) -> Result<Option<String>, std::io::Error> {
+    pub fn get_name_by_gid(&mut self, gid: u32) -> Result<Option<String>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.groups.get_by_gid(gid).map(|e| e.name.to_owned()))
+    }
+
+    ///Look up a group ID by the group name
+    pub fn get_gid_by_name(&mut self, name: &str) -> Result<Option<u32>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.groups.get_by_name(name).map(|e| e.gid))
+    }
+
+    ///Get all groups of a user, like `getgrouplist`. This is the
+    ///primary group followed by every group listing the user as a
+    ///member, without duplicates.
+    pub fn get_groups_for_user(&mut self, user: &PasswdEntry) -> Result<Vec<GroupEntry>, Error> {
         self.refresh_if_needed()?;
         Ok(self
             .groups
+            .get_group_list(&user.username, user.gid)
+            .into_iter()
+            .cloned()
+            .collect())
+    }
+
+    ///Get all group IDs of a user, like `getgrouplist`. The primary
+    ///gid is always included, even if there is no such group.
+    pub fn get_gids_for_user(&mut self, user: &PasswdEntry) -> Result<Vec<u32>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.groups.get_gid_list(&user.username, user.gid))
+    }
+
+    ///Get all groups of a user by username, using `passwd` to find the
+    ///primary group. Returns `None` if the user does not exist.
+    pub fn get_groups_by_username(
+        &mut self,
+        username: &str,
+        passwd: &mut PasswdReader,
+    ) -> Result<Option<Vec<GroupEntry>>, Error> {
+        match passwd.get_by_username(username)? {
+            Some(user) => self.get_groups_for_user(&user).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    ///Get all group IDs of a user by username, using `passwd` to find the
+    ///primary group. Returns `None` if the user does not exist.
+    pub fn get_gids_by_username(
+        &mut self,
+        username: &str,
+        passwd: &mut PasswdReader,
+    ) -> Result<Option<Vec<u32>>, Error> {
+        match passwd.get_by_username(username)? {
+            Some(user) => self.get_gids_for_user(&user).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+///The main entity to read and lookup shadow information. It
+///supports caching the information to avoid having to read
+///the information from disk more than needed.
+///
+///Reading `/etc/shadow` requires root privileges.
+/// ```
+/// use user_lookup::sync_reader::ShadowReader;
+/// use std::time::Duration;
+///
+/// let mut reader = ShadowReader::from_file("test_files/shadow", Duration::new(0, 0));
+/// let entries = reader.get_entries().unwrap();
+///
+/// assert_eq!(3, entries.len());
+/// assert_eq!(Some(90), reader.get_by_name("user1").unwrap().unwrap().max_days);
+/// assert!(reader.get_by_name("user2").unwrap().unwrap().must_change_password());
+/// ```
+pub struct ShadowReader {
+    cache: FileCache,
+    shadow: ShadowIndex,
+}
+
+impl ShadowReader {
+    ///Creates a new ShadowReader for `/etc/shadow` with a
+    ///specified cache_time in seconds.
+    ///
+    ///Use cache_time with a Duration of 0 to disable caching.
+    ///A [CachePolicy] can be passed instead of a Duration for
+    ///more control over when the file is read again.
+    pub fn new<P: Into<CachePolicy>>(cache_time: P) -> Self {
+        Self {
+            cache: FileCache::new("/etc/shadow".into(), cache_time.into()),
+            shadow: ShadowIndex::default(),
+        }
+    }
+
+    ///Creates a new ShadowReader with the
+    ///shadow file at an specified alternative
+    ///location. Uses the specified cache_time in seconds.
+    ///
+    ///Use cache_time with a Duration of 0 to disable caching.
+    ///A [CachePolicy] can be passed instead of a Duration for
+    ///more control over when the file is read again.
+    pub fn from_file<T: Into<PathBuf>, P: Into<CachePolicy>>(file: T, cache_time: P) -> Self {
+        Self {
+            cache: FileCache::new(file.into(), cache_time.into()),
+            shadow: ShadowIndex::default(),
+        }
+    }
+
+    fn refresh_if_needed(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        let stamp = match self.cache.checks_file() {
+            true => Some(FileStamp::from(&std::fs::metadata(self.cache.path())?)),
+            false => None,
+        };
+        if self.cache.is_fresh(now, stamp) {
+            return Ok(());
+        }
+        let contents = std::fs::read(self.cache.path())?;
+        self.shadow = ShadowIndex::new(self.cache.parse(&contents, ShadowEntry::try_parse)?);
+        self.cache.mark_loaded(now, stamp);
+        Ok(())
+    }
+
+    ///Sets how lines that can not be parsed are handled. The default
+    ///is [ParseMode::Lenient], which skips them.
+    pub fn with_parse_mode(mut self, mode: ParseMode) -> Self {
+        self.cache.set_mode(mode);
+        self
+    }
+
+    ///The lines skipped the last time the file was read, in
+    ///[ParseMode::Lenient]
+    pub fn warnings(&self) -> &[ParseError] {
+        self.cache.warnings()
+    }
+
+    ///Returns when the file was last read, or `None` if it has
+    ///not been read yet.
+    pub fn last_loaded(&self) -> Option<SystemTime> {
+        self.cache.last_loaded()
+    }
+
+    ///Get the entire list of shadow entries
+    pub fn get_entries(&mut self) -> Result<&Vec<ShadowEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.shadow.entries())
+    }
+
+    ///Get the index over the shadow entries, to do several
+    ///lookups against the same data without re-checking the file.
+    pub fn get_index(&mut self) -> Result<&ShadowIndex, Error> {
+        self.refresh_if_needed()?;
+        Ok(&self.shadow)
+    }
+
+    ///Will return an iterator over &ShadowEntry
+    pub fn try_iter(&mut self) -> Result<std::slice::Iter<'_, ShadowEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.shadow.entries().iter())
+    }
+
+    ///Look up a ShadowEntry by username
+    pub fn get_by_name(&mut self, name: &str) -> Result<Option<ShadowEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.shadow.get_by_name(name).cloned())
+    }
+}
+
+///The main entity to read and lookup gshadow information. It
+///supports caching the information to avoid having to read
+///the information from disk more than needed.
+///
+///Reading `/etc/gshadow` requires root privileges.
+/// ```
+/// use user_lookup::sync_reader::GshadowReader;
+/// use std::time::Duration;
+///
+/// let mut reader = GshadowReader::from_file("test_files/gshadow", Duration::new(0, 0));
+/// let groups = reader.get_groups().unwrap();
+///
+/// assert_eq!(3, groups.len());
+/// assert_eq!(Some(vec!["user2".to_string()]), reader.get_administrators_by_name("users").unwrap());
+/// assert_eq!(vec!["wheel"], reader.get_administered_by("user1").unwrap().iter().map(|e| e.name.as_str()).collect::<Vec<_>>());
+/// ```
+pub struct GshadowReader {
+    cache: FileCache,
+    groups: GshadowIndex,
+}
+
+impl GshadowReader {
+    ///Creates a new GshadowReader for `/etc/gshadow` with a
+    ///specified cache_time in seconds.
+    ///
+    ///Use cache_time with a duration of 0 to disable caching.
+    ///A [CachePolicy] can be passed instead of a Duration for
+    ///more control over when the file is read again.
+    pub fn new<P: Into<CachePolicy>>(cache_time: P) -> Self {
+        Self {
+            cache: FileCache::new("/etc/gshadow".into(), cache_time.into()),
+            groups: GshadowIndex::default(),
+        }
+    }
+
+    ///Creates a new GshadowReader which reads
+    ///the gshadow file at a specific path, and
+    ///uses the specified cache_time in seconds.
+    ///
+    ///Use cache_time with a duration of 0 to disable caching.
+    ///A [CachePolicy] can be passed instead of a Duration for
+    ///more control over when the file is read again.
+    pub fn from_file<T: Into<PathBuf>, P: Into<CachePolicy>>(file: T, cache_time: P) -> Self {
+        Self {
+            cache: FileCache::new(file.into(), cache_time.into()),
+            groups: GshadowIndex::default(),
+        }
+    }
+
+    fn refresh_if_needed(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        let stamp = match self.cache.checks_file() {
+            true => Some(FileStamp::from(&std::fs::metadata(self.cache.path())?)),
+            false => None,
+        };
+        if self.cache.is_fresh(now, stamp) {
+            return Ok(());
+        }
+        let contents = std::fs::read(self.cache.path())?;
+        self.groups = GshadowIndex::new(self.cache.parse(&contents, GshadowEntry::try_parse)?);
+        self.cache.mark_loaded(now, stamp);
+        Ok(())
+    }
+
+    ///Sets how lines that can not be parsed are handled. The default
+    ///is [ParseMode::Lenient], which skips them.
+    pub fn with_parse_mode(mut self, mode: ParseMode) -> Self {
+        self.cache.set_mode(mode);
+        self
+    }
+
+    ///The lines skipped the last time the file was read, in
+    ///[ParseMode::Lenient]
+    pub fn warnings(&self) -> &[ParseError] {
+        self.cache.warnings()
+    }
+
+    ///Returns when the file was last read, or `None` if it has
+    ///not been read yet.
+    pub fn last_loaded(&self) -> Option<SystemTime> {
+        self.cache.last_loaded()
+    }
+
+    ///Get the entire list of gshadow entries
+    pub fn get_groups(&mut self) -> Result<&Vec<GshadowEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.groups.groups())
+    }
+
+    ///Get the index over the gshadow entries, to do several
+    ///lookups against the same data without re-checking the file.
+    pub fn get_index(&mut self) -> Result<&GshadowIndex, Error> {
+        self.refresh_if_needed()?;
+        Ok(&self.groups)
+    }
+
+    ///Will return an iterator over &GshadowEntry
+    pub fn try_iter(&mut self) -> Result<std::slice::Iter<'_, GshadowEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.groups.groups().iter())
+    }
+
+    ///Look up a GshadowEntry by the group name
+    pub fn get_by_name(&mut self, name: &str) -> Result<Option<GshadowEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.groups.get_by_name(name).cloned())
+    }
+
+    ///Look up the administrators of a group by the group name
+    pub fn get_administrators_by_name(&mut self, name: &str) -> Result<Option<Vec<String>>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self
+            .groups
+            .get_by_name(name)
+            .map(|e| e.administrators.to_owned()))
+    }
+
+    ///Get all groups administered by a user
+    pub fn get_administered_by(&mut self, username: &str) -> Result<Vec<GshadowEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self
+            .groups
+            .groups()
             .iter()
-            .find(|e| e.gid == gid)
-            .map(|e| e.name.to_owned()))
+            .filter(|e| e.is_administrator(username))
+            .cloned()
+            .collect())
     }
+}
 
-    ///Look up a group ID by the group name
-    pub fn get_gid_by_name(&mut self, name: &str) -> Result<Option<u32>, std::io::Error> {
+///The main entity to read and lookup subordinate id ranges from
+///`/etc/subuid` or `/etc/subgid`. It supports caching the
+///information to avoid having to read the information from disk
+///more than needed.
+///
+///Ranges can be owned by a login name or by a numeric uid, in both
+///files. A [PasswdReader] is used to resolve one into the other.
+/// ```
+/// use user_lookup::sync_reader::{PasswdReader, SubIdReader};
+/// use std::time::Duration;
+///
+/// let mut passwd = PasswdReader::from_file("test_files/passwd", Duration::new(0, 0));
+/// let mut reader = SubIdReader::from_file("test_files/subuid", Duration::new(0, 0));
+///
+/// let ranges = reader.get_ranges_by_username("user2", &mut passwd).unwrap();
+/// assert_eq!(vec![165536], ranges.iter().map(|r| r.start).collect::<Vec<_>>());
+///
+/// assert_eq!(Some("user2".to_string()), reader.get_owner_name_of(200000, &mut passwd).unwrap());
+/// assert_eq!(Some(231072), reader.find_free_range(65536).unwrap());
+/// ```
+pub struct SubIdReader {
+    cache: FileCache,
+    ranges: SubIdIndex,
+}
+
+impl SubIdReader {
+    ///Creates a new SubIdReader for `/etc/subuid` with a
+    ///specified cache_time in seconds.
+    ///
+    ///Use cache_time with a duration of 0 to disable caching.
+    ///A [CachePolicy] can be passed instead of a Duration for
+    ///more control over when the file is read again.
+    pub fn subuid<P: Into<CachePolicy>>(cache_time: P) -> Self {
+        Self::from_file("/etc/subuid", cache_time)
+    }
+
+    ///Creates a new SubIdReader for `/etc/subgid` with a
+    ///specified cache_time in seconds.
+    ///
+    ///Use cache_time with a duration of 0 to disable caching.
+    ///A [CachePolicy] can be passed instead of a Duration for
+    ///more control over when the file is read again.
+    pub fn subgid<P: Into<CachePolicy>>(cache_time: P) -> Self {
+        Self::from_file("/etc/subgid", cache_time)
+    }
+
+    ///Creates a new SubIdReader which reads
+    ///the subuid or subgid file at a specific path,
+    ///and uses the specified cache_time in seconds.
+    ///
+    ///Use cache_time with a duration of 0 to disable caching.
+    ///A [CachePolicy] can be passed instead of a Duration for
+    ///more control over when the file is read again.
+    pub fn from_file<T: Into<PathBuf>, P: Into<CachePolicy>>(file: T, cache_time: P) -> Self {
+        Self {
+            cache: FileCache::new(file.into(), cache_time.into()),
+            ranges: SubIdIndex::default(),
+        }
+    }
+
+    fn refresh_if_needed(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        let stamp = match self.cache.checks_file() {
+            true => Some(FileStamp::from(&std::fs::metadata(self.cache.path())?)),
+            false => None,
+        };
+        if self.cache.is_fresh(now, stamp) {
+            return Ok(());
+        }
+        let contents = std::fs::read(self.cache.path())?;
+        self.ranges = SubIdIndex::new(self.cache.parse(&contents, SubIdEntry::try_parse)?);
+        self.cache.mark_loaded(now, stamp);
+        Ok(())
+    }
+
+    ///Sets how lines that can not be parsed are handled. The default
+    ///is [ParseMode::Lenient], which skips them.
+    pub fn with_parse_mode(mut self, mode: ParseMode) -> Self {
+        self.cache.set_mode(mode);
+        self
+    }
+
+    ///The lines skipped the last time the file was read, in
+    ///[ParseMode::Lenient]
+    pub fn warnings(&self) -> &[ParseError] {
+        self.cache.warnings()
+    }
+
+    ///Returns when the file was last read, or `None` if it has
+    ///not been read yet.
+    pub fn last_loaded(&self) -> Option<SystemTime> {
+        self.cache.last_loaded()
+    }
+
+    ///Get the entire list of ranges
+    pub fn get_entries(&mut self) -> Result<&Vec<SubIdEntry>, Error> {
         self.refresh_if_needed()?;
-        Ok(self.groups.iter().find(|e| e.name == name).map(|e| e.gid))
+        Ok(self.ranges.entries())
+    }
+
+    ///Get the index over the ranges, to do several
+    ///lookups against the same data without re-checking the file.
+    pub fn get_index(&mut self) -> Result<&SubIdIndex, Error> {
+        self.refresh_if_needed()?;
+        Ok(&self.ranges)
+    }
+
+    ///Will return an iterator over &SubIdEntry
+    pub fn try_iter(&mut self) -> Result<std::slice::Iter<'_, SubIdEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.ranges.entries().iter())
+    }
+
+    ///Get the ranges owned by a user, whether they are
+    ///listed by username or by uid
+    pub fn get_ranges_for_user(&mut self, user: &PasswdEntry) -> Result<Vec<SubIdEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.ranges.get_by_user(user).cloned().collect())
+    }
+
+    ///Get the ranges owned by a username. The username is resolved
+    ///with `passwd` to also find the ranges listed by uid.
+    pub fn get_ranges_by_username(
+        &mut self,
+        username: &str,
+        passwd: &mut PasswdReader,
+    ) -> Result<Vec<SubIdEntry>, Error> {
+        match passwd.get_by_username(username)? {
+            Some(user) => self.get_ranges_for_user(&user),
+            None => {
+                self.refresh_if_needed()?;
+                let owner = SubIdOwner::Name(username.to_string());
+                Ok(self.ranges.get_by_owner(&owner).cloned().collect())
+            }
+        }
+    }
+
+    ///Look up the range containing an id
+    pub fn get_owner_of(&mut self, id: u32) -> Result<Option<SubIdEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.ranges.get_owner_of(id).cloned())
+    }
+
+    ///Look up the name of the user owning the range containing
+    ///an id. Owners listed by uid are resolved with `passwd`.
+    pub fn get_owner_name_of(
+        &mut self,
+        id: u32,
+        passwd: &mut PasswdReader,
+    ) -> Result<Option<String>, Error> {
+        match self.get_owner_of(id)?.map(|e| e.owner) {
+            Some(SubIdOwner::Name(name)) => Ok(Some(name)),
+            Some(SubIdOwner::Uid(uid)) => passwd.get_username_by_uid(uid),
+            None => Ok(None),
+        }
+    }
+
+    ///Finds the lowest start of `count` free consecutive ids, between
+    ///the default `SUB_UID_MIN` and `SUB_UID_MAX` of `/etc/login.defs`
+    pub fn find_free_range(&mut self, count: u32) -> Result<Option<u32>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.ranges.find_free_range(count))
+    }
+
+    ///Finds the lowest start of `count` free consecutive ids
+    ///between `min` and `max`, inclusive
+    pub fn find_free_range_in(
+        &mut self,
+        count: u32,
+        min: u32,
+        max: u32,
+    ) -> Result<Option<u32>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.ranges.find_free_range_in(count, min, max))
+    }
+}
+
+///A passwd and group reader that can be shared between threads. It is
+///cheap to clone, and all clones share the same cached data. Lookups
+///take `&self` and work on an immutable [Snapshot], so they never wait
+///for each other, nor for a refresh while the cache is fresh. When the
+///files have to be read again, threads asking at the same time share a
+///single read of the files.
+/// ```
+/// use user_lookup::sync_reader::SharedReader;
+/// use user_lookup::cache::CachePolicy;
+///
+/// let reader = SharedReader::from_files("test_files/passwd", "test_files/group", CachePolicy::on_change());
+/// std::thread::scope(|s| {
+///     for uid in [0, 1000, 1001] {
+///         let reader = &reader;
+///         s.spawn(move || assert!(reader.get_by_uid(uid).unwrap().is_some()));
+///     }
+/// });
+/// assert_eq!(Some(vec![100, 10]), reader.get_gids_by_username("user1").unwrap());
+/// ```
+#[derive(Debug, Clone)]
+pub struct SharedReader {
+    inner: Arc<SharedInner>,
+}
+
+#[derive(Debug)]
+struct SharedInner {
+    state: SharedState,
+    refresh: Mutex<()>,
+}
+
+impl SharedReader {
+    ///Creates a new SharedReader for `/etc/passwd` and `/etc/group`
+    ///with a specified cache_time in seconds.
+    ///
+    ///Use cache_time with a Duration of 0 to disable caching.
+    ///A [CachePolicy] can be passed instead of a Duration for
+    ///more control over when the files are read again.
+    pub fn new<P: Into<CachePolicy>>(cache_time: P) -> Self {
+        Self::from_files("/etc/passwd", "/etc/group", cache_time)
+    }
+
+    ///Creates a new SharedReader with the passwd and group files
+    ///at alternative locations. Uses the specified cache_time in seconds.
+    ///
+    ///Use cache_time with a Duration of 0 to disable caching.
+    ///A [CachePolicy] can be passed instead of a Duration for
+    ///more control over when the files are read again.
+    pub fn from_files<T: Into<PathBuf>, U: Into<PathBuf>, P: Into<CachePolicy>>(
+        passwd: T,
+        group: U,
+        cache_time: P,
+    ) -> Self {
+        Self::with_state(SharedState::new(
+            passwd.into(),
+            group.into(),
+            cache_time.into(),
+        ))
+    }
+
+    fn with_state(state: SharedState) -> Self {
+        Self {
+            inner: Arc::new(SharedInner {
+                state,
+                refresh: Mutex::new(()),
+            }),
+        }
+    }
+
+    ///Sets how lines that can not be parsed are handled. The default
+    ///is [ParseMode::Lenient], which skips them.
+    ///
+    ///This gives a reader of its own, which does not share the cache
+    ///with clones made before.
+    pub fn with_parse_mode(self, mode: ParseMode) -> Self {
+        Self::with_state(self.inner.state.with_parse_mode(mode))
+    }
+
+    fn stamps(&self) -> Result<[Option<FileStamp>; 2], Error> {
+        let state = &self.inner.state;
+        match state.checks_file() {
+            true => Ok([
+                Some(FileStamp::from(&std::fs::metadata(state.passwd_path())?)),
+                Some(FileStamp::from(&std::fs::metadata(state.group_path())?)),
+            ]),
+            false => Ok([None, None]),
+        }
+    }
+
+    ///Get the current snapshot of the passwd and group entries, reading
+    ///the files first if the cached ones are stale. The snapshot stays
+    ///the same while it is held, also if the files change.
+    pub fn snapshot(&self) -> Result<Arc<Snapshot>, Error> {
+        let state = &self.inner.state;
+        let requested = Instant::now();
+        if let Some(snapshot) = state.fresh(requested, self.stamps()?) {
+            return Ok(snapshot);
+        }
+        let _refresh = self
+            .inner
+            .refresh
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        //Another thread may have read the files while this one waited
+        let started = Instant::now();
+        let stamps = self.stamps()?;
+        if let Some(snapshot) = state.refreshed(requested, stamps) {
+            return Ok(snapshot);
+        }
+        let passwd = std::fs::read(state.passwd_path())?;
+        let group = std::fs::read(state.group_path())?;
+        state.store(started, stamps, &passwd, &group)
+    }
+
+    ///Returns when the files were last read, or `None` if they have
+    ///not been read yet.
+    pub fn last_loaded(&self) -> Option<SystemTime> {
+        self.inner.state.current().map(|s| s.last_loaded())
+    }
+
+    ///Look up a PasswdEntry by username
+    pub fn get_by_username(&self, username: &str) -> Result<Option<PasswdEntry>, Error> {
+        Ok(self.snapshot()?.passwd().get_by_username(username).cloned())
+    }
+
+    ///Look up a PasswdEntry by uid
+    pub fn get_by_uid(&self, uid: u32) -> Result<Option<PasswdEntry>, Error> {
+        Ok(self.snapshot()?.passwd().get_by_uid(uid).cloned())
+    }
+
+    ///Look up a username by uid
+    pub fn get_username_by_uid(&self, uid: u32) -> Result<Option<String>, Error> {
+        let snapshot = self.snapshot()?;
+        Ok(snapshot
+            .passwd()
+            .get_by_uid(uid)
+            .map(|e| e.username.to_owned()))
+    }
+
+    ///Look up a user ID by username
+    pub fn get_uid_by_username(&self, username: &str) -> Result<Option<u32>, Error> {
+        let snapshot = self.snapshot()?;
+        Ok(snapshot.passwd().get_by_username(username).map(|e| e.uid))
+    }
+
+    ///Look up a GroupEntry by the group name
+    pub fn get_group_by_name(&self, name: &str) -> Result<Option<GroupEntry>, Error> {
+        Ok(self.snapshot()?.groups().get_by_name(name).cloned())
+    }
+
+    ///Look up a GroupEntry by gid
+    pub fn get_group_by_gid(&self, gid: u32) -> Result<Option<GroupEntry>, Error> {
+        Ok(self.snapshot()?.groups().get_by_gid(gid).cloned())
+    }
+
+    ///Look up a group name by gid
+    pub fn get_name_by_gid(&self, gid: u32) -> Result<Option<String>, Error> {
+        let snapshot = self.snapshot()?;
+        Ok(snapshot.groups().get_by_gid(gid).map(|e| e.name.to_owned()))
+    }
+
+    ///Look up a group ID by the group name
+    pub fn get_gid_by_name(&self, name: &str) -> Result<Option<u32>, Error> {
+        let snapshot = self.snapshot()?;
+        Ok(snapshot.groups().get_by_name(name).map(|e| e.gid))
+    }
+
+    ///Get all groups of a user, like `getgrouplist`. This is the
+    ///primary group followed by every group listing the user as a
+    ///member, without duplicates.
+    pub fn get_groups_for_user(&self, user: &PasswdEntry) -> Result<Vec<GroupEntry>, Error> {
+        let snapshot = self.snapshot()?;
+        Ok(snapshot
+            .groups()
+            .get_group_list(&user.username, user.gid)
+            .into_iter()
+            .cloned()
+            .collect())
+    }
+
+    ///Get all group IDs of a user, like `getgrouplist`. The primary
+    ///gid is always included, even if there is no such group.
+    pub fn get_gids_for_user(&self, user: &PasswdEntry) -> Result<Vec<u32>, Error> {
+        let snapshot = self.snapshot()?;
+        Ok(snapshot.groups().get_gid_list(&user.username, user.gid))
+    }
+
+    ///Get all groups of a user by username. The user and the groups
+    ///are looked up in the same snapshot. Returns `None` if the user
+    ///does not exist.
+    pub fn get_groups_by_username(&self, username: &str) -> Result<Option<Vec<GroupEntry>>, Error> {
+        let snapshot = self.snapshot()?;
+        Ok(snapshot
+            .database()
+            .get_groups_by_username(username)
+            .map(|groups| groups.into_iter().cloned().collect()))
+    }
+
+    ///Get all group IDs of a user by username. The user and the groups
+    ///are looked up in the same snapshot. Returns `None` if the user
+    ///does not exist.
+    pub fn get_gids_by_username(&self, username: &str) -> Result<Option<Vec<u32>>, Error> {
+        Ok(self.snapshot()?.database().get_gids_by_username(username))
+    }
+}
+
+///Watches the passwd and group files with inotify, and gives the
+///[Event]s of the users and groups as the files change. It is a blocking
+///iterator, that never ends. A file that can not be read or parsed gives
+///an error, after which the watcher keeps watching.
+/// ```no_run
+/// use user_lookup::sync_reader::Watcher;
+/// use user_lookup::error::ParseMode;
+/// use user_lookup::watch::Event;
+///
+/// for event in Watcher::new(ParseMode::Lenient).unwrap() {
+///     if let Event::UserAdded(user) = event.unwrap() {
+///         println!("{} was added", user.username);
+///     }
+/// }
+/// ```
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub struct Watcher {
+    watcher: FileWatcher,
+    pending: VecDeque<Event>,
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+impl Watcher {
+    ///Creates a new Watcher for `/etc/passwd` and `/etc/group`. In
+    ///[ParseMode::Strict], a line that can not be parsed fails the read.
+    pub fn new(mode: ParseMode) -> Result<Self, Error> {
+        Self::from_files("/etc/passwd", "/etc/group", mode)
+    }
+
+    ///Creates a new Watcher of the passwd and group files at alternative
+    ///locations. The files are read before this returns.
+    pub fn from_files<T: Into<PathBuf>, U: Into<PathBuf>>(
+        passwd: T,
+        group: U,
+        mode: ParseMode,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            watcher: FileWatcher::new(passwd.into(), group.into(), mode)?,
+            pending: VecDeque::new(),
+        })
+    }
+
+    ///The users and groups as last read. The events returned so far have
+    ///been applied, also the ones of the last read not yet returned.
+    pub fn database(&self) -> &UserDatabase {
+        self.watcher.database()
+    }
+
+    ///Blocks until the users or groups change, and returns the events
+    pub fn wait(&mut self) -> Result<Vec<Event>, Error> {
+        if !self.pending.is_empty() {
+            return Ok(self.pending.drain(..).collect());
+        }
+        loop {
+            let events = self.watcher.wait(None)?;
+            if !events.is_empty() {
+                return Ok(events);
+            }
+        }
+    }
+
+    ///Blocks at most `timeout` for the users or groups to change, and
+    ///returns the events. They are empty if nothing changed in time.
+    /// ```
+    /// use user_lookup::sync_reader::Watcher;
+    /// use user_lookup::error::ParseMode;
+    /// use std::time::Duration;
+    ///
+    /// let mut watcher = Watcher::from_files("test_files/passwd", "test_files/group", ParseMode::Strict).unwrap();
+    /// assert_eq!(3, watcher.database().get_entries().len());
+    /// assert!(watcher.wait_timeout(Duration::from_millis(10)).unwrap().is_empty());
+    /// ```
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Result<Vec<Event>, Error> {
+        if !self.pending.is_empty() {
+            return Ok(self.pending.drain(..).collect());
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let events = self.watcher.wait(Some(left))?;
+            if !events.is_empty() || left.is_zero() {
+                return Ok(events);
+            }
+        }
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+impl Iterator for Watcher {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pending.is_empty() {
+            match self.wait() {
+                Ok(events) => self.pending.extend(events),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        self.pending.pop_front().map(Ok)
     }
 }
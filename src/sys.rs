@@ -0,0 +1,130 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `sys` holds the few libc declarations the crate needs, to avoid
+//! depending on the `libc` crate. The layouts and constants are the
+//! ones of Linux on x86_64 and aarch64. Other architectures, like mips64
+//! and sparc64, use other values for some of them, so they are left out
+//! until checked, and use the portable fallbacks.
+#![allow(non_camel_case_types)]
+#![cfg_attr(not(feature = "nss"), allow(dead_code))]
+#![cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+
+use std::os::raw::c_char;
+use std::os::raw::c_int;
+use std::os::raw::c_short;
+
+pub(crate) const EPERM: c_int = 1;
+pub(crate) const ENOENT: c_int = 2;
+pub(crate) const ESRCH: c_int = 3;
+pub(crate) const EBADF: c_int = 9;
+pub(crate) const EAGAIN: c_int = 11;
+pub(crate) const EACCES: c_int = 13;
+pub(crate) const ERANGE: c_int = 34;
+
+pub(crate) const F_SETLK: c_int = 6;
+pub(crate) const F_WRLCK: c_short = 1;
+pub(crate) const SEEK_SET: c_short = 0;
+
+#[repr(C)]
+pub(crate) struct flock {
+    pub(crate) l_type: c_short,
+    pub(crate) l_whence: c_short,
+    pub(crate) l_start: i64,
+    pub(crate) l_len: i64,
+    pub(crate) l_pid: i32,
+}
+
+pub(crate) const IN_NONBLOCK: c_int = 0o4000;
+pub(crate) const IN_CLOEXEC: c_int = 0o2000000;
+pub(crate) const IN_CLOSE_WRITE: u32 = 0x8;
+pub(crate) const IN_MOVED_TO: u32 = 0x80;
+pub(crate) const IN_Q_OVERFLOW: u32 = 0x4000;
+
+pub(crate) const POLLIN: c_short = 1;
+
+#[repr(C)]
+pub(crate) struct pollfd {
+    pub(crate) fd: c_int,
+    pub(crate) events: c_short,
+    pub(crate) revents: c_short,
+}
+
+#[repr(C)]
+pub(crate) struct passwd {
+    pub(crate) pw_name: *mut c_char,
+    pub(crate) pw_passwd: *mut c_char,
+    pub(crate) pw_uid: u32,
+    pub(crate) pw_gid: u32,
+    pub(crate) pw_gecos: *mut c_char,
+    pub(crate) pw_dir: *mut c_char,
+    pub(crate) pw_shell: *mut c_char,
+}
+
+#[repr(C)]
+pub(crate) struct group {
+    pub(crate) gr_name: *mut c_char,
+    pub(crate) gr_passwd: *mut c_char,
+    pub(crate) gr_gid: u32,
+    pub(crate) gr_mem: *mut *mut c_char,
+}
+
+extern "C" {
+    pub(crate) fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+
+    pub(crate) fn __errno_location() -> *mut c_int;
+
+    pub(crate) fn inotify_init1(flags: c_int) -> c_int;
+    pub(crate) fn inotify_add_watch(fd: c_int, pathname: *const c_char, mask: u32) -> c_int;
+    pub(crate) fn poll(fds: *mut pollfd, nfds: u64, timeout: c_int) -> c_int;
+
+    pub(crate) fn getpwnam_r(
+        name: *const c_char,
+        pwd: *mut passwd,
+        buf: *mut c_char,
+        buflen: usize,
+        result: *mut *mut passwd,
+    ) -> c_int;
+    pub(crate) fn getpwuid_r(
+        uid: u32,
+        pwd: *mut passwd,
+        buf: *mut c_char,
+        buflen: usize,
+        result: *mut *mut passwd,
+    ) -> c_int;
+    pub(crate) fn getgrnam_r(
+        name: *const c_char,
+        grp: *mut group,
+        buf: *mut c_char,
+        buflen: usize,
+        result: *mut *mut group,
+    ) -> c_int;
+    pub(crate) fn getgrgid_r(
+        gid: u32,
+        grp: *mut group,
+        buf: *mut c_char,
+        buflen: usize,
+        result: *mut *mut group,
+    ) -> c_int;
+    pub(crate) fn getgrouplist(
+        user: *const c_char,
+        group: u32,
+        groups: *mut u32,
+        ngroups: *mut c_int,
+    ) -> c_int;
+
+    pub(crate) fn setpwent();
+    pub(crate) fn getpwent() -> *mut passwd;
+    pub(crate) fn endpwent();
+    pub(crate) fn setgrent();
+    pub(crate) fn getgrent() -> *mut group;
+    pub(crate) fn endgrent();
+}
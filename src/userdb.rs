@@ -0,0 +1,794 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `userdb` reads the JSON user and group records of systemd, as used by
+//! `systemd-homed` and `userdbctl`, from /etc/userdb, /run/userdb and
+//! /usr/lib/userdb.
+//!
+//! A directory holds `<name>.user` and `<name>.group` files with one record
+//! each, usually with `<uid>.user` and `<gid>.group` symlinks to them, and
+//! `<user>:<group>.membership` files whose names add a user to a group.
+//! Records are converted to and from [PasswdEntry] and [GroupEntry].
+//!
+//! ```
+//! use user_lookup::userdb::{Disposition, UserDbReader};
+//! use std::time::Duration;
+//!
+//! let mut reader = UserDbReader::from_dirs(["test_files/userdb"], Duration::new(0, 0));
+//! let user = reader.get_user_by_uid(1002).unwrap().unwrap();
+//! assert_eq!("user3", user.user_name);
+//! assert_eq!(Some(Disposition::Regular), user.disposition);
+//!
+//! let entry = user.to_passwd_entry().unwrap();
+//! assert_eq!("user3:x:1002:1002:User Three:/home/user3:/bin/zsh", entry.to_line());
+//!
+//! //Members come from the group, `memberOf` of the users and the
+//! //membership files
+//! let admins = reader.get_group_entry_by_name("admins").unwrap().unwrap();
+//! assert_eq!(vec!["user3", "user1"], admins.users);
+//! ```
+use crate::cache::CachePolicy;
+use crate::cache::FileStamp;
+use crate::error::ParseMode;
+use crate::index::GroupIndex;
+use crate::index::PasswdIndex;
+use crate::json::Value;
+use crate::source::GroupSource;
+use crate::source::UserSource;
+use crate::Error;
+use crate::GroupEntry;
+use crate::PasswdEntry;
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Instant;
+use std::time::SystemTime;
+
+pub use crate::json::JsonError;
+
+///The directories searched for records, in order. The first record of a
+///name wins.
+pub const USERDB_DIRS: [&str; 3] = ["/etc/userdb", "/run/userdb", "/usr/lib/userdb"];
+
+///Why a record could not be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RecordError {
+    ///The record is not valid JSON
+    Json(JsonError),
+    ///The record is not a JSON object
+    NotAnObject,
+    ///A required field is missing
+    MissingField(&'static str),
+    ///A field has the wrong type or an invalid value
+    InvalidField(&'static str),
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordError::Json(e) => e.fmt(f),
+            RecordError::NotAnObject => write!(f, "the record is not an object"),
+            RecordError::MissingField(field) => write!(f, "missing field {}", field),
+            RecordError::InvalidField(field) => write!(f, "invalid field {}", field),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+impl From<JsonError> for RecordError {
+    fn from(e: JsonError) -> Self {
+        RecordError::Json(e)
+    }
+}
+
+///The `disposition` of a record, what kind of account it is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Disposition {
+    ///Defined by the system itself, like root and nobody
+    Intrinsic,
+    ///A system account
+    System,
+    ///A dynamically allocated account, like for `DynamicUser=`
+    Dynamic,
+    ///A regular account
+    Regular,
+    ///An account of a container
+    Container,
+    ///A reserved account, that is never used
+    Reserved,
+}
+
+impl Disposition {
+    ///The name of the disposition in a record
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Disposition::Intrinsic => "intrinsic",
+            Disposition::System => "system",
+            Disposition::Dynamic => "dynamic",
+            Disposition::Regular => "regular",
+            Disposition::Container => "container",
+            Disposition::Reserved => "reserved",
+        }
+    }
+
+    ///Parses the name of a disposition
+    pub fn parse(s: &str) -> Option<Disposition> {
+        match s {
+            "intrinsic" => Some(Disposition::Intrinsic),
+            "system" => Some(Disposition::System),
+            "dynamic" => Some(Disposition::Dynamic),
+            "regular" => Some(Disposition::Regular),
+            "container" => Some(Disposition::Container),
+            "reserved" => Some(Disposition::Reserved),
+            _ => None,
+        }
+    }
+}
+
+fn object(json: &Value) -> Result<(), RecordError> {
+    match json {
+        Value::Object(_) => Ok(()),
+        _ => Err(RecordError::NotAnObject),
+    }
+}
+
+fn string(json: &Value, field: &'static str) -> Result<Option<String>, RecordError> {
+    match json.get(field) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(_) => Err(RecordError::InvalidField(field)),
+    }
+}
+
+fn id(json: &Value, field: &'static str) -> Result<Option<u32>, RecordError> {
+    match json.get(field) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value
+            .as_u32()
+            .map(Some)
+            .ok_or(RecordError::InvalidField(field)),
+    }
+}
+
+fn list(json: &Value, field: &'static str) -> Result<Vec<String>, RecordError> {
+    match json.get(field) {
+        None | Some(Value::Null) => Ok(vec![]),
+        Some(Value::Array(_)) => Ok(json.get(field).map(Value::as_str_list).unwrap_or_default()),
+        Some(_) => Err(RecordError::InvalidField(field)),
+    }
+}
+
+fn disposition(json: &Value) -> Result<Option<Disposition>, RecordError> {
+    Ok(string(json, "disposition")?.and_then(|d| Disposition::parse(&d)))
+}
+
+///Sets or removes a field of the JSON record
+fn set(json: &mut Value, field: &str, value: Option<Value>) {
+    match value {
+        Some(value) => json.set(field, value),
+        None => {
+            json.remove(field);
+        }
+    }
+}
+
+fn non_empty(s: &str) -> Option<String> {
+    match s.is_empty() {
+        true => None,
+        false => Some(s.to_string()),
+    }
+}
+
+///A JSON user record. The fields used for passwd entries are parsed, the
+///whole record is kept and written back by [UserRecord::to_json].
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserRecord {
+    ///`userName`
+    pub user_name: String,
+    ///`uid`
+    pub uid: Option<u32>,
+    ///`gid`, the primary group. If missing the gid equals the uid.
+    pub gid: Option<u32>,
+    ///`realName`
+    pub real_name: Option<String>,
+    ///`homeDirectory`
+    pub home_directory: Option<String>,
+    ///`shell`
+    pub shell: Option<String>,
+    ///`memberOf`, the names of additional groups
+    pub member_of: Vec<String>,
+    ///`disposition`, if it is a known one
+    pub disposition: Option<Disposition>,
+    ///The whole record, with the fields not parsed above
+    json: Value,
+}
+
+impl UserRecord {
+    ///Parses a JSON user record
+    /// ```
+    /// use user_lookup::userdb::{RecordError, UserRecord};
+    ///
+    /// let record = UserRecord::parse(r#"{"userName":"user4","uid":1004,"realName":"User Four"}"#).unwrap();
+    /// assert_eq!("user4:x:1004:1004:User Four:/:/bin/sh", record.to_passwd_entry().unwrap().to_line());
+    ///
+    /// assert_eq!(Err(RecordError::MissingField("userName")), UserRecord::parse(r#"{"uid":1004}"#));
+    /// assert_eq!(Err(RecordError::InvalidField("uid")), UserRecord::parse(r#"{"userName":"a","uid":-1}"#));
+    /// ```
+    pub fn parse(s: &str) -> Result<UserRecord, RecordError> {
+        Self::from_json(Value::parse(s)?)
+    }
+
+    ///Creates a record from a parsed JSON object
+    pub(crate) fn from_json(json: Value) -> Result<UserRecord, RecordError> {
+        object(&json)?;
+        Ok(UserRecord {
+            user_name: string(&json, "userName")?.ok_or(RecordError::MissingField("userName"))?,
+            uid: id(&json, "uid")?,
+            gid: id(&json, "gid")?,
+            real_name: string(&json, "realName")?,
+            home_directory: string(&json, "homeDirectory")?,
+            shell: string(&json, "shell")?,
+            member_of: list(&json, "memberOf")?,
+            disposition: disposition(&json)?,
+            json,
+        })
+    }
+
+    ///The record as JSON text, with the parsed fields written back to
+    ///the whole record
+    pub fn to_json(&self) -> String {
+        self.to_value().to_string()
+    }
+
+    fn to_value(&self) -> Value {
+        let mut json = match &self.json {
+            Value::Object(_) => self.json.clone(),
+            _ => Value::Object(vec![]),
+        };
+        json.set("userName", self.user_name.as_str().into());
+        set(&mut json, "uid", self.uid.map(Value::from));
+        set(&mut json, "gid", self.gid.map(Value::from));
+        set(
+            &mut json,
+            "realName",
+            self.real_name.clone().map(Value::from),
+        );
+        set(
+            &mut json,
+            "homeDirectory",
+            self.home_directory.clone().map(Value::from),
+        );
+        set(&mut json, "shell", self.shell.clone().map(Value::from));
+        set(
+            &mut json,
+            "memberOf",
+            Some(self.member_of.clone())
+                .filter(|m| !m.is_empty())
+                .map(Value::from),
+        );
+        set(
+            &mut json,
+            "disposition",
+            self.disposition.map(|d| d.as_str().into()),
+        );
+        json
+    }
+
+    ///Converts the record to a passwd entry. A record without uid can
+    ///not be converted. A missing home directory becomes `/`, and a
+    ///missing shell `/bin/sh`.
+    pub fn to_passwd_entry(&self) -> Result<PasswdEntry, RecordError> {
+        let uid = self.uid.ok_or(RecordError::MissingField("uid"))?;
+        Ok(PasswdEntry {
+            username: self.user_name.clone(),
+            passwd: "x".to_string(),
+            uid,
+            gid: self.gid.unwrap_or(uid),
+            gecos: self.real_name.clone().unwrap_or_default(),
+            home_dir: self.home_directory.clone().unwrap_or_else(|| "/".into()),
+            shell: self.shell.clone().unwrap_or_else(|| "/bin/sh".into()),
+        })
+    }
+}
+
+impl From<&PasswdEntry> for UserRecord {
+    fn from(entry: &PasswdEntry) -> Self {
+        let mut record = UserRecord {
+            user_name: entry.username.clone(),
+            uid: Some(entry.uid),
+            gid: Some(entry.gid),
+            real_name: non_empty(&entry.gecos),
+            home_directory: non_empty(&entry.home_dir),
+            shell: non_empty(&entry.shell),
+            member_of: vec![],
+            disposition: None,
+            json: Value::Object(vec![]),
+        };
+        record.json = record.to_value();
+        record
+    }
+}
+
+///A JSON group record. The fields used for group entries are parsed, the
+///whole record is kept and written back by [GroupRecord::to_json].
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupRecord {
+    ///`groupName`
+    pub group_name: String,
+    ///`gid`
+    pub gid: Option<u32>,
+    ///`description`
+    pub description: Option<String>,
+    ///`members`
+    pub members: Vec<String>,
+    ///`administrators`
+    pub administrators: Vec<String>,
+    ///`disposition`, if it is a known one
+    pub disposition: Option<Disposition>,
+    ///The whole record, with the fields not parsed above
+    json: Value,
+}
+
+impl GroupRecord {
+    ///Parses a JSON group record
+    /// ```
+    /// use user_lookup::userdb::GroupRecord;
+    /// use user_lookup::GroupEntry;
+    ///
+    /// let record = GroupRecord::parse(r#"{"groupName":"dev","gid":2001,"members":["user1"]}"#).unwrap();
+    /// assert_eq!("dev:x:2001:user1", record.to_group_entry().unwrap().to_line());
+    ///
+    /// let entry = GroupEntry::parse("wheel:x:10:user1").unwrap();
+    /// let record = GroupRecord::from(&entry);
+    /// assert_eq!(r#"{"groupName":"wheel","gid":10,"members":["user1"]}"#, record.to_json());
+    /// ```
+    pub fn parse(s: &str) -> Result<GroupRecord, RecordError> {
+        Self::from_json(Value::parse(s)?)
+    }
+
+    ///Creates a record from a parsed JSON object
+    pub(crate) fn from_json(json: Value) -> Result<GroupRecord, RecordError> {
+        object(&json)?;
+        Ok(GroupRecord {
+            group_name: string(&json, "groupName")?
+                .ok_or(RecordError::MissingField("groupName"))?,
+            gid: id(&json, "gid")?,
+            description: string(&json, "description")?,
+            members: list(&json, "members")?,
+            administrators: list(&json, "administrators")?,
+            disposition: disposition(&json)?,
+            json,
+        })
+    }
+
+    ///The record as JSON text, with the parsed fields written back to
+    ///the whole record
+    pub fn to_json(&self) -> String {
+        self.to_value().to_string()
+    }
+
+    fn to_value(&self) -> Value {
+        let mut json = match &self.json {
+            Value::Object(_) => self.json.clone(),
+            _ => Value::Object(vec![]),
+        };
+        let non_empty = |list: &Vec<String>| Some(list.clone()).filter(|l| !l.is_empty());
+        json.set("groupName", self.group_name.as_str().into());
+        set(&mut json, "gid", self.gid.map(Value::from));
+        set(
+            &mut json,
+            "description",
+            self.description.clone().map(Value::from),
+        );
+        set(
+            &mut json,
+            "members",
+            non_empty(&self.members).map(Value::from),
+        );
+        set(
+            &mut json,
+            "administrators",
+            non_empty(&self.administrators).map(Value::from),
+        );
+        set(
+            &mut json,
+            "disposition",
+            self.disposition.map(|d| d.as_str().into()),
+        );
+        json
+    }
+
+    ///Converts the record to a group entry, with the members of the
+    ///record only. A record without gid can not be converted.
+    pub fn to_group_entry(&self) -> Result<GroupEntry, RecordError> {
+        Ok(GroupEntry {
+            name: self.group_name.clone(),
+            passwd: "x".to_string(),
+            gid: self.gid.ok_or(RecordError::MissingField("gid"))?,
+            users: self.members.clone(),
+        })
+    }
+}
+
+impl From<&GroupEntry> for GroupRecord {
+    fn from(entry: &GroupEntry) -> Self {
+        let mut record = GroupRecord {
+            group_name: entry.name.clone(),
+            gid: Some(entry.gid),
+            description: None,
+            members: entry.users.clone(),
+            administrators: vec![],
+            disposition: None,
+            json: Value::Object(vec![]),
+        };
+        record.json = record.to_value();
+        record
+    }
+}
+
+///A record file that could not be read, in [ParseMode::Lenient]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordWarning {
+    ///The path of the record
+    pub path: PathBuf,
+    ///What is wrong with it
+    pub error: RecordError,
+}
+
+struct Loaded {
+    at: Instant,
+    wall_clock: SystemTime,
+    stamps: Vec<Option<FileStamp>>,
+}
+
+///Reads the user and group records of the userdb directories. It
+///supports caching the records to avoid having to read the directories
+///more than needed.
+///
+///With [CachePolicy::on_change], the directories are read again when a
+///record is added, removed or replaced, but not when a record file is
+///modified in place. Records are usually replaced, like `useradd` does.
+pub struct UserDbReader {
+    dirs: Vec<PathBuf>,
+    policy: CachePolicy,
+    mode: ParseMode,
+    loaded: Option<Loaded>,
+    records: Records,
+    passwd: PasswdIndex,
+    group_index: GroupIndex,
+}
+
+impl UserDbReader {
+    ///Creates a new UserDbReader for the directories in [USERDB_DIRS]
+    ///with a specified cache_time.
+    ///
+    ///Use cache_time with a Duration of 0 to disable caching.
+    ///A [CachePolicy] can be passed instead of a Duration for
+    ///more control over when the directories are read again.
+    pub fn new<P: Into<CachePolicy>>(cache_time: P) -> Self {
+        Self::from_dirs(USERDB_DIRS, cache_time)
+    }
+
+    ///Creates a new UserDbReader for the directories, in order. Missing
+    ///directories are skipped.
+    pub fn from_dirs<I, T, P>(dirs: I, cache_time: P) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<PathBuf>,
+        P: Into<CachePolicy>,
+    {
+        Self {
+            dirs: dirs.into_iter().map(Into::into).collect(),
+            policy: cache_time.into(),
+            mode: ParseMode::default(),
+            loaded: None,
+            records: Records::default(),
+            passwd: PasswdIndex::default(),
+            group_index: GroupIndex::default(),
+        }
+    }
+
+    ///Sets how records that can not be read are handled. The default
+    ///is [ParseMode::Lenient], which skips them.
+    pub fn with_parse_mode(mut self, mode: ParseMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    ///The records skipped the last time the directories were read, in
+    ///[ParseMode::Lenient]
+    pub fn warnings(&self) -> &[RecordWarning] {
+        &self.records.warnings
+    }
+
+    ///Returns when the directories were last read, or `None` if they
+    ///have not been read yet.
+    pub fn last_loaded(&self) -> Option<SystemTime> {
+        self.loaded.as_ref().map(|l| l.wall_clock)
+    }
+
+    fn stamps(&self) -> Vec<Option<FileStamp>> {
+        self.dirs
+            .iter()
+            .map(|dir| std::fs::metadata(dir).ok().map(|m| FileStamp::from(&m)))
+            .collect()
+    }
+
+    fn refresh_if_needed(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        let stamps = match self.policy.checks_file() {
+            true => self.stamps(),
+            false => vec![],
+        };
+        if let Some(loaded) = &self.loaded {
+            let expired = self
+                .policy
+                .max_age()
+                .map(|max_age| now.saturating_duration_since(loaded.at) >= max_age)
+                .unwrap_or(false);
+            if !expired && (!self.policy.checks_file() || stamps == loaded.stamps) {
+                return Ok(());
+            }
+        }
+        let mut records = Records::default();
+        for dir in &self.dirs {
+            records.read_dir(dir, self.mode)?;
+        }
+        self.passwd = PasswdIndex::new(
+            records
+                .users
+                .iter()
+                .filter_map(|u| u.to_passwd_entry().ok())
+                .collect(),
+        );
+        self.group_index = GroupIndex::new(
+            records
+                .groups
+                .iter()
+                .filter_map(|g| records.group_entry(g))
+                .collect(),
+        );
+        self.records = records;
+        self.loaded = Some(Loaded {
+            at: now,
+            wall_clock: SystemTime::now(),
+            stamps,
+        });
+        Ok(())
+    }
+
+    ///Get all user records
+    pub fn get_users(&mut self) -> Result<&Vec<UserRecord>, Error> {
+        self.refresh_if_needed()?;
+        Ok(&self.records.users)
+    }
+
+    ///Get all group records
+    pub fn get_group_records(&mut self) -> Result<&Vec<GroupRecord>, Error> {
+        self.refresh_if_needed()?;
+        Ok(&self.records.groups)
+    }
+
+    ///Get the user and group names of all membership files
+    pub fn get_memberships(&mut self) -> Result<&Vec<(String, String)>, Error> {
+        self.refresh_if_needed()?;
+        Ok(&self.records.memberships)
+    }
+
+    ///Look up a user record by user name
+    pub fn get_user_by_name(&mut self, name: &str) -> Result<Option<UserRecord>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.records.user(name).cloned())
+    }
+
+    ///Look up a user record by uid
+    pub fn get_user_by_uid(&mut self, uid: u32) -> Result<Option<UserRecord>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self
+            .passwd
+            .get_by_uid(uid)
+            .and_then(|e| self.records.user(&e.username))
+            .cloned())
+    }
+
+    ///Look up a group record by group name
+    pub fn get_group_by_name(&mut self, name: &str) -> Result<Option<GroupRecord>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.records.group(name).cloned())
+    }
+
+    ///Look up a group record by gid
+    pub fn get_group_by_gid(&mut self, gid: u32) -> Result<Option<GroupRecord>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self
+            .group_index
+            .get_by_gid(gid)
+            .and_then(|e| self.records.group(&e.name))
+            .cloned())
+    }
+
+    ///Get all users as passwd entries, skipping records without uid
+    pub fn get_entries(&mut self) -> Result<Vec<PasswdEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.passwd.entries().clone())
+    }
+
+    ///Get the index over the users as passwd entries
+    pub fn get_index(&mut self) -> Result<&PasswdIndex, Error> {
+        self.refresh_if_needed()?;
+        Ok(&self.passwd)
+    }
+
+    ///Get all groups as group entries, skipping records without gid. The
+    ///members are the members of the record, followed by the users listing
+    ///the group in `memberOf` and the users of membership files.
+    pub fn get_groups(&mut self) -> Result<Vec<GroupEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.group_index.groups().clone())
+    }
+
+    ///Get the index over the groups as group entries, see [Self::get_groups]
+    pub fn get_group_index(&mut self) -> Result<&GroupIndex, Error> {
+        self.refresh_if_needed()?;
+        Ok(&self.group_index)
+    }
+
+    ///Look up a group entry by the group name, see [Self::get_groups]
+    pub fn get_group_entry_by_name(&mut self, name: &str) -> Result<Option<GroupEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.group_index.get_by_name(name).cloned())
+    }
+
+    ///Look up a group entry by gid, see [Self::get_groups]
+    pub fn get_group_entry_by_gid(&mut self, gid: u32) -> Result<Option<GroupEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.group_index.get_by_gid(gid).cloned())
+    }
+}
+
+#[derive(Default)]
+struct Records {
+    users: Vec<UserRecord>,
+    groups: Vec<GroupRecord>,
+    user_names: HashMap<String, usize>,
+    group_names: HashMap<String, usize>,
+    memberships: Vec<(String, String)>,
+    warnings: Vec<RecordWarning>,
+}
+
+impl Records {
+    fn user(&self, name: &str) -> Option<&UserRecord> {
+        self.user_names.get(name).map(|i| &self.users[*i])
+    }
+
+    fn group(&self, name: &str) -> Option<&GroupRecord> {
+        self.group_names.get(name).map(|i| &self.groups[*i])
+    }
+
+    fn group_entry(&self, group: &GroupRecord) -> Option<GroupEntry> {
+        let mut entry = group.to_group_entry().ok()?;
+        let users = self
+            .users
+            .iter()
+            .filter(|u| u.member_of.contains(&group.group_name))
+            .map(|u| &u.user_name);
+        let files = self
+            .memberships
+            .iter()
+            .filter(|(_, g)| *g == group.group_name)
+            .map(|(u, _)| u);
+        for user in users.chain(files) {
+            if !entry.users.contains(user) {
+                entry.users.push(user.clone());
+            }
+        }
+        Some(entry)
+    }
+
+    fn read_dir(&mut self, dir: &Path, mode: ParseMode) -> Result<(), Error> {
+        let mut paths = match std::fs::read_dir(dir) {
+            Ok(entries) => entries
+                .map(|e| e.map(|e| e.path()))
+                .collect::<Result<Vec<_>, _>>()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        paths.sort();
+        for path in paths {
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name,
+                None => continue,
+            };
+            if let Some((user, group)) = name
+                .strip_suffix(".membership")
+                .and_then(|m| m.split_once(':'))
+            {
+                let membership = (user.to_string(), group.to_string());
+                if !self.memberships.contains(&membership) {
+                    self.memberships.push(membership);
+                }
+                continue;
+            }
+            let is_user = name.ends_with(".user");
+            if !is_user && !name.ends_with(".group") {
+                continue;
+            }
+            let contents = match std::fs::read_to_string(&path) {
+                Ok(contents) => contents,
+                //A dangling symlink
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let result = match is_user {
+                true => UserRecord::parse(&contents).map(|user| self.add_user(user)),
+                false => GroupRecord::parse(&contents).map(|group| self.add_group(group)),
+            };
+            match (result, mode) {
+                (Ok(()), _) => {}
+                (Err(e), ParseMode::Strict) => return Err(Error::Record(e)),
+                (Err(error), ParseMode::Lenient) => {
+                    self.warnings.push(RecordWarning { path, error })
+                }
+            }
+        }
+        Ok(())
+    }
+
+    ///Adds the user, unless an earlier record has the same name. The
+    ///`<uid>.user` symlinks are read as the same record again.
+    fn add_user(&mut self, user: UserRecord) {
+        if let Entry::Vacant(name) = self.user_names.entry(user.user_name.clone()) {
+            name.insert(self.users.len());
+            self.users.push(user);
+        }
+    }
+
+    fn add_group(&mut self, group: GroupRecord) {
+        if let Entry::Vacant(name) = self.group_names.entry(group.group_name.clone()) {
+            name.insert(self.groups.len());
+            self.groups.push(group);
+        }
+    }
+}
+
+impl UserSource for UserDbReader {
+    fn get_by_username(&mut self, username: &str) -> Result<Option<PasswdEntry>, Error> {
+        Ok(self
+            .get_user_by_name(username)?
+            .and_then(|u| u.to_passwd_entry().ok()))
+    }
+
+    fn get_by_uid(&mut self, uid: u32) -> Result<Option<PasswdEntry>, Error> {
+        Ok(self
+            .get_user_by_uid(uid)?
+            .and_then(|u| u.to_passwd_entry().ok()))
+    }
+
+    fn get_entries(&mut self) -> Result<Vec<PasswdEntry>, Error> {
+        self.get_entries()
+    }
+}
+
+impl GroupSource for UserDbReader {
+    fn get_by_name(&mut self, name: &str) -> Result<Option<GroupEntry>, Error> {
+        self.get_group_entry_by_name(name)
+    }
+
+    fn get_by_gid(&mut self, gid: u32) -> Result<Option<GroupEntry>, Error> {
+        self.get_group_entry_by_gid(gid)
+    }
+
+    fn get_groups(&mut self) -> Result<Vec<GroupEntry>, Error> {
+        self.get_groups()
+    }
+}
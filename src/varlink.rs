@@ -0,0 +1,591 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `varlink` looks up users and groups with the `io.systemd.UserDatabase`
+//! varlink interface of `systemd-userdbd`, like the `systemd` service of
+//! /etc/nsswitch.conf does. This finds the users of `systemd-homed` and the
+//! dynamic users of services, as well as the records in the userdb
+//! directories.
+//!
+//! The [Client] makes the calls and returns JSON records, and the
+//! [PasswdReader] and [GroupReader] have the same lookup methods as the ones
+//! in `sync_reader`. By default the `io.systemd.Multiplexer` socket in
+//! /run/systemd/userdb is used, which asks every service.
+//!
+//! This module requires the `varlink` feature.
+//!
+//!```rust,ignore
+//! use user_lookup::varlink::PasswdReader;
+//! use std::time::Duration;
+//!
+//! fn main() {
+//!    let mut reader = PasswdReader::new(Duration::new(0,0));
+//!
+//!    println!("User with uid 1000 is: {}",
+//!    reader.get_username_by_uid(1000).unwrap().unwrap());
+//! }
+//!
+//!```
+use crate::cache::CachePolicy;
+use crate::json::Value;
+use crate::userdb::GroupRecord;
+use crate::userdb::UserRecord;
+use crate::Error;
+use crate::GroupEntry;
+use crate::PasswdEntry;
+
+use crate::index::GroupIndex;
+use crate::index::PasswdIndex;
+use crate::source::GroupSource;
+use crate::source::UserSource;
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+///The socket asking every userdb service
+pub const MULTIPLEXER_SOCKET: &str = "/run/systemd/userdb/io.systemd.Multiplexer";
+
+///How long to wait for a reply by default
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+///The error returned when there is no such record
+const NO_RECORD_FOUND: &str = "io.systemd.UserDatabase.NoRecordFound";
+
+///An error reply of a varlink call
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarlinkError {
+    ///The name of the error, like `io.systemd.UserDatabase.BadService`
+    pub error: String,
+    ///The parameters of the error, as JSON text
+    pub parameters: String,
+}
+
+impl fmt::Display for VarlinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "varlink error {}", self.error)
+    }
+}
+
+impl std::error::Error for VarlinkError {}
+
+fn invalid_data(message: &str) -> Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string()).into()
+}
+
+///Makes `io.systemd.UserDatabase` calls on a socket. Every call uses a
+///new connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Client {
+    socket: PathBuf,
+    service: String,
+    timeout: Duration,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::with_socket(MULTIPLEXER_SOCKET)
+    }
+}
+
+impl Client {
+    ///Creates a client for the multiplexer socket
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Creates a client for another socket. The name of the socket is the
+    ///name of the service, like `io.systemd.Home`.
+    pub fn with_socket<T: Into<PathBuf>>(socket: T) -> Self {
+        let socket = socket.into();
+        let service = socket
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self {
+            socket,
+            service,
+            timeout: TIMEOUT,
+        }
+    }
+
+    ///Sets how long to wait for the socket
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    ///Calls a method, returning the parameters of the replies. With
+    ///`more`, the service may send several replies. A `NoRecordFound`
+    ///error gives no replies, other errors give [Error::Varlink].
+    pub(crate) fn call(
+        &self,
+        method: &str,
+        parameters: Value,
+        more: bool,
+    ) -> Result<Vec<Value>, Error> {
+        let mut request = Value::Object(vec![
+            ("method".to_string(), method.into()),
+            ("parameters".to_string(), parameters),
+        ]);
+        if more {
+            request.set("more", true.into());
+        }
+        let mut stream = UnixStream::connect(&self.socket)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut message = request.to_string().into_bytes();
+        message.push(0);
+        stream.write_all(&message)?;
+
+        let mut reader = BufReader::new(stream);
+        let mut replies = vec![];
+        loop {
+            let mut message = vec![];
+            reader.read_until(0, &mut message)?;
+            if message.pop() != Some(0) {
+                return Err(invalid_data("connection closed before the reply"));
+            }
+            let reply = String::from_utf8(message)
+                .map_err(|_| invalid_data("reply is not UTF-8"))
+                .and_then(|s| Value::parse(&s).map_err(|e| invalid_data(&e.to_string())))?;
+            let parameters = reply
+                .get("parameters")
+                .cloned()
+                .unwrap_or(Value::Object(vec![]));
+            match reply.get("error").and_then(Value::as_str) {
+                Some(NO_RECORD_FOUND) => return Ok(replies),
+                Some(error) => {
+                    return Err(Error::Varlink(VarlinkError {
+                        error: error.to_string(),
+                        parameters: parameters.to_string(),
+                    }))
+                }
+                None => replies.push(parameters),
+            }
+            if !more || reply.get("continues").and_then(Value::as_bool) != Some(true) {
+                return Ok(replies);
+            }
+        }
+    }
+
+    fn parameters(&self, key: Option<(&str, Value)>) -> Value {
+        let mut parameters = Value::Object(vec![]);
+        if let Some((key, value)) = key {
+            parameters.set(key, value);
+        }
+        parameters.set("service", self.service.as_str().into());
+        parameters
+    }
+
+    fn user_records(&self, key: Option<(&str, Value)>) -> Result<Vec<UserRecord>, Error> {
+        self.call(
+            "io.systemd.UserDatabase.GetUserRecord",
+            self.parameters(key.clone()),
+            key.is_none(),
+        )?
+        .into_iter()
+        .map(|reply| {
+            let record = reply.get("record").cloned().unwrap_or(Value::Null);
+            Ok(UserRecord::from_json(record)?)
+        })
+        .collect()
+    }
+
+    fn group_records(&self, key: Option<(&str, Value)>) -> Result<Vec<GroupRecord>, Error> {
+        self.call(
+            "io.systemd.UserDatabase.GetGroupRecord",
+            self.parameters(key.clone()),
+            key.is_none(),
+        )?
+        .into_iter()
+        .map(|reply| {
+            let record = reply.get("record").cloned().unwrap_or(Value::Null);
+            Ok(GroupRecord::from_json(record)?)
+        })
+        .collect()
+    }
+
+    ///Look up a user record by user name, with `GetUserRecord`
+    pub fn get_user_record(&self, name: &str) -> Result<Option<UserRecord>, Error> {
+        Ok(self
+            .user_records(Some(("userName", name.into())))?
+            .into_iter()
+            .next())
+    }
+
+    ///Look up a user record by uid, with `GetUserRecord`
+    pub fn get_user_record_by_uid(&self, uid: u32) -> Result<Option<UserRecord>, Error> {
+        Ok(self
+            .user_records(Some(("uid", uid.into())))?
+            .into_iter()
+            .next())
+    }
+
+    ///Get all user records, with `GetUserRecord`
+    pub fn get_user_records(&self) -> Result<Vec<UserRecord>, Error> {
+        self.user_records(None)
+    }
+
+    ///Look up a group record by group name, with `GetGroupRecord`
+    pub fn get_group_record(&self, name: &str) -> Result<Option<GroupRecord>, Error> {
+        Ok(self
+            .group_records(Some(("groupName", name.into())))?
+            .into_iter()
+            .next())
+    }
+
+    ///Look up a group record by gid, with `GetGroupRecord`
+    pub fn get_group_record_by_gid(&self, gid: u32) -> Result<Option<GroupRecord>, Error> {
+        Ok(self
+            .group_records(Some(("gid", gid.into())))?
+            .into_iter()
+            .next())
+    }
+
+    ///Get all group records, with `GetGroupRecord`
+    pub fn get_group_records(&self) -> Result<Vec<GroupRecord>, Error> {
+        self.group_records(None)
+    }
+
+    ///Get the user and group names of memberships, with `GetMemberships`.
+    ///Only the memberships of `user` or `group` are returned, if given.
+    pub fn get_memberships(
+        &self,
+        user: Option<&str>,
+        group: Option<&str>,
+    ) -> Result<Vec<(String, String)>, Error> {
+        let mut parameters = self.parameters(None);
+        if let Some(user) = user {
+            parameters.set("userName", user.into());
+        }
+        if let Some(group) = group {
+            parameters.set("groupName", group.into());
+        }
+        self.call("io.systemd.UserDatabase.GetMemberships", parameters, true)?
+            .into_iter()
+            .map(|reply| {
+                let name = |key| reply.get(key).and_then(Value::as_str).map(str::to_string);
+                match (name("userName"), name("groupName")) {
+                    (Some(user), Some(group)) => Ok((user, group)),
+                    _ => Err(invalid_data("membership without user or group name")),
+                }
+            })
+            .collect()
+    }
+}
+
+///The main entity to lookup user information with `systemd-userdbd`.
+pub struct PasswdReader {
+    client: Client,
+    max_age: Duration,
+    loaded: Option<(Instant, SystemTime)>,
+    passwd: PasswdIndex,
+}
+
+impl PasswdReader {
+    ///Creates a new PasswdReader for the multiplexer socket, keeping the
+    ///list of all users for `cache_time`.
+    ///
+    ///Use cache_time with a Duration of 0 to enumerate the users again
+    ///for every call. A [CachePolicy] can be passed instead of a Duration,
+    ///of which only the maximum age is used.
+    pub fn new<P: Into<CachePolicy>>(cache_time: P) -> Self {
+        Self::with_client(Client::new(), cache_time)
+    }
+
+    ///Creates a new PasswdReader using a specific client
+    pub fn with_client<P: Into<CachePolicy>>(client: Client, cache_time: P) -> Self {
+        Self {
+            client,
+            max_age: cache_time.into().max_age_or_default(),
+            loaded: None,
+            passwd: PasswdIndex::default(),
+        }
+    }
+
+    fn refresh_if_needed(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        if let Some((at, _)) = self.loaded {
+            if now.saturating_duration_since(at) < self.max_age {
+                return Ok(());
+            }
+        }
+        let users = self.client.get_user_records()?;
+        self.passwd = PasswdIndex::new(
+            users
+                .iter()
+                .filter_map(|u| u.to_passwd_entry().ok())
+                .collect(),
+        );
+        self.loaded = Some((now, SystemTime::now()));
+        Ok(())
+    }
+
+    ///Returns when the users were last enumerated, or `None` if they
+    ///have not been enumerated yet.
+    pub fn last_loaded(&self) -> Option<SystemTime> {
+        self.loaded.map(|(_, wall_clock)| wall_clock)
+    }
+
+    ///Get the entire list of passwd entries
+    pub fn get_entries(&mut self) -> Result<&Vec<PasswdEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.passwd.entries())
+    }
+
+    ///Get the index over the enumerated passwd entries
+    pub fn get_index(&mut self) -> Result<&PasswdIndex, Error> {
+        self.refresh_if_needed()?;
+        Ok(&self.passwd)
+    }
+
+    ///Will return an iterator over &PasswdEntry
+    pub fn try_iter(&mut self) -> Result<std::slice::Iter<'_, PasswdEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.passwd.entries().iter())
+    }
+
+    ///Look up a PasswdEntry by username
+    pub fn get_by_username(&mut self, username: &str) -> Result<Option<PasswdEntry>, Error> {
+        Ok(self
+            .client
+            .get_user_record(username)?
+            .and_then(|u| u.to_passwd_entry().ok()))
+    }
+
+    ///Look up a PasswdEntry by uid
+    pub fn get_by_uid(&mut self, uid: u32) -> Result<Option<PasswdEntry>, Error> {
+        Ok(self
+            .client
+            .get_user_record_by_uid(uid)?
+            .and_then(|u| u.to_passwd_entry().ok()))
+    }
+
+    ///Look up a username by uid
+    pub fn get_username_by_uid(&mut self, uid: u32) -> Result<Option<String>, Error> {
+        Ok(self.get_by_uid(uid)?.map(|e| e.username))
+    }
+
+    ///Look up a user ID by username
+    pub fn get_uid_by_username(&mut self, username: &str) -> Result<Option<u32>, Error> {
+        Ok(self.get_by_username(username)?.map(|e| e.uid))
+    }
+}
+
+///The main entity to lookup group information with `systemd-userdbd`.
+///The members of a group are the members of its record, followed by the
+///users of its memberships.
+pub struct GroupReader {
+    client: Client,
+    max_age: Duration,
+    loaded: Option<(Instant, SystemTime)>,
+    groups: GroupIndex,
+}
+
+impl GroupReader {
+    ///Creates a new GroupReader for the multiplexer socket, keeping the
+    ///list of all groups for `cache_time`.
+    ///
+    ///Use cache_time with a Duration of 0 to enumerate the groups again
+    ///for every call. A [CachePolicy] can be passed instead of a Duration,
+    ///of which only the maximum age is used.
+    pub fn new<P: Into<CachePolicy>>(cache_time: P) -> Self {
+        Self::with_client(Client::new(), cache_time)
+    }
+
+    ///Creates a new GroupReader using a specific client
+    pub fn with_client<P: Into<CachePolicy>>(client: Client, cache_time: P) -> Self {
+        Self {
+            client,
+            max_age: cache_time.into().max_age_or_default(),
+            loaded: None,
+            groups: GroupIndex::default(),
+        }
+    }
+
+    fn refresh_if_needed(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        if let Some((at, _)) = self.loaded {
+            if now.saturating_duration_since(at) < self.max_age {
+                return Ok(());
+            }
+        }
+        let memberships = self.client.get_memberships(None, None)?;
+        let groups = self
+            .client
+            .get_group_records()?
+            .iter()
+            .filter_map(|g| with_members(g, &memberships))
+            .collect();
+        self.groups = GroupIndex::new(groups);
+        self.loaded = Some((now, SystemTime::now()));
+        Ok(())
+    }
+
+    fn group_entry(&self, group: Option<GroupRecord>) -> Result<Option<GroupEntry>, Error> {
+        let group = match group {
+            Some(group) => group,
+            None => return Ok(None),
+        };
+        let memberships = self.client.get_memberships(None, Some(&group.group_name))?;
+        Ok(with_members(&group, &memberships))
+    }
+
+    ///Returns when the groups were last enumerated, or `None` if they
+    ///have not been enumerated yet.
+    pub fn last_loaded(&self) -> Option<SystemTime> {
+        self.loaded.map(|(_, wall_clock)| wall_clock)
+    }
+
+    ///Get the entire list of group entries
+    pub fn get_groups(&mut self) -> Result<&Vec<GroupEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.groups.groups())
+    }
+
+    ///Get the index over the enumerated group entries
+    pub fn get_index(&mut self) -> Result<&GroupIndex, Error> {
+        self.refresh_if_needed()?;
+        Ok(&self.groups)
+    }
+
+    ///Will return an iterator over &GroupEntry
+    pub fn try_iter(&mut self) -> Result<std::slice::Iter<'_, GroupEntry>, Error> {
+        self.refresh_if_needed()?;
+        Ok(self.groups.groups().iter())
+    }
+
+    ///Look up a GroupEntry by the group name
+    pub fn get_by_name(&mut self, name: &str) -> Result<Option<GroupEntry>, Error> {
+        let group = self.client.get_group_record(name)?;
+        self.group_entry(group)
+    }
+
+    ///Look up a GroupEntry by gid
+    pub fn get_by_gid(&mut self, gid: u32) -> Result<Option<GroupEntry>, Error> {
+        let group = self.client.get_group_record_by_gid(gid)?;
+        self.group_entry(group)
+    }
+
+    ///Look up a group name by gid
+    pub fn get_name_by_gid(&mut self, gid: u32) -> Result<Option<String>, Error> {
+        Ok(self
+            .client
+            .get_group_record_by_gid(gid)?
+            .map(|g| g.group_name))
+    }
+
+    ///Look up a group ID by the group name
+    pub fn get_gid_by_name(&mut self, name: &str) -> Result<Option<u32>, Error> {
+        Ok(self.client.get_group_record(name)?.and_then(|g| g.gid))
+    }
+
+    ///Get all groups of a user, the primary group followed by the groups
+    ///of its memberships. Groups that can not be looked up are left out.
+    pub fn get_groups_for_user(&mut self, user: &PasswdEntry) -> Result<Vec<GroupEntry>, Error> {
+        let mut groups = vec![];
+        for gid in self.get_gids_for_user(user)? {
+            if let Some(group) = self.get_by_gid(gid)? {
+                groups.push(group);
+            }
+        }
+        Ok(groups)
+    }
+
+    ///Get all group IDs of a user, the primary gid followed by the gids
+    ///of its memberships. The primary gid is always included.
+    pub fn get_gids_for_user(&mut self, user: &PasswdEntry) -> Result<Vec<u32>, Error> {
+        let mut gids = vec![user.gid];
+        for (_, group) in self.client.get_memberships(Some(&user.username), None)? {
+            if let Some(gid) = self.get_gid_by_name(&group)? {
+                if !gids.contains(&gid) {
+                    gids.push(gid);
+                }
+            }
+        }
+        Ok(gids)
+    }
+
+    ///Get all groups of a user by username, using `passwd` to find the
+    ///primary group. Returns `None` if the user does not exist.
+    pub fn get_groups_by_username(
+        &mut self,
+        username: &str,
+        passwd: &mut PasswdReader,
+    ) -> Result<Option<Vec<GroupEntry>>, Error> {
+        match passwd.get_by_username(username)? {
+            Some(user) => self.get_groups_for_user(&user).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    ///Get all group IDs of a user by username, using `passwd` to find the
+    ///primary group. Returns `None` if the user does not exist.
+    pub fn get_gids_by_username(
+        &mut self,
+        username: &str,
+        passwd: &mut PasswdReader,
+    ) -> Result<Option<Vec<u32>>, Error> {
+        match passwd.get_by_username(username)? {
+            Some(user) => self.get_gids_for_user(&user).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+///The group entry of a record, with the users of the memberships added
+fn with_members(group: &GroupRecord, memberships: &[(String, String)]) -> Option<GroupEntry> {
+    let mut entry = group.to_group_entry().ok()?;
+    let mut seen: HashSet<String> = entry.users.iter().cloned().collect();
+    for (user, _) in memberships.iter().filter(|(_, g)| *g == group.group_name) {
+        if seen.insert(user.clone()) {
+            entry.users.push(user.clone());
+        }
+    }
+    Some(entry)
+}
+
+impl UserSource for PasswdReader {
+    fn get_by_username(&mut self, username: &str) -> Result<Option<PasswdEntry>, Error> {
+        self.get_by_username(username)
+    }
+
+    fn get_by_uid(&mut self, uid: u32) -> Result<Option<PasswdEntry>, Error> {
+        self.get_by_uid(uid)
+    }
+
+    fn get_entries(&mut self) -> Result<Vec<PasswdEntry>, Error> {
+        self.get_entries().cloned()
+    }
+}
+
+impl GroupSource for GroupReader {
+    fn get_by_name(&mut self, name: &str) -> Result<Option<GroupEntry>, Error> {
+        self.get_by_name(name)
+    }
+
+    fn get_by_gid(&mut self, gid: u32) -> Result<Option<GroupEntry>, Error> {
+        self.get_by_gid(gid)
+    }
+
+    fn get_groups(&mut self) -> Result<Vec<GroupEntry>, Error> {
+        self.get_groups().cloned()
+    }
+
+    fn get_gids_for_user(&mut self, user: &PasswdEntry) -> Result<Vec<u32>, Error> {
+        self.get_gids_for_user(user)
+    }
+}
@@ -0,0 +1,266 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `watch` tells what changed in the passwd and group files, as they
+//! change. The `Watcher` of `sync_reader` is a blocking iterator over the
+//! [Event]s, and the `Watcher` of `async_reader` hands them out to a
+//! task, together with a `tokio::sync::watch` channel of the latest
+//! [UserDatabase].
+//!
+//! The directories of the files are watched with inotify, so that a file
+//! replaced by renaming another file over it, like `vipw` and `useradd`
+//! do, is seen as well as a file written in place. The module is built on
+//! Linux on x86_64 and aarch64.
+//!
+//! [events] finds the events between any two snapshots.
+//! ```
+//! use user_lookup::database::UserDatabase;
+//! use user_lookup::watch::{events, Event};
+//!
+//! let before = UserDatabase::parse("user1:x:1000:100:::\n", "users:x:100:user1\n");
+//! let after = UserDatabase::parse(
+//!     "user1:x:1000:100::/home/user1:\nuser2:x:1001:100:::\n",
+//!     "users:x:100:user1,user2\n",
+//! );
+//! let events = events(&before, &after);
+//! assert!(matches!(&events[0], Event::UserModified { after, .. } if after.home_dir == "/home/user1"));
+//! assert!(matches!(&events[1], Event::UserAdded(user) if user.username == "user2"));
+//! assert!(matches!(&events[2], Event::GroupMembershipChanged { added, .. } if added == &["user2"]));
+//! ```
+#![cfg_attr(not(any(feature = "sync", feature = "async")), allow(dead_code))]
+use crate::database::UserDatabase;
+use crate::diff::Diff;
+use crate::diff::GroupChange;
+use crate::diff::UserChange;
+use crate::error::ParseMode;
+use crate::sys;
+use crate::Error;
+use crate::GroupEntry;
+use crate::PasswdEntry;
+
+use std::ffi::CString;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+///A change of the users or groups
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Event {
+    ///A user was added
+    UserAdded(PasswdEntry),
+    ///A user was removed
+    UserRemoved(PasswdEntry),
+    ///Fields of a user were changed. If the username is one of them, the
+    ///user was renamed, keeping the uid.
+    UserModified {
+        ///The user before the change
+        before: PasswdEntry,
+        ///The user after the change
+        after: PasswdEntry,
+    },
+    ///A group was added
+    GroupAdded(GroupEntry),
+    ///A group was removed
+    GroupRemoved(GroupEntry),
+    ///The name, password or gid of a group was changed. A change of the
+    ///name is a rename, keeping the gid.
+    GroupModified {
+        ///The group before the change
+        before: GroupEntry,
+        ///The group after the change
+        after: GroupEntry,
+    },
+    ///Users were added to or removed from the members of a group
+    GroupMembershipChanged {
+        ///The group after the change
+        group: GroupEntry,
+        ///The users that became members
+        added: Vec<String>,
+        ///The users that are no longer members
+        removed: Vec<String>,
+    },
+}
+
+///The events turning `before` into `after`, made from the [Diff] of
+///the snapshots. Users and groups are matched by name, and then by id,
+///so a rename is a modification. The events of the users come first,
+///in the order of `after` followed by the removed users, and then the
+///events of the groups in the same way.
+pub fn events(before: &UserDatabase, after: &UserDatabase) -> Vec<Event> {
+    let diff = Diff::new(before, after);
+    let mut events = vec![];
+    for change in diff.users {
+        events.push(match change {
+            UserChange::Added(user) => Event::UserAdded(user),
+            UserChange::Removed(user) => Event::UserRemoved(user),
+            UserChange::Modified { before, after, .. } => Event::UserModified { before, after },
+        });
+    }
+    for change in diff.groups {
+        match change {
+            GroupChange::Added(group) => events.push(Event::GroupAdded(group)),
+            GroupChange::Removed(group) => events.push(Event::GroupRemoved(group)),
+            GroupChange::Modified {
+                before,
+                after,
+                fields,
+                added_members,
+                removed_members,
+            } => {
+                if !fields.is_empty() {
+                    events.push(Event::GroupModified {
+                        before,
+                        after: after.clone(),
+                    });
+                }
+                if !added_members.is_empty() || !removed_members.is_empty() {
+                    events.push(Event::GroupMembershipChanged {
+                        group: after,
+                        added: added_members,
+                        removed: removed_members,
+                    });
+                }
+            }
+        }
+    }
+    events
+}
+
+///An inotify instance watching directories for files being written or
+///moved into them
+struct Inotify {
+    file: File,
+}
+
+impl Inotify {
+    fn new() -> std::io::Result<Self> {
+        let fd = unsafe { sys::inotify_init1(sys::IN_NONBLOCK | sys::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self {
+            file: unsafe { File::from_raw_fd(fd) },
+        })
+    }
+
+    fn watch(&self, dir: &Path) -> std::io::Result<()> {
+        let dir = CString::new(dir.as_os_str().as_bytes())?;
+        let mask = sys::IN_CLOSE_WRITE | sys::IN_MOVED_TO;
+        match unsafe { sys::inotify_add_watch(self.file.as_raw_fd(), dir.as_ptr(), mask) } {
+            wd if wd < 0 => Err(std::io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    ///Waits at most `timeout` for events, and tells if any of them is
+    ///about a file with one of the `names`. Without a timeout it waits
+    ///until there is an event.
+    fn wait(&mut self, timeout: Option<Duration>, names: &[&OsStr]) -> std::io::Result<bool> {
+        let mut fds = sys::pollfd {
+            fd: self.file.as_raw_fd(),
+            events: sys::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.map_or(-1, |t| {
+            t.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32
+        });
+        match unsafe { sys::poll(&mut fds, 1, timeout) } {
+            0 => return Ok(false),
+            n if n < 0 => {
+                let e = std::io::Error::last_os_error();
+                return match e.kind() {
+                    std::io::ErrorKind::Interrupted => Ok(false),
+                    _ => Err(e),
+                };
+            }
+            _ => {}
+        }
+        let mut changed = false;
+        let mut buf = [0u8; 4096];
+        loop {
+            let len = match self.file.read(&mut buf) {
+                Ok(len) => len,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(changed),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            //struct inotify_event { int wd; u32 mask; u32 cookie; u32 len; char name[]; }
+            let mut event = &buf[..len];
+            while event.len() >= 16 {
+                let mask = u32::from_ne_bytes(event[4..8].try_into().unwrap());
+                let name_len = u32::from_ne_bytes(event[12..16].try_into().unwrap()) as usize;
+                if event.len() < 16 + name_len {
+                    break;
+                }
+                let name = &event[16..16 + name_len];
+                let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+                changed |=
+                    mask & sys::IN_Q_OVERFLOW != 0 || names.iter().any(|n| n.as_bytes() == name);
+                event = &event[16 + name_len..];
+            }
+        }
+    }
+}
+
+///The blocking watcher used by both the sync and async `Watcher`
+pub(crate) struct FileWatcher {
+    inotify: Inotify,
+    passwd: PathBuf,
+    group: PathBuf,
+    mode: ParseMode,
+    database: UserDatabase,
+}
+
+impl FileWatcher {
+    ///Starts watching the files, and reads them
+    pub(crate) fn new(passwd: PathBuf, group: PathBuf, mode: ParseMode) -> Result<Self, Error> {
+        let inotify = Inotify::new()?;
+        for path in [&passwd, &group] {
+            match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => inotify.watch(dir)?,
+                _ => inotify.watch(Path::new("."))?,
+            }
+        }
+        let database = UserDatabase::from_files(&passwd, &group, mode)?;
+        Ok(Self {
+            inotify,
+            passwd,
+            group,
+            mode,
+            database,
+        })
+    }
+
+    pub(crate) fn database(&self) -> &UserDatabase {
+        &self.database
+    }
+
+    ///Waits at most `timeout` for the files to change, and reads them
+    ///again if they did. The events are empty if the files did not
+    ///change, or changed without changing any user or group.
+    pub(crate) fn wait(&mut self, timeout: Option<Duration>) -> Result<Vec<Event>, Error> {
+        let names: Vec<&OsStr> = [&self.passwd, &self.group]
+            .iter()
+            .filter_map(|p| p.file_name())
+            .collect();
+        if !self.inotify.wait(timeout, &names)? {
+            return Ok(vec![]);
+        }
+        let database = UserDatabase::from_files(&self.passwd, &self.group, self.mode)?;
+        let events = events(&self.database, &database);
+        self.database = database;
+        Ok(events)
+    }
+}
@@ -0,0 +1,523 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `writer` safely writes modified entries back to /etc/passwd, /etc/group,
+//! /etc/shadow and /etc/gshadow, the same way the shadow-utils tools do.
+//!
+//! While writing, the `/etc/.pwd.lock` lock is held, with the same semantics
+//! as `lckpwdf(3)`. The lock is only supported on Linux on x86_64 and
+//! aarch64, elsewhere taking it fails with [std::io::ErrorKind::Unsupported]. Each file is written to a temporary file next to it,
+//! synced to disk and then renamed into place, so readers only ever see the
+//! old or the new file. The previous version is kept as a backup with a `-`
+//! suffix, like `/etc/passwd-`, and the mode and owner of the file are kept.
+//! Entries with a field holding a `:` or a newline, which would add fields
+//! or lines to the file, are refused with [std::io::ErrorKind::InvalidData]
+//! before anything is written.
+//!
+//! ```
+//! use user_lookup::writer::Writer;
+//! use user_lookup::PasswdEntry;
+//!
+//! let dir = std::env::temp_dir().join(format!("user_lookup_writer_{}", std::process::id()));
+//! std::fs::create_dir_all(&dir).unwrap();
+//! std::fs::copy("test_files/passwd", dir.join("passwd")).unwrap();
+//!
+//! let mut entries: Vec<PasswdEntry> = std::fs::read_to_string(dir.join("passwd"))
+//!     .unwrap()
+//!     .lines()
+//!     .filter_map(PasswdEntry::parse)
+//!     .collect();
+//! entries[1].shell = "/bin/zsh".to_string();
+//!
+//! let writer = Writer::in_dir(&dir);
+//! writer.write_passwd(dir.join("passwd"), &entries).unwrap();
+//!
+//! let written = std::fs::read_to_string(dir.join("passwd")).unwrap();
+//! assert!(written.contains("user1:x:1000:100:User One:/home/user1:/bin/zsh\n"));
+//! let backup = std::fs::read_to_string(dir.join("passwd-")).unwrap();
+//! assert!(backup.contains("user1:x:1000:100:User One:/home/user1:/bin/bash\n"));
+//! # std::fs::remove_dir_all(&dir).unwrap();
+//! ```
+use crate::Error;
+use crate::GroupEntry;
+use crate::GshadowEntry;
+use crate::PasswdEntry;
+use crate::ShadowEntry;
+use crate::SubIdEntry;
+use crate::SubIdOwner;
+
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+///The lock file used by `lckpwdf(3)`
+pub const LOCK_FILE: &str = "/etc/.pwd.lock";
+
+///How long `lckpwdf(3)` waits for the lock
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(15);
+
+const LOCK_RETRY: Duration = Duration::from_millis(100);
+
+///Writes entries to the user and group databases. The lock is taken for
+///each write, or once for several writes with [Writer::lock].
+/// ```
+/// use user_lookup::writer::Writer;
+/// use std::time::Duration;
+///
+/// let dir = std::env::temp_dir().join(format!("user_lookup_lock_{}", std::process::id()));
+/// std::fs::create_dir_all(&dir).unwrap();
+///
+/// let writer = Writer::in_dir(&dir).with_timeout(Duration::from_millis(200));
+/// let lock = writer.lock().unwrap();
+/// assert!(writer.lock().is_err());
+/// drop(lock);
+/// assert!(writer.lock().is_ok());
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Writer {
+    lock_file: PathBuf,
+    timeout: Duration,
+    backup: bool,
+}
+
+impl Default for Writer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Writer {
+    ///Creates a new Writer using the standard lock file `/etc/.pwd.lock`
+    pub fn new() -> Self {
+        Self::with_lock_file(LOCK_FILE)
+    }
+
+    ///Creates a new Writer using the lock file `.pwd.lock` in `dir`. This
+    ///is useful when the databases are at an alternative location.
+    pub fn in_dir<T: AsRef<Path>>(dir: T) -> Self {
+        Self::with_lock_file(dir.as_ref().join(".pwd.lock"))
+    }
+
+    ///Creates a new Writer using the specified lock file
+    pub fn with_lock_file<T: Into<PathBuf>>(lock_file: T) -> Self {
+        Self {
+            lock_file: lock_file.into(),
+            timeout: LOCK_TIMEOUT,
+            backup: true,
+        }
+    }
+
+    ///Sets how long to wait for the lock, the default is [LOCK_TIMEOUT]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    ///Sets whether the previous file is kept with a `-` suffix, the
+    ///default is true
+    pub fn with_backup(mut self, backup: bool) -> Self {
+        self.backup = backup;
+        self
+    }
+
+    ///Takes the lock, waiting at most the timeout for other processes to
+    ///release it. The lock is released when the returned [WriteLock] is
+    ///dropped.
+    pub fn lock(&self) -> Result<WriteLock, Error> {
+        let start = Instant::now();
+        let file = loop {
+            match try_lock(&self.lock_file) {
+                Ok(file) => break file,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    if start.elapsed() >= self.timeout {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::TimedOut,
+                            format!("timed out waiting for {}", self.lock_file.display()),
+                        )
+                        .into());
+                    }
+                    std::thread::sleep(LOCK_RETRY);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        Ok(WriteLock {
+            path: self.lock_file.clone(),
+            file: Some(file),
+            backup: self.backup,
+        })
+    }
+
+    ///Takes the lock and replaces the passwd file at `path`
+    pub fn write_passwd<T: AsRef<Path>>(
+        &self,
+        path: T,
+        entries: &[PasswdEntry],
+    ) -> Result<(), Error> {
+        self.lock()?.write_passwd(path, entries)
+    }
+
+    ///Takes the lock and replaces the group file at `path`
+    pub fn write_group<T: AsRef<Path>>(
+        &self,
+        path: T,
+        entries: &[GroupEntry],
+    ) -> Result<(), Error> {
+        self.lock()?.write_group(path, entries)
+    }
+
+    ///Takes the lock and replaces the shadow file at `path`
+    pub fn write_shadow<T: AsRef<Path>>(
+        &self,
+        path: T,
+        entries: &[ShadowEntry],
+    ) -> Result<(), Error> {
+        self.lock()?.write_shadow(path, entries)
+    }
+
+    ///Takes the lock and replaces the gshadow file at `path`
+    pub fn write_gshadow<T: AsRef<Path>>(
+        &self,
+        path: T,
+        entries: &[GshadowEntry],
+    ) -> Result<(), Error> {
+        self.lock()?.write_gshadow(path, entries)
+    }
+}
+
+///The lock files held by this process. Locks taken with fcntl are per
+///process, and closing any fd of the file releases them, so threads have
+///to be kept apart separately. The lock files are only opened and closed
+///while holding this mutex, and only when not held by another thread.
+static HELD: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+fn try_lock(path: &Path) -> std::io::Result<File> {
+    let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+    if held.iter().any(|p| p == path) {
+        return Err(std::io::ErrorKind::WouldBlock.into());
+    }
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .open(path)?;
+    try_lock_file(&file)?;
+    held.push(path.to_path_buf());
+    Ok(file)
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn try_lock_file(file: &File) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    //lckpwdf uses a write lock on the whole file, taken with fcntl
+    let lock = crate::sys::flock {
+        l_type: crate::sys::F_WRLCK,
+        l_whence: crate::sys::SEEK_SET,
+        l_start: 0,
+        l_len: 0,
+        l_pid: 0,
+    };
+    match unsafe { crate::sys::fcntl(file.as_raw_fd(), crate::sys::F_SETLK, &lock) } {
+        0 => Ok(()),
+        _ => {
+            let e = std::io::Error::last_os_error();
+            //EACCES and EAGAIN both mean that the lock is held
+            match e.raw_os_error() {
+                Some(crate::sys::EAGAIN) | Some(crate::sys::EACCES) => {
+                    Err(std::io::ErrorKind::WouldBlock.into())
+                }
+                _ => Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+fn try_lock_file(_file: &File) -> std::io::Result<()> {
+    //A flock lock would not keep lckpwdf out, so there is no lock at all
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "the lock file is only supported on Linux on x86_64 and aarch64",
+    ))
+}
+
+///Proof that the lock is held. All writes done through it are
+///done while holding the lock, which is released when it is dropped.
+#[derive(Debug)]
+pub struct WriteLock {
+    path: PathBuf,
+    file: Option<File>,
+    backup: bool,
+}
+
+impl Drop for WriteLock {
+    fn drop(&mut self) {
+        //The fcntl lock itself is released when the file is closed, which
+        //has to happen before another thread may open it
+        let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+        drop(self.file.take());
+        held.retain(|p| *p != self.path);
+    }
+}
+
+impl WriteLock {
+    ///Replaces the passwd file at `path`
+    pub fn write_passwd<T: AsRef<Path>>(
+        &self,
+        path: T,
+        entries: &[PasswdEntry],
+    ) -> Result<(), Error> {
+        check_fields(entries)?;
+        self.write_lines(path, entries, 0o644)
+    }
+
+    ///Replaces the group file at `path`
+    pub fn write_group<T: AsRef<Path>>(
+        &self,
+        path: T,
+        entries: &[GroupEntry],
+    ) -> Result<(), Error> {
+        check_fields(entries)?;
+        self.write_lines(path, entries, 0o644)
+    }
+
+    ///Replaces the shadow file at `path`
+    pub fn write_shadow<T: AsRef<Path>>(
+        &self,
+        path: T,
+        entries: &[ShadowEntry],
+    ) -> Result<(), Error> {
+        check_fields(entries)?;
+        self.write_lines(path, entries, 0o600)
+    }
+
+    ///Replaces the gshadow file at `path`
+    pub fn write_gshadow<T: AsRef<Path>>(
+        &self,
+        path: T,
+        entries: &[GshadowEntry],
+    ) -> Result<(), Error> {
+        check_fields(entries)?;
+        self.write_lines(path, entries, 0o600)
+    }
+
+    ///Replaces the subuid or subgid file at `path`
+    pub fn write_subid<T: AsRef<Path>>(
+        &self,
+        path: T,
+        entries: &[SubIdEntry],
+    ) -> Result<(), Error> {
+        check_fields(entries)?;
+        self.write_lines(path, entries, 0o644)
+    }
+
+    ///Replaces the file at `path` with one line per entry. If the file does
+    ///not exist yet it is created with `mode`, otherwise the mode and owner
+    ///of the existing file are kept. An entry that formats as more than one
+    ///line fails the write.
+    pub fn write_lines<T: AsRef<Path>, E: Display>(
+        &self,
+        path: T,
+        entries: &[E],
+        mode: u32,
+    ) -> Result<(), Error> {
+        let mut contents = String::new();
+        for entry in entries {
+            let line = entry.to_string();
+            if line.contains('\n') {
+                return Err(invalid_data(format!("entry '{}' spans several lines", line)).into());
+            }
+            contents.push_str(&line);
+            contents.push('\n');
+        }
+        self.write_bytes(path.as_ref(), contents.as_bytes(), mode)
+    }
+
+    ///Replaces the file at `path` with `contents`, like [WriteLock::write_lines]
+    pub(crate) fn write_bytes(&self, path: &Path, contents: &[u8], mode: u32) -> Result<(), Error> {
+        let existing = match std::fs::metadata(path) {
+            Ok(meta) => Some(meta),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let mode = existing
+            .as_ref()
+            .map(|m| m.permissions().mode() & 0o7777)
+            .unwrap_or(mode);
+        let owner = existing.as_ref().map(|m| (m.uid(), m.gid()));
+
+        if self.backup && existing.is_some() {
+            let backup = with_suffix(path, "-");
+            let previous = std::fs::read(path)?;
+            write_synced(&backup, &previous, mode, owner)?;
+        }
+
+        let temp = with_suffix(path, "+");
+        write_synced(&temp, contents, mode, owner)?;
+        if let Err(e) = std::fs::rename(&temp, path) {
+            let _ = std::fs::remove_file(&temp);
+            return Err(e.into());
+        }
+        sync_dir(path)?;
+        Ok(())
+    }
+}
+
+///The fields of an entry that are written as they are, and the lists,
+///whose items are separated by commas
+pub(crate) trait Fields {
+    fn fields(&self) -> Vec<(&'static str, &str)>;
+
+    fn lists(&self) -> Vec<(&'static str, &[String])> {
+        vec![]
+    }
+}
+
+impl Fields for PasswdEntry {
+    fn fields(&self) -> Vec<(&'static str, &str)> {
+        vec![
+            ("username", &self.username),
+            ("passwd", &self.passwd),
+            ("gecos", &self.gecos),
+            ("home_dir", &self.home_dir),
+            ("shell", &self.shell),
+        ]
+    }
+}
+
+impl Fields for GroupEntry {
+    fn fields(&self) -> Vec<(&'static str, &str)> {
+        vec![("name", &self.name), ("passwd", &self.passwd)]
+    }
+
+    fn lists(&self) -> Vec<(&'static str, &[String])> {
+        vec![("users", &self.users)]
+    }
+}
+
+impl Fields for ShadowEntry {
+    fn fields(&self) -> Vec<(&'static str, &str)> {
+        vec![
+            ("name", &self.name),
+            ("passwd", &self.passwd),
+            ("reserved", &self.reserved),
+        ]
+    }
+}
+
+impl Fields for GshadowEntry {
+    fn fields(&self) -> Vec<(&'static str, &str)> {
+        vec![("name", &self.name), ("passwd", &self.passwd)]
+    }
+
+    fn lists(&self) -> Vec<(&'static str, &[String])> {
+        vec![
+            ("administrators", &self.administrators),
+            ("members", &self.members),
+        ]
+    }
+}
+
+impl Fields for SubIdEntry {
+    fn fields(&self) -> Vec<(&'static str, &str)> {
+        match &self.owner {
+            SubIdOwner::Name(name) => vec![("owner", name)],
+            SubIdOwner::Uid(_) => vec![],
+        }
+    }
+}
+
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+///Fails if a field holds a separator, which would add fields or lines
+///to the file, like a gecos ending one line and starting a new user
+pub(crate) fn check_fields<E: Fields>(entries: &[E]) -> std::io::Result<()> {
+    for entry in entries {
+        let items = entry
+            .lists()
+            .into_iter()
+            .flat_map(|(field, items)| items.iter().map(move |item| (field, item.as_str(), true)));
+        let fields = entry.fields().into_iter().map(|(f, v)| (f, v, false));
+        for (field, value, item) in fields.chain(items) {
+            if value.contains([':', '\n']) || (item && value.contains(',')) {
+                return Err(invalid_data(format!("invalid {} '{}'", field, value)));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+///Writes a file from scratch, with the given mode and owner, and
+///waits for it to reach the disk
+fn write_synced(
+    path: &Path,
+    contents: &[u8],
+    mode: u32,
+    owner: Option<(u32, u32)>,
+) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => (),
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    let result = (|| {
+        if let Some((uid, gid)) = owner {
+            let meta = file.metadata()?;
+            if meta.uid() != uid || meta.gid() != gid {
+                std::os::unix::fs::fchown(&file, Some(uid), Some(gid))?;
+            }
+        }
+        //Set after chown, which may clear setuid/setgid bits
+        file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+        file.write_all(contents)?;
+        file.sync_all()
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
+
+fn sync_dir(path: &Path) -> std::io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
@@ -0,0 +1,234 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tests of the account management operations against copies of the
+//! files in `test_files`.
+use user_lookup::accounts::{
+    AccountError, AccountManager, GroupChanges, NewGroup, NewUser, UserChanges,
+};
+use user_lookup::Error;
+
+mod common;
+use common::TempDir;
+
+///A copy of the test files below a temporary root directory
+struct Root(TempDir);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let root = TempDir::new(name);
+        for file in ["passwd", "group", "shadow", "gshadow"] {
+            root.copy(file, &format!("etc/{}", file));
+        }
+        Self(root)
+    }
+
+    fn manager(&self) -> AccountManager {
+        AccountManager::with_root(self.0.path())
+    }
+
+    fn read(&self, file: &str) -> String {
+        self.0.read(&format!("etc/{}", file))
+    }
+}
+
+#[test]
+fn useradd_writes_all_files() {
+    let root = Root::new("useradd");
+    let mut user = NewUser::new("user3");
+    user.groups = vec!["wheel".to_string()];
+    user.gecos = "User Three".to_string();
+    root.manager().useradd(&user).unwrap();
+
+    assert!(root
+        .read("passwd")
+        .contains("user3:x:1002:1002:User Three:/home/user3:/bin/sh\n"));
+    assert!(root.read("group").contains("wheel:x:10:user1,user3\n"));
+    assert!(root.read("shadow").contains("\nuser3:!:"));
+    assert!(root.read("gshadow").contains("wheel:!:user1:user1,user3\n"));
+    assert!(root.read("gshadow").contains("user3:!::\n"));
+    assert!(root.read("passwd-").ends_with("/home/user2:/bin/bash\n"));
+}
+
+#[test]
+fn useradd_refuses_duplicates() {
+    let root = Root::new("useradd_dup");
+    let manager = root.manager();
+    assert!(matches!(
+        manager.useradd(&NewUser::new("user1")),
+        Err(Error::Account(AccountError::UserExists(_)))
+    ));
+    let mut user = NewUser::new("user3");
+    user.uid = Some(1000);
+    assert!(matches!(
+        manager.useradd(&user),
+        Err(Error::Account(AccountError::UidExists(1000)))
+    ));
+    assert!(matches!(
+        manager.useradd(&NewUser::new("User3")),
+        Err(Error::Account(AccountError::InvalidName(_)))
+    ));
+}
+
+#[test]
+fn system_users_count_down() {
+    let root = Root::new("useradd_system");
+    let mut user = NewUser::new("daemon1");
+    user.system = true;
+    let entry = root.manager().useradd(&user).unwrap();
+    assert_eq!(999, entry.uid);
+    assert_eq!(999, entry.gid);
+}
+
+#[test]
+fn useradd_honours_login_defs() {
+    let root = Root::new("useradd_login_defs");
+    root.0.write(
+        "etc/login.defs",
+        "UID_MIN 100000\nUID_MAX 300000\nGID_MIN 100000\nGID_MAX 300000\nPASS_MAX_DAYS 90\n",
+    );
+    root.0.copy("subuid", "etc/subuid");
+    let entry = root.manager().useradd(&NewUser::new("user3")).unwrap();
+    //100000 to 165535 and 165536 to 231071 are subordinate uids
+    assert_eq!(231072, entry.uid);
+    assert_eq!(231072, entry.gid);
+    assert!(root.read("shadow").contains(":90::::\n"));
+}
+
+#[test]
+fn usermod_renames_everywhere() {
+    let root = Root::new("usermod");
+    let changes = UserChanges {
+        name: Some("user9".to_string()),
+        shell: Some("/bin/zsh".to_string()),
+        groups: Some(vec!["wheel".to_string()]),
+        append: true,
+        ..Default::default()
+    };
+    let entry = root.manager().usermod("user2", &changes).unwrap();
+    assert_eq!("user9", entry.username);
+
+    assert!(root
+        .read("passwd")
+        .contains("user9:x:1001:100:User Two:/home/user2:/bin/zsh\n"));
+    assert!(root.read("group").contains("wheel:x:10:user1,user9\n"));
+    assert!(root.read("group").contains("users:x:100:user1,user9\n"));
+    assert!(root.read("shadow").contains("user9:"));
+    assert!(root.read("gshadow").contains("users:!:user9:user1,user9\n"));
+}
+
+#[test]
+fn usermod_adds_missing_shadow_entry() {
+    let root = Root::new("usermod_shadow");
+    root.0.write(
+        "etc/shadow",
+        "root:!:19000:0:99999:7:::\nuser1:$6$salt$hash:19000:0:90:7:14::\n",
+    );
+    let changes = UserChanges {
+        passwd: Some("$6$new$hash".to_string()),
+        ..Default::default()
+    };
+    let entry = root.manager().usermod("user2", &changes).unwrap();
+    assert_eq!("x", entry.passwd);
+    assert!(root.read("shadow").contains("\nuser2:$6$new$hash:"));
+}
+
+#[test]
+fn fields_that_are_not_utf8_are_kept() {
+    let root = Root::new("latin1");
+    root.0.copy("passwd.latin1", "etc/passwd");
+    root.0.copy("group.latin1", "etc/group");
+    let manager = root.manager();
+
+    let mut user = NewUser::new("user3");
+    user.groups = vec!["users".to_string()];
+    manager.useradd(&user).unwrap();
+    let changes = UserChanges {
+        shell: Some("/bin/zsh".to_string()),
+        ..Default::default()
+    };
+    manager.usermod("user1", &changes).unwrap();
+
+    let passwd = std::fs::read(root.0.join("etc/passwd")).unwrap();
+    let group = std::fs::read(root.0.join("etc/group")).unwrap();
+    let contains = |file: &[u8], line: &[u8]| file.windows(line.len()).any(|w| w == line);
+    assert!(contains(
+        &passwd,
+        b"\nuser1:x:1000:100:Ren\xe9 Fran\xe7ois:/home/user1:/bin/zsh\n"
+    ));
+    assert!(contains(
+        &passwd,
+        b"\nb\xf6rje:x:1001:100::/home/borje:/bin/sh\n"
+    ));
+    assert!(contains(&group, b"users:x:100:user1,b\xf6rje,user3\n"));
+}
+
+#[test]
+fn nis_compat_lines_are_kept() {
+    let root = Root::new("compat");
+    root.0.write(
+        "etc/passwd",
+        "root:x:0:0:root:/root:/bin/bash\n\
+         -baduser::::::\n\
+         user1:x:1000:100:User One:/home/user1:/bin/bash\n\
+         user2:x:1001:100:User Two:/home/user2:/bin/bash\n\
+         +@admins::::::/bin/zsh\n\
+         +::::::\n",
+    );
+    root.0.write(
+        "etc/group",
+        "root:x:0:\nwheel:x:10:user1\nusers:x:100:user1,user2\n+:::\n",
+    );
+    let manager = root.manager();
+    manager.useradd(&NewUser::new("user3")).unwrap();
+    assert_eq!(
+        "root:x:0:0:root:/root:/bin/bash\n\
+         -baduser::::::\n\
+         user1:x:1000:100:User One:/home/user1:/bin/bash\n\
+         user2:x:1001:100:User Two:/home/user2:/bin/bash\n\
+         user3:x:1002:1002::/home/user3:/bin/sh\n\
+         +@admins::::::/bin/zsh\n\
+         +::::::\n",
+        root.read("passwd")
+    );
+    assert!(root.read("group").ends_with("user3:x:1002:\n+:::\n"));
+
+    //The line before a deleted user moves to the end, in its order
+    manager.userdel("user1").unwrap();
+    assert!(root.read("passwd").ends_with(
+        "user3:x:1002:1002::/home/user3:/bin/sh\n-baduser::::::\n+@admins::::::/bin/zsh\n+::::::\n"
+    ));
+}
+
+#[test]
+fn groupmod_moves_primary_gid() {
+    let root = Root::new("groupmod");
+    let changes = GroupChanges {
+        name: Some("staff".to_string()),
+        gid: Some(500),
+        ..Default::default()
+    };
+    root.manager().groupmod("users", &changes).unwrap();
+    assert!(root.read("group").contains("staff:x:500:user1,user2\n"));
+    assert!(root.read("gshadow").contains("staff:!:user2:user1,user2\n"));
+    assert!(root.read("passwd").contains("user1:x:1000:500:"));
+}
+
+#[test]
+fn groupdel_keeps_primary_groups() {
+    let root = Root::new("groupdel");
+    let manager = root.manager();
+    assert!(matches!(
+        manager.groupdel("users"),
+        Err(Error::Account(AccountError::PrimaryGroup { .. }))
+    ));
+    manager.groupadd(&NewGroup::new("developers")).unwrap();
+    manager.groupdel("developers").unwrap();
+    assert!(!root.read("group").contains("developers"));
+    assert!(!root.read("gshadow").contains("developers"));
+}
@@ -0,0 +1,119 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tests of when the readers read their file again.
+#![cfg(any(feature = "sync", feature = "async"))]
+use user_lookup::cache::CachePolicy;
+
+use std::fs::File;
+use std::path::Path;
+
+mod common;
+use common::TempDir;
+
+const PASSWD: &str = "root:x:0:0:root:/root:/bin/bash\nuser1:x:1000:100:::\n";
+
+///Writes the file in place, keeping its modification time
+#[cfg(feature = "sync")]
+fn write_keeping_mtime(path: &Path, contents: &str) {
+    let mtime = std::fs::metadata(path).unwrap().modified().unwrap();
+    std::fs::write(path, contents).unwrap();
+    File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+}
+
+///Replaces the file by renaming another file over it, like `vipw` does,
+///keeping the size and modification time so only the inode differs
+fn rename_keeping_mtime(path: &Path, contents: &str) {
+    let mtime = std::fs::metadata(path).unwrap().modified().unwrap();
+    let tmp = path.with_extension("edit");
+    std::fs::write(&tmp, contents).unwrap();
+    File::options()
+        .write(true)
+        .open(&tmp)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+    std::fs::rename(&tmp, path).unwrap();
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn sync_reader_reloads_after_write_in_place() {
+    use user_lookup::sync_reader::PasswdReader;
+
+    let dir = TempDir::new("cache_in_place");
+    dir.write("passwd", PASSWD);
+    let mut reader = PasswdReader::from_file(dir.join("passwd"), CachePolicy::on_change());
+    assert_eq!(2, reader.get_entries().unwrap().len());
+
+    let mut contents = dir.read("passwd");
+    contents.push_str("user2:x:1001:100:::\n");
+    std::fs::write(dir.join("passwd"), contents).unwrap();
+    assert_eq!(Some(1001), reader.get_uid_by_username("user2").unwrap());
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn sync_reader_reloads_after_rename() {
+    use user_lookup::sync_reader::PasswdReader;
+
+    let dir = TempDir::new("cache_rename");
+    dir.write("passwd", PASSWD);
+    let mut reader = PasswdReader::from_file(dir.join("passwd"), CachePolicy::on_change());
+    assert_eq!(Some(1000), reader.get_uid_by_username("user1").unwrap());
+
+    rename_keeping_mtime(&dir.join("passwd"), &PASSWD.replace("user1", "user9"));
+    assert_eq!(None, reader.get_uid_by_username("user1").unwrap());
+    assert_eq!(Some(1000), reader.get_uid_by_username("user9").unwrap());
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn sync_reader_without_cache_always_reloads() {
+    use std::time::Duration;
+    use user_lookup::sync_reader::PasswdReader;
+
+    let dir = TempDir::new("cache_disabled");
+    dir.write("passwd", PASSWD);
+    let mut cached = PasswdReader::from_file(dir.join("passwd"), CachePolicy::on_change());
+    let mut uncached = PasswdReader::from_file(dir.join("passwd"), Duration::new(0, 0));
+    assert_eq!(Some(1000), cached.get_uid_by_username("user1").unwrap());
+    assert_eq!(Some(1000), uncached.get_uid_by_username("user1").unwrap());
+
+    //The file looks unchanged, so only the reader without cache sees it
+    write_keeping_mtime(&dir.join("passwd"), &PASSWD.replace("user1", "user9"));
+    assert_eq!(Some(1000), cached.get_uid_by_username("user1").unwrap());
+    assert_eq!(None, uncached.get_uid_by_username("user1").unwrap());
+    assert_eq!(Some(1000), uncached.get_uid_by_username("user9").unwrap());
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn async_reader_reloads_after_rename() {
+    use user_lookup::async_reader::PasswdReader;
+
+    let dir = TempDir::new("cache_async_rename");
+    dir.write("passwd", PASSWD);
+    let mut reader = PasswdReader::from_file(dir.join("passwd"), CachePolicy::on_change());
+    assert_eq!(
+        Some(1000),
+        reader.get_uid_by_username("user1").await.unwrap()
+    );
+
+    rename_keeping_mtime(&dir.join("passwd"), &PASSWD.replace("user1", "user9"));
+    assert_eq!(None, reader.get_uid_by_username("user1").await.unwrap());
+    assert_eq!(
+        Some(1000),
+        reader.get_uid_by_username("user9").await.unwrap()
+    );
+}
@@ -0,0 +1,245 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tests of the consistency checks against copies of the files in
+//! `test_files`.
+use user_lookup::check::{CheckedFile, Checker, Finding};
+use user_lookup::error::ParseErrorKind;
+use user_lookup::Error;
+
+mod common;
+use common::TempDir;
+
+use std::os::unix::fs::MetadataExt;
+
+///A copy of the test files below a temporary root directory
+struct Root(TempDir);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let root = TempDir::new(&format!("check_{}", name));
+        for file in ["passwd", "group", "shadow", "gshadow", "login.defs"] {
+            root.copy(file, &format!("etc/{}", file));
+        }
+        Self(root)
+    }
+
+    fn write(&self, file: &str, contents: &str) {
+        self.0.write(&format!("etc/{}", file), contents);
+    }
+
+    fn read(&self, file: &str) -> String {
+        self.0.read(&format!("etc/{}", file))
+    }
+
+    fn checker(&self) -> Checker {
+        Checker::with_root(self.0.path()).with_home_dirs(false)
+    }
+}
+
+#[test]
+fn clean_files_have_no_findings() {
+    let root = Root::new("clean");
+    assert_eq!(Vec::<Finding>::new(), root.checker().check().unwrap());
+}
+
+#[test]
+fn finds_inconsistencies() {
+    let root = Root::new("findings");
+    root.write(
+        "passwd",
+        "root:x:0:0:root:/root:/bin/bash\n\
+         user1:x:1000:100::/home/user1:/bin/bash\n\
+         user1:x:1001:100::/home/user1:/bin/bash\n\
+         user3:x:1000:4711::/home/user3:/bin/zsh\n\
+         broken:x:abc:100:::\n\
+         daemon:x:2:2::/:/usr/sbin/nologin\n",
+    );
+    root.write(
+        "group",
+        "root:x:0:\nusers:x:100:user1,ghost,ghost\nusers:x:101:\nwheel:x:100:\n",
+    );
+    root.write("shells", "# valid shells\n/bin/sh\n/bin/bash\n");
+    let findings = root.checker().check().unwrap();
+    assert_eq!(
+        vec![
+            Finding::MalformedLine {
+                file: CheckedFile::Passwd,
+                error: user_lookup::error::ParseError {
+                    line: 5,
+                    kind: ParseErrorKind::InvalidUid("abc".to_string()),
+                    content: "broken:x:abc:100:::".to_string(),
+                },
+            },
+            Finding::DuplicateUsername {
+                username: "user1".to_string()
+            },
+            Finding::DuplicateUid {
+                uid: 1000,
+                usernames: vec!["user1".to_string(), "user3".to_string()]
+            },
+            Finding::MissingPrimaryGroup {
+                username: "user3".to_string(),
+                gid: 4711
+            },
+            Finding::InvalidShell {
+                username: "user3".to_string(),
+                shell: "/bin/zsh".to_string()
+            },
+            Finding::MissingShadow {
+                username: "user3".to_string()
+            },
+            Finding::MissingPrimaryGroup {
+                username: "daemon".to_string(),
+                gid: 2
+            },
+            Finding::MissingShadow {
+                username: "daemon".to_string()
+            },
+            Finding::DuplicateGroupName {
+                name: "users".to_string()
+            },
+            Finding::DuplicateGid {
+                gid: 100,
+                names: vec!["users".to_string(), "wheel".to_string()]
+            },
+            Finding::UnknownMember {
+                group: "users".to_string(),
+                member: "ghost".to_string()
+            },
+        ],
+        findings
+    );
+    assert_eq!(
+        "uid 1000 is used by 'user1', 'user3'",
+        findings[2].to_string()
+    );
+    assert_eq!(
+        "passwd: line 5: invalid uid 'abc': 'broken:x:abc:100:::'",
+        findings[0].to_string()
+    );
+}
+
+#[test]
+fn checks_home_directories() {
+    let root = Root::new("homes");
+    std::fs::create_dir_all(root.0.join("root")).unwrap();
+    std::fs::create_dir_all(root.0.join("home/user1")).unwrap();
+    let owner = std::fs::metadata(root.0.join("home/user1")).unwrap().uid();
+
+    let findings = Checker::with_root(root.0.path()).check().unwrap();
+    let mut expected = vec![];
+    if owner != 1000 {
+        expected.push(Finding::WrongHomeOwner {
+            username: "user1".to_string(),
+            home_dir: "/home/user1".to_string(),
+            owner,
+        });
+    }
+    expected.push(Finding::MissingHomeDir {
+        username: "user2".to_string(),
+        home_dir: "/home/user2".to_string(),
+    });
+    assert_eq!(expected, findings);
+}
+
+#[test]
+fn fixes_the_safe_findings() {
+    let root = Root::new("fix");
+    root.write(
+        "passwd",
+        "root:x:0:0:root:/root:/bin/bash\n\
+         user1:x:1000:100::/home/user1:/bin/bash\n\
+         user2:x:1001:4711::/home/user2:/bin/bash\n\
+         user3:$6$salt$hash:1002:100::/home/user3:/bin/bash\n",
+    );
+    root.write("group", "root:x:0:\nusers:x:100:user1,ghost,user2\n");
+    root.write("shadow", "root:!:19000:0:99999:7:::\nuser2:!:19000::::::\n");
+    root.write("gshadow", "root:*::\nusers:!:user1:user1,ghost,user2\n");
+    let checker = root.checker();
+
+    let fixed = checker.fix().unwrap();
+    assert_eq!(
+        vec![
+            Finding::MissingShadow {
+                username: "user1".to_string()
+            },
+            Finding::MissingShadow {
+                username: "user3".to_string()
+            },
+            Finding::UnknownMember {
+                group: "users".to_string(),
+                member: "ghost".to_string()
+            },
+        ],
+        fixed
+    );
+    assert!(fixed.iter().all(Finding::is_fixable));
+
+    //Only the primary group is left, which is not safe to fix
+    assert_eq!(
+        vec![Finding::MissingPrimaryGroup {
+            username: "user2".to_string(),
+            gid: 4711
+        }],
+        checker.check().unwrap()
+    );
+    assert_eq!("root:x:0:\nusers:x:100:user1,user2\n", root.read("group"));
+    assert_eq!(
+        "root:*::\nusers:!:user1:user1,user2\n",
+        root.read("gshadow")
+    );
+    assert!(root.read("passwd").contains("user3:x:1002:100:"));
+    let shadow = root.read("shadow");
+    assert!(shadow.contains("\nuser1:!:"));
+    assert!(shadow.contains("\nuser3:$6$salt$hash:"));
+    assert!(checker.fix().unwrap().is_empty());
+}
+
+#[test]
+fn fix_refuses_malformed_files() {
+    let root = Root::new("fix_malformed");
+    root.0.copy("passwd.broken", "etc/passwd");
+    root.write("group", "root:x:0:\nusers:x:100:ghost\n");
+    let checker = root.checker();
+    assert!(checker
+        .check()
+        .unwrap()
+        .iter()
+        .any(|f| matches!(f, Finding::MalformedLine { .. })));
+    assert!(matches!(checker.fix(), Err(Error::Parse(e)) if e.line == 2));
+    assert_eq!("root:x:0:\nusers:x:100:ghost\n", root.read("group"));
+}
+
+#[test]
+fn fix_refuses_files_that_are_not_utf8() {
+    let root = Root::new("fix_latin1");
+    root.0.copy("passwd.latin1", "etc/passwd");
+    let checker = root.checker();
+    assert_eq!(
+        vec![
+            Finding::MissingShadow {
+                username: "b\u{fffd}rje".to_string()
+            },
+            Finding::UnknownMember {
+                group: "users".to_string(),
+                member: "user2".to_string()
+            },
+        ],
+        checker.check().unwrap()
+    );
+    assert!(
+        matches!(checker.fix(), Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::InvalidData)
+    );
+    assert_eq!(
+        std::fs::read("test_files/passwd.latin1").unwrap(),
+        std::fs::read(root.0.join("etc/passwd")).unwrap()
+    );
+    //Nothing is fixed, not even in the files that are UTF-8
+    assert!(root.read("group").contains("users:x:100:user1,user2\n"));
+}
@@ -0,0 +1,64 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+use std::path::Path;
+use std::path::PathBuf;
+
+///A temporary directory, removed when dropped, even if a test fails
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    ///Creates an empty directory named after the test and the process
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("user_lookup_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    ///Creates a directory with copies of files of `test_files`, keeping
+    ///their names
+    pub fn with_files(name: &str, files: &[&str]) -> Self {
+        let dir = Self::new(name);
+        for file in files {
+            dir.copy(file, file);
+        }
+        dir
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.0.join(path)
+    }
+
+    ///Copies a file of `test_files` to `to` below the directory
+    pub fn copy(&self, file: &str, to: &str) {
+        let to = self.join(to);
+        std::fs::create_dir_all(to.parent().unwrap()).unwrap();
+        std::fs::copy(Path::new("test_files").join(file), to).unwrap();
+    }
+
+    pub fn write(&self, file: &str, contents: &str) {
+        std::fs::write(self.join(file), contents).unwrap();
+    }
+
+    pub fn read(&self, file: &str) -> String {
+        std::fs::read_to_string(self.join(file)).unwrap()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
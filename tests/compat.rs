@@ -0,0 +1,159 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tests of the NIS compat mode entries and how they are merged.
+use user_lookup::compat::{
+    CompatGroup, CompatGroupEntry, CompatPasswd, CompatPasswdEntry, Netgroups,
+};
+use user_lookup::error::{ParseErrorKind, ParseMode};
+use user_lookup::source::MemorySource;
+use user_lookup::{Error, GroupEntry, PasswdEntry};
+
+mod common;
+use common::TempDir;
+
+fn nis() -> MemorySource {
+    MemorySource::new(
+        vec![
+            PasswdEntry::parse("alice:x:1000:100:Alice:/home/alice:/bin/sh").unwrap(),
+            PasswdEntry::parse("bob:x:1001:100:Bob:/home/bob:/bin/sh").unwrap(),
+            PasswdEntry::parse("carol:x:1002:100:Carol:/home/carol:/bin/sh").unwrap(),
+            PasswdEntry::parse("root:x:0:0:NIS root:/:/bin/sh").unwrap(),
+        ],
+        vec![
+            GroupEntry::parse("users:x:100:alice,bob").unwrap(),
+            GroupEntry::parse("wheel:x:10:alice").unwrap(),
+            GroupEntry::parse("games:x:60:").unwrap(),
+        ],
+    )
+}
+
+#[test]
+fn lines_roundtrip() {
+    let lines = [
+        "root:x:0:0:root:/root:/bin/bash",
+        "+",
+        "+alice",
+        "+bob:*:::Robert::/bin/zsh",
+        "+@admins",
+        "+@staff::::::/bin/false",
+        "-carol",
+        "-@guests",
+    ];
+    for line in lines {
+        let entry = CompatPasswdEntry::try_parse(line).unwrap();
+        assert_eq!(line, entry.to_string());
+    }
+    assert!(matches!(
+        CompatPasswdEntry::try_parse("+bob:*:::Robert::/bin/zsh").unwrap(),
+        CompatPasswdEntry::IncludeUser(user, o) if user == "bob" && o.passwd.as_deref() == Some("*")
+    ));
+    assert_eq!(
+        Err(ParseErrorKind::EmptyField("username")),
+        CompatPasswdEntry::try_parse("-")
+    );
+    assert_eq!(
+        Err(ParseErrorKind::EmptyField("netgroup")),
+        CompatPasswdEntry::try_parse("+@")
+    );
+
+    for line in ["+", "+games", "+wheel:*::", "-users", "root:x:0:"] {
+        assert_eq!(line, CompatGroupEntry::try_parse(line).unwrap().to_string());
+    }
+    assert!(CompatGroupEntry::try_parse("+@admins").is_err());
+}
+
+#[test]
+fn normal_parsers_reject_compat_lines() {
+    assert_eq!(None, PasswdEntry::parse("+@netgroup::0:0:::"));
+    assert_eq!(
+        Err(ParseErrorKind::CompatEntry),
+        GroupEntry::try_parse("+wheel::10:")
+    );
+}
+
+#[test]
+fn first_deciding_line_wins() {
+    let compat = CompatPasswd::parse(
+        "root:x:0:0:root:/root:/bin/bash\n\
+         -carol\n\
+         +bob::::::/bin/zsh\n\
+         -bob\n\
+         +:*::::/nonexistent:/bin/false\n",
+    );
+    let users: Vec<String> = compat
+        .merge(&mut nis(), &Netgroups::new())
+        .unwrap()
+        .iter()
+        .map(|u| u.to_string())
+        .collect();
+    assert_eq!(
+        vec![
+            "root:x:0:0:root:/root:/bin/bash",
+            "bob:x:1001:100:Bob:/home/bob:/bin/zsh",
+            "alice:*:1000:100:Alice:/nonexistent:/bin/false",
+        ],
+        users
+    );
+}
+
+#[test]
+fn netgroups_include_and_exclude() {
+    let netgroups = Netgroups::parse(
+        "# netgroups\n\
+         staff (,alice,) (,bob,) (,nobody,)\n\
+         guests (host1,bob,) (host2,-,)\n\
+         everyone (,,)\n",
+    );
+    let compat = CompatPasswd::parse("-@guests\n+@staff\n");
+    let users: Vec<String> = compat
+        .merge(&mut nis(), &netgroups)
+        .unwrap()
+        .into_iter()
+        .map(|u| u.username)
+        .collect();
+    assert_eq!(vec!["alice"], users);
+
+    //A triple with any user excludes everyone
+    let compat = CompatPasswd::parse("-@everyone\n+\n");
+    assert!(compat.merge(&mut nis(), &netgroups).unwrap().is_empty());
+}
+
+#[test]
+fn groups_are_merged() {
+    let compat = CompatGroup::parse("root:x:0:\n-games\n+wheel:!::\n+\n");
+    let groups: Vec<String> = compat
+        .merge(&mut nis())
+        .unwrap()
+        .iter()
+        .map(|g| g.to_string())
+        .collect();
+    assert_eq!(
+        vec!["root:x:0:", "wheel:!:10:alice", "users:x:100:alice,bob"],
+        groups
+    );
+}
+
+#[test]
+fn strict_mode_fails_on_broken_lines() {
+    let dir = TempDir::new("compat_strict");
+    dir.write("passwd", "root:x:0:0:root:/root:/bin/bash\n-\n+\n");
+    let path = dir.join("passwd");
+
+    assert_eq!(
+        2,
+        CompatPasswd::from_file(&path, ParseMode::Lenient)
+            .unwrap()
+            .entries()
+            .len()
+    );
+    assert!(matches!(
+        CompatPasswd::from_file(&path, ParseMode::Strict),
+        Err(Error::Parse(e)) if e.line == 2
+    ));
+}
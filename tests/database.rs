@@ -0,0 +1,127 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tests of the UserDatabase snapshot and its cross-references.
+use user_lookup::database::UserDatabase;
+use user_lookup::error::{ParseErrorKind, ParseMode};
+use user_lookup::login_defs::LoginDefs;
+use user_lookup::source::{GroupSource, UserSource};
+use user_lookup::{Error, GroupEntry, PasswdEntry};
+
+fn names<'a>(users: impl IntoIterator<Item = &'a PasswdEntry>) -> Vec<&'a str> {
+    users.into_iter().map(|u| u.username.as_str()).collect()
+}
+
+#[test]
+fn built_the_same_from_files_strings_and_vectors() {
+    let from_files =
+        UserDatabase::from_files("test_files/passwd", "test_files/group", ParseMode::Strict)
+            .unwrap();
+    let from_strings = UserDatabase::parse(
+        &std::fs::read_to_string("test_files/passwd").unwrap(),
+        &std::fs::read_to_string("test_files/group").unwrap(),
+    );
+    let from_vectors = UserDatabase::new(
+        from_files.get_entries().clone(),
+        from_files.get_groups().clone(),
+    );
+    for db in [&from_files, &from_strings, &from_vectors] {
+        assert_eq!(3, db.get_entries().len());
+        assert_eq!(3, db.get_groups().len());
+        assert_eq!(Some("user2"), db.get_username_by_uid(1001));
+        assert_eq!(Some(0), db.get_uid_by_username("root"));
+        assert_eq!(Some("wheel"), db.get_name_by_gid(10));
+        assert_eq!(Some(100), db.get_gid_by_name("users"));
+        assert_eq!(Some(vec![100, 10]), db.get_gids_by_username("user1"));
+    }
+
+    let defs = LoginDefs::from_file("test_files/login.defs").unwrap();
+    assert_eq!(
+        vec!["user1", "user2"],
+        names(from_files.regular_users(&defs))
+    );
+    assert_eq!(vec!["root"], names(from_files.system_users(&defs)));
+
+    //Overlapping ranges put each user in one class only, like classify_uid
+    let defs = LoginDefs::parse("SYS_UID_MAX 1000\nUID_MIN 1000\nSYS_GID_MAX 100\nGID_MIN 100\n");
+    assert_eq!(
+        vec!["user1", "user2"],
+        names(from_files.regular_users(&defs))
+    );
+    assert_eq!(vec!["root"], names(from_files.system_users(&defs)));
+    assert_eq!(
+        vec!["users"],
+        from_files
+            .regular_groups(&defs)
+            .map(|g| g.name.as_str())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(2, from_files.system_groups(&defs).count());
+}
+
+#[test]
+fn cross_references() {
+    let db = UserDatabase::from((
+        vec![
+            PasswdEntry::parse("root:x:0:0:root:/root:/bin/bash").unwrap(),
+            PasswdEntry::parse("alice:x:1000:100:::").unwrap(),
+            PasswdEntry::parse("bob:x:1001:100:::").unwrap(),
+            PasswdEntry::parse("orphan:x:1002:4711:::").unwrap(),
+        ],
+        vec![
+            GroupEntry::parse("root:x:0:").unwrap(),
+            GroupEntry::parse("wheel:x:10:bob,ghost,alice").unwrap(),
+            GroupEntry::parse("users:x:100:bob").unwrap(),
+        ],
+    ));
+    let alice = db.get_by_username("alice").unwrap();
+    assert_eq!("users", db.get_primary_group(alice).unwrap().name);
+    assert_eq!(None, db.get_primary_group(db.get_by_uid(1002).unwrap()));
+
+    //Listed members that exist, then the users of the primary group
+    assert_eq!(
+        vec!["bob", "alice"],
+        names(db.get_members_by_name("wheel").unwrap())
+    );
+    assert_eq!(
+        vec!["bob", "alice"],
+        names(db.get_members_by_name("users").unwrap())
+    );
+    assert_eq!(vec!["root"], names(db.get_members_by_name("root").unwrap()));
+    assert_eq!(None, db.get_members_by_name("nogroup"));
+
+    //A clone shares the same entries
+    let clone = db.clone();
+    assert!(std::ptr::eq(db.get_entries(), clone.get_entries()));
+}
+
+#[test]
+fn usable_as_source() {
+    let mut db = UserDatabase::parse("user1:x:1000:100:::\n", "users:x:100:\nwheel:x:10:user1\n");
+    let user = UserSource::get_by_uid(&mut db, 1000).unwrap().unwrap();
+    assert_eq!(
+        vec![100, 10],
+        GroupSource::get_gids_for_user(&mut db, &user).unwrap()
+    );
+    assert_eq!(2, GroupSource::get_groups(&mut db).unwrap().len());
+}
+
+#[test]
+fn strict_mode_fails_on_broken_lines() {
+    assert!(matches!(
+        UserDatabase::from_files("test_files/passwd.broken", "test_files/group", ParseMode::Strict),
+        Err(Error::Parse(e)) if e.line == 2 && e.kind == ParseErrorKind::InvalidUid("abc".to_string())
+    ));
+    let db = UserDatabase::from_files(
+        "test_files/passwd.broken",
+        "test_files/group",
+        ParseMode::Lenient,
+    )
+    .unwrap();
+    assert_eq!(2, db.get_entries().len());
+}
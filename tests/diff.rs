@@ -0,0 +1,126 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tests of the diff between two sets of users and groups.
+use user_lookup::database::UserDatabase;
+use user_lookup::diff::{diff_groups, diff_users, Diff, FieldChange, GroupChange, UserChange};
+use user_lookup::{GroupEntry, PasswdEntry};
+
+fn users(lines: &[&str]) -> Vec<PasswdEntry> {
+    lines
+        .iter()
+        .map(|l| PasswdEntry::parse(l).unwrap())
+        .collect()
+}
+
+fn groups(lines: &[&str]) -> Vec<GroupEntry> {
+    lines
+        .iter()
+        .map(|l| GroupEntry::parse(l).unwrap())
+        .collect()
+}
+
+#[test]
+fn renames_are_detected_by_id() {
+    let before = users(&[
+        "alice:x:1000:100:Alice:/home/alice:/bin/sh",
+        "bob:x:1001:100:Bob:/home/bob:/bin/sh",
+    ]);
+    let after = users(&[
+        "alicia:x:1000:100:Alice:/home/alice:/bin/sh",
+        "carol:x:1002:100:Carol:/home/carol:/bin/sh",
+    ]);
+    let changes = diff_users(&before, &after);
+    assert_eq!(
+        vec![
+            UserChange::Modified {
+                before: before[0].clone(),
+                after: after[0].clone(),
+                fields: vec![FieldChange {
+                    field: "username",
+                    before: "alice".to_string(),
+                    after: "alicia".to_string(),
+                }],
+            },
+            UserChange::Added(after[1].clone()),
+            UserChange::Removed(before[1].clone()),
+        ],
+        changes
+    );
+    assert!(changes[0].is_rename());
+    assert!(!changes[1].is_rename());
+
+    //A name still in use is not taken for a rename
+    let changes = diff_users(
+        &users(&["a:x:1000:100:::", "b:x:1000:100:::"]),
+        &users(&["b:x:1000:100:::", "c:x:1000:100:::"]),
+    );
+    assert!(changes[0].is_rename());
+    assert!(matches!(&changes[0], UserChange::Modified { before, .. } if before.username == "a"));
+    assert_eq!(1, changes.len());
+}
+
+#[test]
+fn group_fields_and_members() {
+    let before = groups(&["wheel:x:10:alice,bob", "staff:x:50:", "users:x:100:a,b"]);
+    let after = groups(&["admins:!:10:bob,carol", "users:x:100:b,a", "games:x:60:"]);
+    let changes = diff_groups(&before, &after);
+    assert_eq!(3, changes.len());
+    match &changes[0] {
+        GroupChange::Modified {
+            fields,
+            added_members,
+            removed_members,
+            ..
+        } => {
+            assert!(changes[0].is_rename());
+            assert_eq!(
+                vec!["name", "passwd"],
+                fields.iter().map(|f| f.field).collect::<Vec<_>>()
+            );
+            assert_eq!(vec!["carol"], *added_members);
+            assert_eq!(vec!["alice"], *removed_members);
+        }
+        change => panic!("unexpected {:?}", change),
+    }
+    assert_eq!(GroupChange::Added(after[2].clone()), changes[1]);
+    assert_eq!(GroupChange::Removed(before[1].clone()), changes[2]);
+}
+
+#[test]
+fn report_and_json() {
+    let before = UserDatabase::new(
+        users(&["root:x:0:0:root:/root:/bin/bash", "old:x:999:999:::"]),
+        groups(&["wheel:x:10:root", "old:x:999:"]),
+    );
+    let after = UserDatabase::new(
+        users(&["root:x:0:0:root:/root:/bin/bash"]),
+        groups(&["sudo:x:10:root"]),
+    );
+    let diff = Diff::new(&before, &after);
+    assert_eq!(
+        "- user old (uid 999)\n\
+         ~ group wheel -> sudo (gid 10)\n\
+         - group old (gid 999)\n",
+        diff.to_string()
+    );
+
+    assert_eq!(
+        concat!(
+            r#"{"users":[{"change":"removed","name":"old","uid":999,"entry":"old:x:999:999:::"}],"#,
+            r#""groups":[{"change":"renamed","name":"sudo","gid":10,"#,
+            r#""fields":[{"field":"name","before":"wheel","after":"sudo"}],"#,
+            r#""added_members":[],"removed_members":[]},"#,
+            r#"{"change":"removed","name":"old","gid":999,"entry":"old:x:999:"}]}"#
+        ),
+        diff.to_json()
+    );
+
+    assert!(Diff::new(&after, &after).is_empty());
+    assert_eq!("", Diff::new(&after, &after).to_string());
+}
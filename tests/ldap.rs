@@ -0,0 +1,363 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tests of the LDAP readers, against a mock server speaking just enough
+//! LDAPv3 on a local TCP port, and against a directory in memory.
+#![cfg(feature = "ldap")]
+use user_lookup::cache::CachePolicy;
+use user_lookup::ldap::{
+    Directory, Filter, GroupReader, LdapConfig, LdapEntry, LdapError, MemoryDirectory, PasswdReader,
+};
+use user_lookup::Error;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const BIND_DN: &str = "cn=reader,dc=example,dc=org";
+const PAGED_RESULTS: &[u8] = b"1.2.840.113556.1.4.319";
+
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    if content.len() < 0x80 {
+        out.push(content.len() as u8);
+    } else {
+        out.extend_from_slice(&[0x82, (content.len() >> 8) as u8, content.len() as u8]);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+///Splits content into the tags and contents of its elements
+fn split(mut data: &[u8]) -> Vec<(u8, Vec<u8>)> {
+    let mut elements = vec![];
+    while !data.is_empty() {
+        let (header, len) = match data[1] {
+            n if n < 0x80 => (2, n as usize),
+            0x81 => (3, data[2] as usize),
+            _ => (4, (data[2] as usize) << 8 | data[3] as usize),
+        };
+        elements.push((data[0], data[header..header + len].to_vec()));
+        data = &data[header + len..];
+    }
+    elements
+}
+
+fn read_message(stream: &mut TcpStream) -> Option<Vec<(u8, Vec<u8>)>> {
+    let mut head = [0; 2];
+    stream.read_exact(&mut head).ok()?;
+    let mut len = head[1] as usize;
+    if len >= 0x80 {
+        let mut bytes = vec![0; len & 0x7f];
+        stream.read_exact(&mut bytes).unwrap();
+        len = bytes.iter().fold(0, |n, b| n << 8 | *b as usize);
+    }
+    let mut content = vec![0; len];
+    stream.read_exact(&mut content).unwrap();
+    Some(split(&content))
+}
+
+fn result(tag: u8, code: u8) -> Vec<u8> {
+    tlv(
+        tag,
+        &[tlv(0x0a, &[code]), tlv(0x04, b""), tlv(0x04, b"")].concat(),
+    )
+}
+
+fn entry(dn: &str, attributes: &[(&str, &[&str])]) -> Vec<u8> {
+    let attributes: Vec<u8> = attributes
+        .iter()
+        .flat_map(|(name, values)| {
+            let values: Vec<u8> = values
+                .iter()
+                .flat_map(|v| tlv(0x04, v.as_bytes()))
+                .collect();
+            tlv(
+                0x30,
+                &[tlv(0x04, name.as_bytes()), tlv(0x31, &values)].concat(),
+            )
+        })
+        .collect();
+    tlv(
+        0x64,
+        &[tlv(0x04, dn.as_bytes()), tlv(0x30, &attributes)].concat(),
+    )
+}
+
+fn people() -> Vec<Vec<u8>> {
+    vec![
+        entry(
+            "uid=jdoe,ou=people,dc=example,dc=org",
+            &[
+                ("objectClass", &["posixAccount"]),
+                ("uid", &["jdoe"]),
+                ("uidNumber", &["1000"]),
+                ("gidNumber", &["100"]),
+                ("cn", &["John Doe"]),
+                ("homeDirectory", &["/home/jdoe"]),
+                ("loginShell", &["/bin/bash"]),
+            ],
+        ),
+        entry(
+            "uid=asmith,ou=people,dc=example,dc=org",
+            &[
+                ("uid", &["asmith", "anna"]),
+                ("uidNumber", &["1001"]),
+                ("gidNumber", &["100"]),
+                ("gecos", &["Anna Smith"]),
+                ("homeDirectory", &["/home/asmith"]),
+            ],
+        ),
+    ]
+}
+
+fn groups() -> Vec<Vec<u8>> {
+    vec![entry(
+        "cn=staff,ou=groups,dc=example,dc=org",
+        &[
+            ("cn", &["staff"]),
+            ("gidNumber", &["100"]),
+            ("memberUid", &["asmith"]),
+            ("member", &["uid=jdoe,ou=people,dc=example,dc=org"]),
+        ],
+    )]
+}
+
+///Serves binds and searches, one page of a single entry at a time
+fn serve(mut stream: TcpStream) {
+    while let Some(message) = read_message(&mut stream) {
+        let id = tlv(0x02, &message[0].1);
+        let (op_tag, op) = &message[1];
+        let reply =
+            |op: Vec<u8>, controls: Vec<u8>| tlv(0x30, &[id.clone(), op, controls].concat());
+        match op_tag {
+            0x60 => {
+                let bind = split(op);
+                let ok = bind[1].1 == BIND_DN.as_bytes() && bind[2].1 == b"secret";
+                stream
+                    .write_all(&reply(result(0x61, if ok { 0 } else { 49 }), vec![]))
+                    .unwrap();
+            }
+            0x63 => {
+                let search = split(op);
+                let base = String::from_utf8(search[0].1.clone()).unwrap();
+                let entries = match base.as_str() {
+                    "ou=people,dc=example,dc=org" => people(),
+                    "ou=groups,dc=example,dc=org" => groups(),
+                    _ => {
+                        stream.write_all(&reply(result(0x65, 32), vec![])).unwrap();
+                        continue;
+                    }
+                };
+                //The filter must be encoded as BER, not as a string
+                let filter = tlv(0x04, b"objectClass");
+                assert_eq!(0xa3, search[6].0, "{:?}", search[6]);
+                assert!(search[6].1.starts_with(&filter));
+
+                let controls = split(&message.get(2).expect("paged results").1);
+                let control = split(&controls[0].1);
+                assert_eq!(PAGED_RESULTS, control[0].1.as_slice());
+                let value = split(&split(&control[1].1)[0].1);
+                let offset = match value[1].1.as_slice() {
+                    [] => 0,
+                    cookie => cookie[0] as usize,
+                };
+                stream
+                    .write_all(&reply(entries[offset].clone(), vec![]))
+                    .unwrap();
+                let cookie = if offset + 1 < entries.len() {
+                    vec![offset as u8 + 1]
+                } else {
+                    vec![]
+                };
+                let value = tlv(0x30, &[tlv(0x02, &[0]), tlv(0x04, &cookie)].concat());
+                let control = tlv(
+                    0x30,
+                    &[tlv(0x04, PAGED_RESULTS), tlv(0x04, &value)].concat(),
+                );
+                stream
+                    .write_all(&reply(result(0x65, 0), tlv(0xa0, &control)))
+                    .unwrap();
+            }
+            _ => return,
+        }
+    }
+}
+
+fn mock_server() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ldap://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            serve(stream.unwrap());
+        }
+    });
+    url
+}
+
+fn config(url: &str) -> LdapConfig {
+    LdapConfig::new(url, "dc=example,dc=org")
+        .with_user_base("ou=people,dc=example,dc=org")
+        .with_group_base("ou=groups,dc=example,dc=org")
+        .with_bind(BIND_DN, "secret")
+        .with_page_size(1)
+}
+
+#[test]
+fn users_and_groups_are_searched() {
+    let url = mock_server();
+    let mut passwd = PasswdReader::new(config(&url), Duration::from_secs(60));
+    let users: Vec<String> = passwd
+        .get_entries()
+        .unwrap()
+        .iter()
+        .map(|u| u.to_string())
+        .collect();
+    assert_eq!(
+        vec![
+            "jdoe:x:1000:100:John Doe:/home/jdoe:/bin/bash",
+            "asmith:x:1001:100:Anna Smith:/home/asmith:",
+        ],
+        users
+    );
+
+    let mut groups = GroupReader::new(config(&url), Duration::new(0, 0));
+    let staff = groups.get_by_name("staff").unwrap().unwrap();
+    assert_eq!(vec!["asmith", "jdoe"], staff.users);
+    assert_eq!(
+        Some(vec![100]),
+        groups.get_gids_by_username("jdoe", &mut passwd).unwrap()
+    );
+}
+
+#[test]
+fn errors_are_reported() {
+    let url = mock_server();
+    let mut passwd = PasswdReader::new(
+        config(&url).with_bind(BIND_DN, "wrong"),
+        Duration::new(0, 0),
+    );
+    assert!(matches!(
+        passwd.get_by_uid(1000),
+        Err(Error::Ldap(LdapError::Result { code: 49, .. }))
+    ));
+
+    //A missing base gives no entries
+    let mut passwd = PasswdReader::new(
+        config(&url).with_user_base("ou=missing,dc=example,dc=org"),
+        Duration::new(0, 0),
+    );
+    assert!(passwd.get_entries().unwrap().is_empty());
+
+    assert!(matches!(
+        Filter::parse("(&(uid=a)"),
+        Err(LdapError::InvalidFilter(_))
+    ));
+    //Deep nesting is refused instead of overflowing the stack
+    assert!(matches!(
+        Filter::parse(&"(!".repeat(200_000)),
+        Err(LdapError::InvalidFilter(_))
+    ));
+    let nested = format!("{}(uid=a){}", "(!".repeat(100), ")".repeat(100));
+    assert!(Filter::parse(&nested).is_ok());
+}
+
+#[test]
+fn filters_and_nested_groups() {
+    let directory = MemoryDirectory::new(vec![
+        LdapEntry::new("uid=jdoe,ou=people,dc=example,dc=org")
+            .with("objectClass", "posixAccount")
+            .with("uid", "jdoe")
+            .with("uidNumber", "1000")
+            .with("gidNumber", "100"),
+        LdapEntry::new("uid=svc,ou=services,dc=example,dc=org")
+            .with("objectClass", "posixAccount")
+            .with("uid", "svc")
+            .with("uidNumber", "500")
+            .with("gidNumber", "500"),
+        LdapEntry::new("uid=broken,ou=people,dc=example,dc=org")
+            .with("objectClass", "posixAccount")
+            .with("uid", "broken"),
+        LdapEntry::new("cn=admins,ou=groups,dc=example,dc=org")
+            .with("objectClass", "posixGroup")
+            .with("cn", "admins")
+            .with("gidNumber", "10")
+            .with("member", "cn=ops,ou=groups,dc=example,dc=org")
+            .with("member", "uid=ghost,ou=people,dc=example,dc=org"),
+        LdapEntry::new("cn=ops,ou=groups,dc=example,dc=org")
+            .with("objectClass", "posixGroup")
+            .with("cn", "ops")
+            .with("gidNumber", "20")
+            .with("member", "uid=jdoe, ou=people, dc=example, dc=org")
+            .with("member", "cn=admins,ou=groups,dc=example,dc=org"),
+    ]);
+    let config = LdapConfig::new("ldap://localhost", "dc=example,dc=org")
+        .with_user_filter(Filter::parse("(&(objectClass=posixAccount)(uidNumber>=1000))").unwrap());
+
+    let mut passwd =
+        PasswdReader::with_directory(directory.clone(), config.clone(), Duration::new(0, 0));
+    assert_eq!(1, passwd.get_entries().unwrap().len());
+    assert_eq!(None, passwd.get_uid_by_username("svc").unwrap());
+
+    let mut groups = GroupReader::with_directory(directory, config, Duration::new(0, 0));
+    assert_eq!(
+        vec!["ghost", "jdoe"],
+        groups.get_by_gid(10).unwrap().unwrap().users
+    );
+    assert_eq!(
+        vec!["jdoe", "ghost"],
+        groups.get_by_gid(20).unwrap().unwrap().users
+    );
+}
+
+///A directory in memory counting its searches
+struct Counting(MemoryDirectory, Arc<AtomicUsize>);
+
+impl Directory for Counting {
+    fn search(
+        &mut self,
+        base: &str,
+        filter: &Filter,
+        attributes: &[&str],
+    ) -> Result<Vec<LdapEntry>, Error> {
+        self.1.fetch_add(1, Ordering::SeqCst);
+        self.0.search(base, filter, attributes)
+    }
+}
+
+#[test]
+fn cache_policy_sets_the_search_interval() {
+    let directory = MemoryDirectory::new(vec![LdapEntry::new("uid=jdoe,dc=example,dc=org")
+        .with("objectClass", "posixAccount")
+        .with("uid", "jdoe")
+        .with("uidNumber", "1000")
+        .with("gidNumber", "100")]);
+    let config = LdapConfig::new("ldap://localhost", "dc=example,dc=org");
+    let searches = Arc::new(AtomicUsize::new(0));
+
+    //Without a maximum age the entries are still kept for a while
+    let mut passwd = PasswdReader::with_directory(
+        Counting(directory.clone(), searches.clone()),
+        config.clone(),
+        CachePolicy::on_change(),
+    );
+    assert_eq!(Some(1000), passwd.get_uid_by_username("jdoe").unwrap());
+    assert_eq!(Some(1000), passwd.get_uid_by_username("jdoe").unwrap());
+    assert_eq!(1, searches.load(Ordering::SeqCst));
+
+    let mut passwd = PasswdReader::with_directory(
+        Counting(directory, searches.clone()),
+        config,
+        CachePolicy::ttl(Duration::new(0, 0)),
+    );
+    passwd.get_entries().unwrap();
+    passwd.get_entries().unwrap();
+    assert_eq!(3, searches.load(Ordering::SeqCst));
+}
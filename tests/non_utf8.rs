@@ -0,0 +1,81 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tests of files with latin-1 fields, which are not UTF-8.
+use user_lookup::error::ParseMode;
+use user_lookup::{Error, GroupEntry, OsGroupEntry, OsPasswdEntry, PasswdEntry};
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+
+#[test]
+fn bytes_are_kept() {
+    let contents = std::fs::read("test_files/passwd.latin1").unwrap();
+    let users = OsPasswdEntry::from_file("test_files/passwd.latin1", ParseMode::Strict).unwrap();
+    assert_eq!(3, users.len());
+    assert_eq!(OsStr::from_bytes(b"Ren\xe9 Fran\xe7ois"), users[1].gecos);
+    assert_eq!(OsStr::from_bytes(b"b\xf6rje"), users[2].username);
+    let lines: Vec<Vec<u8>> = users.iter().map(|u| u.to_line()).collect();
+    assert_eq!(contents, [lines.join(&b'\n'), b"\n".to_vec()].concat());
+
+    let root = PasswdEntry::parse("root:x:0:0:root:/root:/bin/bash").unwrap();
+    assert_eq!(Some(root.clone()), users[0].to_entry());
+    assert_eq!(users[0], OsPasswdEntry::from(root));
+
+    let groups = OsGroupEntry::from_file("test_files/group.latin1", ParseMode::Strict).unwrap();
+    assert_eq!(OsStr::from_bytes(b"b\xf6rje"), groups[0].users[1]);
+
+    let group = GroupEntry::parse("users:x:100:user1,user2").unwrap();
+    assert_eq!(Some(group.clone()), OsGroupEntry::from(group).to_entry());
+}
+
+#[test]
+fn strict_mode_reports_the_broken_line() {
+    let result = OsPasswdEntry::from_file("test_files/passwd.broken", ParseMode::Strict);
+    assert!(matches!(result, Err(Error::Parse(e)) if e.line == 2));
+    let users = OsPasswdEntry::from_file("test_files/passwd.broken", ParseMode::Lenient).unwrap();
+    assert_eq!(2, users.len());
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn sync_readers_convert_lossy() {
+    use std::time::Duration;
+    use user_lookup::sync_reader::{GroupReader, PasswdReader};
+
+    let mut reader = PasswdReader::from_file("test_files/passwd.latin1", Duration::new(0, 0));
+    assert_eq!(3, reader.get_entries().unwrap().len());
+    assert_eq!(
+        "Ren\u{fffd} Fran\u{fffd}ois",
+        reader.get_by_uid(1000).unwrap().unwrap().gecos
+    );
+    assert_eq!(
+        Some("b\u{fffd}rje".to_string()),
+        reader.get_username_by_uid(1001).unwrap()
+    );
+
+    let mut groups = GroupReader::from_file("test_files/group.latin1", Duration::new(0, 0));
+    assert_eq!(
+        vec!["user1", "b\u{fffd}rje"],
+        groups.get_by_gid(100).unwrap().unwrap().users
+    );
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn async_readers_convert_lossy() {
+    use std::time::Duration;
+    use user_lookup::async_reader::PasswdReader;
+
+    let mut reader = PasswdReader::from_file("test_files/passwd.latin1", Duration::new(0, 0));
+    assert_eq!(3, reader.get_entries().await.unwrap().len());
+    assert_eq!(
+        Some(1000),
+        reader.get_uid_by_username("user1").await.unwrap()
+    );
+}
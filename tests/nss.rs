@@ -0,0 +1,55 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tests of the C library readers against the users of the machine
+//! running the tests.
+#![cfg(all(
+    feature = "nss",
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+use user_lookup::nss::{GroupReader, PasswdReader};
+
+use std::time::Duration;
+
+#[test]
+fn enumeration_is_thread_safe() {
+    let expected = PasswdReader::new(Duration::new(0, 0))
+        .get_entries()
+        .unwrap()
+        .clone();
+    let threads: Vec<_> = (0..8)
+        .map(|_| {
+            std::thread::spawn(|| {
+                let mut reader = PasswdReader::new(Duration::new(0, 0));
+                (0..20)
+                    .map(|_| reader.get_entries().unwrap().clone())
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    for thread in threads {
+        for entries in thread.join().unwrap() {
+            assert_eq!(expected, entries);
+        }
+    }
+}
+
+#[test]
+fn lookups_match_enumeration() {
+    let mut passwd = PasswdReader::new(Duration::from_secs(60));
+    let mut group = GroupReader::new(Duration::from_secs(60));
+    for user in passwd.get_entries().unwrap().clone() {
+        assert_eq!(Some(&user), passwd.get_by_uid(user.uid).unwrap().as_ref());
+        let gids = group.get_gids_for_user(&user).unwrap();
+        assert_eq!(Some(&user.gid), gids.first());
+    }
+    for entry in group.get_groups().unwrap().clone() {
+        assert_eq!(Some(entry.clone()), group.get_by_name(&entry.name).unwrap());
+    }
+}
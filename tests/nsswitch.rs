@@ -0,0 +1,58 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tests of the nsswitch.conf parser and the chains built from it.
+#![cfg(feature = "sync")]
+use user_lookup::nsswitch::NsswitchConf;
+use user_lookup::source::{Action, GroupSource, ShadowSource, Status};
+
+use std::time::Duration;
+
+#[test]
+fn negated_items_apply_to_the_other_statuses() {
+    let conf = NsswitchConf::from_file("test_files/nsswitch.conf").unwrap();
+    let actions = conf.services("shadow")[0].actions();
+    assert_eq!(Action::Return, actions.get(Status::Success));
+    assert_eq!(Action::Return, actions.get(Status::NotFound));
+    assert_eq!(Action::Return, actions.get(Status::Unavail));
+    assert_eq!(Action::Return, actions.get(Status::TryAgain));
+}
+
+#[test]
+fn unknown_items_are_ignored() {
+    let conf = NsswitchConf::parse("group: files [SUCCESS=merge] sss [bogus] # comment\n");
+    let services = conf.services("group");
+    assert_eq!(2, services.len());
+    assert_eq!(Action::Return, services[0].actions().get(Status::Success));
+    assert_eq!(vec!["sss"], conf.unsupported("group"));
+}
+
+#[test]
+fn missing_databases_use_files() {
+    let conf = NsswitchConf::parse("hosts: files dns\n");
+    assert_eq!("files", conf.services("passwd")[0].name());
+    assert!(conf.unsupported("passwd").is_empty());
+}
+
+#[test]
+fn chains_fall_back_to_files() {
+    let conf = NsswitchConf::parse("group: sss ldap\nshadow: files\n").with_files_dir("test_files");
+    assert_eq!(vec!["sss", "ldap"], conf.unsupported("group"));
+    let mut group = conf.group_chain(Duration::new(0, 0));
+    assert_eq!(1, group.len());
+    assert_eq!(
+        Some(100),
+        group.get_by_name("users").unwrap().map(|g| g.gid)
+    );
+
+    let mut shadow = conf.shadow_chain(Duration::new(0, 0));
+    assert_eq!(
+        Some(Some(90)),
+        shadow.get_by_name("user1").unwrap().map(|s| s.max_days)
+    );
+}
@@ -0,0 +1,281 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Property tests checking that `parse(to_line(e)) == e` for randomly
+//! generated entries, and that entries survive a conversion to JSON records.
+use user_lookup::userdb::{GroupRecord, UserRecord};
+use user_lookup::{
+    GroupEntry, GshadowEntry, OsGroupEntry, OsPasswdEntry, PasswdEntry, ShadowEntry, SubIdEntry,
+    SubIdOwner,
+};
+
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+
+const CASES: usize = 2000;
+
+///A small xorshift generator, so the cases are reproducible
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn string_from(&mut self, alphabet: &[char], max_len: u64) -> String {
+        (0..self.below(max_len + 1))
+            .map(|_| alphabet[self.below(alphabet.len() as u64) as usize])
+            .collect()
+    }
+
+    ///Any field content, including separators other than ':'
+    fn field(&mut self) -> String {
+        const ALPHABET: &[char] = &[
+            'a', 'z', 'A', 'Z', '0', '9', ' ', ',', '.', '-', '_', '/', '$', '!', '*', 'å', 'é',
+        ];
+        self.string_from(ALPHABET, 12)
+    }
+
+    ///A user or group name, which can not start with the '+' or '-' of
+    ///NIS compat entries
+    fn name(&mut self) -> String {
+        let mut s = self.field();
+        if s.starts_with(['+', '-']) {
+            s.insert(0, 'n');
+        }
+        s
+    }
+
+    ///Any text, including characters JSON has to escape
+    fn text(&mut self) -> String {
+        const ALPHABET: &[char] = &[
+            'a', 'Z', '9', ' ', ':', '"', '\\', '/', '\n', '\t', '\u{1}', 'å', '😀',
+        ];
+        let mut s = self.string_from(ALPHABET, 12);
+        s.insert(0, 'x');
+        s
+    }
+
+    ///Any field content as bytes, including bytes that are not UTF-8
+    fn bytes(&mut self) -> OsString {
+        const ALPHABET: &[u8] = &[b'a', b'Z', b'0', b' ', b',', b'/', 0x80, 0xc3, 0xe9, 0xff];
+        let bytes = (0..self.below(13))
+            .map(|_| ALPHABET[self.below(ALPHABET.len() as u64) as usize])
+            .collect();
+        OsString::from_vec(bytes)
+    }
+
+    ///A list item, which can not be empty or contain ','
+    fn item(&mut self) -> String {
+        const ALPHABET: &[char] = &['a', 'z', '0', '9', '.', '-', '_'];
+        let mut s = self.string_from(ALPHABET, 8);
+        s.insert(0, 'u');
+        s
+    }
+
+    fn list(&mut self) -> Vec<String> {
+        (0..self.below(4)).map(|_| self.item()).collect()
+    }
+
+    fn id(&mut self) -> u32 {
+        match self.below(3) {
+            0 => 0,
+            1 => u32::MAX,
+            _ => self.next() as u32,
+        }
+    }
+
+    fn days(&mut self) -> Option<i64> {
+        match self.below(4) {
+            0 => None,
+            1 => Some(-1),
+            _ => Some(self.below(100000) as i64),
+        }
+    }
+}
+
+#[test]
+fn passwd_roundtrip() {
+    let mut rng = Rng(0x5eed_0001);
+    for _ in 0..CASES {
+        let entry = PasswdEntry {
+            username: rng.name(),
+            passwd: rng.field(),
+            uid: rng.id(),
+            gid: rng.id(),
+            gecos: rng.field(),
+            home_dir: rng.field(),
+            shell: rng.field(),
+        };
+        assert_eq!(Some(&entry), PasswdEntry::parse(&entry.to_line()).as_ref());
+    }
+}
+
+#[test]
+fn group_roundtrip() {
+    let mut rng = Rng(0x5eed_0002);
+    for _ in 0..CASES {
+        let entry = GroupEntry {
+            name: rng.name(),
+            passwd: rng.field(),
+            gid: rng.id(),
+            users: rng.list(),
+        };
+        assert_eq!(Some(&entry), GroupEntry::parse(&entry.to_line()).as_ref());
+    }
+}
+
+#[test]
+fn os_passwd_roundtrip() {
+    let mut rng = Rng(0x5eed_0008);
+    for _ in 0..CASES {
+        let entry = OsPasswdEntry {
+            username: rng.bytes(),
+            passwd: rng.bytes(),
+            uid: rng.id(),
+            gid: rng.id(),
+            gecos: rng.bytes(),
+            home_dir: rng.bytes(),
+            shell: rng.bytes(),
+        };
+        assert_eq!(
+            Some(&entry),
+            OsPasswdEntry::parse(&entry.to_line()).as_ref()
+        );
+    }
+}
+
+#[test]
+fn os_group_roundtrip() {
+    let mut rng = Rng(0x5eed_0009);
+    for _ in 0..CASES {
+        let entry = OsGroupEntry {
+            name: rng.bytes(),
+            passwd: rng.bytes(),
+            gid: rng.id(),
+            users: rng.list().into_iter().map(OsString::from).collect(),
+        };
+        assert_eq!(Some(&entry), OsGroupEntry::parse(&entry.to_line()).as_ref());
+    }
+}
+
+#[test]
+fn shadow_roundtrip() {
+    let mut rng = Rng(0x5eed_0003);
+    for _ in 0..CASES {
+        let entry = ShadowEntry {
+            name: rng.field(),
+            passwd: rng.field(),
+            last_change: rng.days(),
+            min_days: rng.days(),
+            max_days: rng.days(),
+            warn_days: rng.days(),
+            inactive_days: rng.days(),
+            expire: rng.days(),
+            reserved: rng.field(),
+        };
+        assert_eq!(Some(&entry), ShadowEntry::parse(&entry.to_line()).as_ref());
+    }
+}
+
+#[test]
+fn gshadow_roundtrip() {
+    let mut rng = Rng(0x5eed_0004);
+    for _ in 0..CASES {
+        let entry = GshadowEntry {
+            name: rng.field(),
+            passwd: rng.field(),
+            administrators: rng.list(),
+            members: rng.list(),
+        };
+        assert_eq!(Some(&entry), GshadowEntry::parse(&entry.to_line()).as_ref());
+    }
+}
+
+#[test]
+fn subid_roundtrip() {
+    let mut rng = Rng(0x5eed_0005);
+    for _ in 0..CASES {
+        let entry = SubIdEntry {
+            owner: match rng.below(2) {
+                0 => SubIdOwner::Uid(rng.id()),
+                _ => SubIdOwner::Name(rng.item()),
+            },
+            start: rng.id(),
+            count: rng.id(),
+        };
+        assert_eq!(Some(&entry), SubIdEntry::parse(&entry.to_line()).as_ref());
+    }
+}
+
+#[test]
+fn user_record_roundtrip() {
+    let mut rng = Rng(0x5eed_0006);
+    for _ in 0..CASES {
+        let entry = PasswdEntry {
+            username: rng.text(),
+            passwd: "x".to_string(),
+            uid: rng.id(),
+            gid: rng.id(),
+            gecos: rng.text(),
+            home_dir: rng.text(),
+            shell: rng.text(),
+        };
+        let json = UserRecord::from(&entry).to_json();
+        let record = UserRecord::parse(&json).unwrap();
+        assert_eq!(entry, record.to_passwd_entry().unwrap());
+    }
+}
+
+#[test]
+fn group_record_roundtrip() {
+    let mut rng = Rng(0x5eed_0007);
+    for _ in 0..CASES {
+        let entry = GroupEntry {
+            name: rng.text(),
+            passwd: "x".to_string(),
+            gid: rng.id(),
+            users: (0..rng.below(4)).map(|_| rng.text()).collect(),
+        };
+        let json = GroupRecord::from(&entry).to_json();
+        let record = GroupRecord::parse(&json).unwrap();
+        assert_eq!(entry, record.to_group_entry().unwrap());
+    }
+}
+
+#[test]
+fn lines_roundtrip() {
+    for line in std::fs::read_to_string("test_files/passwd")
+        .unwrap()
+        .lines()
+    {
+        assert_eq!(line, PasswdEntry::parse(line).unwrap().to_line());
+    }
+    for line in std::fs::read_to_string("test_files/group").unwrap().lines() {
+        assert_eq!(line, GroupEntry::parse(line).unwrap().to_line());
+    }
+    for line in std::fs::read_to_string("test_files/shadow")
+        .unwrap()
+        .lines()
+    {
+        assert_eq!(line, ShadowEntry::parse(line).unwrap().to_line());
+    }
+    for line in std::fs::read_to_string("test_files/gshadow")
+        .unwrap()
+        .lines()
+    {
+        assert_eq!(line, GshadowEntry::parse(line).unwrap().to_line());
+    }
+}
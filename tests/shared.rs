@@ -0,0 +1,111 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tests of the shared readers, used from many threads and tasks at once.
+#![cfg(any(feature = "sync", feature = "async"))]
+use std::path::PathBuf;
+
+mod common;
+use common::TempDir;
+
+fn add_user(passwd: &PathBuf) {
+    let mut contents = std::fs::read_to_string(passwd).unwrap();
+    contents.push_str("user3:x:1002:100:User 3:/home/user3:/bin/bash\n");
+    std::fs::write(passwd, contents).unwrap();
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn sync_reader_is_shared_between_threads() {
+    use std::sync::{Arc, Barrier};
+    use user_lookup::cache::CachePolicy;
+    use user_lookup::sync_reader::SharedReader;
+
+    fn assert_send_sync<T: Send + Sync + Clone>() {}
+    assert_send_sync::<SharedReader>();
+
+    let dir = TempDir::with_files("shared_sync", &["passwd", "group"]);
+    let (passwd, group) = (dir.join("passwd"), dir.join("group"));
+    let reader = SharedReader::from_files(&passwd, &group, CachePolicy::on_change());
+    assert_eq!(None, reader.last_loaded());
+    let first = reader.snapshot().unwrap();
+    assert!(Arc::ptr_eq(&first, &reader.clone().snapshot().unwrap()));
+
+    //All threads find the files changed, but only one reads them
+    add_user(&passwd);
+    let barrier = Barrier::new(8);
+    let snapshots: Vec<_> = std::thread::scope(|s| {
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                s.spawn(|| {
+                    barrier.wait();
+                    reader.snapshot().unwrap()
+                })
+            })
+            .collect();
+        threads.into_iter().map(|t| t.join().unwrap()).collect()
+    });
+    assert!(!Arc::ptr_eq(&first, &snapshots[0]));
+    assert!(snapshots.iter().all(|s| Arc::ptr_eq(s, &snapshots[0])));
+
+    //The old snapshot is unchanged
+    assert_eq!(None, first.passwd().get_by_uid(1002));
+    assert_eq!(
+        Some("user3".to_string()),
+        reader.get_username_by_uid(1002).unwrap()
+    );
+    assert_eq!(
+        Some(vec![100]),
+        reader.get_gids_by_username("user3").unwrap()
+    );
+    assert_eq!(Some(10), reader.get_gid_by_name("wheel").unwrap());
+
+    std::fs::remove_file(&group).unwrap();
+    assert!(reader.get_by_uid(0).is_err());
+}
+
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn async_reader_is_shared_between_tasks() {
+    use std::sync::Arc;
+    use user_lookup::async_reader::SharedReader;
+    use user_lookup::cache::CachePolicy;
+
+    let dir = TempDir::with_files("shared_async", &["passwd", "group"]);
+    let (passwd, group) = (dir.join("passwd"), dir.join("group"));
+    let reader = SharedReader::from_files(&passwd, &group, CachePolicy::on_change());
+    let first = reader.snapshot().await.unwrap();
+
+    add_user(&passwd);
+    let tasks: Vec<_> = (0..8)
+        .map(|_| {
+            let reader = reader.clone();
+            tokio::spawn(async move { reader.snapshot().await.unwrap() })
+        })
+        .collect();
+    let mut snapshots = vec![];
+    for task in tasks {
+        snapshots.push(task.await.unwrap());
+    }
+    assert!(!Arc::ptr_eq(&first, &snapshots[0]));
+    assert!(snapshots.iter().all(|s| Arc::ptr_eq(s, &snapshots[0])));
+    assert_eq!(
+        Some(1002),
+        reader.get_uid_by_username("user3").await.unwrap()
+    );
+    assert_eq!(
+        vec!["users"],
+        reader
+            .get_groups_for_user(&reader.get_by_uid(1002).await.unwrap().unwrap())
+            .await
+            .unwrap()
+            .iter()
+            .map(|g| g.name.as_str())
+            .collect::<Vec<_>>()
+    );
+}
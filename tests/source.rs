@@ -0,0 +1,116 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tests of the nsswitch-like action semantics of the source chains.
+#![cfg(feature = "sync")]
+use user_lookup::source::{
+    Action, Actions, GroupChain, GroupSource, MemorySource, Status, UserChain, UserSource,
+};
+use user_lookup::sync_reader::{GroupReader, PasswdReader};
+use user_lookup::{Error, GroupEntry, PasswdEntry};
+
+use std::time::Duration;
+
+fn files() -> Box<PasswdReader> {
+    Box::new(PasswdReader::from_file(
+        "test_files/passwd",
+        Duration::new(0, 0),
+    ))
+}
+
+fn missing() -> Box<PasswdReader> {
+    Box::new(PasswdReader::from_file(
+        "test_files/does_not_exist",
+        Duration::new(0, 0),
+    ))
+}
+
+fn overlay() -> Box<MemorySource> {
+    Box::new(MemorySource::new(
+        vec![
+            PasswdEntry::parse("user1:x:2000:100::/home/user1:/bin/sh").unwrap(),
+            PasswdEntry::parse("user3:x:1002:100::/home/user3:/bin/sh").unwrap(),
+        ],
+        vec![GroupEntry::parse("extra:x:2000:user1").unwrap()],
+    ))
+}
+
+#[test]
+fn first_success_wins() {
+    let mut chain = UserChain::new().with(files()).with(overlay());
+    assert_eq!(Some(1000), uid(&mut chain, "user1"));
+    assert_eq!(Some(1002), uid(&mut chain, "user3"));
+
+    let mut chain = UserChain::new().with(overlay()).with(files());
+    assert_eq!(Some(2000), uid(&mut chain, "user1"));
+}
+
+#[test]
+fn unavailable_sources_are_skipped() {
+    let mut chain = UserChain::new().with(missing()).with(overlay());
+    assert_eq!(Some(1002), uid(&mut chain, "user3"));
+    assert_eq!(2, chain.get_entries().unwrap().len());
+
+    //The error of the last source is returned as is
+    let mut chain = UserChain::new().with(overlay()).with(missing());
+    assert!(matches!(
+        chain.get_by_username("nobody"),
+        Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound
+    ));
+}
+
+#[test]
+fn unavail_return_stops_the_chain() {
+    let actions = Actions::default().on(Status::Unavail, Action::Return);
+    let mut chain = UserChain::new()
+        .with_actions(missing(), actions)
+        .with(overlay());
+    assert!(chain.get_by_username("user3").is_err());
+    assert!(chain.get_entries().is_err());
+}
+
+#[test]
+fn success_continue_asks_the_next_source() {
+    let actions = Actions::default()
+        .on(Status::Success, Action::Continue)
+        .on(Status::NotFound, Action::Return);
+    let mut chain = UserChain::new()
+        .with_actions(files(), actions)
+        .with(overlay());
+    assert_eq!(Some(2000), uid(&mut chain, "user1"));
+    assert_eq!(None, uid(&mut chain, "user3"));
+}
+
+#[test]
+fn chains_nest() {
+    let inner = UserChain::new().with(missing()).with(files());
+    let mut chain = UserChain::new().with(Box::new(inner)).with(overlay());
+    assert_eq!(Some(1000), uid(&mut chain, "user1"));
+    assert_eq!(5, chain.get_entries().unwrap().len());
+}
+
+#[test]
+fn group_lists_are_merged() {
+    let mut chain = GroupChain::new()
+        .with(Box::new(GroupReader::from_file(
+            "test_files/group",
+            Duration::new(0, 0),
+        )))
+        .with(overlay());
+    let user = PasswdEntry::parse("user1:x:1000:100::/home/user1:/bin/sh").unwrap();
+    assert_eq!(vec![100, 10, 2000], chain.get_gids_for_user(&user).unwrap());
+    assert_eq!(
+        Some(2000),
+        chain.get_by_name("extra").unwrap().map(|g| g.gid)
+    );
+    assert_eq!(4, chain.get_groups().unwrap().len());
+}
+
+fn uid(chain: &mut UserChain, username: &str) -> Option<u32> {
+    chain.get_by_username(username).unwrap().map(|e| e.uid)
+}
@@ -0,0 +1,99 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tests of the userdb reader against the records in `test_files/userdb`
+//! and a temporary directory.
+use user_lookup::error::ParseMode;
+use user_lookup::source::{GroupSource, UserSource};
+use user_lookup::userdb::{RecordError, UserDbReader};
+use user_lookup::{Error, PasswdEntry};
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+mod common;
+use common::TempDir;
+
+#[test]
+fn broken_records_are_skipped_or_fail() {
+    let dir = TempDir::new("userdb_broken");
+    dir.write("user4.user", r#"{"userName":"user4","uid":1004}"#);
+    dir.write("user5.user", r#"{"userName":"user5","uid":"#);
+    dir.write("user6.user", r#"{"uid":1006}"#);
+
+    let mut reader = UserDbReader::from_dirs([dir.path()], Duration::new(0, 0));
+    assert_eq!(1, reader.get_users().unwrap().len());
+    let warnings = reader.warnings();
+    assert_eq!(2, warnings.len());
+    assert_eq!(dir.join("user5.user"), warnings[0].path);
+    assert!(matches!(warnings[0].error, RecordError::Json(_)));
+    assert_eq!(RecordError::MissingField("userName"), warnings[1].error);
+
+    let mut reader = UserDbReader::from_dirs([dir.path()], Duration::new(0, 0))
+        .with_parse_mode(ParseMode::Strict);
+    assert!(matches!(
+        reader.get_users(),
+        Err(Error::Record(RecordError::Json(_)))
+    ));
+}
+
+#[test]
+fn earlier_directories_win() {
+    let dir = TempDir::new("userdb_order");
+    dir.write("user3.user", r#"{"userName":"user3","uid":3333}"#);
+    dir.write("user4.user", r#"{"userName":"user4","uid":1004}"#);
+
+    let mut reader = UserDbReader::from_dirs(
+        [dir.path().to_path_buf(), PathBuf::from("test_files/userdb")],
+        Duration::new(0, 0),
+    );
+    assert_eq!(
+        Some(3333),
+        reader.get_by_username("user3").unwrap().map(|e| e.uid)
+    );
+    assert_eq!(2, reader.get_entries().unwrap().len());
+    assert_eq!(2, reader.get_groups().unwrap().len());
+
+    //The record of the earlier directory is the one found by id too
+    assert_eq!(None, reader.get_user_by_uid(1002).unwrap());
+    assert_eq!(
+        Some("user4"),
+        reader
+            .get_index()
+            .unwrap()
+            .get_by_uid(1004)
+            .map(|e| e.username.as_str())
+    );
+    assert_eq!(
+        Some(vec!["user3".to_string(), "user1".to_string()]),
+        reader
+            .get_group_index()
+            .unwrap()
+            .get_by_gid(2000)
+            .map(|g| g.users.clone())
+    );
+}
+
+#[test]
+fn changes_are_noticed() {
+    let dir = TempDir::new("userdb_changes");
+    let mut reader =
+        UserDbReader::from_dirs([dir.path()], user_lookup::cache::CachePolicy::on_change());
+    assert!(reader.get_users().unwrap().is_empty());
+    //Directory timestamps can be coarse
+    std::thread::sleep(Duration::from_millis(10));
+    dir.write(
+        "user4.user",
+        r#"{"userName":"user4","uid":1004,"memberOf":["admins"]}"#,
+    );
+    dir.write("admins.group", r#"{"groupName":"admins","gid":2000}"#);
+    assert_eq!(1, reader.get_users().unwrap().len());
+
+    let user = PasswdEntry::parse("user4:x:1004:1004::/:/bin/sh").unwrap();
+    assert_eq!(vec![1004, 2000], reader.get_gids_for_user(&user).unwrap());
+}
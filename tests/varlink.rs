@@ -0,0 +1,202 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tests of the varlink readers against a mock `io.systemd.UserDatabase`
+//! service on a temporary socket.
+#![cfg(feature = "varlink")]
+use user_lookup::cache::CachePolicy;
+use user_lookup::source::{Status, UserSource};
+use user_lookup::varlink::{Client, GroupReader, PasswdReader};
+use user_lookup::{Error, PasswdEntry};
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::time::Duration;
+
+mod common;
+use common::TempDir;
+
+const USERS: &[&str] = &[
+    r#"{"userName":"user3","uid":1002,"gid":1002,"homeDirectory":"/home/user3","shell":"/bin/zsh"}"#,
+    r#"{"userName":"dynamic","uid":61184,"disposition":"dynamic"}"#,
+];
+
+const GROUPS: &[&str] = &[
+    r#"{"groupName":"user3","gid":1002}"#,
+    r#"{"groupName":"admins","gid":2000,"members":["user3"]}"#,
+];
+
+const MEMBERSHIPS: &[(&str, &str)] = &[("dynamic", "admins"), ("user3", "admins")];
+
+///A mock service answering on a socket in a temporary directory
+struct Mock(TempDir);
+
+impl Mock {
+    fn start(name: &str) -> Self {
+        let dir = TempDir::new(name);
+        let socket = dir.join("io.systemd.Mock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                serve(stream.unwrap());
+            }
+        });
+        Self(dir)
+    }
+
+    fn client(&self) -> Client {
+        Client::with_socket(self.0.join("io.systemd.Mock"))
+    }
+}
+
+fn serve(stream: UnixStream) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    let mut message = vec![];
+    while reader.read_until(0, &mut message).unwrap() > 0 {
+        message.pop();
+        let request = String::from_utf8(std::mem::take(&mut message)).unwrap();
+        let more = request.contains(r#""more":true"#);
+        let replies = reply(&request);
+        let count = replies.len();
+        for (i, reply) in replies.into_iter().enumerate() {
+            let mut bytes = match more && i + 1 < count {
+                true => format!(r#"{},"continues":true}}"#, &reply[..reply.len() - 1]),
+                false => reply,
+            }
+            .into_bytes();
+            bytes.push(0);
+            writer.write_all(&bytes).unwrap();
+        }
+    }
+}
+
+///The string or number of `key` in the compact JSON written by the client
+fn field<'a>(json: &'a str, key: &str) -> Option<&'a str> {
+    let key = format!(r#""{}":"#, key);
+    let value = &json[json.find(&key)? + key.len()..];
+    match value.strip_prefix('"') {
+        Some(value) => value.split('"').next(),
+        None => value.split([',', '}']).next(),
+    }
+}
+
+fn error(name: &str) -> Vec<String> {
+    vec![format!(r#"{{"error":"{}","parameters":{{}}}}"#, name)]
+}
+
+fn replies(parameters: Vec<String>) -> Vec<String> {
+    if parameters.is_empty() {
+        return error("io.systemd.UserDatabase.NoRecordFound");
+    }
+    parameters
+        .into_iter()
+        .map(|p| format!(r#"{{"parameters":{}}}"#, p))
+        .collect()
+}
+
+fn records(records: &[&str], name_key: &str, id_key: &str, parameters: &str) -> Vec<String> {
+    let name = field(parameters, name_key);
+    let id = field(parameters, id_key);
+    records
+        .iter()
+        .filter(|r| name.is_none_or(|n| field(r, name_key) == Some(n)))
+        .filter(|r| id.is_none_or(|i| field(r, id_key) == Some(i)))
+        .map(|r| format!(r#"{{"record":{}}}"#, r))
+        .collect()
+}
+
+fn reply(request: &str) -> Vec<String> {
+    let parameters = &request[request.find(r#""parameters":"#).unwrap()..];
+    if field(parameters, "service") != Some("io.systemd.Mock") {
+        return error("io.systemd.UserDatabase.BadService");
+    }
+    if field(parameters, "uid") == Some("0") {
+        return error("io.systemd.UserDatabase.ServiceNotAvailable");
+    }
+    match field(request, "method").unwrap() {
+        "io.systemd.UserDatabase.GetUserRecord" => {
+            replies(records(USERS, "userName", "uid", parameters))
+        }
+        "io.systemd.UserDatabase.GetGroupRecord" => {
+            replies(records(GROUPS, "groupName", "gid", parameters))
+        }
+        "io.systemd.UserDatabase.GetMemberships" => {
+            let user = field(parameters, "userName");
+            let group = field(parameters, "groupName");
+            replies(
+                MEMBERSHIPS
+                    .iter()
+                    .filter(|(u, g)| user.is_none_or(|n| n == *u) && group.is_none_or(|n| n == *g))
+                    .map(|(u, g)| format!(r#"{{"userName":"{}","groupName":"{}"}}"#, u, g))
+                    .collect(),
+            )
+        }
+        _ => error("org.varlink.service.MethodNotFound"),
+    }
+}
+
+#[test]
+fn users_are_looked_up() {
+    let mock = Mock::start("varlink_users");
+    let mut reader = PasswdReader::with_client(mock.client(), Duration::new(0, 0));
+    let user = reader.get_by_username("user3").unwrap().unwrap();
+    assert_eq!("user3:x:1002:1002::/home/user3:/bin/zsh", user.to_string());
+    assert_eq!(
+        Some("dynamic".to_string()),
+        reader.get_username_by_uid(61184).unwrap()
+    );
+    assert_eq!(None, reader.get_uid_by_username("nobody").unwrap());
+    assert_eq!(2, reader.get_entries().unwrap().len());
+    assert!(reader.last_loaded().is_some());
+}
+
+#[test]
+fn groups_include_memberships() {
+    let mock = Mock::start("varlink_groups");
+    let mut groups = GroupReader::with_client(mock.client(), Duration::new(0, 0));
+    let admins = groups.get_by_gid(2000).unwrap().unwrap();
+    assert_eq!(vec!["user3", "dynamic"], admins.users);
+    assert_eq!(Some(2000), groups.get_gid_by_name("admins").unwrap());
+    assert_eq!(2, groups.get_groups().unwrap().len());
+
+    let mut passwd = PasswdReader::with_client(mock.client(), CachePolicy::on_change());
+    assert_eq!(
+        Some(vec![1002, 2000]),
+        groups.get_gids_by_username("user3", &mut passwd).unwrap()
+    );
+    let user = PasswdEntry::parse("dynamic:x:61184:61184::/:/bin/sh").unwrap();
+    let names: Vec<String> = groups
+        .get_groups_for_user(&user)
+        .unwrap()
+        .into_iter()
+        .map(|g| g.name)
+        .collect();
+    assert_eq!(vec!["admins"], names);
+}
+
+#[test]
+fn errors_are_reported() {
+    let mock = Mock::start("varlink_errors");
+    let mut reader = PasswdReader::with_client(mock.client(), Duration::new(0, 0));
+    let result = UserSource::get_by_uid(&mut reader, 0);
+    assert!(matches!(
+        &result,
+        Err(Error::Varlink(e)) if e.error == "io.systemd.UserDatabase.ServiceNotAvailable" && e.parameters == "{}"
+    ));
+    assert_eq!(Status::Unavail, Status::of(&result));
+
+    let mut reader = PasswdReader::with_client(
+        Client::with_socket(mock.0.join("io.systemd.Missing")),
+        Duration::new(0, 0),
+    );
+    assert!(matches!(
+        reader.get_by_uid(1002),
+        Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound
+    ));
+}
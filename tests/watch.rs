@@ -0,0 +1,162 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tests of watching the files for changes with inotify.
+#![cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+use user_lookup::database::UserDatabase;
+use user_lookup::watch::{events, Event};
+use user_lookup::{GroupEntry, PasswdEntry};
+
+#[cfg(any(feature = "sync", feature = "async"))]
+use std::path::PathBuf;
+
+mod common;
+#[cfg(any(feature = "sync", feature = "async"))]
+use common::TempDir;
+
+///Replaces the file the way `vipw` does, by renaming a new file over it
+#[cfg(any(feature = "sync", feature = "async"))]
+fn replace(path: &PathBuf, contents: &str) {
+    let tmp = path.with_extension("edit");
+    std::fs::write(&tmp, contents).unwrap();
+    std::fs::rename(&tmp, path).unwrap();
+}
+
+#[test]
+fn events_between_snapshots() {
+    let before = UserDatabase::parse(
+        "root:x:0:0:root:/root:/bin/bash\nuser1:x:1000:100:::\n",
+        "root:x:0:\nusers:x:100:user1,user2\nwheel:x:10:\n",
+    );
+    let after = UserDatabase::parse(
+        "root:x:0:0:root:/root:/bin/zsh\nuser2:x:1001:100:::\n",
+        "root:x:0:\nusers:!:100:user2,user3\nadmins:x:11:\n",
+    );
+    let user = |s| PasswdEntry::parse(s).unwrap();
+    let group = |s| GroupEntry::parse(s).unwrap();
+    assert_eq!(
+        vec![
+            Event::UserModified {
+                before: user("root:x:0:0:root:/root:/bin/bash"),
+                after: user("root:x:0:0:root:/root:/bin/zsh"),
+            },
+            Event::UserAdded(user("user2:x:1001:100:::")),
+            Event::UserRemoved(user("user1:x:1000:100:::")),
+            Event::GroupModified {
+                before: group("users:x:100:user1,user2"),
+                after: group("users:!:100:user2,user3"),
+            },
+            Event::GroupMembershipChanged {
+                group: group("users:!:100:user2,user3"),
+                added: vec!["user3".to_string()],
+                removed: vec!["user1".to_string()],
+            },
+            Event::GroupAdded(group("admins:x:11:")),
+            Event::GroupRemoved(group("wheel:x:10:")),
+        ],
+        events(&before, &after)
+    );
+    assert!(events(&after, &after).is_empty());
+
+    //A user or group with the same id and another name is renamed
+    let renamed = UserDatabase::parse(
+        "root:x:0:0:root:/root:/bin/zsh\nuser3:x:1001:100:::\n",
+        "root:x:0:\nstaff:!:100:user2,user3\nadmins:x:11:\n",
+    );
+    assert_eq!(
+        vec![
+            Event::UserModified {
+                before: user("user2:x:1001:100:::"),
+                after: user("user3:x:1001:100:::"),
+            },
+            Event::GroupModified {
+                before: group("users:!:100:user2,user3"),
+                after: group("staff:!:100:user2,user3"),
+            },
+        ],
+        events(&after, &renamed)
+    );
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn sync_watcher_sees_writes_and_renames() {
+    use std::time::Duration;
+    use user_lookup::error::ParseMode;
+    use user_lookup::sync_reader::Watcher;
+
+    let dir = TempDir::with_files("watch_sync", &["passwd", "group"]);
+    let (passwd, group) = (dir.join("passwd"), dir.join("group"));
+    let mut watcher = Watcher::from_files(&passwd, &group, ParseMode::Lenient).unwrap();
+    let timeout = Duration::from_secs(5);
+
+    //Written in place
+    let mut contents = std::fs::read_to_string(&passwd).unwrap();
+    contents.push_str("user3:x:1002:100:User 3:/home/user3:/bin/bash\n");
+    std::fs::write(&passwd, &contents).unwrap();
+    assert!(matches!(
+        watcher.next(),
+        Some(Ok(Event::UserAdded(user))) if user.uid == 1002
+    ));
+    assert_eq!(Some(1002), watcher.database().get_uid_by_username("user3"));
+
+    //Replaced by a rename
+    replace(
+        &group,
+        "root:x:0:\nwheel:x:10:user1\nusers:x:100:user1,user2,user3\n",
+    );
+    assert_eq!(
+        vec![Event::GroupMembershipChanged {
+            group: GroupEntry::parse("users:x:100:user1,user2,user3").unwrap(),
+            added: vec!["user3".to_string()],
+            removed: vec![],
+        }],
+        watcher.wait_timeout(timeout).unwrap()
+    );
+
+    //Other files in the directory are ignored
+    std::fs::write(passwd.with_file_name("shadow"), "").unwrap();
+    assert!(watcher
+        .wait_timeout(Duration::from_millis(100))
+        .unwrap()
+        .is_empty());
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn async_watcher_publishes_database() {
+    use std::time::Duration;
+    use user_lookup::async_reader::Watcher;
+    use user_lookup::error::ParseMode;
+
+    let dir = TempDir::with_files("watch_async", &["passwd", "group"]);
+    let (passwd, group) = (dir.join("passwd"), dir.join("group"));
+    let mut watcher = Watcher::from_files(&passwd, &group, ParseMode::Lenient).unwrap();
+    let mut database = watcher.subscribe();
+
+    replace(
+        &passwd,
+        "root:x:0:0:root:/root:/bin/bash\nuser1:x:1000:100:User One:/home/user1:/bin/bash\n",
+    );
+    let event = tokio::time::timeout(Duration::from_secs(5), watcher.next())
+        .await
+        .unwrap();
+    assert!(matches!(
+        event,
+        Some(Ok(Event::UserRemoved(user))) if user.username == "user2"
+    ));
+    tokio::time::timeout(Duration::from_secs(5), database.changed())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(2, database.borrow().get_entries().len());
+    assert_eq!(2, watcher.database().get_entries().len());
+}
@@ -0,0 +1,110 @@
+// Copyright 2022 Mattias Eriksson
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tests of the writer refusing entries that would change the lines of
+//! the file, and of the lock it holds while writing.
+use user_lookup::writer::Writer;
+
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+use user_lookup::{Error, GroupEntry, PasswdEntry, ShadowEntry};
+
+mod common;
+use common::TempDir;
+
+fn is_invalid_data(result: Result<(), Error>) -> bool {
+    matches!(result, Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::InvalidData)
+}
+
+#[test]
+fn refuses_fields_with_separators() {
+    let dir = TempDir::with_files("writer_separators", &["passwd"]);
+    let path = dir.join("passwd");
+    let writer = Writer::in_dir(dir.path());
+    let mut user = PasswdEntry::parse("user3:x:1002:100:::/bin/sh").unwrap();
+
+    user.gecos = "x\nevil::0:0::/:/bin/sh".to_string();
+    assert!(is_invalid_data(writer.write_passwd(&path, &[user.clone()])));
+    user.gecos = "x:y".to_string();
+    assert!(is_invalid_data(writer.write_passwd(&path, &[user.clone()])));
+
+    let mut group = GroupEntry::parse("users:x:100:user1").unwrap();
+    group.users.push("user2,root".to_string());
+    assert!(is_invalid_data(
+        writer.write_group(dir.join("group"), &[group])
+    ));
+
+    let mut shadow = ShadowEntry::parse("user1:!:19000::::::").unwrap();
+    shadow.passwd = "$6$x\nroot::0::::::".to_string();
+    assert!(is_invalid_data(
+        writer.write_shadow(dir.join("shadow"), &[shadow])
+    ));
+
+    //Nothing was written, not even a temporary file
+    assert_eq!(
+        std::fs::read("test_files/passwd").unwrap(),
+        std::fs::read(&path).unwrap()
+    );
+    let mut files: Vec<String> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    assert_eq!(vec![".pwd.lock", "passwd"], files);
+
+    user.gecos = "User Three".to_string();
+    writer.write_passwd(&path, &[user]).unwrap();
+    assert_eq!(
+        "user3:x:1002:100:User Three::/bin/sh\n",
+        std::fs::read_to_string(&path).unwrap()
+    );
+}
+
+///Run by [lock_is_kept_when_another_thread_gives_up] in another process,
+///where it fails if the lock file given to it can be locked
+#[test]
+fn lock_probe() {
+    if let Some(path) = std::env::var_os("USER_LOOKUP_LOCK_PROBE") {
+        let lock = Writer::with_lock_file(path)
+            .with_timeout(Duration::ZERO)
+            .lock();
+        assert!(lock.is_err());
+    }
+}
+
+///Whether the lock file is locked, as seen from another process
+fn locked_elsewhere(path: &Path) -> bool {
+    Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "lock_probe", "--quiet", "--test-threads=1"])
+        .env("USER_LOOKUP_LOCK_PROBE", path)
+        .output()
+        .unwrap()
+        .status
+        .success()
+}
+
+#[test]
+fn lock_is_kept_when_another_thread_gives_up() {
+    let dir = TempDir::new("writer_lock");
+    let path = dir.join(".pwd.lock");
+    let writer = Writer::in_dir(dir.path()).with_timeout(Duration::from_millis(200));
+    let lock = writer.lock().unwrap();
+    assert!(locked_elsewhere(&path));
+
+    let other = Writer::in_dir(dir.path()).with_timeout(Duration::from_millis(200));
+    let result = std::thread::spawn(move || other.lock().map(drop))
+        .join()
+        .unwrap();
+    assert!(matches!(result, Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::TimedOut));
+    assert!(locked_elsewhere(&path));
+
+    drop(lock);
+    assert!(!locked_elsewhere(&path));
+    assert!(writer.lock().is_ok());
+}